@@ -0,0 +1,344 @@
+//! Support for build functions that return futures (the `async` feature).
+//!
+//! `DepGraph::make_async` polls the futures of all rules whose dependencies are up to date
+//! concurrently on the task it is awaited from. It doesn't depend on any particular runtime, but
+//! timeouts and the delay between retries need a timer: with the `tokio` feature tokio's is used
+//! (so `make_async` must be awaited inside a tokio runtime with the time driver enabled),
+//! otherwise a thread is started to wait for each one.
+//!
+//! The synchronous `make` functions don't run async rules, since the futures may need a reactor
+//! that only exists on the runtime: they fail with `Error::AsyncRule` if one is stale.
+
+use std::future::{self, Future};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+#[cfg(not(feature = "tokio"))]
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+#[cfg(not(feature = "tokio"))]
+use std::sync::Mutex;
+use std::task::Poll;
+#[cfg(any(test, not(feature = "tokio")))]
+use std::task::Waker;
+#[cfg(test)]
+use std::task::{Context, Wake};
+#[cfg(any(test, not(feature = "tokio")))]
+use std::thread;
+#[cfg(test)]
+use std::thread::Thread;
+use std::time::{Duration, Instant};
+
+use petgraph::graph::NodeIndex;
+
+use crate::{
+    restat, BuildAction, BuildError, BuildObserver, DepGraph, DepGraphBuilder, DepResult, Error,
+    MakeParams, RuleOptions,
+};
+
+/// (Internal) A boxed future returned by an async build function.
+pub(crate) type BuildFuture = Pin<Box<dyn Future<Output = Result<(), BuildError>> + Send>>;
+
+/// (Internal) The type of a boxed async build function.
+pub(crate) type AsyncBuildFn = dyn Fn(PathBuf, Vec<PathBuf>) -> BuildFuture + Send + Sync;
+
+/// (Internal) A rule that is currently being built by `make_async`.
+///
+/// With the `parallel` feature the graph is `Sync`, so the future returned by `make_async` can be
+/// sent to another thread, for runtimes that move tasks between threads.
+#[cfg(feature = "parallel")]
+type Running<'a> = Pin<Box<dyn Future<Output = (NodeIndex<u32>, DepResult<()>)> + Send + 'a>>;
+#[cfg(not(feature = "parallel"))]
+type Running<'a> = Pin<Box<dyn Future<Output = (NodeIndex<u32>, DepResult<()>)> + 'a>>;
+
+impl<'a> DepGraphBuilder<'a> {
+    /// Add a new rule whose build function returns a future.
+    ///
+    /// Unlike `add_rule`, the build function is passed the output filename as an owned `PathBuf`
+    /// and the dependency filenames as a `Vec<PathBuf>`, so that the future it returns doesn't
+    /// borrow from the graph.
+    ///
+    /// The rule can only be built by `DepGraph::make_async`: `make` and the other synchronous
+    /// builds fail with `Error::AsyncRule` if it is stale.
+    pub fn add_async_rule<F, Fut, P1, P2>(
+        self,
+        filename: P1,
+        dependencies: &[P2],
+        build_fn: F,
+    ) -> DepGraphBuilder<'a>
+    where
+        F: Fn(PathBuf, Vec<PathBuf>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), String>> + Send + 'static,
+        P1: AsRef<Path>,
+        P2: AsRef<Path>,
+    {
+        self.add_async_rule_with_opts(filename, dependencies, RuleOptions::default(), build_fn)
+    }
+
+    /// Add a new rule, like `add_async_rule`, with options controlling how it is run.
+    pub fn add_async_rule_with_opts<F, Fut, P1, P2>(
+        self,
+        filename: P1,
+        dependencies: &[P2],
+        options: RuleOptions,
+        build_fn: F,
+    ) -> DepGraphBuilder<'a>
+    where
+        F: Fn(PathBuf, Vec<PathBuf>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), String>> + Send + 'static,
+        P1: AsRef<Path>,
+        P2: AsRef<Path>,
+    {
+        self.push_rule(
+            filename,
+            dependencies,
+            options,
+            BuildAction::Async(Arc::new(move |out, deps| {
+                let fut = build_fn(out, deps);
+                Box::pin(async move { fut.await.map_err(BuildError::from) })
+            })),
+        )
+    }
+}
+
+impl<'a> DepGraph<'a> {
+    /// Run the build, awaiting async build functions.
+    ///
+    /// All rules whose dependencies are up to date are polled concurrently. Synchronous build
+    /// functions are called directly, and so will block the task while they run. If any rule
+    /// fails no new rules are started (unless using `MakeParams::KeepGoing`), and the first error
+    /// is returned once the running rules have finished.
+    pub async fn make_async(&self, make_params: MakeParams) -> DepResult<()> {
+        self.make_async_with_observer(make_params, &()).await
+    }
+
+    /// Run the build asynchronously, like `make_async`, notifying `observer` of progress.
+    pub async fn make_async_with_observer(
+        &self,
+        make_params: MakeParams,
+        observer: &dyn BuildObserver,
+    ) -> DepResult<()> {
+        // the toposort is only used to make sure the graph is still acyclic
+        self.toposort()?;
+        if make_params.check_only() {
+            return self.check_up_to_date(self.default_wanted.as_deref());
+        }
+        if make_params.touch() {
+            return self.touch(self.default_wanted.as_deref(), observer);
+        }
+        let force = make_params.force();
+        observer.on_begin(self.rule_count(self.default_wanted.as_deref()));
+
+        let mut scheduler =
+            self.scheduler(self.default_wanted.as_deref(), make_params.keep_going());
+        let mut running: Vec<Running<'_>> = Vec::new();
+        loop {
+            while let Some(idx) = scheduler.next() {
+                running.push(Box::pin(self.build_dependency_async(idx, force, observer)));
+            }
+            if running.is_empty() {
+                break;
+            }
+            let (idx, result) = future::poll_fn(|cx| {
+                for i in 0..running.len() {
+                    if let Poll::Ready(out) = running[i].as_mut().poll(cx) {
+                        drop(running.swap_remove(i));
+                        return Poll::Ready(out);
+                    }
+                }
+                Poll::Pending
+            })
+            .await;
+            scheduler.finished(idx, result);
+        }
+        let result = scheduler.finish();
+        observer.on_end(&result);
+        result
+    }
+
+    /// Async version of `build_dependency`.
+    async fn build_dependency_async(
+        &self,
+        idx: NodeIndex<u32>,
+        force: bool,
+        observer: &dyn BuildObserver,
+    ) -> (NodeIndex<u32>, DepResult<()>) {
+        let dep = self.graph.node_weight(idx).unwrap();
+        let result = async {
+            let children = self.dependency_paths(idx)?;
+            // if there is a build script, and dependencies have changed, run it
+            if let Some(ref f) = dep.build_fn {
+                if let Some(reason) = self.rebuild_reason(dep, &children, force)? {
+                    let snapshot = restat::Snapshot::take(dep)?;
+                    observer.on_start(&dep.filename);
+                    let start = Instant::now();
+                    #[cfg(feature = "tracing")]
+                    let span = crate::rule_span(dep, &children, &reason);
+                    #[cfg(not(feature = "tracing"))]
+                    let _ = reason;
+                    let mut attempt = 0;
+                    loop {
+                        let result = match f {
+                            BuildAction::Sync(_) | BuildAction::Scoped(_) => {
+                                #[cfg(feature = "tracing")]
+                                let _span = span.enter();
+                                f.run(&dep.filename, &children, dep.options.timeout)
+                            }
+                            BuildAction::Async(f) => {
+                                let fut = f(
+                                    dep.filename.clone(),
+                                    children.iter().map(|p| p.to_path_buf()).collect(),
+                                );
+                                let fut = with_timeout(
+                                    &dep.filename,
+                                    &children,
+                                    fut,
+                                    dep.options.timeout,
+                                );
+                                #[cfg(feature = "tracing")]
+                                let fut = tracing::Instrument::instrument(fut, span.clone());
+                                fut.await
+                            }
+                        };
+                        match result {
+                            Ok(()) => break,
+                            Err(e) if attempt >= dep.options.retries => return Err(e),
+                            Err(_) => {
+                                delay(dep.options.delay_before_retry(attempt)).await;
+                                attempt += 1;
+                            }
+                        }
+                    }
+                    dep.check_built()?;
+                    snapshot.restore_unchanged()?;
+                    let duration = start.elapsed();
+                    self.record_built(dep, &children, duration)?;
+                    observer.on_finished(&dep.filename, duration);
+                } else {
+                    observer.on_skipped(&dep.filename);
+                }
+            }
+            dep.check_exists()
+        }
+        .await;
+        if let (Some(_), Err(e)) = (&dep.build_fn, &result) {
+            observer.on_failed(&dep.filename, e);
+        }
+        (idx, result)
+    }
+}
+
+/// Await a build function's future, giving up with `Error::Timeout` after `timeout`.
+#[cfg(feature = "tokio")]
+pub(crate) async fn with_timeout(
+    filename: &Path,
+    deps: &[&Path],
+    fut: BuildFuture,
+    timeout: Option<Duration>,
+) -> DepResult<()> {
+    let result = match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, fut)
+            .await
+            .map_err(|_| Error::Timeout(filename.to_owned()))?,
+        None => fut.await,
+    };
+    result.map_err(Error::build_failed(filename, deps))
+}
+
+/// Await a build function's future, giving up with `Error::Timeout` after `timeout`.
+#[cfg(not(feature = "tokio"))]
+pub(crate) async fn with_timeout(
+    filename: &Path,
+    deps: &[&Path],
+    fut: BuildFuture,
+    timeout: Option<Duration>,
+) -> DepResult<()> {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return fut.await.map_err(Error::build_failed(filename, deps)),
+    };
+    let mut fut = fut;
+    let mut timer = std::pin::pin!(delay(timeout));
+    future::poll_fn(|cx| {
+        if let Poll::Ready(result) = fut.as_mut().poll(cx) {
+            return Poll::Ready(result.map_err(Error::build_failed(filename, deps)));
+        }
+        if timer.as_mut().poll(cx).is_ready() {
+            return Poll::Ready(Err(Error::Timeout(filename.to_owned())));
+        }
+        Poll::Pending
+    })
+    .await
+}
+
+/// A future that completes after `duration`, using tokio's timer.
+#[cfg(feature = "tokio")]
+async fn delay(duration: Duration) {
+    tokio::time::sleep(duration).await
+}
+
+/// A future that completes after `duration`, without blocking the task that awaits it.
+///
+/// Without the `tokio` feature we don't have a runtime's timer to use, so a thread is spawned to
+/// do the waiting.
+#[cfg(not(feature = "tokio"))]
+pub(crate) async fn delay(duration: Duration) {
+    if duration.is_zero() {
+        return;
+    }
+    let done = Arc::new(AtomicBool::new(false));
+    // the waker from the latest poll, as the task may have moved since the thread was started
+    let waker = Arc::new(Mutex::new(None::<Waker>));
+    let mut started = false;
+    future::poll_fn(|cx| {
+        {
+            let mut waker = waker.lock().unwrap();
+            // checked with the lock held, so the thread either sees this waker or it is done
+            if done.load(Ordering::Acquire) {
+                return Poll::Ready(());
+            }
+            match *waker {
+                Some(ref waker) if waker.will_wake(cx.waker()) => (),
+                _ => *waker = Some(cx.waker().clone()),
+            }
+        }
+        if !started {
+            started = true;
+            let done = done.clone();
+            let waker = waker.clone();
+            thread::spawn(move || {
+                thread::sleep(duration);
+                done.store(true, Ordering::Release);
+                if let Some(waker) = waker.lock().unwrap().take() {
+                    waker.wake();
+                }
+            });
+        }
+        Poll::Pending
+    })
+    .await
+}
+
+/// Wakes a thread blocked in `block_on`.
+#[cfg(test)]
+struct ThreadWaker(Thread);
+
+#[cfg(test)]
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Drive a future to completion on the current thread.
+#[cfg(test)]
+pub(crate) fn block_on<F: Future>(fut: F) -> F::Output {
+    let mut fut = std::pin::pin!(fut);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        match fut.as_mut().poll(&mut cx) {
+            Poll::Ready(out) => return out,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
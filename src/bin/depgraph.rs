@@ -0,0 +1,291 @@
+//! The `depgraph` command (the `cli` feature), which runs the rules in a manifest file outside of
+//! a build script.
+
+use std::env;
+use std::error::Error as _;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use depgraph::{DepGraph, DepGraphBuilder, DepResult, Error, MakeParams};
+
+const USAGE: &str = "\
+Usage: depgraph [options] <command>
+
+Commands:
+    build [target..]  Build the given targets (or aliases), or every out of date target
+    clean             Remove every generated file
+    graph             Print the dependency graph in Graphviz DOT format
+    explain <target>  Say whether a target (or alias) would be rebuilt, and why
+    status            List the targets that are out of date, failing if there are any
+
+Options:
+    -f, --file <path>  The file to read the rules from. Manifests (.toml, .yaml, .yml or .json),
+                       Makefiles, ninja files and compile_commands.json are understood. By
+                       default the first of depgraph.toml, depgraph.yaml, depgraph.yml and
+                       depgraph.json in the current directory is used.
+    -l, --log <path>   Keep a build log, so changed commands rebuild their targets
+    -j, --jobs <n>     Run up to n build functions at once (build only)
+    -B, --force        Rebuild every target (build only)
+    -k, --keep-going   Keep building after a failure (build only)
+    --dot              Print the graph in DOT format (graph only, the default)
+    --json             Print the graph as JSON (graph only)
+    --mermaid          Print the graph as a Mermaid flowchart (graph only)
+    -h, --help         Print this message
+";
+
+/// The manifests that are looked for if `--file` isn't given.
+const DEFAULT_FILES: &[&str] = &[
+    "depgraph.toml",
+    "depgraph.yaml",
+    "depgraph.yml",
+    "depgraph.json",
+];
+
+/// (Internal) What to do, from the command line.
+enum Command {
+    Build(Vec<PathBuf>),
+    Clean,
+    Graph(GraphFormat),
+    Explain(PathBuf),
+    Status,
+}
+
+/// (Internal) How `graph` prints the graph.
+#[derive(Clone, Copy)]
+enum GraphFormat {
+    Dot,
+    Json,
+    Mermaid,
+}
+
+/// (Internal) The parsed command line.
+struct Args {
+    command: Command,
+    file: Option<PathBuf>,
+    log: Option<PathBuf>,
+    jobs: usize,
+    force: bool,
+    keep_going: bool,
+}
+
+fn main() {
+    let args = match parse_args(env::args().skip(1)) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            print!("{}", USAGE);
+            return;
+        }
+        Err(message) => {
+            eprintln!("depgraph: {} (see `depgraph --help`)", message);
+            process::exit(2);
+        }
+    };
+    match run(args) {
+        Ok(true) => (),
+        Ok(false) => process::exit(1),
+        Err(e) => {
+            report_error(&e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Parse the arguments, or return `None` if help was asked for.
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Option<Args>, String> {
+    let mut command = None;
+    let mut targets = Vec::new();
+    let mut file = None;
+    let mut log = None;
+    let mut jobs = 1;
+    let mut force = false;
+    let mut keep_going = false;
+    let mut format = None;
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or_else(|| format!("{} needs a value", name));
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-f" | "--file" => file = Some(PathBuf::from(value(&arg)?)),
+            "-l" | "--log" => log = Some(PathBuf::from(value(&arg)?)),
+            "-j" | "--jobs" => {
+                let n = value(&arg)?;
+                jobs = match n.parse() {
+                    Ok(n) if n > 0 => n,
+                    _ => return Err(format!("invalid number of jobs `{}`", n)),
+                };
+            }
+            "-B" | "--force" => force = true,
+            "-k" | "--keep-going" => keep_going = true,
+            "--dot" => format = Some(GraphFormat::Dot),
+            "--json" => format = Some(GraphFormat::Json),
+            "--mermaid" => format = Some(GraphFormat::Mermaid),
+            _ if arg.starts_with('-') => return Err(format!("unknown option `{}`", arg)),
+            _ if command.is_none() => command = Some(arg),
+            _ if command.as_deref() == Some("build")
+                || command.as_deref() == Some("explain") && targets.is_empty() =>
+            {
+                targets.push(PathBuf::from(arg))
+            }
+            _ => return Err(format!("unexpected argument `{}`", arg)),
+        }
+    }
+    let command = match command.as_deref() {
+        Some("build") => Command::Build(targets),
+        Some("clean") => Command::Clean,
+        Some("graph") => Command::Graph(format.unwrap_or(GraphFormat::Dot)),
+        Some("explain") => Command::Explain(targets.pop().ok_or("explain needs a target")?),
+        Some("status") => Command::Status,
+        Some(command) => return Err(format!("unknown command `{}`", command)),
+        None => return Err("no command given".to_owned()),
+    };
+    Ok(Some(Args {
+        command,
+        file,
+        log,
+        jobs,
+        force,
+        keep_going,
+    }))
+}
+
+/// Run the command, returning whether it succeeded.
+fn run(args: Args) -> DepResult<bool> {
+    let file = match args.file {
+        Some(file) => file,
+        None => default_file()?,
+    };
+    let mut builder = load(&file)?;
+    if let Some(log) = args.log {
+        builder = builder.build_log(log);
+    }
+    let graph = builder.build()?;
+    let params = if args.force {
+        MakeParams::ForceBuild
+    } else if args.keep_going {
+        MakeParams::KeepGoing
+    } else {
+        MakeParams::None
+    };
+    match args.command {
+        Command::Build(targets) if !targets.is_empty() => {
+            for target in targets {
+                graph.make_target(&target, params)?;
+            }
+        }
+        Command::Build(_) => {
+            let report = graph.make_parallel_with_report(params, args.jobs)?;
+            for (target, duration) in report.built() {
+                println!("built {} ({:.2?})", target.display(), duration);
+            }
+            println!(
+                "{} built, {} up to date, in {:.2?}",
+                report.built().len(),
+                report.skipped().len(),
+                report.elapsed()
+            );
+        }
+        Command::Clean => {
+            for path in graph.clean()? {
+                println!("removed {}", path.display());
+            }
+        }
+        Command::Graph(format) => print_graph(&graph, format)?,
+        Command::Explain(target) => {
+            let reason = graph.explain(&target, MakeParams::None)?;
+            println!("{}: {}", target.display(), reason);
+        }
+        Command::Status => {
+            let plan = graph.plan(MakeParams::None)?;
+            if plan.is_empty() {
+                println!("everything is up to date");
+            }
+            for target in plan.targets() {
+                let reason = graph.explain(target, MakeParams::None)?;
+                println!("{}: {}", target.display(), reason);
+            }
+            return Ok(plan.is_empty());
+        }
+    }
+    Ok(true)
+}
+
+/// The first of `DEFAULT_FILES` that exists.
+fn default_file() -> DepResult<PathBuf> {
+    DEFAULT_FILES
+        .iter()
+        .map(PathBuf::from)
+        .find(|file| file.is_file())
+        .ok_or_else(|| {
+            Error::Io(io::Error::new(
+                io::ErrorKind::NotFound,
+                "no depgraph.toml, depgraph.yaml, depgraph.yml or depgraph.json here; use --file",
+            ))
+        })
+}
+
+/// Read the rules from `file`, going by its name.
+fn load(file: &Path) -> DepResult<DepGraphBuilder<'static>> {
+    let name = file
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let extension = file.extension().and_then(|ext| ext.to_str());
+    if name == "compile_commands.json" {
+        DepGraphBuilder::from_compile_commands(file)
+    } else if name == "Makefile" || name == "makefile" || name == "GNUmakefile" {
+        DepGraphBuilder::from_makefile(file)
+    } else {
+        match extension {
+            Some("toml") => DepGraphBuilder::from_toml(file),
+            Some("yaml") | Some("yml") => DepGraphBuilder::from_yaml(file),
+            Some("json") => DepGraphBuilder::from_json(file),
+            Some("ninja") => DepGraphBuilder::from_ninja(file),
+            Some("mk") => DepGraphBuilder::from_makefile(file),
+            _ => Err(Error::InvalidManifest(
+                file.to_owned(),
+                "unknown kind of file, expected a manifest, Makefile or ninja file".to_owned(),
+            )),
+        }
+    }
+}
+
+fn print_graph(graph: &DepGraph, format: GraphFormat) -> DepResult<()> {
+    let text = match format {
+        GraphFormat::Dot => graph.to_dot(),
+        GraphFormat::Json => graph.to_json()?,
+        GraphFormat::Mermaid => graph.to_mermaid(),
+    };
+    print!("{}", text);
+    Ok(())
+}
+
+/// Print `e` to stderr, with everything that explains it.
+fn report_error(e: &Error) {
+    eprintln!("depgraph: error: {}", e);
+    match e {
+        Error::BuildFailed { source, .. } => eprintln!("{}", source),
+        Error::BuildsFailed { failed, skipped } => {
+            for (target, e) in failed {
+                eprintln!("failed: {}: {}", target.display(), e);
+                if let Error::BuildFailed { source, .. } = e {
+                    eprintln!("{}", source);
+                }
+            }
+            for target in skipped {
+                eprintln!("skipped: {}", target.display());
+            }
+        }
+        Error::OutOfDate(targets) => {
+            for target in targets {
+                eprintln!("out of date: {}", target.display());
+            }
+        }
+        _ => {
+            let mut source = e.source();
+            while let Some(cause) = source {
+                eprintln!("caused by: {}", cause);
+                source = cause.source();
+            }
+        }
+    }
+}
@@ -0,0 +1,278 @@
+//! Integration with cargo build scripts.
+
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::{BuildObserver, DepGraph, DepGraphBuilder, DepResult, Error, Rule};
+
+/// The parts of the environment cargo sets for build scripts that are useful when building
+/// dependencies.
+#[derive(Debug, Clone)]
+pub struct CargoEnv {
+    out_dir: PathBuf,
+    target: String,
+    host: String,
+    profile: String,
+}
+
+impl CargoEnv {
+    /// Read `OUT_DIR`, `TARGET`, `HOST` and `PROFILE` from the environment.
+    ///
+    /// Returns `Error::MissingEnvVar` if any of them isn't set, for example because this isn't
+    /// running in a build script.
+    pub fn from_env() -> DepResult<CargoEnv> {
+        CargoEnv::from_lookup(|name| env::var(name).ok())
+    }
+
+    /// Read `OUT_DIR`, `TARGET`, `HOST` and `PROFILE` from `vars` rather than the environment,
+    /// such as when running the build outside cargo, or in tests.
+    ///
+    /// Returns `Error::MissingEnvVar` if any of them isn't in `vars`.
+    pub fn from_vars(vars: &HashMap<String, String>) -> DepResult<CargoEnv> {
+        CargoEnv::from_lookup(|name| vars.get(name).cloned())
+    }
+
+    fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> DepResult<CargoEnv> {
+        let var = |name: &str| lookup(name).ok_or_else(|| Error::MissingEnvVar(name.to_owned()));
+        Ok(CargoEnv {
+            out_dir: PathBuf::from(var("OUT_DIR")?),
+            target: var("TARGET")?,
+            host: var("HOST")?,
+            profile: var("PROFILE")?,
+        })
+    }
+
+    /// The directory build outputs should be written to.
+    pub fn out_dir(&self) -> &Path {
+        &self.out_dir
+    }
+
+    /// The target triple being compiled for.
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    /// The triple of the machine doing the compiling.
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// The profile being built, `debug` or `release`.
+    pub fn profile(&self) -> &str {
+        &self.profile
+    }
+}
+
+/// Print `message` as a `cargo:warning=` line, so cargo shows it when running the build script.
+///
+/// Cargo only shows the first line of each warning, so each line of a multi-line message is
+/// printed as a warning of its own. Useful in build functions for things like an optional tool
+/// not being installed.
+pub fn cargo_warning(message: &str) {
+    // nowhere to report a failure to print to
+    let _ = write_cargo_warning(&mut io::stdout().lock(), message);
+}
+
+fn write_cargo_warning(out: &mut dyn Write, message: &str) -> io::Result<()> {
+    for line in message.lines() {
+        writeln!(out, "cargo:warning={}", line)?;
+    }
+    Ok(())
+}
+
+/// An observer that reports build events as `cargo:warning=` lines, so they show up in cargo's
+/// output rather than being hidden in the build script's log.
+///
+/// By default only failures are reported; use the `warn_on_*` methods to choose which events
+/// are.
+///
+/// ```no_run
+/// # let graph = depgraph::DepGraphBuilder::new().build().unwrap();
+/// let warnings = depgraph::CargoWarnings::new().warn_on_built(true);
+/// graph
+///     .make_with_observer(depgraph::MakeParams::KeepGoing, &warnings)
+///     .unwrap();
+/// ```
+pub struct CargoWarnings {
+    out: Mutex<Box<dyn Write + Send>>,
+    failed: bool,
+    skipped: bool,
+    built: bool,
+}
+
+impl CargoWarnings {
+    /// Report warnings on stdout, where cargo looks for them.
+    pub fn new() -> CargoWarnings {
+        CargoWarnings::to_writer(io::stdout())
+    }
+
+    /// Write warnings to `out` instead of stdout.
+    pub fn to_writer<W: Write + Send + 'static>(out: W) -> CargoWarnings {
+        CargoWarnings {
+            out: Mutex::new(Box::new(out)),
+            failed: true,
+            skipped: false,
+            built: false,
+        }
+    }
+
+    /// Whether to warn when a target fails to build. The default is `true`.
+    pub fn warn_on_failed(mut self, warn: bool) -> CargoWarnings {
+        self.failed = warn;
+        self
+    }
+
+    /// Whether to warn when a target is up to date, so isn't built. The default is `false`.
+    pub fn warn_on_skipped(mut self, warn: bool) -> CargoWarnings {
+        self.skipped = warn;
+        self
+    }
+
+    /// Whether to warn when a target is built. The default is `false`.
+    pub fn warn_on_built(mut self, warn: bool) -> CargoWarnings {
+        self.built = warn;
+        self
+    }
+
+    fn warn(&self, message: &str) {
+        let _ = write_cargo_warning(&mut *self.out.lock().unwrap(), message);
+    }
+}
+
+impl Default for CargoWarnings {
+    fn default() -> CargoWarnings {
+        CargoWarnings::new()
+    }
+}
+
+impl BuildObserver for CargoWarnings {
+    fn on_skipped(&self, target: &Path) {
+        if self.skipped {
+            self.warn(&format!("{} is up to date", target.display()));
+        }
+    }
+
+    fn on_finished(&self, target: &Path, duration: Duration) {
+        if self.built {
+            self.warn(&format!("built {} in {:?}", target.display(), duration));
+        }
+    }
+
+    fn on_failed(&self, target: &Path, err: &Error) {
+        if self.failed {
+            self.warn(&format!("failed to build {}: {}", target.display(), err));
+        }
+    }
+}
+
+impl<'a> DepGraphBuilder<'a> {
+    /// Create a `DepGraphBuilder` whose relative output paths are in cargo's `OUT_DIR`.
+    ///
+    /// This is `DepGraphBuilder::new().out_dir(..)` with the directory from `CargoEnv`, and fails
+    /// with `Error::MissingEnvVar` outside of a build script.
+    pub fn new_in_out_dir() -> DepResult<DepGraphBuilder<'a>> {
+        Ok(DepGraphBuilder::new().out_dir(CargoEnv::from_env()?.out_dir()))
+    }
+
+    /// Treat relative output paths as relative to `dir`.
+    ///
+    /// When the graph is built, each relative output of a rule with a build function (including
+    /// extra outputs, implicit outputs and depfiles) has `dir` prepended, and so does any
+    /// dependency naming one of those outputs. Other relative dependencies are left alone, so
+    /// they are still found relative to the current directory (the crate root in a build
+    /// script).
+    pub fn out_dir<P: AsRef<Path>>(mut self, dir: P) -> DepGraphBuilder<'a> {
+        self.out_dir = Some(dir.as_ref().to_owned());
+        self
+    }
+}
+
+/// Move the relative outputs of `rules` into `dir`, and update the dependencies on them.
+///
+/// Returns the new path of each output that was moved.
+pub(crate) fn resolve_outputs(rules: &mut [Rule], dir: &Path) -> HashMap<PathBuf, PathBuf> {
+    let mut moved = HashMap::new();
+    for rule in rules.iter_mut().filter(|rule| rule.build_fn.is_some()) {
+        let outputs = std::iter::once(&mut rule.filename)
+            .chain(rule.extra_outputs.iter_mut())
+            .chain(rule.options.implicit_outputs.iter_mut())
+            .chain(rule.options.depfile.iter_mut());
+        for output in outputs.filter(|output| output.is_relative()) {
+            let resolved = dir.join(&*output);
+            moved.insert(output.clone(), resolved.clone());
+            *output = resolved;
+        }
+    }
+    for rule in rules.iter_mut() {
+        let deps = rule
+            .dependencies
+            .iter_mut()
+            .chain(rule.options.optional_deps.iter_mut());
+        for dep in deps {
+            if let Some(resolved) = moved.get(dep) {
+                *dep = resolved.clone();
+            }
+        }
+    }
+    moved
+}
+
+impl<'a> DepGraph<'a> {
+    /// Write a `cargo:rerun-if-changed=` line for each source file in the graph (each file
+    /// without a rule), and a `cargo:rerun-if-env-changed=` line for each environment variable
+    /// in a rule's `RuleOptions::env_deps`, so cargo reruns the build script when any of them
+    /// changes.
+    ///
+    /// Files found through depfiles and scanners are included, and optional dependencies that
+    /// don't exist are left out (cargo would otherwise rerun the build script every time).
+    pub fn write_cargo_rerun_if_changed<W: Write>(&self, mut out: W) -> DepResult<()> {
+        let mut seen = HashSet::new();
+        for path in self.source_paths()? {
+            if seen.insert(path.clone()) {
+                writeln!(out, "cargo:rerun-if-changed={}", path.display())?;
+            }
+        }
+        let mut seen = HashSet::new();
+        for name in self
+            .graph
+            .node_weights()
+            .flat_map(|dep| &dep.options.env_deps)
+        {
+            if seen.insert(name) {
+                writeln!(out, "cargo:rerun-if-env-changed={}", name)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Print `cargo:rerun-if-changed=` and `cargo:rerun-if-env-changed=` lines to stdout, for use
+    /// in build scripts. See `write_cargo_rerun_if_changed`.
+    pub fn emit_cargo_rerun_if_changed(&self) -> DepResult<()> {
+        self.write_cargo_rerun_if_changed(io::stdout().lock())
+    }
+
+    /// The source files in the graph, followed by those discovered by rules.
+    pub(crate) fn source_paths(&self) -> DepResult<Vec<PathBuf>> {
+        let mut paths = Vec::new();
+        for dep in self.graph.node_weights() {
+            let is_source = dep.build_fn.is_none() && !dep.phony && !dep.extra_output;
+            let missing_optional = dep.optional && !dep.filename.exists();
+            if is_source && !missing_optional {
+                paths.push(dep.filename.clone());
+            }
+        }
+        for idx in self.graph.node_indices() {
+            let dep = &self.graph[idx];
+            if dep.build_fn.is_some() {
+                let deps = self.dependency_files(idx);
+                let discovered = self.discovered_deps(dep, &deps)?;
+                paths.extend(discovered.into_iter().filter(|path| path.exists()));
+            }
+        }
+        Ok(paths)
+    }
+}
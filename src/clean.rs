@@ -0,0 +1,93 @@
+//! Removing generated files.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::{DepGraph, DepResult};
+
+impl<'a> DepGraph<'a> {
+    /// Delete every generated file, i.e. the outputs of every rule with a build function, including
+    /// `RuleOptions::implicit_outputs`.
+    ///
+    /// Source files are left alone, and directory targets are removed along with everything in
+    /// them. Returns the files that were actually removed; targets that don't exist are skipped.
+    pub fn clean(&self) -> DepResult<Vec<PathBuf>> {
+        let mut removed = Vec::new();
+        for target in self.generated_files() {
+            let result = if target.is_dir() {
+                fs::remove_dir_all(target)
+            } else {
+                fs::remove_file(target)
+            };
+            match result {
+                Ok(()) => removed.push(target.to_owned()),
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(removed)
+    }
+
+    /// The files that `clean` would remove, without removing them.
+    pub fn clean_dry_run(&self) -> Vec<&Path> {
+        self.generated_files()
+            .filter(|target| target.exists())
+            .collect()
+    }
+
+    /// Delete files in `out_dirs` (and their subdirectories) that aren't part of the graph.
+    ///
+    /// This cleans up outputs left behind by rules that have since been renamed or removed. Files
+    /// in the graph, implicit outputs, the build log, and the freshness checkers' state (such as a
+    /// `ContentHash` database, see `FreshnessChecker::state_file`) are kept; anything else is
+    /// removed. The paths of the files in `out_dirs` are compared with the paths in the graph as
+    /// given, so they should be specified the same way (e.g. both absolute). Returns the files
+    /// that were removed.
+    pub fn prune<P: AsRef<Path>>(&self, out_dirs: &[P]) -> DepResult<Vec<PathBuf>> {
+        let mut removed = Vec::new();
+        for dir in out_dirs {
+            self.prune_dir(dir.as_ref(), &mut removed)?;
+        }
+        Ok(removed)
+    }
+
+    fn prune_dir(&self, dir: &Path, removed: &mut Vec<PathBuf>) -> DepResult<()> {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            if self.keep_when_pruning(&path) {
+                continue;
+            } else if entry.file_type()?.is_dir() {
+                self.prune_dir(&path, removed)?;
+            } else {
+                fs::remove_file(&path)?;
+                removed.push(path);
+            }
+        }
+        Ok(())
+    }
+
+    fn keep_when_pruning(&self, path: &Path) -> bool {
+        self.file_hash.contains_key(path)
+            || self.log.as_ref().is_some_and(|log| log.path() == path)
+            || self.freshness.state_file() == Some(path)
+            || self.graph.node_weights().any(|dep| {
+                dep.implicit_outputs().any(|p| p == path)
+                    || dep
+                        .options
+                        .freshness_checker
+                        .as_ref()
+                        .is_some_and(|checker| checker.state_file() == Some(path))
+            })
+    }
+
+    /// The outputs (including implicit outputs and depfiles) of all rules with build functions that
+    /// build files.
+    fn generated_files(&self) -> impl Iterator<Item = &Path> {
+        self.graph
+            .node_weights()
+            .filter(|dep| dep.build_fn.is_some() && dep.target.is_none())
+            .flat_map(|dep| dep.outputs().chain(dep.implicit_outputs()))
+    }
+}
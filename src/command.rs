@@ -0,0 +1,453 @@
+//! Rules that run an external program or shell command.
+
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+use crate::{DepGraphBuilder, RuleOptions, CONSOLE_POOL};
+
+/// How much of each of stdout and stderr is kept in error messages by default.
+const DEFAULT_OUTPUT_LIMIT: usize = 8 * 1024;
+
+/// A description of a program to run to build a target, for `DepGraphBuilder::add_command_rule`.
+///
+/// In the arguments, `{out}` is replaced by the path of the target. An argument that is exactly
+/// `{deps}` is replaced by one argument for each dependency, and `{deps}` inside a longer
+/// argument by the dependencies separated by spaces.
+///
+/// The program's stdout and stderr are captured, rather than mixed in with the build script's
+/// output (which cargo reads instructions from). If it fails, they are included in the error.
+/// Rules in the console pool (see `CONSOLE_POOL`) are the exception: the program is connected to
+/// the terminal instead.
+///
+/// ```
+/// let cmd = depgraph::CommandSpec::new("yasm")
+///     .args(["-f", "elf64", "-o", "{out}", "{deps}"])
+///     .env("YASM_DEBUG", "0");
+/// ```
+#[derive(Debug, Clone)]
+pub struct CommandSpec {
+    program: Program,
+    args: Vec<OsString>,
+    /// Whether to start from an empty environment, rather than the build script's
+    env_clear: bool,
+    /// Variables to set, or remove if the value is `None`, in order
+    env: Vec<(OsString, Option<OsString>)>,
+    current_dir: Option<PathBuf>,
+    output_limit: usize,
+}
+
+/// (Internal) What a `CommandSpec` runs.
+#[derive(Debug, Clone)]
+enum Program {
+    /// A program, run directly
+    Exec(OsString),
+    /// A shell script, run with `sh -c` or `cmd /C`
+    Shell(String),
+}
+
+/// (Internal) A command with its placeholders replaced, see `CommandSpec::invocation`.
+pub(crate) enum Invocation {
+    /// The program followed by its arguments
+    Exec(Vec<OsString>),
+    /// A shell script
+    Shell(String),
+}
+
+impl CommandSpec {
+    /// Run `program`, found on the `PATH` if it isn't a path itself, with no arguments.
+    pub fn new<S: AsRef<OsStr>>(program: S) -> CommandSpec {
+        CommandSpec::with_program(Program::Exec(program.as_ref().to_owned()))
+    }
+
+    /// Run the shell command `script`, with `sh -c` on Unix and `cmd /C` on Windows.
+    ///
+    /// `{out}` and `{deps}` in the script are replaced by the quoted paths of the target and its
+    /// dependencies. Any arguments added are quoted and appended to the script.
+    pub fn shell(script: &str) -> CommandSpec {
+        CommandSpec::with_program(Program::Shell(script.to_owned()))
+    }
+
+    fn with_program(program: Program) -> CommandSpec {
+        CommandSpec {
+            program,
+            args: Vec::new(),
+            env_clear: false,
+            env: Vec::new(),
+            current_dir: None,
+            output_limit: DEFAULT_OUTPUT_LIMIT,
+        }
+    }
+
+    /// Add an argument.
+    pub fn arg<S: AsRef<OsStr>>(mut self, arg: S) -> CommandSpec {
+        self.args.push(arg.as_ref().to_owned());
+        self
+    }
+
+    /// Add several arguments.
+    pub fn args<I, S>(mut self, args: I) -> CommandSpec
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.args
+            .extend(args.into_iter().map(|arg| arg.as_ref().to_owned()));
+        self
+    }
+
+    /// Set an environment variable for the program.
+    pub fn env<K, V>(mut self, key: K, value: V) -> CommandSpec
+    where
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        self.env
+            .push((key.as_ref().to_owned(), Some(value.as_ref().to_owned())));
+        self
+    }
+
+    /// Remove an environment variable, so the program doesn't inherit it.
+    pub fn env_remove<K: AsRef<OsStr>>(mut self, key: K) -> CommandSpec {
+        self.env.push((key.as_ref().to_owned(), None));
+        self
+    }
+
+    /// Don't let the program inherit any environment variables, including any set with `env`
+    /// before this is called. Useful for making sure a tool runs the same way wherever it's
+    /// run.
+    ///
+    /// Without `PATH` the program may not be found, so it is best given as a full path.
+    pub fn env_clear(mut self) -> CommandSpec {
+        self.env_clear = true;
+        self.env.clear();
+        self
+    }
+
+    /// Run the program in `dir` rather than the current directory.
+    pub fn current_dir<P: AsRef<Path>>(mut self, dir: P) -> CommandSpec {
+        self.current_dir = Some(dir.as_ref().to_owned());
+        self
+    }
+
+    /// How many bytes of each of stdout and stderr to include in the error if the program fails.
+    /// Longer output is cut from the start, since the end usually says what went wrong. The
+    /// default is 8 KiB, and 0 leaves the output out.
+    pub fn output_limit(mut self, bytes: usize) -> CommandSpec {
+        self.output_limit = bytes;
+        self
+    }
+
+    /// The command to run to build `out` from `deps`.
+    fn command(&self, out: &Path, deps: &[&Path]) -> Command {
+        let mut command = match self.invocation(out, deps) {
+            Invocation::Exec(args) => {
+                let mut command = Command::new(&args[0]);
+                command.args(&args[1..]);
+                command
+            }
+            Invocation::Shell(script) => shell(&script),
+        };
+        if self.env_clear {
+            command.env_clear();
+        }
+        for (key, value) in &self.env {
+            match value {
+                Some(value) => command.env(key, value),
+                None => command.env_remove(key),
+            };
+        }
+        if let Some(ref dir) = self.current_dir {
+            command.current_dir(dir);
+        }
+        command
+    }
+
+    /// The program and its arguments, or the shell script, that build `out` from `deps`, with the
+    /// placeholders replaced.
+    pub(crate) fn invocation(&self, out: &Path, deps: &[&Path]) -> Invocation {
+        match self.program {
+            Program::Exec(ref program) => {
+                let mut args = vec![program.clone()];
+                for arg in &self.args {
+                    if arg == "{deps}" {
+                        args.extend(deps.iter().map(|dep| dep.as_os_str().to_owned()));
+                    } else {
+                        args.push(substitute(arg, out, deps));
+                    }
+                }
+                Invocation::Exec(args)
+            }
+            Program::Shell(ref script) => {
+                let mut script = shell_script(script, out, deps);
+                for arg in &self.args {
+                    script.push(' ');
+                    if arg == "{deps}" {
+                        script.push_str(&quote_all(deps));
+                    } else {
+                        script.push_str(&quote(&substitute(arg, out, deps)));
+                    }
+                }
+                Invocation::Shell(script)
+            }
+        }
+    }
+
+    /// The directory the command is run in, if it isn't the build script's.
+    pub(crate) fn dir(&self) -> Option<&Path> {
+        self.current_dir.as_deref()
+    }
+
+    /// The program or script, for error messages.
+    fn name(&self) -> String {
+        match self.program {
+            Program::Exec(ref program) => program.to_string_lossy().into_owned(),
+            Program::Shell(ref script) => script.clone(),
+        }
+    }
+
+    /// Everything that affects what the command does, for `RuleOptions::fingerprint`.
+    pub(crate) fn fingerprint(&self) -> String {
+        format!(
+            "{:?} {:?} {:?} {:?} {:?}",
+            self.program, self.args, self.env_clear, self.env, self.current_dir
+        )
+    }
+
+    /// The command as a single shell command line for a ninja file, with `$out` and `$in` in
+    /// place of the placeholders and other `$` signs escaped. Returns `None` if it can't be
+    /// written on one line, or the environment would have to be cleared.
+    pub(crate) fn ninja_command(&self) -> Option<String> {
+        if self.env_clear {
+            return None;
+        }
+        let mut line = String::new();
+        if let Some(ref dir) = self.current_dir {
+            line.push_str(&format!("cd {} && ", quote(dir.as_os_str())));
+        }
+        for (key, value) in &self.env {
+            let key = key.to_string_lossy();
+            match value {
+                Some(value) => line.push_str(&format!("export {}={} && ", key, quote(value))),
+                None => line.push_str(&format!("unset {} && ", key)),
+            }
+        }
+        match self.program {
+            Program::Exec(ref program) => line.push_str(&quote(program)),
+            Program::Shell(ref script) => line.push_str(script),
+        }
+        for arg in &self.args {
+            line.push(' ');
+            if arg == "{out}" || arg == "{deps}" {
+                line.push_str(&arg.to_string_lossy());
+            } else {
+                line.push_str(&quote(arg));
+            }
+        }
+        if line.contains(['\n', '\r']) {
+            return None;
+        }
+        Some(
+            line.replace('$', "$$")
+                .replace("{out}", "$out")
+                .replace("{deps}", "$in"),
+        )
+    }
+
+    /// Run the program, returning an error including its output if it fails.
+    pub(crate) fn run(&self, out: &Path, deps: &[&Path]) -> Result<(), String> {
+        let name = self.name();
+        let output = self
+            .command(out, deps)
+            .output()
+            .map_err(|e| format!("couldn't run {}: {}", name, e))?;
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(self.failure_message(&name, &output))
+        }
+    }
+
+    /// Run the program with the build script's stdin, stdout and stderr, for the console pool.
+    /// Its output has already been seen, so it isn't included in the error if it fails.
+    pub(crate) fn run_on_console(&self, out: &Path, deps: &[&Path]) -> Result<(), String> {
+        let name = self.name();
+        let status = self
+            .command(out, deps)
+            .status()
+            .map_err(|e| format!("couldn't run {}: {}", name, e))?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(format!("{} failed ({})", name, status))
+        }
+    }
+
+    /// Describe the failed run of `name` that produced `output`.
+    fn failure_message(&self, name: &str, output: &Output) -> String {
+        let mut message = format!("{} failed ({})", name, output.status);
+        for (stream, bytes) in [("stdout", &output.stdout), ("stderr", &output.stderr)] {
+            if self.output_limit > 0 && !bytes.is_empty() {
+                message.push_str(&format!("\n--- {} ---\n", stream));
+                message.push_str(&truncate(bytes, self.output_limit));
+            }
+        }
+        message
+    }
+}
+
+/// The last `limit` bytes of `bytes` as text, noting how much was cut.
+fn truncate(bytes: &[u8], limit: usize) -> String {
+    if bytes.len() <= limit {
+        return String::from_utf8_lossy(bytes).into_owned();
+    }
+    let cut = bytes.len() - limit;
+    format!(
+        "[{} bytes omitted]\n{}",
+        cut,
+        String::from_utf8_lossy(&bytes[cut..])
+    )
+}
+
+/// The command to run the shell command `script`: `sh -c` on Unix and `cmd /C` on Windows.
+#[cfg(not(windows))]
+fn shell(script: &str) -> Command {
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(script);
+    command
+}
+
+/// The command to run the shell command `script`: `sh -c` on Unix and `cmd /C` on Windows.
+#[cfg(windows)]
+fn shell(script: &str) -> Command {
+    use std::os::windows::process::CommandExt;
+
+    // cmd doesn't follow the usual quoting rules, so pass the script exactly as it is
+    let mut command = Command::new("cmd");
+    command.arg("/C").raw_arg(script);
+    command
+}
+
+/// Quote `word` so the shell treats it as a single word.
+#[cfg(not(windows))]
+pub(crate) fn quote(word: &OsStr) -> String {
+    let word = word.to_string_lossy();
+    let safe = |c: char| c.is_ascii_alphanumeric() || "/._-+=:,@%".contains(c);
+    if !word.is_empty() && word.chars().all(safe) {
+        word.into_owned()
+    } else {
+        format!("'{}'", word.replace('\'', "'\\''"))
+    }
+}
+
+/// Quote `word` so the shell treats it as a single word.
+#[cfg(windows)]
+pub(crate) fn quote(word: &OsStr) -> String {
+    format!("\"{}\"", word.to_string_lossy().replace('"', "\"\""))
+}
+
+/// Quote each of `paths`, separated by spaces.
+fn quote_all(paths: &[&Path]) -> String {
+    paths
+        .iter()
+        .map(|path| quote(path.as_os_str()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Replace `{out}` and `{deps}` in `script` with the quoted paths.
+fn shell_script(script: &str, out: &Path, deps: &[&Path]) -> String {
+    script
+        .replace("{out}", &quote(out.as_os_str()))
+        .replace("{deps}", &quote_all(deps))
+}
+
+/// Replace the placeholders in `arg`.
+fn substitute(arg: &OsStr, out: &Path, deps: &[&Path]) -> OsString {
+    let arg = arg.to_string_lossy();
+    if !arg.contains('{') {
+        return arg.into_owned().into();
+    }
+    let deps = deps
+        .iter()
+        .map(|dep| dep.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(" ");
+    arg.replace("{out}", &out.to_string_lossy())
+        .replace("{deps}", &deps)
+        .into()
+}
+
+impl<'a> DepGraphBuilder<'a> {
+    /// Add a rule that builds `filename` by running a program, described by `command`.
+    ///
+    /// The rule fails if the program can't be started or exits unsuccessfully, and the error
+    /// includes its output. The command is used as the rule's `RuleOptions::fingerprint`, so with
+    /// a build log the target is rebuilt when the command changes.
+    pub fn add_command_rule<P1, P2>(
+        self,
+        filename: P1,
+        dependencies: &[P2],
+        command: CommandSpec,
+    ) -> DepGraphBuilder<'a>
+    where
+        P1: AsRef<Path>,
+        P2: AsRef<Path>,
+    {
+        self.add_command_rule_with_opts(filename, dependencies, RuleOptions::default(), command)
+    }
+
+    /// Add a rule that runs a program, like `add_command_rule`, with options controlling how it
+    /// is run. The command is used as the fingerprint unless `options` has one already.
+    pub fn add_command_rule_with_opts<P1, P2>(
+        self,
+        filename: P1,
+        dependencies: &[P2],
+        mut options: RuleOptions,
+        command: CommandSpec,
+    ) -> DepGraphBuilder<'a>
+    where
+        P1: AsRef<Path>,
+        P2: AsRef<Path>,
+    {
+        if options.fingerprint.is_none() {
+            options.fingerprint = Some(command.fingerprint());
+        }
+        let spec = command.clone();
+        let console = options.pool.as_deref() == Some(CONSOLE_POOL);
+        let mut builder =
+            self.add_rule_with_opts(filename, dependencies, options, move |out, deps| {
+                if console {
+                    command.run_on_console(out, deps)
+                } else {
+                    command.run(out, deps)
+                }
+            });
+        builder.rules.last_mut().unwrap().command = Some(spec);
+        builder
+    }
+
+    /// Add a rule that builds `filename` by running the shell command `script`, like a Makefile
+    /// rule.
+    ///
+    /// `{out}` in the script is replaced by the path of the target, and `{deps}` by the paths of
+    /// the dependencies, quoted as needed. This is a shorthand for `add_command_rule` with
+    /// `CommandSpec::shell`.
+    ///
+    /// ```no_run
+    /// let builder = depgraph::DepGraphBuilder::new()
+    ///     .add_shell_rule("out/app.o", &["src/app.asm"], "yasm -f elf64 -o {out} {deps}");
+    /// ```
+    pub fn add_shell_rule<P1, P2>(
+        self,
+        filename: P1,
+        dependencies: &[P2],
+        script: &str,
+    ) -> DepGraphBuilder<'a>
+    where
+        P1: AsRef<Path>,
+        P2: AsRef<Path>,
+    {
+        self.add_command_rule(filename, dependencies, CommandSpec::shell(script))
+    }
+}
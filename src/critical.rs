@@ -0,0 +1,86 @@
+//! Finding the chain of rules that bounds the length of a parallel build.
+
+use std::path::Path;
+use std::time::Duration;
+
+use petgraph::graph::NodeIndex;
+
+use crate::{DepGraph, DepResult};
+
+impl<'a> DepGraph<'a> {
+    /// The critical path: the chain of targets, each depending on the one before, whose build
+    /// functions take the longest to run one after another. However many jobs a parallel build
+    /// runs at once, a full rebuild can't take less time than this, so it shows which rule is
+    /// worth making faster.
+    ///
+    /// Each target is given with the time its build function took last time, from the build log
+    /// (see `DepGraphBuilder::build_log` and `last_duration`), in build order. Targets that
+    /// haven't been built since the log was started count as taking no time, and the path is empty
+    /// if none have. If the builder was given a default target, only it and what it depends on
+    /// are considered.
+    pub fn critical_path(&self) -> DepResult<Vec<(&Path, Duration)>> {
+        let ordered = self.toposort()?;
+        let wanted = self.default_wanted.as_deref();
+        // the longest time to build each node and everything it depends on, and the dependency
+        // that takes longest
+        let mut longest = vec![(Duration::ZERO, None); self.graph.node_count()];
+        for &idx in ordered.iter().rev() {
+            if !wanted.is_none_or(|wanted| wanted[idx.index()]) {
+                continue;
+            }
+            let slowest = self
+                .children(idx)
+                .into_iter()
+                .max_by_key(|child| longest[child.index()].0);
+            let before = slowest.map_or(Duration::ZERO, |child| longest[child.index()].0);
+            longest[idx.index()] = (before + self.expected_duration(idx), slowest);
+        }
+        let mut next = self
+            .graph
+            .node_indices()
+            .max_by_key(|idx| longest[idx.index()].0)
+            .filter(|idx| longest[idx.index()].0 > Duration::ZERO);
+        let mut path = Vec::new();
+        while let Some(idx) = next {
+            let dep = &self.graph[idx];
+            if dep.build_fn.is_some() {
+                path.push((dep.filename.as_path(), self.expected_duration(idx)));
+            }
+            next = longest[idx.index()].1;
+        }
+        path.reverse();
+        Ok(path)
+    }
+
+    /// The order to start building nodes in, indexed by node: how long each will take to build,
+    /// together with the longest chain of targets that depend on it, then its priority (see
+    /// `RuleOptions::priority`). Building the nodes with the most time left first keeps the
+    /// critical path moving, so a parallel build finishes sooner.
+    #[cfg(feature = "parallel")]
+    pub(crate) fn build_priorities(&self) -> DepResult<Vec<(Duration, i32)>> {
+        let ordered = self.toposort()?;
+        let mut priorities = vec![(Duration::ZERO, 0); self.graph.node_count()];
+        // dependents come first
+        for idx in ordered {
+            let (after, inherited) = self
+                .graph
+                .neighbors_directed(idx, petgraph::Incoming)
+                .map(|parent| priorities[parent.index()])
+                .fold((Duration::ZERO, i32::MIN), |(time, priority), parent| {
+                    (time.max(parent.0), priority.max(parent.1))
+                });
+            let priority = self.graph[idx].options.priority.max(inherited);
+            priorities[idx.index()] = (after + self.expected_duration(idx), priority);
+        }
+        Ok(priorities)
+    }
+
+    /// How long `idx` took to build last time, or zero if it isn't in the build log.
+    fn expected_duration(&self, idx: NodeIndex) -> Duration {
+        let dep = &self.graph[idx];
+        match dep.build_fn {
+            Some(_) => self.last_duration(&dep.filename).unwrap_or_default(),
+            None => Duration::ZERO,
+        }
+    }
+}
@@ -0,0 +1,154 @@
+//! Keeping a graph in memory between builds (the `watch` and `parallel` features).
+
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::watch::{changed_files, Snapshot, POLL_INTERVAL};
+use crate::{DepGraph, DepResult, MakeParams};
+
+/// A graph kept on a background thread, which rebuilds it when its source files change and
+/// builds targets on request. Created with `DepGraph::into_daemon`.
+///
+/// The graph, its freshness checker, build log and the state of the watched files are kept in
+/// memory between builds, so each build only has to check what may have changed. Dropping the
+/// daemon (or calling `stop`) stops the thread, waiting for any build in progress to finish.
+pub struct Daemon {
+    requests: Option<mpsc::Sender<Request>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+/// (Internal) Something for the daemon thread to do.
+enum Request {
+    /// Build the target (or everything) now, and send back the result
+    Build(Option<PathBuf>, mpsc::Sender<DepResult<()>>),
+}
+
+impl DepGraph<'static> {
+    /// Move the graph to a background thread that builds it, then rebuilds it whenever its source
+    /// files change, like `watch`, until the returned `Daemon` is dropped. The daemon can also be
+    /// asked to build the graph, or a single target, straight away.
+    ///
+    /// The graph's build functions can't borrow (see `DepGraphBuilder::add_scoped_rule`), since
+    /// the thread outlives the caller.
+    ///
+    /// `on_rebuild` is called after the first build and each rebuild caused by a change, with the
+    /// source files that changed and the result of `make`. It isn't called for builds asked for
+    /// through the `Daemon`, which return their results directly. Changes picked up by
+    /// `Daemon::build` aren't rebuilt again, but those only needed by other targets than the one
+    /// given to `Daemon::build_target` still are.
+    pub fn into_daemon<F>(self, make_params: MakeParams, on_rebuild: F) -> Daemon
+    where
+        F: FnMut(&[PathBuf], DepResult<()>) + Send + 'static,
+    {
+        self.into_daemon_with_interval(make_params, POLL_INTERVAL, on_rebuild)
+    }
+
+    /// Like `into_daemon`, but checking the source files every `interval`, however often builds
+    /// are asked for.
+    pub fn into_daemon_with_interval<F>(
+        self,
+        make_params: MakeParams,
+        interval: Duration,
+        mut on_rebuild: F,
+    ) -> Daemon
+    where
+        F: FnMut(&[PathBuf], DepResult<()>) + Send + 'static,
+    {
+        let (requests, receiver) = mpsc::channel();
+        let thread = thread::spawn(move || {
+            let mut snapshot = Snapshot::new();
+            let result = self.make_watching(&mut snapshot, || self.make(make_params));
+            on_rebuild(&[], result.and_then(|result| result));
+            // polls are timed from the last one, so a stream of requests doesn't hold them off
+            let mut last_poll = Instant::now();
+            loop {
+                match receiver.recv_timeout(interval.saturating_sub(last_poll.elapsed())) {
+                    Ok(Request::Build(target, reply)) => {
+                        let result = match target {
+                            // only a full build brings every watched file up to date, so only
+                            // it can update the snapshot without hiding changes from the poll
+                            Some(ref target) => self.make_target(target, make_params),
+                            None => self
+                                .make_watching(&mut snapshot, || self.make(make_params))
+                                .and_then(|result| result),
+                        };
+                        // the requester may have given up waiting
+                        let _ = reply.send(result);
+                    }
+                    Err(RecvTimeoutError::Timeout) => (),
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+                if last_poll.elapsed() < interval {
+                    continue;
+                }
+                last_poll = Instant::now();
+                let changed = match self.snapshot() {
+                    Ok(current) => changed_files(&snapshot, &current),
+                    Err(e) => {
+                        on_rebuild(&[], Err(e));
+                        continue;
+                    }
+                };
+                if !changed.is_empty() {
+                    let result = self.make_watching(&mut snapshot, || self.make(make_params));
+                    on_rebuild(&changed, result.and_then(|result| result));
+                }
+            }
+        });
+        Daemon {
+            requests: Some(requests),
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Daemon {
+    /// Build everything that is out of date now, waiting for the build to finish.
+    ///
+    /// # Panics
+    ///
+    /// If the daemon thread has panicked, because `on_rebuild` or a build function did.
+    pub fn build(&self) -> DepResult<()> {
+        self.request(None)
+    }
+
+    /// Build `target` and everything it depends on now, waiting for the build to finish. See
+    /// `DepGraph::make_target`.
+    ///
+    /// # Panics
+    ///
+    /// If the daemon thread has panicked, because `on_rebuild` or a build function did.
+    pub fn build_target<P: AsRef<Path>>(&self, target: P) -> DepResult<()> {
+        self.request(Some(target.as_ref().to_owned()))
+    }
+
+    /// Stop the daemon, waiting for any build in progress to finish. This is the same as
+    /// dropping it.
+    pub fn stop(self) {}
+
+    fn request(&self, target: Option<PathBuf>) -> DepResult<()> {
+        let (reply, result) = mpsc::channel();
+        let requests = self.requests.as_ref().expect("only taken when dropped");
+        requests
+            .send(Request::Build(target, reply))
+            .expect("the daemon thread panicked");
+        result.recv().expect("the daemon thread panicked")
+    }
+}
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        // the thread stops when the channel is closed
+        self.requests.take();
+        if let Some(thread) = self.thread.take() {
+            if let Err(panic) = thread.join() {
+                if !thread::panicking() {
+                    panic::resume_unwind(panic);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,137 @@
+//! Reading Makefile-format dependency files, as written by `gcc -MMD` and similar tools.
+//!
+//! A depfile contains one or more `targets: prerequisites` lines. Lines can be continued with a
+//! trailing `\`, and spaces in file names are escaped as `\ `. Every prerequisite listed is
+//! treated as a dependency, whichever target it is listed for.
+//!
+//! Rules added with `DepGraphBuilder::add_rule_with_dynamic_deps` record the dependencies their
+//! build function reports in a depfile in the same format.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::{BuildAction, BuildError, DepGraphBuilder, MaybeSend, MaybeSync, RuleOptions};
+
+impl<'a> DepGraphBuilder<'a> {
+    /// Add a rule whose build function returns the extra files it read, such as a wrapper around
+    /// a tool that reports what it included but can't write a depfile itself.
+    ///
+    /// The returned files are treated as further dependencies of the target on the next run, in
+    /// the same way as the files listed in `RuleOptions::depfile`. They are saved to a depfile
+    /// next to the target, named by appending `.d` to its file name (`out.o` uses `out.o.d`), so
+    /// they are remembered between runs.
+    pub fn add_rule_with_dynamic_deps<F, P1, P2>(
+        self,
+        filename: P1,
+        dependencies: &[P2],
+        build_fn: F,
+    ) -> DepGraphBuilder<'a>
+    where
+        F: Fn(&Path, &[&Path]) -> Result<Vec<PathBuf>, String> + MaybeSend + MaybeSync + 'static,
+        P1: AsRef<Path>,
+        P2: AsRef<Path>,
+    {
+        let options = RuleOptions {
+            depfile: Some(depfile_for(filename.as_ref())),
+            ..Default::default()
+        };
+        // the target may be moved (see `DepGraphBuilder::out_dir`), so find the depfile from the
+        // path the build function is given
+        let build_fn = move |out: &Path, deps: &[&Path]| {
+            let discovered = build_fn(out, deps)?;
+            write(&depfile_for(out), out, &discovered).map_err(BuildError::from)
+        };
+        self.push_rule(
+            filename,
+            dependencies,
+            options,
+            BuildAction::Sync(Arc::new(build_fn)),
+        )
+    }
+}
+
+/// The depfile for the dynamic dependencies of `target`.
+fn depfile_for(target: &Path) -> PathBuf {
+    let mut depfile = target.as_os_str().to_owned();
+    depfile.push(".d");
+    PathBuf::from(depfile)
+}
+
+/// Write a depfile at `path` listing `deps` as the dependencies of `target`.
+pub(crate) fn write(path: &Path, target: &Path, deps: &[PathBuf]) -> io::Result<()> {
+    let mut contents = format!("{}:", escape(target));
+    for dep in deps {
+        contents.push_str(" \\\n  ");
+        contents.push_str(&escape(dep));
+    }
+    contents.push('\n');
+    fs::write(path, contents)
+}
+
+/// Escape the characters in `path` that are special in a depfile.
+fn escape(path: &Path) -> String {
+    path.to_string_lossy()
+        .replace('$', "$$")
+        .replace(' ', "\\ ")
+        .replace('#', "\\#")
+}
+
+/// The dependencies listed in the depfile at `path`, or none if it doesn't exist (for example
+/// because the rule hasn't been built yet).
+pub(crate) fn read(path: &Path) -> io::Result<Vec<PathBuf>> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(parse(&contents)),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// The prerequisites listed in `contents`, in order and without duplicates.
+pub(crate) fn parse(contents: &str) -> Vec<PathBuf> {
+    let mut deps: Vec<PathBuf> = Vec::new();
+    let contents = contents.replace("\\\r\n", " ").replace("\\\n", " ");
+    for line in contents.lines() {
+        let mut in_prerequisites = false;
+        for word in split_words(line) {
+            if !in_prerequisites {
+                // the target list ends with a `:`, either on its own or at the end of a word
+                in_prerequisites = word.ends_with(':');
+                continue;
+            }
+            let dep = PathBuf::from(word);
+            if !deps.contains(&dep) {
+                deps.push(dep);
+            }
+        }
+    }
+    deps
+}
+
+/// Split `line` on unescaped whitespace, removing the escapes.
+fn split_words(line: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if matches!(chars.peek(), Some(' ') | Some('#')) => {
+                word.push(chars.next().unwrap());
+            }
+            '$' if chars.peek() == Some(&'$') => {
+                word.push(chars.next().unwrap());
+            }
+            c if c.is_whitespace() => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            c => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
@@ -1,28 +1,108 @@
-use std::{io, path::PathBuf};
+use std::io;
+use std::path::{Path, PathBuf};
 use thiserror::Error as ThisError;
 
 /// Any error that can occur during build
 ///
-/// One area for improvement is allowing more data to be present in an error, and possibly making
-/// `BuildFailed` use a trait object (impl std::Error) to be more general. Suggestions welcome!
+/// One area for improvement is allowing more data to be present in an error. Suggestions welcome!
 #[derive(Debug, ThisError)]
 pub enum Error {
-    /// Cyclic dependencies detected
-    #[error("cyclic dependencies detected")]
-    Cycle,
+    /// Cyclic dependencies detected. Each of the files depends on the next, and the last depends
+    /// on the first.
+    #[error("cyclic dependencies detected: {}", cycle_display(.0))]
+    Cycle(Vec<PathBuf>),
     /// Same file added more than once
-    #[error("same file added more than once")]
-    DuplicateFile,
+    #[error("{path:?} is added by more than one rule (rules {first} and {second})")]
+    DuplicateFile {
+        /// The file
+        path: PathBuf,
+        /// The position of the rule that added it first, counting from 0 in the order the rules
+        /// were added. Rules made from pattern rules count after all the others.
+        first: usize,
+        /// The position of the rule that added it again
+        second: usize,
+    },
     /// A file that should either be present or be crated during build is missing.
     #[error("a file that should either be present or be crated during build is missing")]
     MissingFile(PathBuf),
     /// The supplied build script returned an error
-    #[error("the supplied build script returned an error")]
-    BuildFailed(String),
+    #[error("building {target:?} failed")]
+    BuildFailed {
+        /// The target the build function was building
+        target: PathBuf,
+        /// The dependencies it was given
+        dependencies: Vec<PathBuf>,
+        /// The error it returned
+        #[source]
+        source: BuildError,
+    },
+    /// The given file isn't part of the graph
+    #[error("{0:?} is not part of the dependency graph")]
+    UnknownTarget(PathBuf),
+    /// A build function didn't finish within the rule's timeout
+    #[error("building {0:?} timed out")]
+    Timeout(PathBuf),
+    /// A rule added with `DepGraphBuilder::add_async_rule` was stale in a synchronous build. Its
+    /// future may need a runtime (such as tokio's reactor) that only the caller of `make_async`
+    /// has.
+    #[error("{0:?} has an async build function, which only `make_async` can run")]
+    AsyncRule(PathBuf),
+    /// Some targets failed to build in keep-going mode (`MakeParams::KeepGoing`)
+    #[error("{} target(s) failed to build, {} skipped", failed.len(), skipped.len())]
+    BuildsFailed {
+        /// Each target that failed, along with the reason
+        failed: Vec<(PathBuf, Error)>,
+        /// The targets that weren't built because one of their dependencies failed
+        skipped: Vec<PathBuf>,
+    },
+    /// Targets were out of date when checking with `MakeParams::CheckOnly`
+    #[error("{} target(s) are out of date", .0.len())]
+    OutOfDate(Vec<PathBuf>),
+    /// An environment variable cargo sets for build scripts isn't set
+    #[error("environment variable {0} is not set (is this running in a build script?)")]
+    MissingEnvVar(String),
+    /// A manifest file, Makefile or ninja file couldn't be parsed, or declares an invalid rule
+    #[error("invalid manifest {0:?}: {1}")]
+    InvalidManifest(PathBuf, String),
+    /// A rule is in a pool that wasn't added with `DepGraphBuilder::pool`
+    #[error("unknown pool {0:?}")]
+    UnknownPool(String),
     /// Generic I/O error
     #[error("I/O error")]
     Io(#[from] io::Error),
 }
 
+impl Error {
+    /// (Internal) A function making the `BuildFailed` error for building `target` from
+    /// `dependencies`, for `map_err`.
+    pub(crate) fn build_failed<'a>(
+        target: &'a Path,
+        dependencies: &'a [&Path],
+    ) -> impl FnOnce(BuildError) -> Error + 'a {
+        move |source| Error::BuildFailed {
+            target: target.to_owned(),
+            dependencies: dependencies.iter().map(|p| p.to_path_buf()).collect(),
+            source,
+        }
+    }
+}
+
+/// The files in a cycle, with the first repeated at the end to close it.
+fn cycle_display(cycle: &[PathBuf]) -> String {
+    cycle
+        .iter()
+        .chain(cycle.first())
+        .map(|path| path.display().to_string())
+        .collect::<Vec<_>>()
+        .join(" -> ")
+}
+
 /// The ubiquitous crate result type
 pub type DepResult<T> = Result<T, Error>;
+
+/// The error returned by build functions added with `DepGraphBuilder::add_fallible_rule`, and
+/// kept by `Error::BuildFailed`. The `String` errors of other build functions are converted into
+/// it.
+///
+/// Any error type converts into it with `?`, keeping its source chain.
+pub type BuildError = Box<dyn std::error::Error + Send + Sync>;
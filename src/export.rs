@@ -0,0 +1,308 @@
+//! Writing the dependency graph out in formats other tools understand.
+
+use std::env;
+use std::ffi::OsStr;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use petgraph::visit::EdgeRef;
+
+use crate::command::Invocation;
+use crate::{json, DepGraph};
+
+/// The extensions of the source files that go in a compilation database.
+const COMPILED_EXTENSIONS: &[&str] = &[
+    "c", "cc", "cpp", "cxx", "c++", "m", "mm", "s", "S", "asm", "cu",
+];
+
+impl<'a> DepGraph<'a> {
+    /// Write the graph in Graphviz DOT format.
+    ///
+    /// Each node is labeled with its file name, and there is an edge from each target to each of
+    /// its dependencies. Source files (those without a build function) are drawn as boxes.
+    pub fn write_dot<W: Write>(&self, mut out: W) -> io::Result<()> {
+        writeln!(out, "digraph depgraph {{")?;
+        for idx in self.graph.node_indices() {
+            let dep = &self.graph[idx];
+            let shape = if dep.build_fn.is_some() {
+                "ellipse"
+            } else {
+                "box"
+            };
+            writeln!(
+                out,
+                "    n{} [label=\"{}\", shape={}];",
+                idx.index(),
+                escape_dot(&dep.filename.display().to_string()),
+                shape
+            )?;
+        }
+        for edge in self.graph.edge_references() {
+            writeln!(
+                out,
+                "    n{} -> n{};",
+                edge.source().index(),
+                edge.target().index()
+            )?;
+        }
+        writeln!(out, "}}")
+    }
+
+    /// Get the graph in Graphviz DOT format. See `write_dot`.
+    pub fn to_dot(&self) -> String {
+        let mut out = Vec::new();
+        self.write_dot(&mut out)
+            .expect("writing to a Vec can't fail");
+        String::from_utf8(out).expect("file names are written with `Path::display`")
+    }
+}
+
+/// The structure of a dependency graph, as written by `DepGraph::write_json`.
+///
+/// With the `serde` feature this implements `Serialize` and `Deserialize`, so the JSON written by
+/// `write_json` can be read back in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GraphExport {
+    /// Every file in the graph
+    pub nodes: Vec<ExportedNode>,
+    /// An edge from each target to each of its dependencies
+    pub edges: Vec<ExportedEdge>,
+}
+
+/// A file in a `GraphExport`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ExportedNode {
+    /// The node's position in the graph, which edges refer to it by
+    pub id: usize,
+    /// The file
+    pub path: PathBuf,
+    /// Whether the file is built by a rule, rather than being a source file
+    pub has_build_fn: bool,
+}
+
+/// An edge in a `GraphExport`, from a target to one of its dependencies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ExportedEdge {
+    /// The `id` of the target
+    pub from: usize,
+    /// The `id` of the dependency
+    pub to: usize,
+}
+
+impl<'a> DepGraph<'a> {
+    /// Get the structure of the graph: its files, and the edges between them.
+    pub fn export(&self) -> GraphExport {
+        GraphExport {
+            nodes: self
+                .graph
+                .node_indices()
+                .map(|idx| {
+                    let dep = &self.graph[idx];
+                    ExportedNode {
+                        id: idx.index(),
+                        path: dep.filename.clone(),
+                        has_build_fn: dep.build_fn.is_some(),
+                    }
+                })
+                .collect(),
+            edges: self
+                .graph
+                .edge_references()
+                .map(|edge| ExportedEdge {
+                    from: edge.source().index(),
+                    to: edge.target().index(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Write the graph structure (see `export`) as JSON, with `serde_json` (the `serde`
+    /// feature).
+    ///
+    /// The output is an object with a `nodes` array, where each node has an `id`, its `path`, and
+    /// whether it `has_build_fn`, and an `edges` array of `{"from": id, "to": id}` objects, from
+    /// each target to each of its dependencies. It can be read back in as a `GraphExport`.
+    ///
+    /// Fails if a path isn't valid UTF-8, as JSON can't represent it.
+    #[cfg(feature = "serde")]
+    pub fn write_json<W: Write>(&self, mut out: W) -> io::Result<()> {
+        serde_json::to_writer_pretty(&mut out, &self.export())?;
+        writeln!(out)
+    }
+
+    /// Get the graph structure as JSON. See `write_json`.
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> io::Result<String> {
+        let mut out = Vec::new();
+        self.write_json(&mut out)?;
+        Ok(String::from_utf8(out).expect("serde_json writes UTF-8"))
+    }
+}
+
+impl<'a> DepGraph<'a> {
+    /// Write the graph in GraphML format, for tools like Gephi and yEd.
+    ///
+    /// Nodes have `path` and `has_build_fn` attributes, and there is an edge from each target to
+    /// each of its dependencies.
+    pub fn write_graphml<W: Write>(&self, mut out: W) -> io::Result<()> {
+        writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(
+            out,
+            r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#
+        )?;
+        writeln!(
+            out,
+            r#"  <key id="path" for="node" attr.name="path" attr.type="string"/>"#
+        )?;
+        writeln!(
+            out,
+            r#"  <key id="has_build_fn" for="node" attr.name="has_build_fn" attr.type="boolean"/>"#
+        )?;
+        writeln!(out, r#"  <graph id="depgraph" edgedefault="directed">"#)?;
+        for idx in self.graph.node_indices() {
+            let dep = &self.graph[idx];
+            writeln!(out, r#"    <node id="n{}">"#, idx.index())?;
+            writeln!(
+                out,
+                r#"      <data key="path">{}</data>"#,
+                escape_xml(&dep.filename.display().to_string())
+            )?;
+            writeln!(
+                out,
+                r#"      <data key="has_build_fn">{}</data>"#,
+                dep.build_fn.is_some()
+            )?;
+            writeln!(out, "    </node>")?;
+        }
+        for edge in self.graph.edge_references() {
+            writeln!(
+                out,
+                r#"    <edge source="n{}" target="n{}"/>"#,
+                edge.source().index(),
+                edge.target().index()
+            )?;
+        }
+        writeln!(out, "  </graph>")?;
+        writeln!(out, "</graphml>")
+    }
+}
+
+impl<'a> DepGraph<'a> {
+    /// Write the graph as a Mermaid flowchart, which can be embedded in markdown.
+    ///
+    /// There is an arrow from each target to each of its dependencies. Source files (those
+    /// without a build function) are drawn with rounded ends.
+    pub fn write_mermaid<W: Write>(&self, mut out: W) -> io::Result<()> {
+        writeln!(out, "graph TD")?;
+        for idx in self.graph.node_indices() {
+            let dep = &self.graph[idx];
+            let label = escape_mermaid(&dep.filename.display().to_string());
+            if dep.build_fn.is_some() {
+                writeln!(out, "    n{}[\"{}\"]", idx.index(), label)?;
+            } else {
+                writeln!(out, "    n{}([\"{}\"])", idx.index(), label)?;
+            }
+        }
+        for edge in self.graph.edge_references() {
+            writeln!(
+                out,
+                "    n{} --> n{}",
+                edge.source().index(),
+                edge.target().index()
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Get the graph as a Mermaid flowchart. See `write_mermaid`.
+    pub fn to_mermaid(&self) -> String {
+        let mut out = Vec::new();
+        self.write_mermaid(&mut out)
+            .expect("writing to a Vec can't fail");
+        String::from_utf8(out).expect("file names are written with `Path::display`")
+    }
+}
+
+impl<'a> DepGraph<'a> {
+    /// Write a `compile_commands.json` compilation database, so clangd, clang-tidy and other
+    /// tools know how the C, C++ and assembly files in the graph are compiled.
+    ///
+    /// There is an entry for each rule added with `add_command_rule` (such as `rules::c_object`)
+    /// whose dependencies include a source file, recognised by its extension. The `file` is the
+    /// first such dependency, and the command has its placeholders replaced as when it is run.
+    /// Shell commands are written as a `command` string, and others as an `arguments` array.
+    ///
+    /// The `directory` of each entry is the current directory (joined with the command's own
+    /// directory if it has one), so this fails if the current directory can't be found.
+    pub fn write_compile_commands<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let current_dir = env::current_dir()?;
+        let mut entries = Vec::new();
+        for idx in self.graph.node_indices() {
+            let dep = &self.graph[idx];
+            let command = match dep.command {
+                Some(ref command) => command,
+                None => continue,
+            };
+            let deps = self.dependency_files(idx);
+            let file = match deps.iter().find(|dep| is_compiled(dep)) {
+                Some(file) => file,
+                None => continue,
+            };
+            let directory = match command.dir() {
+                Some(dir) => current_dir.join(dir),
+                None => current_dir.clone(),
+            };
+            let invocation = match command.invocation(&dep.filename, &deps) {
+                Invocation::Exec(args) => {
+                    let args: Vec<String> = args
+                        .iter()
+                        .map(|arg| json::string(&arg.to_string_lossy()))
+                        .collect();
+                    format!("\"arguments\": [{}]", args.join(", "))
+                }
+                Invocation::Shell(script) => format!("\"command\": {}", json::string(&script)),
+            };
+            entries.push(format!(
+                "  {{\"directory\": {}, \"file\": {}, \"output\": {}, {}}}",
+                json::string(&directory.display().to_string()),
+                json::string(&file.display().to_string()),
+                json::string(&dep.filename.display().to_string()),
+                invocation
+            ));
+        }
+        writeln!(out, "[")?;
+        if !entries.is_empty() {
+            writeln!(out, "{}", entries.join(",\n"))?;
+        }
+        writeln!(out, "]")
+    }
+}
+
+/// Whether `path` is a source file that is compiled, going by its extension.
+fn is_compiled(path: &Path) -> bool {
+    path.extension()
+        .and_then(OsStr::to_str)
+        .is_some_and(|ext| COMPILED_EXTENSIONS.contains(&ext))
+}
+
+/// Escape a string for use inside a quoted Mermaid label.
+fn escape_mermaid(s: &str) -> String {
+    s.replace('"', "#quot;")
+}
+
+/// Escape a string for use as XML character data or in an attribute value.
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Escape a string for use inside a quoted DOT identifier.
+fn escape_dot(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
@@ -0,0 +1,176 @@
+//! Deciding whether a target needs to be rebuilt.
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::DepResult;
+
+/// Why a target would be rebuilt, or why it wouldn't. Returned by `DepGraph::explain`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RebuildReason {
+    /// The target is up to date and would not be rebuilt.
+    UpToDate,
+    /// The file is a source file (it has no rule), so is never built.
+    NoRule,
+    /// The build was forced with `MakeParams::ForceBuild`.
+    Forced,
+    /// The rule is always rebuilt (see `RuleOptions::always_rebuild`).
+    AlwaysRebuild,
+    /// The target doesn't exist.
+    MissingOutput,
+    /// The given dependency was modified more recently than the target.
+    DepNewer(PathBuf),
+    /// The contents of the given dependency differ from when the target was last built.
+    DepChanged(PathBuf),
+    /// The given dependency, discovered from a depfile, no longer exists (see
+    /// `RuleOptions::depfile`).
+    DepMissing(PathBuf),
+    /// The given dependency will itself be rebuilt.
+    DepRebuilt(PathBuf),
+    /// The given dependency is treated as new (see `DepGraphBuilder::assume_new`).
+    DepAssumedNew(PathBuf),
+    /// The target is treated as old, so is never rebuilt (see `DepGraphBuilder::assume_old`).
+    AssumedOld,
+    /// There is no record of the target having been built before.
+    NoRecord,
+    /// The dependencies differ from those recorded in the build log for the last build.
+    FingerprintChanged,
+    /// The rule's fingerprint, or the value of one of the environment variables it depends on,
+    /// differs from the last build (see `RuleOptions::fingerprint` and `RuleOptions::env_deps`).
+    RuleChanged,
+    /// The freshness checker said the target is stale, without giving a more specific reason.
+    Stale,
+}
+
+impl RebuildReason {
+    /// Whether the target would be rebuilt.
+    pub fn is_rebuild(&self) -> bool {
+        !matches!(
+            self,
+            RebuildReason::UpToDate | RebuildReason::NoRule | RebuildReason::AssumedOld
+        )
+    }
+}
+
+impl fmt::Display for RebuildReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RebuildReason::UpToDate => write!(f, "up to date"),
+            RebuildReason::NoRule => write!(f, "source file with no rule"),
+            RebuildReason::Forced => write!(f, "build was forced"),
+            RebuildReason::AlwaysRebuild => write!(f, "rule is always rebuilt"),
+            RebuildReason::MissingOutput => write!(f, "output is missing"),
+            RebuildReason::DepNewer(dep) => write!(f, "{} is newer", dep.display()),
+            RebuildReason::DepChanged(dep) => write!(f, "{} has changed", dep.display()),
+            RebuildReason::DepMissing(dep) => write!(f, "{} is missing", dep.display()),
+            RebuildReason::DepRebuilt(dep) => write!(f, "{} will be rebuilt", dep.display()),
+            RebuildReason::DepAssumedNew(dep) => write!(f, "{} is assumed new", dep.display()),
+            RebuildReason::AssumedOld => write!(f, "assumed old"),
+            RebuildReason::NoRecord => write!(f, "no record of a previous build"),
+            RebuildReason::FingerprintChanged => {
+                write!(f, "dependencies differ from the build log")
+            }
+            RebuildReason::RuleChanged => write!(f, "the rule has changed"),
+            RebuildReason::Stale => write!(f, "freshness checker reported it stale"),
+        }
+    }
+}
+
+/// Decides whether a target is stale, i.e. whether its build function needs to run.
+///
+/// `make` consults the graph's checker for every rule that has a build function (unless the build
+/// is forced). The default is `ModifiedTime`, and `ContentHash` is also provided. Set a different
+/// checker with `DepGraphBuilder::freshness_checker`.
+pub trait FreshnessChecker: Send + Sync {
+    /// Whether `target` needs to be rebuilt from `deps`.
+    ///
+    /// All of `deps` are guaranteed to exist when this is called, but `target` may not.
+    fn is_stale(&self, target: &Path, deps: &[&Path]) -> DepResult<bool>;
+
+    /// Why `target` needs to be rebuilt from `deps`, or `None` if it doesn't.
+    ///
+    /// This is used by `DepGraph::explain`, and must agree with `is_stale`. The default calls
+    /// `is_stale` and gives `RebuildReason::Stale` as the reason.
+    fn explain(&self, target: &Path, deps: &[&Path]) -> DepResult<Option<RebuildReason>> {
+        Ok(if self.is_stale(target, deps)? {
+            Some(RebuildReason::Stale)
+        } else {
+            None
+        })
+    }
+
+    /// Called after the build function for `target` has run successfully.
+    ///
+    /// This can be used to record state for the next run. The default does nothing.
+    fn built(&self, target: &Path, deps: &[&Path]) -> DepResult<()> {
+        let _ = (target, deps);
+        Ok(())
+    }
+
+    /// Whether `target` exists.
+    ///
+    /// This is only asked of a checker set for a rule with `RuleOptions::freshness_checker`, which
+    /// can decide what its targets are. The default checks that the file exists.
+    fn exists(&self, target: &Path) -> DepResult<bool> {
+        Ok(target.exists())
+    }
+
+    /// The file the checker keeps its state in between runs, if it has one, such as the database
+    /// of a `ContentHash`.
+    ///
+    /// `DepGraph::prune` doesn't remove it. The default is `None`.
+    fn state_file(&self) -> Option<&Path> {
+        None
+    }
+}
+
+impl fmt::Debug for dyn FreshnessChecker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("FreshnessChecker")
+    }
+}
+
+/// The default freshness checker: a target is stale if it is missing, or if any of its
+/// dependencies were modified more recently than it was.
+///
+/// A dependency that is a directory counts as modified when anything inside it (at any depth)
+/// was.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ModifiedTime;
+
+impl FreshnessChecker for ModifiedTime {
+    fn is_stale(&self, target: &Path, deps: &[&Path]) -> DepResult<bool> {
+        Ok(self.explain(target, deps)?.is_some())
+    }
+
+    fn explain(&self, target: &Path, deps: &[&Path]) -> DepResult<Option<RebuildReason>> {
+        if !target.exists() {
+            return Ok(Some(RebuildReason::MissingOutput));
+        }
+        let file_mod_time = fs::metadata(target)?.modified()?;
+        for dep in deps {
+            let dep_mod_time = newest_modified(dep)?;
+            if dep_mod_time > file_mod_time {
+                return Ok(Some(RebuildReason::DepNewer(dep.to_path_buf())));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// The modification time of `path`, or for a directory the latest modification time of the
+/// directory or anything in it.
+pub(crate) fn newest_modified(path: &Path) -> io::Result<SystemTime> {
+    let metadata = fs::metadata(path)?;
+    let mut newest = metadata.modified()?;
+    if metadata.is_dir() {
+        for entry in fs::read_dir(path)? {
+            newest = newest.max(newest_modified(&entry?.path())?);
+        }
+    }
+    Ok(newest)
+}
@@ -0,0 +1,217 @@
+//! Expanding glob patterns in dependency lists.
+//!
+//! The supported syntax is `?` (any character), `*` (any number of characters), `[abc]`/`[a-z]`
+//! (a set of characters, negated with `[!abc]`) within a single path component, and `**` as a
+//! whole component for any number of directories. Wildcards don't match names starting with `.`
+//! unless the pattern does too, so hidden files and directories are skipped.
+
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+use crate::ignore::Ignore;
+
+/// Whether `path` contains any glob syntax.
+pub(crate) fn is_glob(path: &Path) -> bool {
+    path.to_string_lossy().contains(['*', '?', '['])
+}
+
+/// Replace any glob patterns in `paths` with the files they match, in sorted order. Other paths
+/// are left as they are.
+///
+/// If `respect_ignore_files` is set, files excluded by `.gitignore` or `.ignore` files are
+/// skipped.
+pub(crate) fn expand_all(
+    paths: Vec<PathBuf>,
+    respect_ignore_files: bool,
+) -> io::Result<Vec<PathBuf>> {
+    let ignore = Ignore::default();
+    let ignore = if respect_ignore_files {
+        Some(&ignore)
+    } else {
+        None
+    };
+    let mut expanded = Vec::with_capacity(paths.len());
+    for path in paths {
+        if is_glob(&path) {
+            expanded.extend(expand(&path, ignore)?);
+        } else {
+            expanded.push(path);
+        }
+    }
+    Ok(expanded)
+}
+
+/// The paths of the existing files and directories that match `pattern`, sorted.
+///
+/// If `ignore` is given, ignore files are read from each directory searched, and anything they
+/// exclude is skipped.
+pub(crate) fn expand(pattern: &Path, ignore: Option<&Ignore>) -> io::Result<Vec<PathBuf>> {
+    // split into the literal directory to start from, and the components to match
+    let mut base = PathBuf::new();
+    let mut rest = Vec::new();
+    for component in pattern.components() {
+        if rest.is_empty() && !is_glob(component.as_os_str().as_ref()) {
+            base.push(component);
+        } else {
+            match component {
+                Component::Normal(name) => rest.push(name.to_string_lossy().into_owned()),
+                _ => rest.push(component.as_os_str().to_string_lossy().into_owned()),
+            }
+        }
+    }
+    if base.as_os_str().is_empty() {
+        base.push(".");
+    }
+    let mut out = Vec::new();
+    walk(&base, &rest, pattern.starts_with("."), ignore, &mut out)?;
+    out.sort();
+    out.dedup();
+    Ok(out)
+}
+
+/// Add the paths under `dir` matching the components `rest` to `out`.
+fn walk(
+    dir: &Path,
+    rest: &[String],
+    keep_dot: bool,
+    ignore: Option<&Ignore>,
+    out: &mut Vec<PathBuf>,
+) -> io::Result<()> {
+    let (first, rest) = match rest.split_first() {
+        Some(split) => split,
+        None => {
+            if dir.exists() {
+                out.push(strip_dot(dir, keep_dot));
+            }
+            return Ok(());
+        }
+    };
+    if first == "**" {
+        walk(dir, rest, keep_dot, ignore, out)?;
+    }
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        // nothing can match inside something that isn't a directory
+        Err(_) if !dir.is_dir() => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    let ignore = match ignore {
+        Some(ignore) => Some(ignore.with_dir(dir)?),
+        None => None,
+    };
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let path = entry.path();
+        let is_dir = entry.file_type()?.is_dir();
+        if let Some(ref ignore) = ignore {
+            if ignore.is_ignored(&path, is_dir) {
+                continue;
+            }
+        }
+        if first == "**" {
+            if !name.starts_with('.') && is_dir {
+                walk(
+                    &path,
+                    &rest_with(first, rest),
+                    keep_dot,
+                    ignore.as_ref(),
+                    out,
+                )?;
+            }
+        } else if matches(first, &name) {
+            if rest.is_empty() {
+                out.push(strip_dot(&path, keep_dot));
+            } else if is_dir {
+                walk(&path, rest, keep_dot, ignore.as_ref(), out)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `**` followed by `rest`, for descending into a directory while still matching `**`.
+fn rest_with(first: &str, rest: &[String]) -> Vec<String> {
+    let mut all = Vec::with_capacity(rest.len() + 1);
+    all.push(first.to_owned());
+    all.extend(rest.iter().cloned());
+    all
+}
+
+/// Remove the `./` added when the pattern had no literal directory part.
+fn strip_dot(path: &Path, keep_dot: bool) -> PathBuf {
+    if keep_dot {
+        path.to_owned()
+    } else {
+        path.strip_prefix(".").unwrap_or(path).to_owned()
+    }
+}
+
+/// Whether the path components `path` match the pattern components `pattern`, where a `**`
+/// component matches any number of components.
+pub(crate) fn matches_components(pattern: &[String], path: &[String]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((first, rest)) if first == "**" => {
+            (0..=path.len()).any(|i| matches_components(rest, &path[i..]))
+        }
+        Some((first, rest)) => match path.split_first() {
+            Some((name, path_rest)) => matches(first, name) && matches_components(rest, path_rest),
+            None => false,
+        },
+    }
+}
+
+/// Whether the file name `name` matches the single component `pattern`.
+pub(crate) fn matches(pattern: &str, name: &str) -> bool {
+    if name.starts_with('.') && !pattern.starts_with('.') {
+        return false;
+    }
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    matches_from(&pattern, &name)
+}
+
+fn matches_from(pattern: &[char], name: &[char]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => (0..=name.len()).any(|i| matches_from(rest, &name[i..])),
+        Some(('?', rest)) => !name.is_empty() && matches_from(rest, &name[1..]),
+        Some(('[', rest)) => match (name.split_first(), parse_set(rest)) {
+            (Some((c, name_rest)), Some((set_matches, after))) => {
+                set_matches(*c) && matches_from(after, name_rest)
+            }
+            // an unclosed `[` is matched literally
+            (Some(('[', name_rest)), None) => matches_from(rest, name_rest),
+            _ => false,
+        },
+        Some((p, rest)) => name.first() == Some(p) && matches_from(rest, &name[1..]),
+    }
+}
+
+/// Parse a character set after its opening `[`, returning a matcher and the rest of the pattern.
+fn parse_set(pattern: &[char]) -> Option<(impl Fn(char) -> bool + '_, &[char])> {
+    let (negated, body) = match pattern.first() {
+        Some('!') | Some('^') => (true, &pattern[1..]),
+        _ => (false, pattern),
+    };
+    // a `]` straight after the `[` is part of the set
+    let end = body.iter().skip(1).position(|c| *c == ']')? + 1;
+    let set = &body[..end];
+    let matcher = move |c: char| {
+        let mut i = 0;
+        let mut found = false;
+        while i < set.len() {
+            if i + 2 < set.len() && set[i + 1] == '-' {
+                found |= set[i] <= c && c <= set[i + 2];
+                i += 3;
+            } else {
+                found |= set[i] == c;
+                i += 1;
+            }
+        }
+        found != negated
+    };
+    Some((matcher, &body[end + 1..]))
+}
@@ -0,0 +1,322 @@
+//! Content hash based staleness checking.
+//!
+//! Instead of comparing modification times, a rule is rebuilt when the contents of any of its
+//! dependencies differ from when it was last built. The hashes used for the last build of each
+//! target are stored in a small text database, one `target\tdependency\thash` line per entry.
+//! Backslashes, tabs and line breaks in the paths are escaped with a backslash, and bytes that
+//! aren't valid UTF-8 are written as `\xHH`.
+//!
+//! Files are hashed with BLAKE3 with the `blake3` feature, and with 64-bit FNV-1a otherwise.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::{DepResult, FreshnessChecker, RebuildReason};
+
+/// target -> (dependency -> hash)
+type Entries = HashMap<PathBuf, HashMap<PathBuf, FileHash>>;
+
+/// Decides staleness by comparing file contents rather than modification times.
+///
+/// The hashes of each rule's dependencies are stored in a database file after it is built, and
+/// the rule is only run again when one of those hashes changes. The database is loaded the first
+/// time it is needed.
+pub struct ContentHash {
+    path: PathBuf,
+    /// `None` until loaded
+    entries: Mutex<Option<Entries>>,
+}
+
+impl ContentHash {
+    /// Create a checker that stores its hashes in the file at `database`.
+    pub fn new<P: AsRef<Path>>(database: P) -> ContentHash {
+        ContentHash {
+            path: database.as_ref().to_owned(),
+            entries: Mutex::new(None),
+        }
+    }
+
+    /// Run `f` on the database, loading it first if necessary.
+    fn with_entries<T>(&self, f: impl FnOnce(&mut Entries) -> DepResult<T>) -> DepResult<T> {
+        let mut entries = self.entries.lock().unwrap();
+        if entries.is_none() {
+            *entries = Some(load(&self.path)?);
+        }
+        f(entries.as_mut().unwrap())
+    }
+}
+
+impl FreshnessChecker for ContentHash {
+    fn is_stale(&self, target: &Path, deps: &[&Path]) -> DepResult<bool> {
+        Ok(self.explain(target, deps)?.is_some())
+    }
+
+    fn explain(&self, target: &Path, deps: &[&Path]) -> DepResult<Option<RebuildReason>> {
+        if !target.exists() {
+            return Ok(Some(RebuildReason::MissingOutput));
+        }
+        self.with_entries(|entries| {
+            let recorded = match entries.get(target) {
+                Some(recorded) => recorded,
+                None => return Ok(Some(RebuildReason::NoRecord)),
+            };
+            for dep in deps {
+                match recorded.get(*dep) {
+                    Some(hash) if *hash == hash_file(dep)? => (),
+                    _ => return Ok(Some(RebuildReason::DepChanged(dep.to_path_buf()))),
+                }
+            }
+            // all current deps match, so one must have been removed
+            if recorded.len() != deps.len() {
+                return Ok(Some(RebuildReason::Stale));
+            }
+            Ok(None)
+        })
+    }
+
+    fn built(&self, target: &Path, deps: &[&Path]) -> DepResult<()> {
+        let mut hashes = HashMap::new();
+        for dep in deps {
+            hashes.insert(dep.to_path_buf(), hash_file(dep)?);
+        }
+        self.with_entries(|entries| {
+            entries.insert(target.to_owned(), hashes);
+            save(&self.path, entries)
+        })
+    }
+
+    fn state_file(&self) -> Option<&Path> {
+        Some(&self.path)
+    }
+}
+
+/// Load the database at `path`, starting empty if it doesn't exist yet.
+fn load(path: &Path) -> DepResult<Entries> {
+    let mut entries = Entries::new();
+    match File::open(path) {
+        Ok(file) => {
+            for line in BufReader::new(file).lines() {
+                let line = line?;
+                // ignore corrupt lines, the affected target will just be rebuilt
+                if let Some((target, dep, hash)) = parse_line(&line) {
+                    entries.entry(target).or_default().insert(dep, hash);
+                }
+            }
+        }
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+        Err(e) => return Err(e.into()),
+    }
+    Ok(entries)
+}
+
+fn parse_line(line: &str) -> Option<(PathBuf, PathBuf, FileHash)> {
+    let mut parts = line.split('\t');
+    let target = unescape_path(parts.next()?)?;
+    let dep = unescape_path(parts.next()?)?;
+    let hex = parts.next()?;
+    let mut hash = FileHash::default();
+    if hex.len() != 2 * hash.len() {
+        // from a build with or without the `blake3` feature
+        return None;
+    }
+    for (i, byte) in hash.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(2 * i..2 * i + 2)?, 16).ok()?;
+    }
+    Some((target, dep, hash))
+}
+
+/// Write the database out, replacing the old copy only once the new one is complete.
+fn save(path: &Path, entries: &Entries) -> DepResult<()> {
+    let mut tmp_name = path.to_owned().into_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_name);
+    {
+        let mut out = io::BufWriter::new(File::create(&tmp_path)?);
+        for (target, hashes) in entries.iter() {
+            for (dep, hash) in hashes.iter() {
+                write!(out, "{}\t{}\t", escape_path(target), escape_path(dep))?;
+                for byte in hash {
+                    write!(out, "{:02x}", byte)?;
+                }
+                writeln!(out)?;
+            }
+        }
+        out.flush()?;
+    }
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+/// Write `path` so it can't be confused with the separators in the database (or the build log).
+pub(crate) fn escape_path(path: &Path) -> String {
+    let mut out = String::new();
+    for chunk in path_bytes(path).utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '\\' => out.push_str("\\\\"),
+                '\t' => out.push_str("\\t"),
+                '\n' => out.push_str("\\n"),
+                '\r' => out.push_str("\\r"),
+                c => out.push(c),
+            }
+        }
+        for byte in chunk.invalid() {
+            out.push_str(&format!("\\x{:02x}", byte));
+        }
+    }
+    out
+}
+
+/// Read a path written by `escape_path`, or `None` if it is corrupt.
+pub(crate) fn unescape_path(field: &str) -> Option<PathBuf> {
+    let mut bytes = Vec::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            bytes.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+            continue;
+        }
+        match chars.next()? {
+            '\\' => bytes.push(b'\\'),
+            't' => bytes.push(b'\t'),
+            'n' => bytes.push(b'\n'),
+            'r' => bytes.push(b'\r'),
+            'x' => {
+                let hex: String = chars.by_ref().take(2).collect();
+                bytes.push(u8::from_str_radix(&hex, 16).ok()?);
+            }
+            _ => return None,
+        }
+    }
+    path_from_bytes(bytes)
+}
+
+/// The bytes of `path`, as they are stored by the OS on Unix.
+#[cfg(unix)]
+fn path_bytes(path: &Path) -> &[u8] {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes()
+}
+
+/// The bytes of `path`. Paths that aren't valid Unicode can't be read back from these, so
+/// targets that have them are rebuilt every time.
+#[cfg(not(unix))]
+fn path_bytes(path: &Path) -> &[u8] {
+    path.as_os_str().as_encoded_bytes()
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: Vec<u8>) -> Option<PathBuf> {
+    use std::os::unix::ffi::OsStringExt;
+    Some(PathBuf::from(std::ffi::OsString::from_vec(bytes)))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: Vec<u8>) -> Option<PathBuf> {
+    String::from_utf8(bytes).ok().map(PathBuf::from)
+}
+
+/// (Internal) A 64-bit FNV-1a hasher.
+///
+/// This isn't cryptographically secure, but it is stable between runs and compiler versions
+/// (unlike `std::collections::hash_map::DefaultHasher`), which is all we need to detect changes.
+pub(crate) struct Fnv(u64);
+
+impl Fnv {
+    pub(crate) fn new() -> Fnv {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+
+    pub(crate) fn write(&mut self, bytes: &[u8]) {
+        const PRIME: u64 = 0x0000_0100_0000_01b3;
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(PRIME);
+        }
+    }
+
+    pub(crate) fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// (Internal) The hash of a file's contents.
+#[cfg(feature = "blake3")]
+pub(crate) type FileHash = [u8; 32];
+
+/// (Internal) The hash of a file's contents.
+#[cfg(not(feature = "blake3"))]
+pub(crate) type FileHash = [u8; 8];
+
+/// Hash the contents of a file, with BLAKE3 if the `blake3` feature is enabled and 64-bit FNV-1a
+/// if not.
+///
+/// For a directory, the names and contents of everything in it are hashed.
+pub(crate) fn hash_file(path: &Path) -> io::Result<FileHash> {
+    let mut hasher = FileHasher::new();
+    if path.is_dir() {
+        let mut entries = fs::read_dir(path)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<io::Result<Vec<_>>>()?;
+        entries.sort();
+        for entry in entries {
+            hasher.write_all(entry.file_name().unwrap_or_default().as_encoded_bytes())?;
+            hasher.write_all(&hash_file(&entry)?)?;
+        }
+    } else {
+        io::copy(&mut File::open(path)?, &mut hasher)?;
+    }
+    Ok(hasher.finish())
+}
+
+/// (Internal) The hasher used by `hash_file`.
+#[cfg(feature = "blake3")]
+struct FileHasher(blake3::Hasher);
+
+#[cfg(feature = "blake3")]
+impl FileHasher {
+    fn new() -> FileHasher {
+        FileHasher(blake3::Hasher::new())
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        self.0.update(bytes);
+    }
+
+    fn finish(&self) -> FileHash {
+        self.0.finalize().into()
+    }
+}
+
+/// (Internal) The hasher used by `hash_file`.
+#[cfg(not(feature = "blake3"))]
+struct FileHasher(Fnv);
+
+#[cfg(not(feature = "blake3"))]
+impl FileHasher {
+    fn new() -> FileHasher {
+        FileHasher(Fnv::new())
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        self.0.write(bytes);
+    }
+
+    fn finish(&self) -> FileHash {
+        self.0.finish().to_be_bytes()
+    }
+}
+
+impl Write for FileHasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
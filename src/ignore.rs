@@ -0,0 +1,129 @@
+//! Skipping files excluded by `.gitignore` and `.ignore` files.
+//!
+//! This supports the commonly used parts of the gitignore format: comments, negation with `!`,
+//! patterns that only match directories (with a trailing `/`), patterns anchored to the
+//! directory of the ignore file (containing a `/`), and the glob syntax described in the `glob`
+//! module.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::{glob, DepResult};
+
+/// The names of the files ignore rules are read from, in order of increasing precedence.
+const IGNORE_FILES: &[&str] = &[".gitignore", ".ignore"];
+
+/// List the files under `root`, recursively and in sorted order, skipping hidden files and
+/// anything excluded by `.gitignore` or `.ignore` files.
+///
+/// This is useful for generating dependency lists from a source tree without picking up editor
+/// temporary files or build droppings. Only ignore files in `root` and below are read.
+pub fn source_files<P: AsRef<Path>>(root: P) -> DepResult<Vec<PathBuf>> {
+    let files = glob::expand(
+        &root.as_ref().join("**").join("*"),
+        Some(&Ignore::default()),
+    )?;
+    Ok(files.into_iter().filter(|path| path.is_file()).collect())
+}
+
+/// (Internal) The ignore rules that apply in a directory.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Ignore {
+    rules: Vec<Rule>,
+}
+
+/// (Internal) A single line of an ignore file.
+#[derive(Debug, Clone)]
+struct Rule {
+    /// The directory containing the ignore file
+    base: PathBuf,
+    /// The pattern, split into path components
+    pattern: Vec<String>,
+    /// Whether the pattern must match the whole path relative to `base`, rather than just the
+    /// file name
+    anchored: bool,
+    /// Whether the pattern only matches directories
+    dir_only: bool,
+    /// Whether this rule re-includes files (`!pattern`)
+    negated: bool,
+}
+
+impl Ignore {
+    /// The rules that apply in `dir`: these rules, plus any from ignore files in `dir`.
+    pub(crate) fn with_dir(&self, dir: &Path) -> io::Result<Ignore> {
+        let mut ignore = self.clone();
+        for name in IGNORE_FILES {
+            match fs::read_to_string(dir.join(name)) {
+                Ok(contents) => ignore
+                    .rules
+                    .extend(contents.lines().filter_map(|line| Rule::parse(dir, line))),
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(ignore)
+    }
+
+    /// Whether `path` is excluded. Later rules take precedence over earlier ones.
+    pub(crate) fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        for rule in self.rules.iter().rev() {
+            if rule.matches(path, is_dir) {
+                return !rule.negated;
+            }
+        }
+        false
+    }
+}
+
+impl Rule {
+    /// Parse a line from an ignore file in `base`, or return `None` for blank lines and comments.
+    fn parse(base: &Path, line: &str) -> Option<Rule> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(line) => (true, line),
+            None => (false, line.strip_prefix('\\').unwrap_or(line)),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(line) => (true, line),
+            None => (false, line),
+        };
+        let anchored = line.contains('/');
+        let line = line.strip_prefix('/').unwrap_or(line);
+        if line.is_empty() {
+            return None;
+        }
+        Some(Rule {
+            base: base.to_owned(),
+            pattern: line.split('/').map(str::to_owned).collect(),
+            anchored,
+            dir_only,
+            negated,
+        })
+    }
+
+    fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let relative = match path.strip_prefix(&self.base) {
+            Ok(relative) => relative,
+            Err(_) => return false,
+        };
+        let components: Vec<String> = relative
+            .iter()
+            .map(|c| c.to_string_lossy().into_owned())
+            .collect();
+        if self.anchored {
+            glob::matches_components(&self.pattern, &components)
+        } else {
+            match components.last() {
+                Some(name) => glob::matches(&self.pattern[0], name),
+                None => false,
+            }
+        }
+    }
+}
@@ -43,157 +43,1042 @@
 //! ```
 //!
 
+#[cfg(feature = "async")]
+mod asynchronous;
+mod cargo;
+mod clean;
+mod command;
+mod critical;
+#[cfg(all(feature = "watch", feature = "parallel"))]
+mod daemon;
+mod depfile;
 mod error;
+mod export;
+mod freshness;
+mod glob;
+mod hash;
+mod ignore;
+mod json;
+mod log;
+mod makefile;
+#[cfg(feature = "manifest")]
+pub mod manifest;
+mod ninja;
+mod observer;
+mod options;
+#[cfg(feature = "parallel")]
+mod parallel;
+mod pattern;
+mod plan;
+#[cfg(feature = "progress")]
+mod progress;
+mod query;
+mod report;
+mod restat;
+pub mod rules;
+mod scanners;
+mod schedule;
+mod sha256;
+mod subgraph;
+mod target;
+mod touch;
+mod tree;
+pub mod util;
+#[cfg(feature = "watch")]
+mod watch;
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt;
-use std::fs;
 use std::path::{Path, PathBuf};
+#[cfg(feature = "parallel")]
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
 use petgraph::graph::NodeIndex;
 use petgraph::Graph;
 
+use crate::log::{BuildLog, LogEntry};
+use crate::schedule::Scheduler;
+
 #[cfg(feature = "petgraph_visible")]
 pub use petgraph;
 
-pub use crate::error::{DepResult, Error};
+pub use crate::cargo::{cargo_warning, CargoEnv, CargoWarnings};
+pub use crate::command::CommandSpec;
+#[cfg(all(feature = "watch", feature = "parallel"))]
+pub use crate::daemon::Daemon;
+pub use crate::error::{BuildError, DepResult, Error};
+pub use crate::export::{ExportedEdge, ExportedNode, GraphExport};
+pub use crate::freshness::{FreshnessChecker, ModifiedTime, RebuildReason};
+pub use crate::hash::ContentHash;
+pub use crate::ignore::source_files;
+pub use crate::observer::{BuildEvent, BuildObserver};
+pub use crate::options::{RuleOptions, CONSOLE_POOL};
+pub use crate::plan::BuildPlan;
+#[cfg(feature = "progress")]
+pub use crate::progress::ProgressBar;
+pub use crate::report::MakeReport;
+pub use crate::scanners::{IncludeScanner, ProtoScanner, Scanner};
+pub use crate::target::{MemoryTarget, Target};
+
+/// A bound on build functions that is `Send` with the `parallel` feature, and nothing otherwise.
+///
+/// `DepGraph::make_parallel` runs build functions on other threads, so with the `parallel`
+/// feature they must be `Send + Sync` (see `MaybeSync`). Without it, a build function can capture
+/// an `Rc` or a `RefCell`. Every type that can be used implements this trait.
+#[cfg(feature = "parallel")]
+pub trait MaybeSend: Send {}
+#[cfg(feature = "parallel")]
+impl<T: Send + ?Sized> MaybeSend for T {}
+
+/// A bound on build functions that is `Send` with the `parallel` feature, and nothing otherwise.
+#[cfg(not(feature = "parallel"))]
+pub trait MaybeSend {}
+#[cfg(not(feature = "parallel"))]
+impl<T: ?Sized> MaybeSend for T {}
+
+/// A bound on build functions that is `Sync` with the `parallel` feature, and nothing otherwise.
+///
+/// See `MaybeSend`.
+#[cfg(feature = "parallel")]
+pub trait MaybeSync: Sync {}
+#[cfg(feature = "parallel")]
+impl<T: Sync + ?Sized> MaybeSync for T {}
+
+/// A bound on build functions that is `Sync` with the `parallel` feature, and nothing otherwise.
+///
+/// See `MaybeSend`.
+#[cfg(not(feature = "parallel"))]
+pub trait MaybeSync {}
+#[cfg(not(feature = "parallel"))]
+impl<T: ?Sized> MaybeSync for T {}
+
+/// (Internal) The type of a boxed build function.
+///
+/// With the `parallel` feature, build functions must be `Send + Sync` so that independent rules
+/// can be run concurrently by `DepGraph::make_parallel`.
+#[cfg(feature = "parallel")]
+type BuildFn = dyn Fn(&Path, &[&Path]) -> Result<(), BuildError> + Send + Sync;
+#[cfg(not(feature = "parallel"))]
+type BuildFn = dyn Fn(&Path, &[&Path]) -> Result<(), BuildError>;
+
+/// (Internal) The type of a boxed build function that may borrow from its surroundings, see
+/// `DepGraphBuilder::add_scoped_rule`.
+#[cfg(feature = "parallel")]
+type ScopedBuildFn<'a> = dyn Fn(&Path, &[&Path]) -> Result<(), BuildError> + Send + Sync + 'a;
+#[cfg(not(feature = "parallel"))]
+type ScopedBuildFn<'a> = dyn Fn(&Path, &[&Path]) -> Result<(), BuildError> + 'a;
+
+/// (Internal) How a rule's output is produced.
+#[derive(Clone)]
+enum BuildAction<'a> {
+    /// A plain function, run on the calling thread.
+    Sync(Arc<BuildFn>),
+    /// A function that may borrow, which can't be abandoned if it times out.
+    Scoped(Arc<ScopedBuildFn<'a>>),
+    /// A function returning a future, see `DepGraphBuilder::add_async_rule`.
+    #[cfg(feature = "async")]
+    Async(Arc<asynchronous::AsyncBuildFn>),
+}
+
+impl BuildAction<'_> {
+    /// Run the build action to completion on the current thread. Async build functions can only
+    /// be run by `make_async`, so fail with `Error::AsyncRule`.
+    ///
+    /// If `timeout` is given and the build takes longer, it is abandoned and `Error::Timeout` is
+    /// returned. Synchronous build functions can't be interrupted, so with the `parallel` feature
+    /// they are run on a separate thread that is left to finish in the background. Scoped build
+    /// functions can't outlive what they borrow (and without the `parallel` feature no build
+    /// function can be moved to another thread), so they are run to the end, and fail with
+    /// `Error::Timeout` if they overran.
+    fn run(&self, filename: &Path, deps: &[&Path], timeout: Option<Duration>) -> DepResult<()> {
+        match (self, timeout) {
+            (BuildAction::Sync(f), None) => {
+                f(filename, deps).map_err(Error::build_failed(filename, deps))
+            }
+            (BuildAction::Scoped(f), None) => {
+                f(filename, deps).map_err(Error::build_failed(filename, deps))
+            }
+            (BuildAction::Scoped(f), Some(timeout)) => run_to_end(&**f, filename, deps, timeout),
+            #[cfg(not(feature = "parallel"))]
+            (BuildAction::Sync(f), Some(timeout)) => run_to_end(&**f, filename, deps, timeout),
+            #[cfg(feature = "parallel")]
+            (BuildAction::Sync(f), Some(timeout)) => {
+                let f = f.clone();
+                let out = filename.to_owned();
+                let owned_deps: Vec<PathBuf> = deps.iter().map(|p| p.to_path_buf()).collect();
+                let (tx, rx) = mpsc::channel();
+                #[cfg(feature = "tracing")]
+                let span = tracing::Span::current();
+                thread::spawn(move || {
+                    #[cfg(feature = "tracing")]
+                    let _span = span.entered();
+                    let deps: Vec<&Path> = owned_deps.iter().map(|p| p.as_path()).collect();
+                    // the receiver has gone if we timed out, so there's no-one to tell
+                    let _ = tx.send(f(&out, &deps));
+                });
+                match rx.recv_timeout(timeout) {
+                    Ok(result) => result.map_err(Error::build_failed(filename, deps)),
+                    Err(mpsc::RecvTimeoutError::Timeout) => {
+                        Err(Error::Timeout(filename.to_owned()))
+                    }
+                    Err(mpsc::RecvTimeoutError::Disconnected) => {
+                        Err(Error::build_failed(filename, deps)(
+                            "the build function panicked".into(),
+                        ))
+                    }
+                }
+            }
+            #[cfg(feature = "async")]
+            (BuildAction::Async(_), _) => Err(Error::AsyncRule(filename.to_owned())),
+        }
+    }
+}
+
+/// (Internal) The `tracing` span a rule's build function is run in, so that anything it logs is
+/// attributed to the target.
+#[cfg(feature = "tracing")]
+fn rule_span(dep: &DependencyNode, deps: &[&Path], reason: &RebuildReason) -> tracing::Span {
+    tracing::info_span!(
+        "rule",
+        target = %dep.filename.display(),
+        deps = deps.len(),
+        reason = ?reason,
+    )
+}
+
+/// (Internal) Run a build function that can't be abandoned, failing with `Error::Timeout` if it
+/// took longer than `timeout`.
+fn run_to_end(
+    f: &ScopedBuildFn<'_>,
+    filename: &Path,
+    deps: &[&Path],
+    timeout: Duration,
+) -> DepResult<()> {
+    let start = Instant::now();
+    let result = f(filename, deps).map_err(Error::build_failed(filename, deps));
+    if start.elapsed() > timeout {
+        return Err(Error::Timeout(filename.to_owned()));
+    }
+    result
+}
 
 /// (Internal) Information on a dependency (how to build it and what it's called)
 ///
 /// TODO keep copy of dependencies in order, so we don't have to look them up on the graph, and
 /// they stay in order
-struct DependencyNode {
+#[derive(Clone)]
+struct DependencyNode<'a> {
     filename: PathBuf,
-    build_fn: Option<Box<dyn Fn(&Path, &[&Path]) -> Result<(), String>>>,
+    build_fn: Option<BuildAction<'a>>,
+    options: RuleOptions,
+    /// Whether this is a phony target (a name for a group of files, rather than a file)
+    phony: bool,
+    /// Whether this is a source file that some rule lists in `RuleOptions::optional_deps`, so
+    /// may be missing
+    optional: bool,
+    /// Other files the build function creates (see `DepGraphBuilder::add_rule_multi`)
+    extra_outputs: Vec<PathBuf>,
+    /// Whether this is one of the `extra_outputs` of the rule it depends on
+    extra_output: bool,
+    /// The command the build function runs, for rules added with `add_command_rule`
+    command: Option<CommandSpec>,
+    /// What the node is, if it isn't a file (see `DepGraphBuilder::add_target_rule`)
+    target: Option<Arc<dyn Target>>,
+}
+
+impl DependencyNode<'_> {
+    /// All the files the build function creates.
+    fn outputs(&self) -> impl Iterator<Item = &Path> {
+        std::iter::once(self.filename.as_path())
+            .chain(self.extra_outputs.iter().map(|p| p.as_path()))
+    }
+
+    /// Files the build function may create that aren't tracked: implicit outputs and the depfile.
+    fn implicit_outputs(&self) -> impl Iterator<Item = &Path> {
+        self.options
+            .implicit_outputs
+            .iter()
+            .chain(self.options.depfile.iter())
+            .map(|p| p.as_path())
+    }
+
+    /// Check that the file exists, unless this is a phony target or an optional source file.
+    fn check_exists(&self) -> DepResult<()> {
+        if self.phony || (self.optional && self.build_fn.is_none()) {
+            Ok(())
+        } else if let Some(ref target) = self.target {
+            match target.is_present()? {
+                true => Ok(()),
+                false => Err(Error::MissingFile(self.filename.clone())),
+            }
+        } else {
+            self.check_output_exists(&self.filename)
+        }
+    }
+
+    /// Check that the build function created all of its outputs.
+    fn check_built(&self) -> DepResult<()> {
+        if self.target.is_some() {
+            return self.check_exists();
+        }
+        for output in self.outputs() {
+            self.check_output_exists(output)?;
+        }
+        Ok(())
+    }
+
+    /// Check that `output` exists, using the rule's freshness checker if it has one.
+    fn check_output_exists(&self, output: &Path) -> DepResult<()> {
+        match self.options.freshness_checker {
+            Some(ref checker) if !checker.exists(output)? => {
+                Err(Error::MissingFile(output.to_owned()))
+            }
+            Some(_) => Ok(()),
+            None => check_exists(output),
+        }
+    }
+
+    /// Whether the node decides for itself whether it exists, rather than being a file.
+    fn custom_exists(&self) -> bool {
+        self.target.is_some() || self.options.freshness_checker.is_some()
+    }
 }
 
-impl fmt::Debug for DependencyNode {
+impl fmt::Debug for DependencyNode<'_> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "DependencyNode(\"{:?}\")", self.filename)
     }
 }
 
+/// (Internal) A rule as added to the builder.
+struct Rule<'a> {
+    filename: PathBuf,
+    dependencies: Vec<PathBuf>,
+    /// `None` for phony rules
+    build_fn: Option<BuildAction<'a>>,
+    options: RuleOptions,
+    /// Outputs other than `filename`
+    extra_outputs: Vec<PathBuf>,
+    /// The command the build function runs, for rules added with `add_command_rule`
+    command: Option<CommandSpec>,
+    /// What the rule builds, if it isn't a file
+    target: Option<Arc<dyn Target>>,
+}
+
 /// Used to construct a DepGraph
 ///
 /// See the module level documentation for an example of how to use this
-pub struct DepGraphBuilder {
-    /// List of edges, .0 is dependent, .1 is dependencies, .2 is build fn
-    edges: Vec<(
-        PathBuf,
-        Vec<PathBuf>,
-        Box<dyn Fn(&Path, &[&Path]) -> Result<(), String>>,
-    )>,
+///
+/// `'a` is how long the build functions can borrow for, see `add_scoped_rule`. It is `'static`
+/// for graphs whose build functions own everything they use.
+pub struct DepGraphBuilder<'a> {
+    /// List of rules, in the order they were added
+    rules: Vec<Rule<'a>>,
+    /// Pattern rules, instantiated when the graph is built
+    pattern_rules: Vec<pattern::PatternRule<'a>>,
+    /// How to decide whether a rule needs rebuilding
+    freshness: Arc<dyn FreshnessChecker>,
+    /// Where to keep the log of successful builds, if anywhere
+    log_path: Option<PathBuf>,
+    /// Files to treat as newer or older than they are
+    assume: Assume,
+    /// Whether glob patterns skip files excluded by `.gitignore` and `.ignore` files
+    respect_ignore_files: bool,
+    /// The directory relative outputs are put in, if any
+    out_dir: Option<PathBuf>,
+    /// Short names for targets
+    aliases: HashMap<String, PathBuf>,
+    /// What `make` builds, if not everything
+    default_target: Option<PathBuf>,
+    /// The depth of each pool
+    pools: HashMap<String, usize>,
+}
+
+impl Default for DepGraphBuilder<'_> {
+    fn default() -> Self {
+        DepGraphBuilder::new()
+    }
 }
 
-impl DepGraphBuilder {
+impl<'a> DepGraphBuilder<'a> {
     /// Create a `DepGraphBuilder` with no rules.
-    pub fn new() -> DepGraphBuilder {
-        DepGraphBuilder { edges: Vec::new() }
+    pub fn new() -> DepGraphBuilder<'a> {
+        DepGraphBuilder {
+            rules: Vec::new(),
+            pattern_rules: Vec::new(),
+            freshness: Arc::new(ModifiedTime),
+            log_path: None,
+            assume: Assume::default(),
+            respect_ignore_files: false,
+            out_dir: None,
+            aliases: HashMap::new(),
+            default_target: None,
+            pools: HashMap::from([(CONSOLE_POOL.to_owned(), 1)]),
+        }
     }
 
     /// Add a new rule (a file with its dependent files and build instructions).
     ///
     /// These can be added in any order, and can be chained.
+    ///
+    /// Dependencies can be glob patterns like `shaders/**/*.glsl`, which are replaced with the
+    /// matching files when the graph is built. Files created after that aren't picked up until the
+    /// graph is built again.
     pub fn add_rule<F, P1, P2>(
+        self,
+        filename: P1,
+        dependencies: &[P2],
+        build_fn: F,
+    ) -> DepGraphBuilder<'a>
+    where
+        F: Fn(&Path, &[&Path]) -> Result<(), String> + MaybeSend + MaybeSync + 'static,
+        P1: AsRef<Path>,
+        P2: AsRef<Path>,
+    {
+        self.add_rule_with_opts(filename, dependencies, RuleOptions::default(), build_fn)
+    }
+
+    /// Add a new rule, like `add_rule`, with options controlling how it is run.
+    pub fn add_rule_with_opts<F, P1, P2>(
+        self,
+        filename: P1,
+        dependencies: &[P2],
+        options: RuleOptions,
+        build_fn: F,
+    ) -> DepGraphBuilder<'a>
+    where
+        F: Fn(&Path, &[&Path]) -> Result<(), String> + MaybeSend + MaybeSync + 'static,
+        P1: AsRef<Path>,
+        P2: AsRef<Path>,
+    {
+        self.add_fallible_rule_with_opts(filename, dependencies, options, move |out, deps| {
+            build_fn(out, deps).map_err(BuildError::from)
+        })
+    }
+
+    /// Add a new rule, like `add_rule`, whose build function can return any error, such as an
+    /// `io::Error` or an error type of its own, rather than a `String`. The error is kept as the
+    /// source of `Error::BuildFailed`, so its chain isn't lost.
+    ///
+    /// ```no_run
+    /// use std::fs;
+    ///
+    /// let builder = depgraph::DepGraphBuilder::new().add_fallible_rule(
+    ///     "out/data.bin",
+    ///     &["data.txt"],
+    ///     |out, deps| {
+    ///         fs::copy(deps[0], out)?;
+    ///         Ok(())
+    ///     },
+    /// );
+    /// ```
+    pub fn add_fallible_rule<F, P1, P2>(
+        self,
+        filename: P1,
+        dependencies: &[P2],
+        build_fn: F,
+    ) -> DepGraphBuilder<'a>
+    where
+        F: Fn(&Path, &[&Path]) -> Result<(), BuildError> + MaybeSend + MaybeSync + 'static,
+        P1: AsRef<Path>,
+        P2: AsRef<Path>,
+    {
+        self.add_fallible_rule_with_opts(filename, dependencies, RuleOptions::default(), build_fn)
+    }
+
+    /// Add a rule whose build function can return any error, like `add_fallible_rule`, with
+    /// options controlling how it is run.
+    pub fn add_fallible_rule_with_opts<F, P1, P2>(
+        self,
+        filename: P1,
+        dependencies: &[P2],
+        options: RuleOptions,
+        build_fn: F,
+    ) -> DepGraphBuilder<'a>
+    where
+        F: Fn(&Path, &[&Path]) -> Result<(), BuildError> + MaybeSend + MaybeSync + 'static,
+        P1: AsRef<Path>,
+        P2: AsRef<Path>,
+    {
+        self.push_rule(
+            filename,
+            dependencies,
+            options,
+            BuildAction::Sync(Arc::new(build_fn)),
+        )
+    }
+
+    /// Add a new rule, like `add_rule`, whose build function can borrow from its surroundings,
+    /// such as settings in `main`, rather than having to own what it uses. The graph can't
+    /// outlive what the function borrows.
+    ///
+    /// ```no_run
+    /// struct Settings {
+    ///     banner: String,
+    /// }
+    ///
+    /// let settings = Settings {
+    ///     banner: "// generated".to_owned(),
+    /// };
+    /// depgraph::DepGraphBuilder::new()
+    ///     .add_scoped_rule("out/lib.rs", &["src/lib.rs.in"], |out, deps| {
+    ///         let text = std::fs::read_to_string(deps[0]).map_err(|e| e.to_string())?;
+    ///         std::fs::write(out, format!("{}\n{}", settings.banner, text))
+    ///             .map_err(|e| e.to_string())
+    ///     })
+    ///     .build()
+    ///     .unwrap()
+    ///     .make(depgraph::MakeParams::None)
+    ///     .unwrap();
+    /// ```
+    pub fn add_scoped_rule<F, P1, P2>(
+        self,
+        filename: P1,
+        dependencies: &[P2],
+        build_fn: F,
+    ) -> DepGraphBuilder<'a>
+    where
+        F: Fn(&Path, &[&Path]) -> Result<(), String> + MaybeSend + MaybeSync + 'a,
+        P1: AsRef<Path>,
+        P2: AsRef<Path>,
+    {
+        self.add_scoped_rule_with_opts(filename, dependencies, RuleOptions::default(), build_fn)
+    }
+
+    /// Add a rule whose build function can borrow, like `add_scoped_rule`, with options
+    /// controlling how it is run.
+    ///
+    /// A build function that borrows can't be abandoned, so if it has a timeout (see
+    /// `RuleOptions::timeout`) it is run to the end, and fails with `Error::Timeout` if it took
+    /// too long.
+    pub fn add_scoped_rule_with_opts<F, P1, P2>(
+        self,
+        filename: P1,
+        dependencies: &[P2],
+        options: RuleOptions,
+        build_fn: F,
+    ) -> DepGraphBuilder<'a>
+    where
+        F: Fn(&Path, &[&Path]) -> Result<(), String> + MaybeSend + MaybeSync + 'a,
+        P1: AsRef<Path>,
+        P2: AsRef<Path>,
+    {
+        self.push_rule(
+            filename,
+            dependencies,
+            options,
+            BuildAction::Scoped(Arc::new(move |out: &Path, deps: &[&Path]| {
+                build_fn(out, deps).map_err(BuildError::from)
+            })),
+        )
+    }
+
+    /// Add a new rule, like `add_rule`, whose build function can change the state it captures,
+    /// such as a counter or a cache, without a `Mutex` or `Cell` of its own.
+    ///
+    /// The function is run by one thread at a time, so `make_parallel` never runs it twice at
+    /// once. If it panics, it isn't run again: the rule fails instead.
+    ///
+    /// ```no_run
+    /// let mut runs = 0;
+    /// let builder = depgraph::DepGraphBuilder::new().add_rule_mut(
+    ///     "out/version.txt",
+    ///     &["Cargo.toml"],
+    ///     move |out, _| {
+    ///         runs += 1;
+    ///         std::fs::write(out, format!("build {}", runs)).map_err(|e| e.to_string())
+    ///     },
+    /// );
+    /// ```
+    pub fn add_rule_mut<F, P1, P2>(
+        self,
+        filename: P1,
+        dependencies: &[P2],
+        build_fn: F,
+    ) -> DepGraphBuilder<'a>
+    where
+        F: FnMut(&Path, &[&Path]) -> Result<(), String> + MaybeSend + 'static,
+        P1: AsRef<Path>,
+        P2: AsRef<Path>,
+    {
+        let build_fn = Mutex::new(build_fn);
+        self.add_rule(filename, dependencies, move |out, deps| {
+            let mut build_fn = build_fn
+                .lock()
+                .map_err(|_| "the build function panicked in an earlier build".to_owned())?;
+            build_fn(out, deps)
+        })
+    }
+
+    /// Add a new rule, like `add_rule`, whose build function can only be run once, so it can move
+    /// what it captures, such as a temporary directory or a handle that is closed when dropped.
+    ///
+    /// This is meant for graphs that are made once, see `DepGraph::make_once`. If the rule is
+    /// stale again in a later build, or is retried (see `RuleOptions::retries`), it fails.
+    pub fn add_rule_once<F, P1, P2>(
+        self,
+        filename: P1,
+        dependencies: &[P2],
+        build_fn: F,
+    ) -> DepGraphBuilder<'a>
+    where
+        F: FnOnce(&Path, &[&Path]) -> Result<(), String> + MaybeSend + 'static,
+        P1: AsRef<Path>,
+        P2: AsRef<Path>,
+    {
+        let build_fn = Mutex::new(Some(build_fn));
+        self.add_rule(filename, dependencies, move |out, deps| {
+            let build_fn = build_fn
+                .lock()
+                .ok()
+                .and_then(|mut build_fn| build_fn.take());
+            match build_fn {
+                Some(build_fn) => build_fn(out, deps),
+                None => Err("the build function has already been run".to_owned()),
+            }
+        })
+    }
+
+    /// Helper function to add a rule with any kind of build function.
+    fn push_rule<P1, P2>(
         mut self,
         filename: P1,
         dependencies: &[P2],
+        options: RuleOptions,
+        build_fn: BuildAction<'a>,
+    ) -> DepGraphBuilder<'a>
+    where
+        P1: AsRef<Path>,
+        P2: AsRef<Path>,
+    {
+        self.rules.push(Rule {
+            filename: filename.as_ref().to_path_buf(),
+            dependencies: dependencies
+                .iter()
+                .map(|s| s.as_ref().to_path_buf())
+                .collect(),
+            build_fn: Some(build_fn),
+            options,
+            extra_outputs: Vec::new(),
+            command: None,
+            target: None,
+        });
+        self
+    }
+
+    /// Add a rule whose build function creates several files, such as a parser generator that
+    /// writes a source file and a header.
+    ///
+    /// The build function is passed all of `outputs`. It is run if any of them is stale, and the
+    /// rule fails if any of them is missing afterwards. Each output can be depended on by other
+    /// rules as usual.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `outputs` is empty.
+    pub fn add_rule_multi<F, P1, P2>(
+        mut self,
+        outputs: &[P1],
+        dependencies: &[P2],
         build_fn: F,
-    ) -> DepGraphBuilder
+    ) -> DepGraphBuilder<'a>
     where
-        F: Fn(&Path, &[&Path]) -> Result<(), String> + 'static,
+        F: Fn(&[&Path], &[&Path]) -> Result<(), String> + MaybeSend + MaybeSync + 'static,
         P1: AsRef<Path>,
         P2: AsRef<Path>,
     {
-        self.edges.push((
-            filename.as_ref().to_path_buf(),
-            dependencies
+        assert!(!outputs.is_empty(), "a rule must have at least one output");
+        let outputs: Vec<PathBuf> = outputs.iter().map(|p| p.as_ref().to_path_buf()).collect();
+        let extra_outputs = outputs[1..].to_vec();
+        let filename = outputs[0].clone();
+        let build_fn = move |_: &Path, deps: &[&Path]| {
+            let outputs: Vec<&Path> = outputs.iter().map(|p| p.as_path()).collect();
+            build_fn(&outputs, deps).map_err(BuildError::from)
+        };
+        self.rules.push(Rule {
+            filename,
+            dependencies: dependencies
                 .iter()
                 .map(|s| s.as_ref().to_path_buf())
                 .collect(),
-            Box::new(build_fn),
-        ));
+            build_fn: Some(BuildAction::Sync(Arc::new(build_fn))),
+            options: RuleOptions::default(),
+            extra_outputs,
+            command: None,
+            target: None,
+        });
+        self
+    }
+
+    /// Add a phony target: a name for a group of dependencies, rather than a file.
+    ///
+    /// Making a phony target just makes its dependencies, and nothing checks that a file called
+    /// `name` exists. A rule that depends on a phony target is passed (and checked against) the
+    /// phony target's dependencies instead.
+    pub fn add_phony_rule<P1, P2>(mut self, name: P1, dependencies: &[P2]) -> DepGraphBuilder<'a>
+    where
+        P1: AsRef<Path>,
+        P2: AsRef<Path>,
+    {
+        self.rules.push(Rule {
+            filename: name.as_ref().to_path_buf(),
+            dependencies: dependencies
+                .iter()
+                .map(|s| s.as_ref().to_path_buf())
+                .collect(),
+            build_fn: None,
+            options: RuleOptions::default(),
+            extra_outputs: Vec::new(),
+            command: None,
+            target: None,
+        });
         self
     }
 
+    /// Add a named group of targets, which can be built together with `DepGraph::make_target`,
+    /// like `make textures`.
+    ///
+    /// This is the phony rule `name` (see `add_phony_rule`), so a rule can also depend on the
+    /// whole group.
+    ///
+    /// ```no_run
+    /// # fn compress(out: &std::path::Path, deps: &[&std::path::Path]) -> Result<(), String> { Ok(()) }
+    /// let graph = depgraph::DepGraphBuilder::new()
+    ///     .add_rule("out/grass.ktx2", &["textures/grass.png"], compress)
+    ///     .add_rule("out/rock.ktx2", &["textures/rock.png"], compress)
+    ///     .group("textures", &["out/grass.ktx2", "out/rock.ktx2"])
+    ///     .build()
+    ///     .unwrap();
+    /// graph.make_target("textures", depgraph::MakeParams::None).unwrap();
+    /// ```
+    pub fn group<P: AsRef<Path>>(self, name: &str, targets: &[P]) -> DepGraphBuilder<'a> {
+        self.add_phony_rule(name, targets)
+    }
+
     /// Add a dependency to all previously added files. Will only affect previously added files,
     /// not those added in the future.
     ///
     /// This can be used to make all rules depend on `build.rs`, for example.
-    pub fn add_dep_to_all<P>(mut self, dep: P) -> DepGraphBuilder
+    pub fn add_dep_to_all<P>(mut self, dep: P) -> DepGraphBuilder<'a>
     where
         P: AsRef<Path>,
     {
-        for edge in self.edges.iter_mut() {
-            edge.1.push(dep.as_ref().to_owned());
+        for rule in self.rules.iter_mut() {
+            rule.dependencies.push(dep.as_ref().to_owned());
         }
         self
     }
 
+    /// Decide whether to rebuild by comparing file contents rather than modification times.
+    ///
+    /// The hashes of each rule's dependencies are stored in `database` (a file, usually in
+    /// `OUT_DIR`) after it is built, and the rule is only run again when one of those hashes
+    /// changes. This is useful on CI, where checkouts don't preserve modification times.
+    ///
+    /// This is shorthand for `freshness_checker(ContentHash::new(database))`.
+    pub fn content_hashes<P>(self, database: P) -> DepGraphBuilder<'a>
+    where
+        P: AsRef<Path>,
+    {
+        self.freshness_checker(ContentHash::new(database))
+    }
+
+    /// Set how to decide whether a rule needs rebuilding. The default is `ModifiedTime`.
+    pub fn freshness_checker<C>(mut self, checker: C) -> DepGraphBuilder<'a>
+    where
+        C: FreshnessChecker + 'static,
+    {
+        self.freshness = Arc::new(checker);
+        self
+    }
+
+    /// Keep a persistent log of successful builds in the file at `path` (usually in `OUT_DIR`).
+    ///
+    /// The log records how long each rule took and a fingerprint of its dependencies' contents.
+    /// A target whose dependencies no longer match its fingerprint is rebuilt even if the
+    /// freshness checker thinks it is up to date, which catches dependencies being replaced by
+    /// older files. The recorded durations are available from `DepGraph::last_duration`.
+    pub fn build_log<P>(mut self, path: P) -> DepGraphBuilder<'a>
+    where
+        P: AsRef<Path>,
+    {
+        self.log_path = Some(path.as_ref().to_owned());
+        self
+    }
+
+    /// Skip files excluded by `.gitignore` and `.ignore` files when expanding glob patterns in
+    /// dependency lists.
+    ///
+    /// Only ignore files in the directories being searched are read, not those in their parents.
+    /// To ignore files in a directory dependency, depend on a glob like `dir/**/*` instead.
+    pub fn respect_ignore_files(mut self) -> DepGraphBuilder<'a> {
+        self.respect_ignore_files = true;
+        self
+    }
+
+    /// Treat `path` as newer than every other file, like `make -W`, so that everything depending
+    /// on it is rebuilt.
+    pub fn assume_new<P>(mut self, path: P) -> DepGraphBuilder<'a>
+    where
+        P: AsRef<Path>,
+    {
+        self.assume.new.insert(path.as_ref().to_owned());
+        self
+    }
+
+    /// Treat `path` as older than every other file, like `make -o`.
+    ///
+    /// If `path` is a target it is never rebuilt, and changes to its modification time don't
+    /// cause anything that depends on it to be rebuilt. Checkers that don't use modification
+    /// times (like `ContentHash`) still notice if its contents change.
+    pub fn assume_old<P>(mut self, path: P) -> DepGraphBuilder<'a>
+    where
+        P: AsRef<Path>,
+    {
+        self.assume.old.insert(path.as_ref().to_owned());
+        self
+    }
+
+    /// Give `target` a short name, which can be used in place of its path with `make_target`,
+    /// `explain` and the other `DepGraph` methods that take a target, and with the `depgraph`
+    /// command.
+    ///
+    /// Paths in the graph take precedence over aliases with the same name, and adding an alias
+    /// again replaces it. `build` returns `Error::UnknownTarget` if `target` isn't in the graph.
+    ///
+    /// ```no_run
+    /// # let out_dir = std::path::Path::new("out");
+    /// let builder = depgraph::DepGraphBuilder::new()
+    ///     .add_shell_rule(out_dir.join("bindings.rs"), &["api.h"], "bindgen {deps} -o {out}")
+    ///     .alias("bindings", out_dir.join("bindings.rs"));
+    /// ```
+    pub fn alias<P: AsRef<Path>>(mut self, name: &str, target: P) -> DepGraphBuilder<'a> {
+        self.aliases
+            .insert(name.to_owned(), target.as_ref().to_owned());
+        self
+    }
+
+    /// Make `make` (and the other methods that build the whole graph) build only `target` and what
+    /// it depends on, like the first target in a Makefile. `target` can be a path, an alias or a
+    /// group name.
+    ///
+    /// This is useful for leaving out targets that are only wanted sometimes, such as
+    /// documentation or debugging aids. They can still be built with `DepGraph::make_target`, and
+    /// everything with `DepGraph::make_all`. `build` returns `Error::UnknownTarget` if `target`
+    /// isn't in the graph.
+    pub fn default_target<P: AsRef<Path>>(mut self, target: P) -> DepGraphBuilder<'a> {
+        self.default_target = Some(target.as_ref().to_owned());
+        self
+    }
+
+    /// Add a pool named `name`, which lets at most `depth` of the rules in it (see
+    /// `RuleOptions::pool`) run at once, however many jobs the build has, like a ninja pool. This
+    /// is useful for tools that use a lot of memory, or that only have a few licences.
+    ///
+    /// Adding a pool again replaces it. `build` returns `Error::UnknownPool` if a rule is in a
+    /// pool that hasn't been added. The console pool (see `CONSOLE_POOL`) is always there.
+    ///
+    /// # Panics
+    ///
+    /// If `depth` is 0, or `name` is `CONSOLE_POOL`.
+    pub fn pool(mut self, name: &str, depth: usize) -> DepGraphBuilder<'a> {
+        assert!(depth > 0, "a pool must have a depth of at least 1");
+        assert!(name != CONSOLE_POOL, "the console pool can't be replaced");
+        self.pools.insert(name.to_owned(), depth);
+        self
+    }
+
     /// Build the make graph and check for errors like cyclic dependencies and duplicate files.
-    pub fn build(self) -> DepResult<DepGraph> {
+    pub fn build(self) -> DepResult<DepGraph<'a>> {
         // used to check a file isn't added more than once. (filename -> NodeId)
         let mut files = HashMap::new();
         // used between passes to store edges
-        let mut edges_after_node = Vec::with_capacity(self.edges.len());
+        let mut edges_after_node = Vec::with_capacity(self.rules.len());
         // the resulting graph
         let mut graph = Graph::new();
 
+        let instances =
+            pattern::instantiate(&self.pattern_rules, &self.rules, self.respect_ignore_files)?;
+        let mut rules = self.rules;
+        rules.extend(instances);
+        let moved = match self.out_dir {
+            Some(ref dir) => cargo::resolve_outputs(&mut rules, dir),
+            None => HashMap::new(),
+        };
+
+        // the position of the rule that added each node, for reporting duplicates
+        let mut positions = Vec::new();
+        let duplicate = |path: PathBuf, idx: &NodeIndex<u32>, positions: &[usize], second| {
+            Error::DuplicateFile {
+                path,
+                first: positions[idx.index()],
+                second,
+            }
+        };
+
         // Job of first iteration is to add nodes and save ids for them
-        for edge in self.edges.into_iter() {
-            let (filename, dependencies, build_fn) = edge;
+        for (position, rule) in rules.into_iter().enumerate() {
             // error if file already added
-            if files.contains_key(&filename) {
-                return Err(Error::DuplicateFile);
+            if let Some(idx) = files.get(&rule.filename) {
+                return Err(duplicate(rule.filename, idx, &positions, position));
+            }
+            if let Some(ref pool) = rule.options.pool {
+                if !self.pools.contains_key(pool) {
+                    return Err(Error::UnknownPool(pool.clone()));
+                }
             }
             // add node to graph and get index
+            let mut dependencies = glob::expand_all(rule.dependencies, self.respect_ignore_files)?;
+            let optional = rule.options.optional_deps.clone();
+            dependencies.extend(optional.iter().cloned());
             let idx = graph.add_node(DependencyNode {
-                filename: filename.clone(),
-                build_fn: Some(build_fn),
+                filename: rule.filename.clone(),
+                phony: rule.build_fn.is_none() && rule.target.is_none(),
+                build_fn: rule.build_fn,
+                options: rule.options,
+                optional: false,
+                extra_outputs: rule.extra_outputs.clone(),
+                extra_output: false,
+                command: rule.command,
+                target: rule.target,
             });
+            positions.push(position);
             // add file to list
-            files.insert(filename, idx);
-            edges_after_node.push((idx, dependencies));
+            files.insert(rule.filename, idx);
+            // extra outputs depend on the main one, so that they are "built" after it
+            for output in rule.extra_outputs {
+                if let Some(idx) = files.get(&output) {
+                    return Err(duplicate(output, idx, &positions, position));
+                }
+                let idx2 = graph.add_node(DependencyNode {
+                    filename: output.clone(),
+                    build_fn: None,
+                    options: RuleOptions::default(),
+                    phony: false,
+                    optional: false,
+                    extra_outputs: Vec::new(),
+                    extra_output: true,
+                    command: None,
+                    target: None,
+                });
+                positions.push(position);
+                files.insert(output, idx2);
+                graph.add_edge(idx2, idx, ());
+            }
+            edges_after_node.push((idx, dependencies, optional));
         }
 
         // Job of second iteration is to add in edges using `edges_after_node` and add in leaves
         // for files not found elsewhere
         for edge in edges_after_node.into_iter() {
-            let (idx, dependencies) = edge;
+            let (idx, dependencies, optional) = edge;
             for dep in dependencies.into_iter() {
+                let is_optional = optional.contains(&dep);
                 // value is just number so deref to copy it
-                let maybe_dep = files.get(&dep).map(|v| *v);
-                if let Some(idx2) = maybe_dep {
-                    // file already a dependency, so add directed edge from file to it's dependency
-                    graph.add_edge(idx, idx2, ());
+                let maybe_dep = files.get(&dep).copied();
+                let idx2 = if let Some(idx2) = maybe_dep {
+                    idx2
                 } else {
                     // file not yet a dependency - add it
                     let idx2 = graph.add_node(DependencyNode {
                         filename: dep.clone(),
                         build_fn: None,
+                        options: RuleOptions::default(),
+                        phony: false,
+                        optional: false,
+                        extra_outputs: Vec::new(),
+                        extra_output: false,
+                        command: None,
+                        target: None,
                     });
                     files.insert(dep, idx2);
-                    graph.add_edge(idx, idx2, ());
-                }
+                    idx2
+                };
+                graph[idx2].optional |= is_optional;
+                // add directed edge from file to it's dependency
+                graph.add_edge(idx, idx2, ());
             }
         }
 
-        if petgraph::algo::is_cyclic_directed(&graph) {
-            return Err(Error::Cycle);
+        if let Some(cycle) = find_cycle(&graph) {
+            return Err(Error::Cycle(cycle));
         }
 
-        Ok(DepGraph {
-            graph: graph,
-            //file_hash: files,
-        })
+        let mut aliases = HashMap::new();
+        for (name, target) in self.aliases {
+            let target = moved.get(&target).cloned().unwrap_or(target);
+            match files.get(&target) {
+                Some(idx) => aliases.insert(name, *idx),
+                None => return Err(Error::UnknownTarget(target)),
+            };
+        }
+
+        let default_target = match self.default_target {
+            Some(target) => {
+                let target = moved.get(&target).cloned().unwrap_or(target);
+                let idx = files
+                    .get(&target)
+                    .or_else(|| target.to_str().and_then(|name| aliases.get(name)));
+                match idx {
+                    Some(idx) => Some(*idx),
+                    None => return Err(Error::UnknownTarget(target)),
+                }
+            }
+            None => None,
+        };
+
+        let log = match self.log_path {
+            Some(path) => Some(Arc::new(BuildLog::open(path)?)),
+            None => None,
+        };
+
+        let mut depgraph = DepGraph {
+            graph,
+            freshness: self.freshness,
+            log,
+            file_hash: files,
+            aliases,
+            default_wanted: None,
+            assume: self.assume,
+            stamps: Mutex::default(),
+            pools: self.pools,
+        };
+        depgraph.default_wanted = default_target.map(|target| depgraph.reachable(&[target]));
+        Ok(depgraph)
     }
 }
 
 /// Contains the checked and parsed dependency graph, ready for execution (`fn make`)
-pub struct DepGraph {
+pub struct DepGraph<'a> {
     /// Node is file (weight is filename, build function), edge is dependency
-    graph: Graph<DependencyNode, ()>,
-    //file_hash: HashMap<String, NodeIndex<u32>>,
+    graph: Graph<DependencyNode<'a>, ()>,
+    /// Decides whether a rule needs rebuilding
+    freshness: Arc<dyn FreshnessChecker>,
+    /// The log of successful builds, if enabled
+    log: Option<Arc<BuildLog>>,
+    /// Lookup from file name to node
+    file_hash: HashMap<PathBuf, NodeIndex<u32>>,
+    /// Lookup from alias to node
+    aliases: HashMap<String, NodeIndex<u32>>,
+    /// What `make` builds, if not everything: the default target and what it depends on, indexed
+    /// by node
+    default_wanted: Option<Vec<bool>>,
+    /// Files to treat as newer or older than they are
+    assume: Assume,
+    /// The stamps of the dependencies each target that isn't a file was last built against
+    stamps: Mutex<HashMap<PathBuf, Vec<Option<SystemTime>>>>,
+    /// The depth of each pool
+    pools: HashMap<String, usize>,
+}
+
+/// (Internal) The files set with `DepGraphBuilder::assume_new` and `assume_old`.
+#[derive(Debug, Clone, Default)]
+struct Assume {
+    new: HashSet<PathBuf>,
+    old: HashSet<PathBuf>,
 }
 
 /// When running the build scripts, we can either only build when output files are newer than their
@@ -205,86 +1090,560 @@ pub enum MakeParams {
     None,
     /// Always build, regardless of status of source
     ForceBuild,
+    /// Build normally, but don't stop at the first failure (like `make -k`)
+    ///
+    /// Everything that doesn't depend on a failed target is still built, and then
+    /// `Error::BuildsFailed` is returned listing every failed and skipped target.
+    KeepGoing,
+    /// Don't build anything, but return `Error::OutOfDate` if anything would be rebuilt
+    ///
+    /// This is useful in CI, to check that committed generated files are up to date. See
+    /// `DepGraph::verify`.
+    CheckOnly,
+    /// Don't run any build functions, but mark stale targets as up to date (like `make -t`)
+    ///
+    /// Targets are touched (or created empty if missing) and recorded as built. This is useful
+    /// when the outputs are known to be correct but their modification times are not, for
+    /// example after restoring them from a cache.
+    Touch,
 }
 
-impl DepGraph {
-    /// Run the build
-    ///
-    /// If force is true, all build functions will be run, regardless of file times, otherwise
-    /// build will only be run if one of the dependency files is newer than the output file.
-    // There are possible optimizations here as there are redundent metadata checks, I don't think
-    // this is a big deal though.
-    pub fn make(&self, make_params: MakeParams) -> DepResult<()> {
-        // Get files in dependency order
-        // Needs to be reversed to build in right order
-        let ordered_deps_rev =
-            petgraph::algo::toposort(&self.graph, None).map_err(|_| Error::Cycle)?;
-        let force: bool = match make_params {
-            MakeParams::None => false,
+impl MakeParams {
+    /// Whether build functions should run regardless of file times.
+    fn force(self) -> bool {
+        match self {
+            MakeParams::None
+            | MakeParams::KeepGoing
+            | MakeParams::CheckOnly
+            | MakeParams::Touch => false,
             MakeParams::ForceBuild => true,
-        };
-        for node in ordered_deps_rev.iter().rev() {
-            self.build_dependency(*node, force)?;
         }
-        Ok(())
+    }
+
+    /// Whether to continue building unaffected targets after a failure.
+    fn keep_going(self) -> bool {
+        matches!(self, MakeParams::KeepGoing)
+    }
+
+    /// Whether to only check that everything is up to date, without building.
+    fn check_only(self) -> bool {
+        matches!(self, MakeParams::CheckOnly)
+    }
+
+    /// Whether to touch stale targets instead of building them.
+    fn touch(self) -> bool {
+        matches!(self, MakeParams::Touch)
+    }
+}
+
+impl<'a> DepGraph<'a> {
+    /// Run the build
+    ///
+    /// If force is true, all build functions will be run, regardless of file times, otherwise
+    /// build will only be run if one of the dependency files is newer than the output file.
+    ///
+    /// If the builder was given a default target, only it and what it depends on are built.
+    ///
+    /// With the `rayon` feature, rules whose dependencies are up to date are run concurrently on
+    /// rayon's thread pool, like `make_parallel`.
+    // There are possible optimizations here as there are redundent metadata checks, I don't think
+    // this is a big deal though.
+    pub fn make(&self, make_params: MakeParams) -> DepResult<()> {
+        self.make_with_observer(make_params, &())
+    }
+
+    /// Run the build, like `make`, notifying `observer` of progress.
+    pub fn make_with_observer(
+        &self,
+        make_params: MakeParams,
+        observer: &dyn BuildObserver,
+    ) -> DepResult<()> {
+        self.make_from(self.default_wanted.as_deref(), make_params, observer)
+    }
+
+    /// Build everything, like `make` without a default target (see
+    /// `DepGraphBuilder::default_target`).
+    pub fn make_all(&self, make_params: MakeParams) -> DepResult<()> {
+        self.make_from(None, make_params, &())
+    }
+
+    /// Run the build, like `make`, and drop the graph, along with everything its build functions
+    /// captured. Rules added with `DepGraphBuilder::add_rule_once` can only be run once, so this
+    /// makes sure the graph isn't made again.
+    pub fn make_once(self, make_params: MakeParams) -> DepResult<()> {
+        self.make(make_params)
+    }
+
+    /// Build `target` and everything it depends on, like `make`, leaving the rest of the graph
+    /// alone.
+    ///
+    /// Returns `Error::UnknownTarget` if `target` isn't in the graph.
+    pub fn make_target<P: AsRef<Path>>(&self, target: P, make_params: MakeParams) -> DepResult<()> {
+        let root = self.node(target.as_ref())?;
+        self.make_from(Some(&self.reachable(&[root])), make_params, &())
+    }
+
+    /// Build the targets for which `predicate` returns `true`, and everything they depend on, like
+    /// `make`, leaving the rest of the graph alone. For example, a predicate of
+    /// `|path| path.starts_with("out/shaders")` builds only the shaders.
+    ///
+    /// The predicate is given the path of every target with a build function. Phony targets (see
+    /// `DepGraphBuilder::group`) aren't matched, but their dependencies are.
+    pub fn make_where<F>(&self, make_params: MakeParams, predicate: F) -> DepResult<()>
+    where
+        F: Fn(&Path) -> bool,
+    {
+        let roots: Vec<_> = self
+            .graph
+            .node_indices()
+            .filter(|idx| {
+                let dep = &self.graph[*idx];
+                dep.build_fn.is_some() && predicate(&dep.filename)
+            })
+            .collect();
+        self.make_from(Some(&self.reachable(&roots)), make_params, &())
+    }
+
+    /// The nodes in dependency order, with targets before their dependencies, or
+    /// `Error::Cycle` if the graph isn't acyclic.
+    pub(crate) fn toposort(&self) -> DepResult<Vec<NodeIndex<u32>>> {
+        petgraph::algo::toposort(&self.graph, None)
+            .map_err(|_| Error::Cycle(find_cycle(&self.graph).unwrap_or_default()))
+    }
+
+    /// Run the build, of everything or only the nodes in `wanted`.
+    ///
+    /// With the `rayon` feature, independent rules are run on rayon's thread pool, as
+    /// `make_parallel` does with as many jobs as the pool has threads.
+    #[cfg(feature = "rayon")]
+    fn make_from(
+        &self,
+        wanted: Option<&[bool]>,
+        make_params: MakeParams,
+        observer: &dyn BuildObserver,
+    ) -> DepResult<()> {
+        self.make_parallel_from(wanted, make_params, rayon::current_num_threads(), observer)
+    }
+
+    /// Run the build, of everything or only the nodes in `wanted`.
+    #[cfg(not(feature = "rayon"))]
+    fn make_from(
+        &self,
+        wanted: Option<&[bool]>,
+        make_params: MakeParams,
+        observer: &dyn BuildObserver,
+    ) -> DepResult<()> {
+        // the toposort is only used to make sure the graph is still acyclic
+        self.toposort()?;
+        if make_params.check_only() {
+            return self.check_up_to_date(wanted);
+        }
+        if make_params.touch() {
+            return self.touch(wanted, observer);
+        }
+        let force = make_params.force();
+        observer.on_begin(self.rule_count(wanted));
+        let mut scheduler = self.scheduler(wanted, make_params.keep_going());
+        while let Some(idx) = scheduler.next() {
+            let result = self.build_dependency(idx, force, observer);
+            scheduler.finished(idx, result);
+        }
+        let result = scheduler.finish();
+        observer.on_end(&result);
+        result
+    }
+
+    /// A scheduler for everything, or only the nodes in `wanted`.
+    pub(crate) fn scheduler(&self, wanted: Option<&[bool]>, keep_going: bool) -> Scheduler<'_> {
+        let scheduler = Scheduler::new(&self.graph, keep_going).pools(&self.pools);
+        match wanted {
+            Some(wanted) => scheduler.only(wanted.to_vec()),
+            None => scheduler,
+        }
+    }
+
+    /// The number of targets that have build functions, of everything or only the nodes in
+    /// `wanted`.
+    fn rule_count(&self, wanted: Option<&[bool]>) -> usize {
+        self.graph
+            .node_indices()
+            .filter(|idx| wanted.is_none_or(|wanted| wanted[idx.index()]))
+            .filter(|idx| self.graph[*idx].build_fn.is_some())
+            .count()
+    }
+
+    /// Helper function to build a specific dependency, reporting any failure to `observer`
+    fn build_dependency(
+        &self,
+        idx: NodeIndex<u32>,
+        force: bool,
+        observer: &dyn BuildObserver,
+    ) -> DepResult<()> {
+        let dep = self.graph.node_weight(idx).unwrap();
+        let result = self.build_dependency_inner(idx, force, observer);
+        if let (Some(_), Err(e)) = (&dep.build_fn, &result) {
+            observer.on_failed(&dep.filename, e);
+        }
+        result
     }
 
     /// Helper function to build a specific dependency
-    fn build_dependency(&self, idx: NodeIndex<u32>, force: bool) -> DepResult<()> {
+    fn build_dependency_inner(
+        &self,
+        idx: NodeIndex<u32>,
+        force: bool,
+        observer: &dyn BuildObserver,
+    ) -> DepResult<()> {
         let dep = self.graph.node_weight(idx).unwrap();
-        // collect names of children (don't copy strings)
-        let children: Vec<&Path> = self
+        let children = self.dependency_paths(idx)?;
+        // if there is a build script, and dependencies have changed, run it
+        if let Some(ref f) = dep.build_fn {
+            if let Some(reason) = self.rebuild_reason(dep, &children, force)? {
+                let snapshot = restat::Snapshot::take(dep)?;
+                observer.on_start(&dep.filename);
+                let start = Instant::now();
+                {
+                    #[cfg(feature = "tracing")]
+                    let _span = rule_span(dep, &children, &reason).entered();
+                    #[cfg(not(feature = "tracing"))]
+                    let _ = reason;
+                    let mut attempt = 0;
+                    while let Err(e) = f.run(&dep.filename, &children, dep.options.timeout) {
+                        if attempt >= dep.options.retries {
+                            return Err(e);
+                        }
+                        thread::sleep(dep.options.delay_before_retry(attempt));
+                        attempt += 1;
+                    }
+                }
+                dep.check_built()?;
+                snapshot.restore_unchanged()?;
+                let duration = start.elapsed();
+                self.record_built(dep, &children, duration)?;
+                observer.on_finished(&dep.filename, duration);
+            } else {
+                observer.on_skipped(&dep.filename);
+            }
+        }
+        dep.check_exists()
+    }
+
+    /// Why `dep`'s build function should run, or `None` if it is up to date. Forced builds and
+    /// `RuleOptions::always_rebuild` don't check whether it is stale.
+    fn rebuild_reason(
+        &self,
+        dep: &DependencyNode,
+        deps: &[&Path],
+        force: bool,
+    ) -> DepResult<Option<RebuildReason>> {
+        if force {
+            Ok(Some(RebuildReason::Forced))
+        } else if dep.options.always_rebuild {
+            Ok(Some(RebuildReason::AlwaysRebuild))
+        } else {
+            self.stale_reason(dep, deps)
+        }
+    }
+
+    /// Why `dep` needs rebuilding, according to the freshness checker and the build log, or
+    /// `None` if it doesn't.
+    ///
+    /// A rule with several outputs is stale if any of them is.
+    fn stale_reason(
+        &self,
+        dep: &DependencyNode,
+        deps: &[&Path],
+    ) -> DepResult<Option<RebuildReason>> {
+        if self.uses_stamps(dep, deps) {
+            return self.stamp_stale_reason(dep, deps);
+        }
+        let discovered = self.discovered_deps(dep, deps)?;
+        if let Some(missing) = discovered.iter().find(|path| !path.exists()) {
+            return Ok(Some(RebuildReason::DepMissing(missing.clone())));
+        }
+        let deps = with_discovered(deps, &discovered);
+        for output in dep.outputs() {
+            match self.output_stale_reason(dep, output, &deps)? {
+                // a restat output keeps its old time when rebuilt without changes, so it is older
+                // than its dependencies even when up to date
+                Some(RebuildReason::DepNewer(_))
+                    if dep.options.restat && self.deps_unchanged(dep, &deps)? => {}
+                Some(reason) => return Ok(Some(reason)),
+                None => (),
+            }
+        }
+        if let Some(ref log) = self.log {
+            if let Some(entry) = log.get(&dep.filename) {
+                if entry.rule != log::rule_fingerprint(&dep.options) {
+                    return Ok(Some(RebuildReason::RuleChanged));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// The freshness checker for `dep`: its own, or the graph's.
+    fn checker<'s>(&'s self, dep: &'s DependencyNode) -> &'s dyn FreshnessChecker {
+        match dep.options.freshness_checker {
+            Some(ref checker) => &**checker,
+            None => &*self.freshness,
+        }
+    }
+
+    /// Whether the single file `filename` needs rebuilding, and why.
+    fn output_stale_reason(
+        &self,
+        dep: &DependencyNode,
+        filename: &Path,
+        deps: &[&Path],
+    ) -> DepResult<Option<RebuildReason>> {
+        if self.assume.old.contains(filename) {
+            return Ok(None);
+        }
+        if let Some(dep) = deps.iter().find(|dep| self.assume.new.contains(**dep)) {
+            return Ok(Some(RebuildReason::DepAssumedNew(dep.to_path_buf())));
+        }
+        let checker = self.checker(dep);
+        let mut reason = checker.explain(filename, deps)?;
+        if let Some(RebuildReason::DepNewer(ref dep)) = reason {
+            if self.assume.old.contains(dep) {
+                // ask again as if the files assumed old weren't there
+                let deps: Vec<_> = deps
+                    .iter()
+                    .copied()
+                    .filter(|dep| !self.assume.old.contains(*dep))
+                    .collect();
+                reason = checker.explain(filename, &deps)?;
+            }
+        }
+        if reason.is_some() {
+            return Ok(reason);
+        }
+        if let Some(ref log) = self.log {
+            if let Some(entry) = log.get(filename) {
+                if !entry.deps_unchanged(deps)? {
+                    return Ok(Some(RebuildReason::FingerprintChanged));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Whether the contents of `deps` match those recorded in the build log for the last build of
+    /// `dep`. Always false if there is no build log.
+    fn deps_unchanged(&self, dep: &DependencyNode, deps: &[&Path]) -> DepResult<bool> {
+        match self.log.as_ref().and_then(|log| log.get(&dep.filename)) {
+            Some(entry) => Ok(entry.deps_unchanged(deps)?),
+            None => Ok(false),
+        }
+    }
+
+    /// Called after `dep` has been built successfully.
+    ///
+    /// Directory outputs have their modification time updated, as it doesn't change when the
+    /// files inside are rewritten.
+    fn record_built(
+        &self,
+        dep: &DependencyNode,
+        deps: &[&Path],
+        duration: Duration,
+    ) -> DepResult<()> {
+        if self.uses_stamps(dep, deps) {
+            return self.record_stamps(dep, deps);
+        }
+        let discovered = self.discovered_deps(dep, deps)?;
+        let deps = &with_discovered(deps, &discovered)[..];
+        for output in dep.outputs() {
+            if output.is_dir() {
+                touch::touch(output)?;
+            }
+            self.checker(dep).built(output, deps)?;
+        }
+        if let Some(ref log) = self.log {
+            let entry = LogEntry {
+                duration,
+                mtimes: log::mtime_fingerprint(deps)?,
+                fingerprint: log::fingerprint(deps)?,
+                rule: log::rule_fingerprint(&dep.options),
+            };
+            log.record(&dep.filename, entry)?;
+        }
+        Ok(())
+    }
+
+    /// The dependencies listed in `dep`'s depfile, and those found by its scanner in `deps`.
+    fn discovered_deps(&self, dep: &DependencyNode, deps: &[&Path]) -> DepResult<Vec<PathBuf>> {
+        let mut discovered = match dep.options.depfile {
+            Some(ref depfile) => depfile::read(depfile)?,
+            None => Vec::new(),
+        };
+        if let Some(ref scanner) = dep.options.scanner {
+            for dep in deps {
+                if dep.is_file() {
+                    discovered.extend(scanner.scan(dep)?);
+                }
+            }
+        }
+        Ok(discovered)
+    }
+
+    /// How long `target` took to build the last time it was built, according to the build log.
+    ///
+    /// Returns `None` if there is no build log (see `DepGraphBuilder::build_log`) or the target
+    /// hasn't been built since the log was started.
+    pub fn last_duration<P: AsRef<Path>>(&self, target: P) -> Option<Duration> {
+        self.log
+            .as_ref()
+            .and_then(|log| log.get(target.as_ref()))
+            .map(|entry| entry.duration)
+    }
+
+    /// Find the node for `filename`, which may be an alias, or return `Error::UnknownTarget`.
+    fn node(&self, filename: &Path) -> DepResult<NodeIndex<u32>> {
+        self.file_hash
+            .get(filename)
+            .or_else(|| filename.to_str().and_then(|name| self.aliases.get(name)))
+            .copied()
+            .ok_or_else(|| Error::UnknownTarget(filename.to_owned()))
+    }
+
+    /// Which nodes are one of `roots` or something they depend on, indexed by node.
+    pub(crate) fn reachable(&self, roots: &[NodeIndex<u32>]) -> Vec<bool> {
+        let mut wanted = vec![false; self.graph.node_count()];
+        let mut dfs = petgraph::visit::Dfs::empty(&self.graph);
+        for &root in roots {
+            dfs.move_to(root);
+            while let Some(idx) = dfs.next(&self.graph) {
+                wanted[idx.index()] = true;
+            }
+        }
+        wanted
+    }
+
+    /// The direct dependencies of a node, in the order they were given to the builder.
+    pub(crate) fn children(&self, idx: NodeIndex<u32>) -> Vec<NodeIndex<u32>> {
+        // petgraph iterates over edges from the most recently added
+        let mut children: Vec<_> = self
             .graph
             .neighbors_directed(idx, petgraph::Outgoing)
-            .map(|idx| self.graph.node_weight(idx).unwrap().filename.as_path())
             .collect();
+        children.reverse();
+        children
+    }
+
+    /// Collect the paths of a node's dependencies, checking that they all exist.
+    fn dependency_paths(&self, idx: NodeIndex<u32>) -> DepResult<Vec<&Path>> {
+        let children = self.dependency_files(idx);
         for child in children.iter() {
-            if !Path::new(child).exists() {
-                return Err(Error::MissingFile((*child).to_owned()));
+            match self.file_hash.get(*child).map(|&idx| &self.graph[idx]) {
+                Some(node) if node.custom_exists() => node.check_exists()?,
+                _ => check_exists(child)?,
             }
         }
-        // if there is a build script, and dependency timestamps are newer, run it
-        if let Some(ref f) = dep.build_fn {
-            if force || dependencies_newer(&dep.filename, &children) {
-                f(&dep.filename, &children).map_err(|s| Error::BuildFailed(s))?;
+        Ok(children)
+    }
+
+    /// The `Target` for `path`, if it is a node that isn't a file.
+    fn target(&self, path: &Path) -> Option<&dyn Target> {
+        let idx = self.file_hash.get(path)?;
+        self.graph[*idx].target.as_deref()
+    }
+
+    /// Collect the paths of a node's dependencies, replacing phony targets with their
+    /// dependencies and leaving out missing optional dependencies.
+    fn dependency_files(&self, idx: NodeIndex<u32>) -> Vec<&Path> {
+        let mut children = Vec::new();
+        self.push_dependency_files(idx, &mut children);
+        children
+    }
+
+    fn push_dependency_files<'s>(&'s self, idx: NodeIndex<u32>, out: &mut Vec<&'s Path>) {
+        let optional = &self.graph[idx].options.optional_deps;
+        for child in self.children(idx) {
+            let dep = &self.graph[child];
+            if dep.phony {
+                self.push_dependency_files(child, out);
+            } else if optional.contains(&dep.filename) && !dep.filename.exists() {
+                continue;
+            } else if !out.contains(&dep.filename.as_path()) {
+                out.push(&dep.filename);
             }
         }
-        // check that file has been created
-        if Path::new(&dep.filename).exists() {
-            Ok(())
-        } else {
-            Err(Error::MissingFile(dep.filename.clone()))
-        }
     }
 
-    /// Get the underlying graph
+    /// Get the underlying graph, with each node's file name as its weight, and the lookup from
+    /// file name to node.
+    ///
+    /// There is an edge from each target to each of its dependencies.
     #[cfg(feature = "petgraph_visible")]
-    pub fn into_inner(self) -> (Graph<DependencyNode, ()>, HashMap<String, NodeIndex<u32>>) {
-        (self.graph, self.file_hash)
+    pub fn into_inner(self) -> (Graph<PathBuf, ()>, HashMap<PathBuf, NodeIndex<u32>>) {
+        let graph = self.graph.map(|_, node| node.filename.clone(), |_, _| ());
+        (graph, self.file_hash)
     }
 }
 
-/// Checks if any of the files in the dependency list are newer than the file given by `filename`.
-fn dependencies_newer(filename: &Path, deps: &[&Path]) -> bool {
-    if !filename.exists() {
-        return true;
+/// A cycle in `graph`, as the files in it, each depending on the next, if there is one.
+fn find_cycle(graph: &Graph<DependencyNode<'_>, ()>) -> Option<Vec<PathBuf>> {
+    for component in petgraph::algo::tarjan_scc(graph) {
+        // start from the rule that was added first
+        let start = *component.iter().min().unwrap();
+        if component.len() == 1 && !graph.contains_edge(start, start) {
+            continue;
+        }
+        // find the shortest way back to `start`, staying in the component
+        let component: HashSet<_> = component.into_iter().collect();
+        let mut parents = HashMap::new();
+        let mut queue = VecDeque::from([start]);
+        while let Some(idx) = queue.pop_front() {
+            for next in graph.neighbors(idx) {
+                if next == start {
+                    let mut cycle = vec![graph[idx].filename.clone()];
+                    let mut idx = idx;
+                    while idx != start {
+                        idx = parents[&idx];
+                        cycle.push(graph[idx].filename.clone());
+                    }
+                    cycle.reverse();
+                    return Some(cycle);
+                }
+                if component.contains(&next) && !parents.contains_key(&next) {
+                    parents.insert(next, idx);
+                    queue.push_back(next);
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Checks that a file that should either be present or have been built exists.
+fn check_exists(filename: &Path) -> DepResult<()> {
+    if filename.exists() {
+        Ok(())
+    } else {
+        Err(Error::MissingFile(filename.to_owned()))
     }
-    let file_mod_time = fs::metadata(filename).unwrap().modified().unwrap();
-    for dep in deps {
-        let dep_mod_time = fs::metadata(Path::new(dep)).unwrap().modified().unwrap();
-        if dep_mod_time > file_mod_time {
-            return true;
+}
+
+/// `deps` followed by those of `discovered` that aren't already in it.
+fn with_discovered<'a>(deps: &[&'a Path], discovered: &'a [PathBuf]) -> Vec<&'a Path> {
+    let mut all = deps.to_vec();
+    for path in discovered {
+        if !all.contains(&path.as_path()) {
+            all.push(path);
         }
     }
-    false
+    all
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::fs;
     use std::fs::File;
     use std::io;
     use std::io::{Read, Write};
+    use std::time::SystemTime;
     use tempdir::TempDir;
 
     fn copy_build(fname: &Path, deps: &[&Path]) -> Result<(), String> {
@@ -319,13 +1678,3706 @@ mod tests {
             .unwrap();
         {
             let mut file3 = File::create(tmp.join("file3")).unwrap();
-            write!(&mut file3, "file3\n").unwrap();
+            writeln!(&mut file3, "file3").unwrap();
 
             let mut file5 = File::create(tmp.join("file5")).unwrap();
-            write!(&mut file5, "file5\n").unwrap();
+            writeln!(&mut file5, "file5").unwrap();
             let mut file6 = File::create(tmp.join("file6")).unwrap();
-            write!(&mut file6, "file6\n").unwrap();
+            writeln!(&mut file6, "file6").unwrap();
+        }
+        makegraph.make(MakeParams::None).unwrap();
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_build() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let mut builder = DepGraphBuilder::new();
+        for i in 0..8 {
+            builder = builder.add_rule(
+                tmp.join(format!("out{}", i)),
+                &[tmp.join("src")],
+                copy_build,
+            );
+        }
+        let outs: Vec<PathBuf> = (0..8).map(|i| tmp.join(format!("out{}", i))).collect();
+        let makegraph = builder
+            .add_rule(tmp.join("all"), &outs, copy_build)
+            .build()
+            .unwrap();
+        {
+            let mut src = File::create(tmp.join("src")).unwrap();
+            writeln!(&mut src, "src").unwrap();
+        }
+        makegraph.make_parallel(MakeParams::None, 4).unwrap();
+        let mut all = String::new();
+        File::open(tmp.join("all"))
+            .unwrap()
+            .read_to_string(&mut all)
+            .unwrap();
+        assert_eq!(all, "src\n".repeat(8));
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_build_panics() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let makegraph = DepGraphBuilder::new()
+            .add_rule(tmp.join("a"), &[] as &[&Path], |_, _| panic!("oops"))
+            .add_rule(tmp.join("b"), &[] as &[&Path], |out, _| {
+                fs::write(out, "b").map_err(|e| e.to_string())
+            })
+            .build()
+            .unwrap();
+        // the panic is resumed on this thread, rather than the build waiting forever
+        let panic = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            makegraph.make_parallel(MakeParams::None, 2)
+        }))
+        .unwrap_err();
+        assert_eq!(panic.downcast_ref::<&str>(), Some(&"oops"));
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn rayon_make() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let on_pool = |out: &Path, _: &[&Path]| {
+            let index = rayon::current_thread_index().ok_or("not on the pool")?;
+            fs::write(out, index.to_string()).map_err(|e| e.to_string())
+        };
+        let makegraph = DepGraphBuilder::new()
+            .add_rule(tmp.join("a"), &[] as &[&Path], on_pool)
+            .add_rule(tmp.join("b"), &[] as &[&Path], on_pool)
+            .build()
+            .unwrap();
+        // `make` uses the pool it is called on, even when its thread is the pool's only one
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap();
+        pool.install(|| makegraph.make(MakeParams::None)).unwrap();
+        assert_eq!(fs::read_to_string(tmp.join("a")).unwrap(), "0");
+        assert_eq!(fs::read_to_string(tmp.join("b")).unwrap(), "0");
+        pool.install(|| makegraph.make_parallel(MakeParams::ForceBuild, 2))
+            .unwrap();
+    }
+
+    #[test]
+    fn content_hashes() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let builds = Arc::new(AtomicUsize::new(0));
+        let counter = builds.clone();
+        let makegraph = DepGraphBuilder::new()
+            .add_rule(tmp.join("out"), &[tmp.join("in")], move |out, deps| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                copy_build(out, deps)
+            })
+            .content_hashes(tmp.join("hashes"))
+            .build()
+            .unwrap();
+        fs::write(tmp.join("in"), "one").unwrap();
+        makegraph.make(MakeParams::None).unwrap();
+        assert_eq!(builds.load(Ordering::SeqCst), 1);
+        // rewriting the same contents doesn't trigger a rebuild
+        fs::write(tmp.join("in"), "one").unwrap();
+        makegraph.make(MakeParams::None).unwrap();
+        assert_eq!(builds.load(Ordering::SeqCst), 1);
+        fs::write(tmp.join("in"), "two").unwrap();
+        makegraph.make(MakeParams::None).unwrap();
+        assert_eq!(builds.load(Ordering::SeqCst), 2);
+        assert_eq!(fs::read_to_string(tmp.join("out")).unwrap(), "two");
+
+        let database = fs::read_to_string(tmp.join("hashes")).unwrap();
+        let hash = database.trim_end().rsplit('\t').next().unwrap();
+        #[cfg(feature = "blake3")]
+        assert_eq!(hash, blake3::hash(b"two").to_hex().as_str());
+        #[cfg(not(feature = "blake3"))]
+        assert_eq!(hash.len(), 16);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn content_hashes_odd_paths() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let input = tmp.join(OsStr::from_bytes(b"in\tput\n\\x\xff"));
+        let output = tmp.join("out\ttab\nline");
+        fs::write(&input, "one").unwrap();
+        let builder = || {
+            DepGraphBuilder::new()
+                .add_rule(&output, &[&input], copy_build)
+                .content_hashes(tmp.join("hashes"))
+                .build()
+                .unwrap()
+        };
+        builder().make(MakeParams::None).unwrap();
+        // a new graph has to read the hashes back from the database
+        assert!(builder().is_up_to_date(&output).unwrap());
+        fs::write(&input, "two").unwrap();
+        assert_eq!(
+            builder().explain(&output, MakeParams::None).unwrap(),
+            RebuildReason::DepChanged(input.clone())
+        );
+    }
+
+    #[test]
+    fn keep_going() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let makegraph = DepGraphBuilder::new()
+            .add_rule(tmp.join("file1"), &[tmp.join("file2")], copy_build)
+            .add_rule(
+                tmp.join("file2"),
+                &[tmp.join("file3")],
+                |_: &Path, _: &[&Path]| Err("oops".to_owned()),
+            )
+            .add_rule(tmp.join("file4"), &[tmp.join("file3")], copy_build)
+            .build()
+            .unwrap();
+        fs::write(tmp.join("file3"), "file3").unwrap();
+        match makegraph.make(MakeParams::KeepGoing) {
+            Err(Error::BuildsFailed { failed, skipped }) => {
+                assert_eq!(failed.len(), 1);
+                assert_eq!(failed[0].0, tmp.join("file2"));
+                assert_eq!(skipped, vec![tmp.join("file1")]);
+            }
+            other => panic!("unexpected result {:?}", other),
         }
+        assert!(tmp.join("file4").exists());
+    }
+
+    #[test]
+    fn retries() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let attempts = AtomicUsize::new(0);
+        let flaky = move |out: &Path, deps: &[&Path]| {
+            if attempts.fetch_add(1, Ordering::SeqCst) < 3 {
+                Err("transient failure".to_owned())
+            } else {
+                copy_build(out, deps)
+            }
+        };
+        let opts = RuleOptions {
+            retries: 2,
+            ..Default::default()
+        };
+        let makegraph = DepGraphBuilder::new()
+            .add_rule_with_opts(tmp.join("file1"), &[tmp.join("file2")], opts, flaky)
+            .build()
+            .unwrap();
+        fs::write(tmp.join("file2"), "file2").unwrap();
+        // fails three times, but only two retries are allowed
+        assert!(makegraph.make(MakeParams::None).is_err());
         makegraph.make(MakeParams::None).unwrap();
+        assert!(tmp.join("file1").exists());
+    }
+
+    #[test]
+    fn timeout() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let opts = RuleOptions {
+            timeout: Some(Duration::from_millis(50)),
+            ..Default::default()
+        };
+        let makegraph = DepGraphBuilder::new()
+            .add_rule_with_opts(tmp.join("file1"), &[tmp.join("file2")], opts, |_, _| {
+                thread::sleep(Duration::from_secs(1));
+                Ok(())
+            })
+            .build()
+            .unwrap();
+        fs::write(tmp.join("file2"), "file2").unwrap();
+        match makegraph.make(MakeParams::None) {
+            Err(Error::Timeout(path)) => assert_eq!(path, tmp.join("file1")),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn observer() {
+        use std::sync::Mutex;
+
+        #[derive(Default)]
+        struct Recorder(Mutex<Vec<String>>);
+        impl BuildObserver for Recorder {
+            fn on_begin(&self, total: usize) {
+                self.0.lock().unwrap().push(format!("begin {}", total));
+            }
+            fn on_start(&self, target: &Path) {
+                let name = target.file_name().unwrap().to_string_lossy();
+                self.0.lock().unwrap().push(format!("start {}", name));
+            }
+            fn on_skipped(&self, target: &Path) {
+                let name = target.file_name().unwrap().to_string_lossy();
+                self.0.lock().unwrap().push(format!("skipped {}", name));
+            }
+            fn on_finished(&self, target: &Path, _: Duration) {
+                let name = target.file_name().unwrap().to_string_lossy();
+                self.0.lock().unwrap().push(format!("finished {}", name));
+            }
+        }
+
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let makegraph = DepGraphBuilder::new()
+            .add_rule(tmp.join("file1"), &[tmp.join("file2")], copy_build)
+            .build()
+            .unwrap();
+        fs::write(tmp.join("file2"), "file2").unwrap();
+        let recorder = Recorder::default();
+        makegraph
+            .make_with_observer(MakeParams::None, &recorder)
+            .unwrap();
+        makegraph
+            .make_with_observer(MakeParams::None, &recorder)
+            .unwrap();
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            vec![
+                "begin 1",
+                "start file1",
+                "finished file1",
+                "begin 1",
+                "skipped file1"
+            ]
+        );
+    }
+
+    #[test]
+    fn events() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let makegraph = DepGraphBuilder::new()
+            .add_rule(tmp.join("file1"), &[tmp.join("file2")], copy_build)
+            .add_rule(
+                tmp.join("file3"),
+                &[tmp.join("file2")],
+                |_: &Path, _: &[&Path]| Err("oops".to_owned()),
+            )
+            .build()
+            .unwrap();
+        fs::write(tmp.join("file2"), "file2").unwrap();
+        let (tx, rx) = std::sync::mpsc::channel();
+        assert!(makegraph
+            .make_with_events(MakeParams::KeepGoing, tx)
+            .is_err());
+        let events: Vec<BuildEvent> = rx.iter().collect();
+        assert_eq!(events.len(), 4);
+        assert!(events.iter().any(|e| matches!(
+            e,
+            BuildEvent::Succeeded { target, .. } if *target == tmp.join("file1")
+        )));
+        assert!(events.iter().any(|e| matches!(
+            e,
+            BuildEvent::Failed { target, .. } if *target == tmp.join("file3")
+        )));
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn tracing_spans() {
+        use std::fmt::Debug;
+        use tracing::field::{Field, Visit};
+        use tracing::span::{Attributes, Id, Record};
+        use tracing::{Event, Metadata};
+
+        /// Records the fields of each span created.
+        #[derive(Default)]
+        struct Spans(Arc<Mutex<Vec<String>>>);
+        struct Fields(String);
+        impl Visit for Fields {
+            fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+                self.0.push_str(&format!(" {}={:?}", field.name(), value));
+            }
+        }
+        impl tracing::Subscriber for Spans {
+            fn enabled(&self, _: &Metadata<'_>) -> bool {
+                true
+            }
+            fn new_span(&self, span: &Attributes<'_>) -> Id {
+                let mut fields = Fields(span.metadata().name().to_owned());
+                span.record(&mut fields);
+                let mut spans = self.0.lock().unwrap();
+                spans.push(fields.0);
+                Id::from_u64(spans.len() as u64)
+            }
+            fn record(&self, _: &Id, _: &Record<'_>) {}
+            fn record_follows_from(&self, _: &Id, _: &Id) {}
+            fn event(&self, _: &Event<'_>) {}
+            fn enter(&self, _: &Id) {}
+            fn exit(&self, _: &Id) {}
+        }
+
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let makegraph = DepGraphBuilder::new()
+            .add_rule(tmp.join("file1"), &[tmp.join("file2")], copy_build)
+            .build()
+            .unwrap();
+        fs::write(tmp.join("file2"), "file2").unwrap();
+        let spans = Spans::default();
+        let recorded = spans.0.clone();
+        tracing::subscriber::with_default(spans, || {
+            makegraph.make(MakeParams::None).unwrap();
+        });
+        assert_eq!(
+            *recorded.lock().unwrap(),
+            vec![format!(
+                "rule target={} deps=1 reason=MissingOutput",
+                tmp.join("file1").display()
+            )]
+        );
+    }
+
+    #[cfg(feature = "progress")]
+    #[test]
+    fn progress_failed_dependency() {
+        /// A buffer the progress bar can write to while the test keeps hold of it.
+        #[derive(Clone, Default)]
+        struct Shared(Arc<Mutex<Vec<u8>>>);
+        impl Write for Shared {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let makegraph = DepGraphBuilder::new()
+            .add_rule(tmp.join("file1"), &[tmp.join("file2")], copy_build)
+            .add_rule(tmp.join("file2"), &[] as &[&Path], |_, _| {
+                Err("oops".to_owned())
+            })
+            .build()
+            .unwrap();
+        let out = Shared::default();
+        let progress = ProgressBar::to_writer(out.clone());
+        assert!(makegraph
+            .make_with_observer(MakeParams::None, &progress)
+            .is_err());
+        let out = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        assert!(out.trim_end().ends_with("] 1/2 failed"), "{:?}", out);
+    }
+
+    #[test]
+    #[cfg(feature = "petgraph_visible")]
+    fn into_inner() {
+        let makegraph = DepGraphBuilder::new()
+            .add_rule("out.o", &["in.asm"], copy_build)
+            .build()
+            .unwrap();
+        let (graph, lookup) = makegraph.into_inner();
+        let out = lookup[Path::new("out.o")];
+        let input = lookup[Path::new("in.asm")];
+        assert_eq!(graph[out], Path::new("out.o"));
+        assert!(graph.contains_edge(out, input));
+    }
+
+    #[test]
+    fn dot_export() {
+        let makegraph = DepGraphBuilder::new()
+            .add_rule("out.o", &["in \"quoted\".asm"], copy_build)
+            .build()
+            .unwrap();
+        let dot = makegraph.to_dot();
+        assert!(dot.starts_with("digraph depgraph {\n"));
+        assert!(dot.contains("n0 [label=\"out.o\", shape=ellipse];"));
+        assert!(dot.contains("n1 [label=\"in \\\"quoted\\\".asm\", shape=box];"));
+        assert!(dot.contains("n0 -> n1;"));
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn json_export_round_trip() {
+        let makegraph = DepGraphBuilder::new()
+            .add_rule("out.o", &["in.asm"], copy_build)
+            .build()
+            .unwrap();
+        let export: GraphExport = serde_json::from_str(&makegraph.to_json().unwrap()).unwrap();
+        assert_eq!(export, makegraph.export());
+        assert_eq!(export.nodes[1].path, Path::new("in.asm"));
+        assert!(!export.nodes[1].has_build_fn);
+        assert_eq!(export.edges, vec![ExportedEdge { from: 0, to: 1 }]);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+            let makegraph = DepGraphBuilder::new()
+                .add_rule(
+                    std::ffi::OsStr::from_bytes(b"out\xff.o"),
+                    &["in.asm"],
+                    copy_build,
+                )
+                .build()
+                .unwrap();
+            assert!(makegraph.to_json().is_err());
+        }
+    }
+
+    #[test]
+    fn graphml_export() {
+        let makegraph = DepGraphBuilder::new()
+            .add_rule("out.o", &["a&b.asm"], copy_build)
+            .build()
+            .unwrap();
+        let mut out = Vec::new();
+        makegraph.write_graphml(&mut out).unwrap();
+        let graphml = String::from_utf8(out).unwrap();
+        assert!(graphml.contains(r#"<data key="path">a&amp;b.asm</data>"#));
+        assert!(graphml.contains(r#"<edge source="n0" target="n1"/>"#));
+        assert!(graphml.ends_with("</graphml>\n"));
+    }
+
+    #[test]
+    fn mermaid_export() {
+        let makegraph = DepGraphBuilder::new()
+            .add_rule("out.o", &["in.asm"], copy_build)
+            .build()
+            .unwrap();
+        assert_eq!(
+            makegraph.to_mermaid(),
+            "graph TD\n    n0[\"out.o\"]\n    n1([\"in.asm\"])\n    n0 --> n1\n"
+        );
+    }
+
+    #[test]
+    fn tree() {
+        let makegraph = DepGraphBuilder::new()
+            .add_rule("a", &["b", "c"], copy_build)
+            .add_rule("b", &["c"], copy_build)
+            .build()
+            .unwrap();
+        let mut out = Vec::new();
+        makegraph.write_tree("a", &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "a\n├── b\n│   └── c\n└── c (*)\n"
+        );
+        assert!(matches!(
+            makegraph.write_tree("d", Vec::new()),
+            Err(Error::UnknownTarget(_))
+        ));
+    }
+
+    #[test]
+    fn cargo_rerun_if_changed() {
+        let makegraph = DepGraphBuilder::new()
+            .add_rule("a", &["b", "c"], copy_build)
+            .add_rule_with_opts(
+                "b",
+                &["c"],
+                RuleOptions {
+                    optional_deps: vec![PathBuf::from("missing-optional")],
+                    ..Default::default()
+                },
+                copy_build,
+            )
+            .add_phony_rule("all", &["a"])
+            .build()
+            .unwrap();
+        let mut out = Vec::new();
+        makegraph.write_cargo_rerun_if_changed(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "cargo:rerun-if-changed=c\n"
+        );
+    }
+
+    #[test]
+    fn env_deps() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        // this isn't set, and the tests don't set it: changing the environment would race with
+        // other tests reading it
+        let var = "DEPGRAPH_TEST_ENV_DEPS";
+        let options = RuleOptions {
+            env_deps: vec![var.to_owned()],
+            ..Default::default()
+        };
+        let with_value =
+            |value: Option<&str>| log::rule_fingerprint_with(&options, |_| value.map(Into::into));
+        assert_ne!(with_value(Some("one")), with_value(Some("two")));
+        assert_ne!(with_value(Some("")), with_value(None));
+        assert_eq!(with_value(None), log::rule_fingerprint(&options));
+
+        let makegraph = DepGraphBuilder::new()
+            .add_rule_with_opts(tmp.join("out"), &[tmp.join("in")], options, copy_build)
+            .build_log(tmp.join("log"))
+            .build()
+            .unwrap();
+        fs::write(tmp.join("in"), "in").unwrap();
+        makegraph.make(MakeParams::None).unwrap();
+        assert!(makegraph.is_up_to_date(tmp.join("out")).unwrap());
+
+        let mut out = Vec::new();
+        makegraph.write_cargo_rerun_if_changed(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!(
+                "cargo:rerun-if-changed={}\ncargo:rerun-if-env-changed={}\n",
+                tmp.join("in").display(),
+                var
+            )
+        );
+    }
+
+    #[test]
+    fn out_dir() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let mut vars: HashMap<String, String> = [
+            ("OUT_DIR", tmp.to_str().unwrap()),
+            ("TARGET", "x86_64-unknown-linux-gnu"),
+            ("HOST", "x86_64-unknown-linux-gnu"),
+        ]
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect();
+        assert!(matches!(
+            CargoEnv::from_vars(&vars),
+            Err(Error::MissingEnvVar(name)) if name == "PROFILE"
+        ));
+        vars.insert("PROFILE".to_owned(), "debug".to_owned());
+        let cargo_env = CargoEnv::from_vars(&vars).unwrap();
+        assert_eq!(cargo_env.out_dir(), tmp);
+        assert_eq!(cargo_env.profile(), "debug");
+        fs::write(tmp.join("in"), "in").unwrap();
+        let makegraph = DepGraphBuilder::new()
+            .out_dir(cargo_env.out_dir())
+            .add_rule("generated", &[tmp.join("in")], copy_build)
+            .add_rule("final", &["generated"], copy_build)
+            .build()
+            .unwrap();
+        makegraph.make(MakeParams::None).unwrap();
+        assert_eq!(fs::read_to_string(tmp.join("final")).unwrap(), "in");
+        assert!(makegraph.is_up_to_date(tmp.join("generated")).unwrap());
+    }
+
+    #[test]
+    fn cargo_warnings() {
+        use std::sync::Mutex;
+
+        #[derive(Clone, Default)]
+        struct Shared(Arc<Mutex<Vec<u8>>>);
+        impl Write for Shared {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let makegraph = DepGraphBuilder::new()
+            .add_rule(
+                tmp.join("out"),
+                &[tmp.join("in")],
+                |_: &Path, _: &[&Path]| Err("tool not found\ninstall it first".to_owned()),
+            )
+            .build()
+            .unwrap();
+        fs::write(tmp.join("in"), "in").unwrap();
+        let out = Shared::default();
+        let warnings = CargoWarnings::to_writer(out.clone());
+        assert!(makegraph
+            .make_with_observer(MakeParams::None, &warnings)
+            .is_err());
+        let out = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<_> = out.lines().collect();
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].starts_with("cargo:warning=failed to build"));
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn num_jobs() {
+        let cpus = thread::available_parallelism().map_or(1, |n| n.get());
+        assert_eq!(parallel::default_jobs_from(Some("3")), 3);
+        assert_eq!(parallel::default_jobs_from(Some("not a number")), cpus);
+        assert_eq!(parallel::default_jobs_from(Some("0")), cpus);
+        assert_eq!(parallel::default_jobs_from(None), cpus);
+    }
+
+    #[test]
+    fn make_report() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let makegraph = DepGraphBuilder::new()
+            .add_rule(tmp.join("file1"), &[tmp.join("file2")], copy_build)
+            .build()
+            .unwrap();
+        fs::write(tmp.join("file2"), "file2").unwrap();
+        let report = makegraph.make_with_report(MakeParams::None).unwrap();
+        assert_eq!(report.built().len(), 1);
+        assert_eq!(report.built()[0].0, tmp.join("file1"));
+        assert!(report.skipped().is_empty());
+        let again = makegraph.make_with_report(MakeParams::None).unwrap();
+        assert!(again.built().is_empty());
+        assert_eq!(again.skipped(), [tmp.join("file1")]);
+        let mut out = Vec::new();
+        report.write_chrome_trace(&mut out).unwrap();
+        let trace = String::from_utf8(out).unwrap();
+        assert!(trace.starts_with("{\"traceEvents\": [\n"));
+        assert!(trace.contains("\"ph\": \"X\""));
+        assert!(trace.contains("file1"));
+        assert!(!trace.contains("file2"));
+    }
+
+    #[test]
+    fn build_log() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let builder = || {
+            DepGraphBuilder::new()
+                .add_rule(tmp.join("out"), &[tmp.join("in")], copy_build)
+                .build_log(tmp.join("log"))
+        };
+        fs::write(tmp.join("in"), "one").unwrap();
+        let makegraph = builder().build().unwrap();
+        makegraph.make(MakeParams::None).unwrap();
+        assert!(makegraph.last_duration(tmp.join("out")).is_some());
+
+        // replace the input with an older file, which the mtimes alone wouldn't notice
+        fs::write(tmp.join("in"), "two").unwrap();
+        File::options()
+            .write(true)
+            .open(tmp.join("in"))
+            .unwrap()
+            .set_modified(SystemTime::UNIX_EPOCH)
+            .unwrap();
+        let makegraph = builder().build().unwrap();
+        assert!(makegraph.last_duration(tmp.join("out")).is_some());
+        makegraph.make(MakeParams::None).unwrap();
+        assert_eq!(fs::read_to_string(tmp.join("out")).unwrap(), "two");
+
+        // the input is only hashed again if its modification time has changed
+        fs::write(tmp.join("in"), "six").unwrap();
+        File::options()
+            .write(true)
+            .open(tmp.join("in"))
+            .unwrap()
+            .set_modified(SystemTime::UNIX_EPOCH)
+            .unwrap();
+        assert!(makegraph.is_up_to_date(tmp.join("out")).unwrap());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn build_log_odd_names() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let out = tmp.join(std::ffi::OsStr::from_bytes(b"out\tnew\nline\\\xff"));
+        let builder = || {
+            DepGraphBuilder::new()
+                .add_rule(&out, &[tmp.join("in")], copy_build)
+                .build_log(tmp.join("log"))
+                .build()
+                .unwrap()
+        };
+        fs::write(tmp.join("in"), "in").unwrap();
+        builder().make(MakeParams::None).unwrap();
+        assert!(builder().last_duration(&out).is_some());
+        assert_eq!(
+            fs::read_to_string(tmp.join("log")).unwrap().lines().count(),
+            2
+        );
+    }
+
+    #[test]
+    fn rule_fingerprint() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let builder = |fingerprint: &str| {
+            DepGraphBuilder::new()
+                .add_rule_with_opts(
+                    tmp.join("out"),
+                    &[tmp.join("in")],
+                    RuleOptions {
+                        fingerprint: Some(fingerprint.to_owned()),
+                        ..Default::default()
+                    },
+                    copy_build,
+                )
+                .build_log(tmp.join("log"))
+                .build()
+                .unwrap()
+        };
+        fs::write(tmp.join("in"), "in").unwrap();
+        builder("cc -O2").make(MakeParams::None).unwrap();
+        assert!(builder("cc -O2").is_up_to_date(tmp.join("out")).unwrap());
+        assert_eq!(
+            builder("cc -O3")
+                .explain(tmp.join("out"), MakeParams::None)
+                .unwrap(),
+            RebuildReason::RuleChanged
+        );
+    }
+
+    #[test]
+    fn restat() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let builds = Arc::new(AtomicUsize::new(0));
+        let counter = builds.clone();
+        let makegraph = DepGraphBuilder::new()
+            .add_rule_with_opts(
+                tmp.join("generated"),
+                &[tmp.join("in")],
+                RuleOptions {
+                    restat: true,
+                    ..Default::default()
+                },
+                |out: &Path, _: &[&Path]| fs::write(out, "same").map_err(|e| e.to_string()),
+            )
+            .add_rule(
+                tmp.join("out"),
+                &[tmp.join("generated")],
+                move |out: &Path, deps: &[&Path]| {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    copy_build(out, deps)
+                },
+            )
+            .build_log(tmp.join("log"))
+            .build()
+            .unwrap();
+        fs::write(tmp.join("in"), "one").unwrap();
+        makegraph.make(MakeParams::None).unwrap();
+        assert_eq!(builds.load(Ordering::SeqCst), 1);
+
+        fs::write(tmp.join("in"), "two").unwrap();
+        File::options()
+            .write(true)
+            .open(tmp.join("in"))
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(5))
+            .unwrap();
+        makegraph.make(MakeParams::None).unwrap();
+        // `generated` was rebuilt with the same contents, so `out` wasn't
+        assert_eq!(builds.load(Ordering::SeqCst), 1);
+        assert!(makegraph.is_up_to_date(tmp.join("generated")).unwrap());
+        assert!(makegraph.is_up_to_date(tmp.join("out")).unwrap());
+    }
+
+    #[test]
+    fn build_plan() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let makegraph = DepGraphBuilder::new()
+            .add_rule(tmp.join("file1"), &[tmp.join("file2")], copy_build)
+            .add_rule(tmp.join("file2"), &[tmp.join("file3")], copy_build)
+            .add_rule(tmp.join("file4"), &[tmp.join("file5")], copy_build)
+            .build()
+            .unwrap();
+        assert!(makegraph.plan(MakeParams::None).is_err());
+        fs::write(tmp.join("file3"), "file3").unwrap();
+        fs::write(tmp.join("file5"), "file5").unwrap();
+        let plan = makegraph.plan(MakeParams::None).unwrap();
+        assert_eq!(plan.targets().len(), 3);
+        let pos = |p: &str| plan.targets().iter().position(|t| *t == tmp.join(p));
+        assert!(pos("file2") < pos("file1"));
+        // planning doesn't build anything
+        assert!(!tmp.join("file1").exists());
+
+        assert_eq!(makegraph.stale_targets().unwrap(), plan.targets());
+
+        makegraph.make(MakeParams::None).unwrap();
+        assert!(makegraph.plan(MakeParams::None).unwrap().is_empty());
+        assert!(makegraph.stale_targets().unwrap().is_empty());
+        assert_eq!(
+            makegraph
+                .plan(MakeParams::ForceBuild)
+                .unwrap()
+                .targets()
+                .len(),
+            3
+        );
+    }
+
+    #[test]
+    fn explain() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let makegraph = DepGraphBuilder::new()
+            .add_rule(tmp.join("a"), &[tmp.join("b")], copy_build)
+            .add_rule(tmp.join("b"), &[tmp.join("c")], copy_build)
+            .build()
+            .unwrap();
+        fs::write(tmp.join("c"), "c").unwrap();
+        let explain = |p: &str, params| makegraph.explain(tmp.join(p), params).unwrap();
+        assert_eq!(explain("b", MakeParams::None), RebuildReason::MissingOutput);
+        assert_eq!(
+            explain("a", MakeParams::None),
+            RebuildReason::DepRebuilt(tmp.join("b"))
+        );
+        assert_eq!(explain("c", MakeParams::None), RebuildReason::NoRule);
+
+        makegraph.make(MakeParams::None).unwrap();
+        assert_eq!(explain("a", MakeParams::None), RebuildReason::UpToDate);
+        assert_eq!(explain("a", MakeParams::ForceBuild), RebuildReason::Forced);
+
+        File::options()
+            .write(true)
+            .open(tmp.join("b"))
+            .unwrap()
+            .set_modified(SystemTime::UNIX_EPOCH)
+            .unwrap();
+        assert_eq!(
+            explain("b", MakeParams::None),
+            RebuildReason::DepNewer(tmp.join("c"))
+        );
+        assert!(matches!(
+            makegraph.explain(tmp.join("d"), MakeParams::None),
+            Err(Error::UnknownTarget(_))
+        ));
+    }
+
+    #[test]
+    fn is_up_to_date() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let makegraph = DepGraphBuilder::new()
+            .add_rule(tmp.join("a"), &[tmp.join("b")], copy_build)
+            .add_rule(tmp.join("b"), &[tmp.join("c")], copy_build)
+            .add_rule(tmp.join("d"), &[tmp.join("e")], copy_build)
+            .build()
+            .unwrap();
+        fs::write(tmp.join("c"), "c").unwrap();
+        // `e` is missing, but `a` doesn't depend on it
+        assert!(!makegraph.is_up_to_date(tmp.join("a")).unwrap());
+        assert!(makegraph.is_up_to_date(tmp.join("d")).is_err());
+        fs::write(tmp.join("e"), "e").unwrap();
+        makegraph.make(MakeParams::None).unwrap();
+        assert!(makegraph.is_up_to_date(tmp.join("a")).unwrap());
+        fs::remove_file(tmp.join("b")).unwrap();
+        assert!(!makegraph.is_up_to_date(tmp.join("a")).unwrap());
+        assert!(makegraph.is_up_to_date(tmp.join("d")).unwrap());
+    }
+
+    #[test]
+    fn check_only() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let makegraph = DepGraphBuilder::new()
+            .add_rule(tmp.join("a"), &[tmp.join("b")], copy_build)
+            .add_rule(tmp.join("b"), &[tmp.join("c")], copy_build)
+            .build()
+            .unwrap();
+        fs::write(tmp.join("c"), "c").unwrap();
+        match makegraph.make(MakeParams::CheckOnly) {
+            Err(Error::OutOfDate(targets)) => {
+                assert_eq!(targets, vec![tmp.join("b"), tmp.join("a")])
+            }
+            other => panic!("expected OutOfDate, got {:?}", other),
+        }
+        assert!(!tmp.join("b").exists());
+        makegraph.make(MakeParams::None).unwrap();
+        makegraph.make(MakeParams::CheckOnly).unwrap();
+        makegraph.verify().unwrap();
+    }
+
+    #[test]
+    fn clean() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let makegraph = DepGraphBuilder::new()
+            .add_rule(tmp.join("a"), &[tmp.join("b")], copy_build)
+            .add_rule(tmp.join("b"), &[tmp.join("c")], copy_build)
+            .build()
+            .unwrap();
+        fs::write(tmp.join("c"), "c").unwrap();
+        assert!(makegraph.clean_dry_run().is_empty());
+        makegraph.make(MakeParams::None).unwrap();
+        let mut listed = makegraph.clean_dry_run();
+        listed.sort();
+        assert_eq!(listed, vec![tmp.join("a"), tmp.join("b")]);
+        let mut removed = makegraph.clean().unwrap();
+        removed.sort();
+        assert_eq!(removed, vec![tmp.join("a"), tmp.join("b")]);
+        assert!(!tmp.join("a").exists() && !tmp.join("b").exists());
+        assert!(tmp.join("c").exists());
+    }
+
+    #[test]
+    fn prune() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let out = tmp.join("out");
+        fs::create_dir_all(out.join("sub")).unwrap();
+        let makegraph = DepGraphBuilder::new()
+            .add_rule(out.join("a"), &[tmp.join("b")], copy_build)
+            .build_log(out.join("log"))
+            .content_hashes(out.join("hashes"))
+            .build()
+            .unwrap();
+        fs::write(tmp.join("b"), "b").unwrap();
+        makegraph.make(MakeParams::None).unwrap();
+        assert!(out.join("hashes").exists());
+        fs::write(out.join("old"), "old").unwrap();
+        fs::write(out.join("sub").join("older"), "older").unwrap();
+        let mut removed = makegraph.prune(&[&out]).unwrap();
+        removed.sort();
+        assert_eq!(
+            removed,
+            vec![out.join("old"), out.join("sub").join("older")]
+        );
+        assert!(out.join("a").exists() && out.join("log").exists());
+        assert!(out.join("hashes").exists());
+        assert!(tmp.join("b").exists());
+    }
+
+    #[test]
+    fn touch() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let makegraph = DepGraphBuilder::new()
+            .add_rule(tmp.join("a"), &[tmp.join("b")], copy_build)
+            .add_rule(tmp.join("b"), &[tmp.join("c")], copy_build)
+            .build()
+            .unwrap();
+        fs::write(tmp.join("c"), "c").unwrap();
+        fs::write(tmp.join("b"), "restored").unwrap();
+        File::options()
+            .write(true)
+            .open(tmp.join("b"))
+            .unwrap()
+            .set_modified(SystemTime::UNIX_EPOCH)
+            .unwrap();
+        makegraph.make(MakeParams::Touch).unwrap();
+        // nothing was built, but everything is now up to date
+        assert_eq!(fs::read_to_string(tmp.join("b")).unwrap(), "restored");
+        assert_eq!(fs::read_to_string(tmp.join("a")).unwrap(), "");
+        assert!(makegraph.stale_targets().unwrap().is_empty());
+    }
+
+    #[test]
+    fn touch_directory() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let dir = tmp_dir.path().join("out");
+        fs::create_dir(&dir).unwrap();
+        let before = fs::metadata(&dir).unwrap().modified().unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        touch::touch(&dir).unwrap();
+        assert!(fs::metadata(&dir).unwrap().modified().unwrap() > before);
+    }
+
+    #[test]
+    fn assume_new_and_old() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let builder = || {
+            DepGraphBuilder::new()
+                .add_rule(tmp.join("a"), &[tmp.join("b")], copy_build)
+                .add_rule(tmp.join("b"), &[tmp.join("c")], copy_build)
+        };
+        let set_old = |p: &str| {
+            File::options()
+                .write(true)
+                .open(tmp.join(p))
+                .unwrap()
+                .set_modified(SystemTime::UNIX_EPOCH)
+                .unwrap()
+        };
+        fs::write(tmp.join("c"), "one").unwrap();
+        builder().build().unwrap().make(MakeParams::None).unwrap();
+
+        let makegraph = builder().assume_new(tmp.join("c")).build().unwrap();
+        assert_eq!(
+            makegraph.explain(tmp.join("b"), MakeParams::None).unwrap(),
+            RebuildReason::DepAssumedNew(tmp.join("c"))
+        );
+        assert_eq!(makegraph.stale_targets().unwrap().len(), 2);
+
+        // `b` and `a` are both out of date, but neither is rebuilt
+        fs::write(tmp.join("c"), "two").unwrap();
+        set_old("a");
+        let makegraph = builder().assume_old(tmp.join("b")).build().unwrap();
+        assert_eq!(
+            makegraph.explain(tmp.join("b"), MakeParams::None).unwrap(),
+            RebuildReason::AssumedOld
+        );
+        assert!(makegraph.is_up_to_date(tmp.join("a")).unwrap());
+        makegraph.make(MakeParams::None).unwrap();
+        assert_eq!(fs::read_to_string(tmp.join("b")).unwrap(), "one");
+    }
+
+    #[test]
+    fn phony() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let makegraph = DepGraphBuilder::new()
+            .add_phony_rule("all", &[tmp.join("a"), tmp.join("b")])
+            .add_rule(tmp.join("a"), &[tmp.join("c")], copy_build)
+            .add_rule(tmp.join("b"), &[tmp.join("c")], copy_build)
+            .add_rule(tmp.join("d"), &["all"], |out: &Path, deps: &[&Path]| {
+                assert_eq!(deps.len(), 2);
+                fs::write(out, "d").map_err(|e| e.to_string())
+            })
+            .build()
+            .unwrap();
+        fs::write(tmp.join("c"), "c").unwrap();
+        assert!(matches!(
+            makegraph.explain("all", MakeParams::None).unwrap(),
+            RebuildReason::DepRebuilt(_)
+        ));
+        assert!(!makegraph.plan(MakeParams::None).unwrap().contains("all"));
+        makegraph.make(MakeParams::None).unwrap();
+        assert!(tmp.join("d").exists());
+        assert!(!Path::new("all").exists());
+        assert!(makegraph.is_up_to_date("all").unwrap());
+    }
+
+    #[test]
+    fn always_rebuild() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let runs = Arc::new(AtomicUsize::new(0));
+        let makegraph = DepGraphBuilder::new()
+            .add_rule_with_opts(
+                tmp.join("a"),
+                &[tmp.join("b")],
+                RuleOptions {
+                    always_rebuild: true,
+                    ..Default::default()
+                },
+                {
+                    let runs = runs.clone();
+                    move |out: &Path, deps: &[&Path]| {
+                        runs.fetch_add(1, Ordering::SeqCst);
+                        copy_build(out, deps)
+                    }
+                },
+            )
+            .add_rule(tmp.join("c"), &[tmp.join("b")], copy_build)
+            .build()
+            .unwrap();
+        fs::write(tmp.join("b"), "b").unwrap();
+        makegraph.make(MakeParams::None).unwrap();
+        makegraph.make(MakeParams::None).unwrap();
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        assert_eq!(
+            makegraph.explain(tmp.join("a"), MakeParams::None).unwrap(),
+            RebuildReason::AlwaysRebuild
+        );
+        assert!(makegraph.is_up_to_date(tmp.join("c")).unwrap());
+    }
+
+    #[test]
+    fn optional_deps() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let makegraph = DepGraphBuilder::new()
+            .add_rule_with_opts(
+                tmp.join("out"),
+                &[tmp.join("in")],
+                RuleOptions {
+                    optional_deps: vec![tmp.join("override")],
+                    ..Default::default()
+                },
+                |out: &Path, deps: &[&Path]| {
+                    let input = deps.iter().find(|dep| dep.ends_with("override"));
+                    let input = input.unwrap_or(&deps[0]);
+                    fs::copy(input, out).map(|_| ()).map_err(|e| e.to_string())
+                },
+            )
+            .build()
+            .unwrap();
+        fs::write(tmp.join("in"), "default").unwrap();
+        makegraph.make(MakeParams::None).unwrap();
+        assert_eq!(fs::read_to_string(tmp.join("out")).unwrap(), "default");
+        assert!(makegraph.is_up_to_date(tmp.join("out")).unwrap());
+
+        // make sure the new file is newer than the output
+        for file in ["in", "out"] {
+            File::options()
+                .write(true)
+                .open(tmp.join(file))
+                .unwrap()
+                .set_modified(SystemTime::UNIX_EPOCH)
+                .unwrap();
+        }
+        fs::write(tmp.join("override"), "override").unwrap();
+        assert!(!makegraph.is_up_to_date(tmp.join("out")).unwrap());
+        makegraph.make(MakeParams::None).unwrap();
+        assert_eq!(fs::read_to_string(tmp.join("out")).unwrap(), "override");
+    }
+
+    #[test]
+    fn multiple_outputs() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let makegraph = DepGraphBuilder::new()
+            .add_rule_multi(
+                &[tmp.join("out.c"), tmp.join("out.h")],
+                &[tmp.join("in")],
+                |outs: &[&Path], deps: &[&Path]| {
+                    for out in outs {
+                        fs::copy(deps[0], out).map_err(|e| e.to_string())?;
+                    }
+                    Ok(())
+                },
+            )
+            .add_rule(tmp.join("user"), &[tmp.join("out.h")], copy_build)
+            .build()
+            .unwrap();
+        fs::write(tmp.join("in"), "in").unwrap();
+        makegraph.make(MakeParams::None).unwrap();
+        assert_eq!(fs::read_to_string(tmp.join("user")).unwrap(), "in");
+        assert!(makegraph.stale_targets().unwrap().is_empty());
+
+        fs::remove_file(tmp.join("out.h")).unwrap();
+        assert_eq!(
+            makegraph.stale_targets().unwrap(),
+            vec![tmp.join("out.c"), tmp.join("user")]
+        );
+        makegraph.make(MakeParams::None).unwrap();
+        assert!(tmp.join("out.h").exists());
+
+        let duplicate = DepGraphBuilder::new()
+            .add_rule_multi(&["a", "b"], &["c"], |_: &[&Path], _: &[&Path]| Ok(()))
+            .add_rule("b", &["c"], |_: &Path, _: &[&Path]| Ok(()))
+            .build();
+        match duplicate {
+            Err(Error::DuplicateFile {
+                path,
+                first,
+                second,
+            }) => assert_eq!((path, first, second), (PathBuf::from("b"), 0, 1)),
+            other => panic!("expected a duplicate file, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn implicit_outputs() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let makegraph = DepGraphBuilder::new()
+            .add_rule_with_opts(
+                tmp.join("out"),
+                &[tmp.join("in")],
+                RuleOptions {
+                    implicit_outputs: vec![tmp.join("out.d")],
+                    ..Default::default()
+                },
+                |out: &Path, deps: &[&Path]| {
+                    fs::write(out.with_extension("d"), "deps").map_err(|e| e.to_string())?;
+                    copy_build(out, deps)
+                },
+            )
+            .build()
+            .unwrap();
+        fs::write(tmp.join("in"), "in").unwrap();
+        makegraph.make(MakeParams::None).unwrap();
+        // removing the implicit output doesn't cause a rebuild
+        fs::remove_file(tmp.join("out.d")).unwrap();
+        assert!(makegraph.is_up_to_date(tmp.join("out")).unwrap());
+        makegraph.make(MakeParams::ForceBuild).unwrap();
+        assert!(makegraph.prune(&[tmp]).unwrap().is_empty());
+        let mut removed = makegraph.clean().unwrap();
+        removed.sort();
+        assert_eq!(removed, vec![tmp.join("out"), tmp.join("out.d")]);
+    }
+
+    #[test]
+    fn directory_target() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let makegraph = DepGraphBuilder::new()
+            .add_rule(
+                tmp.join("site"),
+                &[tmp.join("page")],
+                |out: &Path, deps: &[&Path]| {
+                    fs::create_dir_all(out).map_err(|e| e.to_string())?;
+                    copy_build(&out.join("index.html"), deps)
+                },
+            )
+            .build()
+            .unwrap();
+        fs::write(tmp.join("page"), "one").unwrap();
+        makegraph.make(MakeParams::None).unwrap();
+        assert!(makegraph.is_up_to_date(tmp.join("site")).unwrap());
+
+        // rewriting a file in the directory doesn't change the directory's mtime, so make sure
+        // the rebuild is what marks it up to date
+        File::open(tmp.join("site"))
+            .unwrap()
+            .set_modified(SystemTime::UNIX_EPOCH)
+            .unwrap();
+        assert!(!makegraph.is_up_to_date(tmp.join("site")).unwrap());
+        makegraph.make(MakeParams::None).unwrap();
+        assert!(makegraph.is_up_to_date(tmp.join("site")).unwrap());
+
+        assert!(makegraph.prune(&[tmp]).unwrap().is_empty());
+        assert_eq!(makegraph.clean().unwrap(), vec![tmp.join("site")]);
+        assert!(!tmp.join("site").exists());
+    }
+
+    #[test]
+    fn directory_dependency() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let assets = tmp.join("assets");
+        fs::create_dir_all(assets.join("textures")).unwrap();
+        fs::write(assets.join("textures").join("a.png"), "a").unwrap();
+        let makegraph = DepGraphBuilder::new()
+            .add_rule(tmp.join("packed"), &[&assets], |out: &Path, _: &[&Path]| {
+                fs::write(out, "packed").map_err(|e| e.to_string())
+            })
+            .build()
+            .unwrap();
+        makegraph.make(MakeParams::None).unwrap();
+        assert!(makegraph.is_up_to_date(tmp.join("packed")).unwrap());
+
+        // only a file deep inside the directory changes
+        File::options()
+            .write(true)
+            .open(assets.join("textures").join("a.png"))
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(
+            makegraph
+                .explain(tmp.join("packed"), MakeParams::None)
+                .unwrap(),
+            RebuildReason::DepNewer(assets.clone())
+        );
+
+        // content hashes work on directories too
+        let makegraph = DepGraphBuilder::new()
+            .add_rule(tmp.join("packed"), &[&assets], |out: &Path, _: &[&Path]| {
+                fs::write(out, "packed").map_err(|e| e.to_string())
+            })
+            .content_hashes(tmp.join("hashes"))
+            .build()
+            .unwrap();
+        makegraph.make(MakeParams::None).unwrap();
+        assert!(makegraph.is_up_to_date(tmp.join("packed")).unwrap());
+        fs::write(assets.join("textures").join("b.png"), "b").unwrap();
+        assert!(!makegraph.is_up_to_date(tmp.join("packed")).unwrap());
+    }
+
+    #[test]
+    fn glob_dependencies() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let shaders = tmp.join("shaders");
+        fs::create_dir_all(shaders.join("post")).unwrap();
+        fs::create_dir_all(shaders.join(".cache")).unwrap();
+        for file in [
+            "a.glsl",
+            "b.txt",
+            "post/c.glsl",
+            ".cache/d.glsl",
+            "[e].glsl",
+        ] {
+            fs::write(shaders.join(file), file).unwrap();
+        }
+        let makegraph = DepGraphBuilder::new()
+            .add_rule(
+                tmp.join("all"),
+                &[shaders.join("**").join("*.glsl")],
+                |out: &Path, deps: &[&Path]| {
+                    let names: Vec<_> = deps.iter().map(|d| d.display().to_string()).collect();
+                    fs::write(
+                        out,
+                        names.join(
+                            "
+",
+                        ),
+                    )
+                    .map_err(|e| e.to_string())
+                },
+            )
+            .add_rule(tmp.join("some"), &[shaders.join("[a-b].gls?")], copy_build)
+            .build()
+            .unwrap();
+        makegraph.make(MakeParams::None).unwrap();
+        let mut built: Vec<_> = fs::read_to_string(tmp.join("all"))
+            .unwrap()
+            .lines()
+            .map(PathBuf::from)
+            .collect();
+        built.sort();
+        assert_eq!(
+            built,
+            vec![
+                shaders.join("[e].glsl"),
+                shaders.join("a.glsl"),
+                shaders.join("post/c.glsl"),
+            ]
+        );
+        assert_eq!(fs::read_to_string(tmp.join("some")).unwrap(), "a.glsl");
+    }
+
+    #[test]
+    fn ignore_files() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let src = tmp.join("src");
+        fs::create_dir_all(src.join("gen")).unwrap();
+        fs::create_dir_all(src.join("sub")).unwrap();
+        fs::write(src.join(".gitignore"), "*.swp\n/gen/\n!keep.swp\n").unwrap();
+        fs::write(src.join("sub").join(".ignore"), "local.rs\n").unwrap();
+        for file in [
+            "a.rs",
+            "a.rs.swp",
+            "keep.swp",
+            "gen/b.rs",
+            "sub/c.rs",
+            "sub/local.rs",
+            "sub/d.swp",
+        ] {
+            fs::write(src.join(file), file).unwrap();
+        }
+        let expected = vec![src.join("a.rs"), src.join("keep.swp"), src.join("sub/c.rs")];
+        assert_eq!(source_files(&src).unwrap(), expected);
+
+        let makegraph = DepGraphBuilder::new()
+            .add_rule(
+                tmp.join("out"),
+                &[src.join("**/*.*")],
+                |out: &Path, deps: &[&Path]| {
+                    assert_eq!(deps.len(), 3);
+                    fs::write(out, "out").map_err(|e| e.to_string())
+                },
+            )
+            .respect_ignore_files()
+            .build()
+            .unwrap();
+        makegraph.make(MakeParams::None).unwrap();
+    }
+
+    #[test]
+    fn pattern_rules() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        fs::create_dir_all(tmp.join("src")).unwrap();
+        fs::create_dir_all(tmp.join("out")).unwrap();
+        for file in ["boot.asm", "main.asm", "notes.txt"] {
+            fs::write(tmp.join("src").join(file), file).unwrap();
+        }
+        let makegraph = DepGraphBuilder::new()
+            .add_pattern_rule(tmp.join("out/%.o"), tmp.join("src/%.asm"), copy_build)
+            .add_rule(
+                tmp.join("out/main.o"),
+                &[tmp.join("src/main.asm")],
+                |out: &Path, _: &[&Path]| fs::write(out, "explicit").map_err(|e| e.to_string()),
+            )
+            .build()
+            .unwrap();
+        makegraph.make(MakeParams::None).unwrap();
+        assert_eq!(
+            fs::read_to_string(tmp.join("out/boot.o")).unwrap(),
+            "boot.asm"
+        );
+        assert_eq!(
+            fs::read_to_string(tmp.join("out/main.o")).unwrap(),
+            "explicit"
+        );
+        assert!(!tmp.join("out/notes.o").exists());
+    }
+
+    #[test]
+    fn rules_from_files() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let inputs = [tmp.join("a.proto"), tmp.join("b.proto")];
+        for input in &inputs {
+            fs::write(input, input.file_name().unwrap().to_str().unwrap()).unwrap();
+        }
+        let makegraph = DepGraphBuilder::new()
+            .add_rules_from(&inputs, |input: &Path| {
+                (
+                    input.with_extension("rs"),
+                    vec![input.to_owned()],
+                    copy_build,
+                )
+            })
+            .build()
+            .unwrap();
+        makegraph.make(MakeParams::None).unwrap();
+        assert_eq!(fs::read_to_string(tmp.join("a.rs")).unwrap(), "a.proto");
+        assert_eq!(fs::read_to_string(tmp.join("b.rs")).unwrap(), "b.proto");
+    }
+
+    #[test]
+    fn depfile() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let header = tmp.join("my header.h");
+        fs::write(tmp.join("main.c"), "").unwrap();
+        fs::write(&header, "").unwrap();
+        let depfile = tmp.join("main.d");
+        let depfile_contents = format!(
+            "{}: {} \\\n  {}\n",
+            tmp.join("main.o").display(),
+            tmp.join("main.c").display(),
+            header.display().to_string().replace(' ', "\\ ")
+        );
+        let makegraph = DepGraphBuilder::new()
+            .add_rule_with_opts(
+                tmp.join("main.o"),
+                &[tmp.join("main.c")],
+                RuleOptions {
+                    depfile: Some(depfile.clone()),
+                    ..Default::default()
+                },
+                move |out: &Path, _: &[&Path]| {
+                    fs::write(out, "").map_err(|e| e.to_string())?;
+                    fs::write(&depfile, &depfile_contents).map_err(|e| e.to_string())
+                },
+            )
+            .build()
+            .unwrap();
+        makegraph.make(MakeParams::None).unwrap();
+        assert!(makegraph.is_up_to_date(tmp.join("main.o")).unwrap());
+
+        // a change to the header, which is only listed in the depfile, rebuilds the target
+        File::options()
+            .write(true)
+            .open(&header)
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(
+            makegraph
+                .explain(tmp.join("main.o"), MakeParams::None)
+                .unwrap(),
+            RebuildReason::DepNewer(header.clone())
+        );
+
+        fs::remove_file(&header).unwrap();
+        assert_eq!(
+            makegraph
+                .explain(tmp.join("main.o"), MakeParams::None)
+                .unwrap(),
+            RebuildReason::DepMissing(header)
+        );
+    }
+
+    #[test]
+    fn dynamic_deps() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let included = tmp.join("included #1.txt");
+        fs::write(tmp.join("main.txt"), included.to_str().unwrap()).unwrap();
+        fs::write(&included, "included").unwrap();
+        let makegraph = DepGraphBuilder::new()
+            .add_rule_with_dynamic_deps(
+                tmp.join("out.txt"),
+                &[tmp.join("main.txt")],
+                |out: &Path, deps: &[&Path]| {
+                    let included = PathBuf::from(fs::read_to_string(deps[0]).unwrap());
+                    fs::copy(&included, out).map_err(|e| e.to_string())?;
+                    Ok(vec![included])
+                },
+            )
+            .build()
+            .unwrap();
+        makegraph.make(MakeParams::None).unwrap();
+        assert!(tmp.join("out.txt.d").exists());
+        assert!(makegraph.is_up_to_date(tmp.join("out.txt")).unwrap());
+
+        File::options()
+            .write(true)
+            .open(&included)
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(
+            makegraph
+                .explain(tmp.join("out.txt"), MakeParams::None)
+                .unwrap(),
+            RebuildReason::DepNewer(included)
+        );
+    }
+
+    #[test]
+    fn include_scanner() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        fs::create_dir_all(tmp.join("include")).unwrap();
+        fs::write(
+            tmp.join("main.c"),
+            "#include <stdio.h>\n#include \"local.h\"\nint main() {}\n",
+        )
+        .unwrap();
+        fs::write(tmp.join("local.h"), "#  include <lib.h>\n").unwrap();
+        fs::write(tmp.join("include/lib.h"), "").unwrap();
+        let makegraph = DepGraphBuilder::new()
+            .add_rule_with_opts(
+                tmp.join("main.o"),
+                &[tmp.join("main.c")],
+                RuleOptions {
+                    scanner: Some(Arc::new(
+                        IncludeScanner::new().include_dir(tmp.join("include")),
+                    )),
+                    ..Default::default()
+                },
+                copy_build,
+            )
+            .build()
+            .unwrap();
+        makegraph.make(MakeParams::None).unwrap();
+        assert!(makegraph.is_up_to_date(tmp.join("main.o")).unwrap());
+
+        File::options()
+            .write(true)
+            .open(tmp.join("include/lib.h"))
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(
+            makegraph
+                .explain(tmp.join("main.o"), MakeParams::None)
+                .unwrap(),
+            RebuildReason::DepNewer(tmp.join("include/lib.h"))
+        );
+    }
+
+    #[test]
+    fn atomic_write() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        util::atomic_write(tmp.join("out"), "one").unwrap();
+        util::atomic_write(tmp.join("out"), "two").unwrap();
+        assert_eq!(fs::read_to_string(tmp.join("out")).unwrap(), "two");
+        // the temporary file is gone, whether the write worked or not
+        assert!(util::atomic_write(tmp.join("missing/out"), "three").is_err());
+        assert_eq!(fs::read_dir(tmp).unwrap().count(), 1);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn command_rule() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        fs::write(tmp.join("a"), "a").unwrap();
+        fs::write(tmp.join("b"), "b").unwrap();
+        let makegraph = DepGraphBuilder::new()
+            .add_command_rule(
+                tmp.join("out"),
+                &[tmp.join("a"), tmp.join("b")],
+                CommandSpec::new("sh").args(["-c", "cat \"$@\" > {out}", "sh", "{deps}"]),
+            )
+            .add_command_rule(
+                tmp.join("fails"),
+                &[tmp.join("a")],
+                CommandSpec::new("sh").args(["-c", "echo oops >&2; exit 3"]),
+            )
+            .build()
+            .unwrap();
+        match makegraph.make(MakeParams::KeepGoing) {
+            Err(Error::BuildsFailed { failed, .. }) => {
+                assert_eq!(failed.len(), 1);
+                assert!(
+                    matches!(&failed[0].1, Error::BuildFailed { source: msg, .. } if msg.to_string().contains("oops"))
+                );
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+        let out = fs::read_to_string(tmp.join("out")).unwrap();
+        assert!(out == "ab" || out == "ba");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn shell_rule() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        fs::write(tmp.join("a"), "a").unwrap();
+        fs::write(tmp.join("it's b"), "b").unwrap();
+        let makegraph = DepGraphBuilder::new()
+            .add_shell_rule(
+                tmp.join("out file"),
+                &[tmp.join("a"), tmp.join("it's b")],
+                "cat {deps} > {out}",
+            )
+            .build()
+            .unwrap();
+        makegraph.make(MakeParams::None).unwrap();
+        let out = fs::read_to_string(tmp.join("out file")).unwrap();
+        assert!(out == "ab" || out == "ba");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn command_output() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        fs::write(tmp.join("in"), "in").unwrap();
+        let makegraph = DepGraphBuilder::new()
+            .add_command_rule(
+                tmp.join("out"),
+                &[tmp.join("in")],
+                CommandSpec::shell("echo checking; echo 0123456789 >&2; exit 1").output_limit(4),
+            )
+            .build()
+            .unwrap();
+        match makegraph.make(MakeParams::None) {
+            Err(Error::BuildFailed { source: msg, .. }) => {
+                assert!(msg
+                    .to_string()
+                    .contains("--- stdout ---\n[5 bytes omitted]\ning\n"));
+                assert!(msg
+                    .to_string()
+                    .contains("--- stderr ---\n[7 bytes omitted]\n789\n"));
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn command_env() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        fs::create_dir_all(tmp.join("dir")).unwrap();
+        fs::write(tmp.join("in"), "in").unwrap();
+        let makegraph = DepGraphBuilder::new()
+            .add_command_rule(
+                tmp.join("out"),
+                &[tmp.join("in")],
+                CommandSpec::new("/bin/sh")
+                    .args(["-c", "echo \"${HOME:-unset} $GREETING $(pwd)\" > {out}"])
+                    .env("DROPPED", "1")
+                    .env_clear()
+                    .env("GREETING", "hello")
+                    .current_dir(tmp.join("dir")),
+            )
+            .build()
+            .unwrap();
+        makegraph.make(MakeParams::None).unwrap();
+        assert_eq!(
+            fs::read_to_string(tmp.join("out")).unwrap(),
+            format!(
+                "unset hello {}\n",
+                tmp.join("dir").canonicalize().unwrap().display()
+            )
+        );
+    }
+
+    #[test]
+    #[cfg(all(unix, not(feature = "cc")))]
+    fn c_object_rule() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        fs::create_dir_all(tmp.join("include")).unwrap();
+        fs::write(tmp.join("main.c"), "#include <config.h>\n").unwrap();
+        fs::write(tmp.join("include/config.h"), "").unwrap();
+        // a stand-in compiler that records its arguments
+        let compiler = tmp.join("fake-cc");
+        fs::write(
+            &compiler,
+            "#!/bin/sh\nfor o; do :; done\necho \"$@\" > \"$o\"\n",
+        )
+        .unwrap();
+        fs::set_permissions(&compiler, fs::Permissions::from_mode(0o755)).unwrap();
+        let cc = rules::CBuild::new()
+            .compiler(&compiler)
+            .include(tmp.join("include"))
+            .define("NDEBUG", None)
+            .flag("-O2");
+        let makegraph = rules::c_object(
+            DepGraphBuilder::new(),
+            tmp.join("main.c"),
+            tmp.join("main.o"),
+            &cc,
+        )
+        .build()
+        .unwrap();
+        makegraph.make(MakeParams::None).unwrap();
+        assert_eq!(
+            fs::read_to_string(tmp.join("main.o")).unwrap(),
+            format!(
+                "-I {} -DNDEBUG -O2 -c {} -o {}\n",
+                tmp.join("include").display(),
+                tmp.join("main.c").display(),
+                tmp.join("main.o").display()
+            )
+        );
+
+        File::options()
+            .write(true)
+            .open(tmp.join("include/config.h"))
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(
+            makegraph
+                .explain(tmp.join("main.o"), MakeParams::None)
+                .unwrap(),
+            RebuildReason::DepNewer(tmp.join("include/config.h"))
+        );
+    }
+
+    #[test]
+    #[cfg(all(unix, feature = "cc"))]
+    fn c_object_rule_with_cc() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        fs::write(tmp.join("main.c"), "").unwrap();
+        // a stand-in compiler that records its arguments, and that cc detects as gcc
+        let compiler = tmp.join("fake-cc");
+        fs::write(
+            &compiler,
+            "#!/bin/sh\n\
+             for o; do\n\
+             case \"$o\" in -\\?) exit 1;; -E) echo '\"gcc\"'; exit 0;; esac\n\
+             done\n\
+             echo \"$@\" > \"$o\"\n",
+        )
+        .unwrap();
+        fs::set_permissions(&compiler, fs::Permissions::from_mode(0o755)).unwrap();
+        // set everything cc would otherwise read from cargo's build script variables
+        let mut base = cc::Build::new();
+        base.target("x86_64-unknown-linux-gnu")
+            .host("x86_64-unknown-linux-gnu")
+            .opt_level(0)
+            .debug(false);
+        let cc = rules::CBuild::from_cc(base)
+            .compiler(&compiler)
+            .include(tmp.join("include"))
+            .define("NDEBUG", None)
+            .flag("-O2");
+        rules::c_object(
+            DepGraphBuilder::new(),
+            tmp.join("main.c"),
+            tmp.join("main.o"),
+            &cc,
+        )
+        .build()
+        .unwrap()
+        .make(MakeParams::None)
+        .unwrap();
+        let args = fs::read_to_string(tmp.join("main.o")).unwrap();
+        // cc adds its own flags for the target
+        assert!(args.contains(" -fPIC "), "{}", args);
+        assert!(args.contains(&format!(" -I {} ", tmp.join("include").display())));
+        assert!(args.contains(" -O2 "));
+        assert!(args.contains(" -DNDEBUG "));
+        assert!(
+            args.ends_with(&format!(
+                " -c {} -o {}\n",
+                tmp.join("main.c").display(),
+                tmp.join("main.o").display()
+            )),
+            "{}",
+            args
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn protoc_rule() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let protos = tmp.join("proto");
+        fs::create_dir_all(&protos).unwrap();
+        fs::write(
+            protos.join("api.proto"),
+            "syntax = \"proto3\";\npackage my.api;\nimport \"common.proto\";\n",
+        )
+        .unwrap();
+        fs::write(protos.join("common.proto"), "syntax = \"proto3\";\n").unwrap();
+        // a stand-in protoc that writes its arguments to the file it would generate
+        let fake_protoc = tmp.join("fake-protoc");
+        fs::write(
+            &fake_protoc,
+            "#!/bin/sh\nfor a; do case \"$a\" in --prost_out=*) d=\"${a#--prost_out=}\";; esac; done\n\
+             echo \"$@\" > \"$d/my.api.rs\"\n",
+        )
+        .unwrap();
+        fs::set_permissions(&fake_protoc, fs::Permissions::from_mode(0o755)).unwrap();
+        let settings = rules::Protoc::new().protoc(&fake_protoc).include(&protos);
+        let makegraph = rules::protoc(
+            DepGraphBuilder::new(),
+            &[protos.join("api.proto")],
+            tmp,
+            &settings,
+        )
+        .unwrap()
+        .build()
+        .unwrap();
+        makegraph.make(MakeParams::None).unwrap();
+        assert_eq!(
+            fs::read_to_string(tmp.join("my.api.rs")).unwrap(),
+            format!(
+                "-I {} --prost_out={} {}\n",
+                protos.display(),
+                tmp.display(),
+                protos.join("api.proto").display()
+            )
+        );
+
+        File::options()
+            .write(true)
+            .open(protos.join("common.proto"))
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(
+            makegraph
+                .explain(tmp.join("my.api.rs"), MakeParams::None)
+                .unwrap(),
+            RebuildReason::DepNewer(protos.join("common.proto"))
+        );
+    }
+
+    #[test]
+    #[cfg(all(unix, feature = "schema-tools"))]
+    fn schema_rules() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let schemas = tmp.join("schemas");
+        fs::create_dir_all(tmp.join("include/capnp")).unwrap();
+        fs::create_dir_all(&schemas).unwrap();
+        fs::write(schemas.join("monster.fbs"), "include \"weapon.fbs\";\n").unwrap();
+        fs::write(schemas.join("weapon.fbs"), "").unwrap();
+        fs::write(
+            schemas.join("point.capnp"),
+            "using Cxx = import \"/capnp/c++.capnp\";\n",
+        )
+        .unwrap();
+        fs::write(tmp.join("include/capnp/c++.capnp"), "").unwrap();
+        let settings = rules::SchemaCompiler::new().include(tmp.join("include"));
+        let builder = rules::flatc(
+            DepGraphBuilder::new(),
+            &[schemas.join("monster.fbs")],
+            tmp,
+            &settings,
+        )
+        .unwrap();
+        let makegraph = rules::capnp(builder, &[schemas.join("point.capnp")], tmp, &settings)
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(
+            makegraph
+                .explain(tmp.join("monster_generated.rs"), MakeParams::None)
+                .unwrap(),
+            RebuildReason::MissingOutput
+        );
+        let mut rerun = Vec::new();
+        makegraph.write_cargo_rerun_if_changed(&mut rerun).unwrap();
+        let rerun = String::from_utf8(rerun).unwrap();
+        assert!(rerun.contains(&schemas.join("weapon.fbs").display().to_string()));
+        assert!(rerun.contains(&tmp.join("include/capnp/c++.capnp").display().to_string()));
+    }
+
+    #[test]
+    fn shader_rules() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        fs::create_dir_all(tmp.join("common")).unwrap();
+        fs::write(tmp.join("mesh.vert"), "#include \"lighting.glsl\"\n").unwrap();
+        fs::write(tmp.join("mesh.frag"), "").unwrap();
+        fs::write(tmp.join("common/lighting.glsl"), "").unwrap();
+        let settings = rules::shaders::ShaderCompiler::new().include(tmp.join("common"));
+        let makegraph = rules::shaders::glslc_all(
+            DepGraphBuilder::new(),
+            &[tmp.join("mesh.vert"), tmp.join("mesh.frag")],
+            tmp.join("out"),
+            &settings,
+        )
+        .build()
+        .unwrap();
+        let plan = makegraph.plan(MakeParams::None).unwrap();
+        assert!(plan.contains(tmp.join("out/mesh.vert.spv")));
+        assert!(plan.contains(tmp.join("out/mesh.frag.spv")));
+        let mut rerun = Vec::new();
+        makegraph.write_cargo_rerun_if_changed(&mut rerun).unwrap();
+        let rerun = String::from_utf8(rerun).unwrap();
+        assert!(rerun.contains(&tmp.join("common/lighting.glsl").display().to_string()));
+    }
+
+    #[test]
+    fn wasm_rule() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let krate = tmp.join("web");
+        fs::create_dir_all(krate.join("src")).unwrap();
+        fs::write(krate.join("Cargo.toml"), "[package]\nname = \"web-app\"\n").unwrap();
+        fs::write(krate.join("src/lib.rs"), "").unwrap();
+        let makegraph = rules::wasm_cargo(
+            DepGraphBuilder::new(),
+            &krate,
+            tmp.join("app.wasm"),
+            &rules::WasmBuild::new(),
+        )
+        .build()
+        .unwrap();
+        let mut rerun = Vec::new();
+        makegraph.write_cargo_rerun_if_changed(&mut rerun).unwrap();
+        assert_eq!(
+            String::from_utf8(rerun).unwrap(),
+            format!(
+                "cargo:rerun-if-changed={}\ncargo:rerun-if-changed={}\n",
+                krate.join("Cargo.toml").display(),
+                krate.join("src").display()
+            )
+        );
+        assert!(makegraph
+            .plan(MakeParams::None)
+            .unwrap()
+            .contains(tmp.join("app.wasm")));
+    }
+
+    #[test]
+    #[cfg(feature = "assets")]
+    fn asset_rules() {
+        use rules::assets::{self, AssetTool};
+
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        fs::write(tmp.join("jump.wav"), "").unwrap();
+        fs::write(tmp.join("land.wav"), "").unwrap();
+        let builder = assets::convert_all(
+            DepGraphBuilder::new(),
+            &[tmp.join("jump.wav"), tmp.join("land.wav")],
+            tmp.join("sounds"),
+            "ogg",
+            &AssetTool::new().arg("-q6"),
+            assets::wav_to_ogg,
+        );
+        let makegraph = builder.build().unwrap();
+        let plan = makegraph.plan(MakeParams::None).unwrap();
+        assert_eq!(
+            plan.targets(),
+            [tmp.join("sounds/jump.ogg"), tmp.join("sounds/land.ogg")]
+        );
+    }
+
+    #[test]
+    fn copy_rule() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let src = tmp.join("src.txt");
+        let dst = tmp.join("nested/dir/dst.txt");
+        fs::write(&src, "contents").unwrap();
+        let makegraph = rules::copy(DepGraphBuilder::new(), &src, &dst)
+            .build()
+            .unwrap();
+        makegraph.make(MakeParams::None).unwrap();
+        assert_eq!(fs::read_to_string(&dst).unwrap(), "contents");
+        assert!(fs::read_dir(dst.parent().unwrap()).unwrap().count() == 1);
+        let plan = makegraph.plan(MakeParams::None).unwrap();
+        assert!(plan.targets().is_empty());
+    }
+
+    #[test]
+    fn concat_rule() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let names = ["c", "a", "d", "b"];
+        for name in names {
+            fs::write(tmp.join(name), name).unwrap();
+        }
+        let inputs: Vec<_> = names.iter().map(|name| tmp.join(name)).collect();
+        let out = tmp.join("out/all");
+        let makegraph = rules::concat(DepGraphBuilder::new(), &out, &inputs)
+            .build()
+            .unwrap();
+        makegraph.make(MakeParams::None).unwrap();
+        assert_eq!(fs::read_to_string(&out).unwrap(), "cadb");
+    }
+
+    #[test]
+    #[cfg(feature = "templates")]
+    fn template_rule() {
+        use rules::TemplateContext;
+
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let template = tmp.join("config.in");
+        let out = tmp.join("config");
+        fs::write(
+            &template,
+            "name = {{ name | upper }}\nport = {{port}}\n{% if debug %}debug = true\n{% endif %}",
+        )
+        .unwrap();
+        // no build log, as the context is recorded in a stamp file
+        let build = |port| {
+            let context = TemplateContext::new()
+                .set("name", "server")
+                .set("port", port)
+                .set("debug", "");
+            rules::template(DepGraphBuilder::new(), &template, &out, context)
+                .build()
+                .unwrap()
+        };
+        build(80).make(MakeParams::None).unwrap();
+        assert_eq!(
+            fs::read_to_string(&out).unwrap(),
+            "name = SERVER\nport = 80\n"
+        );
+        assert!(tmp.join("config.context").exists());
+        assert!(build(80)
+            .plan(MakeParams::None)
+            .unwrap()
+            .targets()
+            .is_empty());
+        // so the rewritten stamp is newer, however coarse the file times are
+        File::options()
+            .write(true)
+            .open(&out)
+            .unwrap()
+            .set_modified(SystemTime::now() - Duration::from_secs(5))
+            .unwrap();
+        build(8080).make(MakeParams::None).unwrap();
+        assert_eq!(
+            fs::read_to_string(&out).unwrap(),
+            "name = SERVER\nport = 8080\n"
+        );
+
+        fs::write(&template, "{{ missing }}").unwrap();
+        assert!(build(8080).make(MakeParams::ForceBuild).is_err());
+    }
+
+    #[test]
+    fn sha256() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let digest = |contents: &[u8]| {
+            let path = tmp_dir.path().join("file");
+            fs::write(&path, contents).unwrap();
+            util::sha256_file(&path).unwrap()
+        };
+        assert_eq!(
+            digest(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            digest(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            digest(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+        assert_eq!(
+            digest(&[b'a'; 1_000_000]),
+            "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "compression")]
+    fn compression_rules() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let src = tmp.join("data.txt");
+        let out = tmp.join("data.txt.gz");
+        fs::write(&src, "hello hello hello hello").unwrap();
+        let build = |level| {
+            rules::gzip(DepGraphBuilder::new(), &src, &out, level)
+                .build_log(tmp.join("depgraph.log"))
+                .build()
+                .unwrap()
+        };
+        build(6).make(MakeParams::None).unwrap();
+        assert_eq!(&fs::read(&out).unwrap()[..2], [0x1f, 0x8b]);
+        assert!(build(6)
+            .plan(MakeParams::None)
+            .unwrap()
+            .targets()
+            .is_empty());
+        assert_eq!(
+            build(9).plan(MakeParams::None).unwrap().targets(),
+            std::slice::from_ref(&out)
+        );
+        assert_eq!(fs::read_dir(tmp).unwrap().count(), 3);
+
+        let makegraph = rules::zstd(DepGraphBuilder::new(), &src, tmp.join("data.txt.zst"), 22)
+            .build()
+            .unwrap();
+        makegraph.make(MakeParams::None).unwrap();
+        assert_eq!(
+            &fs::read(tmp.join("data.txt.zst")).unwrap()[..4],
+            [0x28, 0xb5, 0x2f, 0xfd]
+        );
+        let makegraph = rules::brotli(DepGraphBuilder::new(), &src, tmp.join("data.txt.br"), 11)
+            .build()
+            .unwrap();
+        makegraph.make(MakeParams::None).unwrap();
+        let compressed = fs::read(tmp.join("data.txt.br")).unwrap();
+        let mut decompressed = Vec::new();
+        brotli::BrotliDecompress(&mut &compressed[..], &mut decompressed).unwrap();
+        assert_eq!(decompressed, b"hello hello hello hello");
+    }
+
+    #[test]
+    fn verify_rule() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let file = tmp.join("blob");
+        let out = tmp.join("out");
+        fs::write(&file, "abc").unwrap();
+        let stamp = rules::verified_stamp(&file);
+        assert_eq!(stamp, tmp.join("blob.verified"));
+        let sha256 = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        let makegraph = rules::verify_sha256(DepGraphBuilder::new(), &file, sha256)
+            .add_rule(&out, &[&file, &stamp], copy_build)
+            .build()
+            .unwrap();
+        makegraph.make(MakeParams::None).unwrap();
+        assert_eq!(
+            fs::read_to_string(&out).unwrap(),
+            format!("abc{}\n", sha256)
+        );
+
+        fs::write(&file, "abd").unwrap();
+        File::options()
+            .write(true)
+            .open(&file)
+            .unwrap()
+            .set_modified(SystemTime::now() + Duration::from_secs(5))
+            .unwrap();
+        assert!(matches!(
+            makegraph.make(MakeParams::None),
+            Err(Error::BuildFailed { source: msg, .. }) if msg.to_string().contains("checksum mismatch")
+        ));
+        assert!(fs::read_to_string(&out).unwrap().starts_with("abc"));
+    }
+
+    #[test]
+    #[cfg(feature = "fetch")]
+    fn download_rule() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        // a server that answers every request with "abc"
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/remote.txt", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    match stream.read(&mut buf).unwrap() {
+                        0 => break,
+                        n => request.extend_from_slice(&buf[..n]),
+                    }
+                }
+                stream
+                    .write_all(
+                        b"HTTP/1.1 200 OK\r\nContent-Length: 3\r\nConnection: close\r\n\r\nabc",
+                    )
+                    .unwrap();
+            }
+        });
+        let dest = tmp.join("downloads/local.txt");
+        let sha256 = "BA7816BF8F01CFEA414140DE5DAE2223B00361A396177A9CB410FF61F20015AD";
+        let makegraph = rules::download(DepGraphBuilder::new(), &url, &dest, sha256)
+            .build()
+            .unwrap();
+        makegraph.make(MakeParams::None).unwrap();
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "abc");
+        assert!(makegraph
+            .plan(MakeParams::None)
+            .unwrap()
+            .targets()
+            .is_empty());
+
+        // a file with another digest, such as one pinned before, is downloaded again
+        fs::write(&dest, "old").unwrap();
+        assert_eq!(
+            makegraph.plan(MakeParams::None).unwrap().targets(),
+            std::slice::from_ref(&dest)
+        );
+        makegraph.make(MakeParams::None).unwrap();
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "abc");
+
+        let wrong = "0".repeat(64);
+        let makegraph = rules::download(DepGraphBuilder::new(), &url, &dest, &wrong)
+            .build()
+            .unwrap();
+        assert!(matches!(
+            makegraph.make(MakeParams::None),
+            Err(Error::BuildFailed { source: msg, .. }) if msg.to_string().contains("checksum mismatch")
+        ));
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "abc");
+        assert_eq!(fs::read_dir(dest.parent().unwrap()).unwrap().count(), 1);
+    }
+
+    #[test]
+    #[cfg(all(unix, feature = "manifest"))]
+    fn toml_manifest() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        fs::write(tmp.join("a.txt"), "a").unwrap();
+        fs::write(tmp.join("b.txt"), "b").unwrap();
+        let manifest = tmp.join("depgraph.toml");
+        let contents = r#"
+# a comment
+[[rule]]
+output = "DIR/ab.txt"
+deps = [
+    "DIR/a.txt",
+    'DIR/b.txt', # trailing comma
+]
+command = "cat {deps} > {out}"
+
+[[rule]]
+output = "DIR/greeting.txt"
+deps = ["DIR/ab.txt"]
+command = ['sh', '-c', """printf '%s %s' "$GREETING" "$(cat $1)" > $0""", "{out}", "{deps}"]
+env = { GREETING = "hello\tthere" }
+
+[alias]
+greeting = "DIR/greeting.txt"
+"#;
+        fs::write(&manifest, contents.replace("DIR", &tmp.to_string_lossy())).unwrap();
+        let makegraph = DepGraphBuilder::from_toml(&manifest)
+            .unwrap()
+            .build()
+            .unwrap();
+        makegraph.make(MakeParams::None).unwrap();
+        assert_eq!(fs::read_to_string(tmp.join("ab.txt")).unwrap(), "ab");
+        assert_eq!(
+            fs::read_to_string(tmp.join("greeting.txt")).unwrap(),
+            "hello\tthere ab"
+        );
+        assert!(makegraph.is_up_to_date("greeting").unwrap());
+
+        let bad = tmp.join("bad.toml");
+        fs::write(
+            &bad,
+            "[[rule]]\noutput = \"x\"\ncommand = \"true\"\ndeps = [1]\n",
+        )
+        .unwrap();
+        assert!(matches!(
+            DepGraphBuilder::from_toml(&bad),
+            Err(Error::InvalidManifest(_, msg)) if msg == "line 4: invalid type: integer `1`, expected path string"
+        ));
+        fs::write(&bad, "[[rule]]\noutput = \"x\" y\n").unwrap();
+        assert!(matches!(
+            DepGraphBuilder::from_toml(&bad),
+            Err(Error::InvalidManifest(_, msg)) if msg == "line 2: unexpected key or value, expected newline, `#`"
+        ));
+        fs::write(&bad, "[[rule]]\noutput = \"x\"\ncommand = []\n").unwrap();
+        assert!(matches!(
+            DepGraphBuilder::from_toml(&bad),
+            Err(Error::InvalidManifest(_, msg)) if msg == "rule 1: `command` is empty"
+        ));
+        fs::write(
+            &bad,
+            "[[rule]]\noutput = \"x\"\ncommand = \"true\"\ndeps = []\nenvs = {}\n",
+        )
+        .unwrap();
+        assert!(matches!(
+            DepGraphBuilder::from_toml(&bad),
+            Err(Error::InvalidManifest(_, msg)) if msg.starts_with("line 5: unknown field `envs`")
+        ));
+    }
+
+    #[test]
+    #[cfg(all(unix, feature = "manifest"))]
+    fn yaml_manifest() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        fs::write(tmp.join("a.txt"), "a").unwrap();
+        fs::write(tmp.join("b.txt"), "b").unwrap();
+        let manifest = tmp.join("depgraph.yaml");
+        let contents = r#"---
+# a comment
+rule:
+  - output: DIR/ab.txt
+    deps:
+      - DIR/a.txt
+      - 'DIR/b.txt' # quoted
+    command: cat {deps} > {out}
+
+  - output: "DIR/greeting.txt"
+    deps: [
+      DIR/ab.txt,
+    ]
+    command: |
+      printf '%s %s' "$GREETING" "$(cat {deps})" > {out}
+    env: {GREETING: "hello\tthere"}
+
+group:
+  all: [DIR/ab.txt, DIR/greeting.txt]
+"#;
+        fs::write(&manifest, contents.replace("DIR", &tmp.to_string_lossy())).unwrap();
+        let makegraph = DepGraphBuilder::from_yaml(&manifest)
+            .unwrap()
+            .build()
+            .unwrap();
+        makegraph.make_target("all", MakeParams::None).unwrap();
+        assert_eq!(fs::read_to_string(tmp.join("ab.txt")).unwrap(), "ab");
+        assert_eq!(
+            fs::read_to_string(tmp.join("greeting.txt")).unwrap(),
+            "hello\tthere ab"
+        );
+
+        let bad = tmp.join("bad.yaml");
+        fs::write(&bad, "rule:\n  - output: x\n    command: true\n").unwrap();
+        assert!(matches!(
+            DepGraphBuilder::from_yaml(&bad),
+            Err(Error::InvalidManifest(_, msg))
+                if msg == "rule[0]: `command` should be a string or a list of strings at line 2 column 5"
+        ));
+        fs::write(&bad, "rule:\n  - output: x\n     command: y\n").unwrap();
+        assert!(matches!(
+            DepGraphBuilder::from_yaml(&bad),
+            Err(Error::InvalidManifest(_, msg))
+                if msg == "mapping values are not allowed in this context at line 3 column 13"
+        ));
+    }
+
+    #[test]
+    #[cfg(all(unix, feature = "manifest"))]
+    fn json_manifest() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        fs::write(tmp.join("a.txt"), "a").unwrap();
+        let manifest = tmp.join("depgraph.json");
+        let contents = r#"{
+            "rule": [
+                {
+                    "output": "DIR/a.out",
+                    "deps": ["DIR/a.txt"],
+                    "command": ["sh", "-c", "printf \"%s\u00e9\" \"$(cat $1)\" > $0", "{out}", "{deps}"]
+                }
+            ]
+        }"#;
+        fs::write(&manifest, contents.replace("DIR", &tmp.to_string_lossy())).unwrap();
+        let makegraph = DepGraphBuilder::from_json(&manifest)
+            .unwrap()
+            .build()
+            .unwrap();
+        makegraph.make(MakeParams::None).unwrap();
+        assert_eq!(fs::read_to_string(tmp.join("a.out")).unwrap(), "aé");
+
+        let bad = tmp.join("bad.json");
+        fs::write(&bad, "{\"rule\": [{\"output\": \"x\"}]}").unwrap();
+        assert!(matches!(
+            DepGraphBuilder::from_json(&bad),
+            Err(Error::InvalidManifest(_, msg)) if msg == "missing field `command` at line 1 column 25"
+        ));
+        assert!(manifest::JSON_SCHEMA.contains("\"required\": [\"output\", \"command\"]"));
+    }
+
+    #[test]
+    #[cfg(all(unix, feature = "manifest"))]
+    fn compile_commands_import() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        fs::write(tmp.join("a.c"), "a").unwrap();
+        fs::write(tmp.join("b.c"), "b").unwrap();
+        let database = tmp.join("compile_commands.json");
+        let contents = r#"[
+            {
+                "directory": "DIR",
+                "file": "a.c",
+                "command": "sh -c 'cat \"$0\" > \"$2\"' a.c -o a.o"
+            },
+            {
+                "directory": "DIR",
+                "file": "b.c",
+                "arguments": ["sh", "-c", "cat \"$0\" > b.o", "b.c", "-ob.o", "-Iinclude"]
+            }
+        ]"#;
+        fs::write(&database, contents.replace("DIR", &tmp.to_string_lossy())).unwrap();
+        let makegraph = DepGraphBuilder::from_compile_commands(&database)
+            .unwrap()
+            .build()
+            .unwrap();
+        makegraph.make(MakeParams::None).unwrap();
+        assert_eq!(fs::read_to_string(tmp.join("a.o")).unwrap(), "a");
+        assert_eq!(fs::read_to_string(tmp.join("b.o")).unwrap(), "b");
+
+        fs::write(
+            &database,
+            r#"[{"directory": "/", "file": "a.c", "arguments": ["cc", "-c", "a.c"]}]"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            DepGraphBuilder::from_compile_commands(&database),
+            Err(Error::InvalidManifest(_, msg)) if msg == "entry 1: no `-o` argument or `output`"
+        ));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn makefile_import() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        fs::write(tmp.join("a.txt"), "a").unwrap();
+        fs::write(tmp.join("b.txt"), "b").unwrap();
+        let makefile = tmp.join("Makefile");
+        let contents = "\
+# variables
+D := DIR
+SOURCES = $(D)/a.txt \\
+          $(D)/b.txt
+GREETING ?= hello
+GREETING += there
+
+.PHONY: all clean
+all: $(D)/ab.txt $(D)/greeting.txt
+
+$(D)/ab.txt: $(SOURCES)
+\t@cat $^ > $@
+
+$(D)/greeting.txt: $(D)/ab.txt
+\t-false
+\tprintf '%s %s' '$(GREETING)' \"$$(cat $<)\" > $@
+
+clean:
+\trm -f $(D)/ab.txt
+";
+        fs::write(&makefile, contents.replace("DIR", &tmp.to_string_lossy())).unwrap();
+        let makegraph = DepGraphBuilder::from_makefile(&makefile)
+            .unwrap()
+            .build()
+            .unwrap();
+        makegraph.make(MakeParams::None).unwrap();
+        assert_eq!(fs::read_to_string(tmp.join("ab.txt")).unwrap(), "ab");
+        assert_eq!(
+            fs::read_to_string(tmp.join("greeting.txt")).unwrap(),
+            "hello there ab"
+        );
+
+        fs::write(&makefile, "OBJS = $(wildcard *.o)\nall: $(OBJS)\n").unwrap();
+        assert!(matches!(
+            DepGraphBuilder::from_makefile(&makefile),
+            Err(Error::InvalidManifest(_, msg)) if msg == "line 2: functions aren't supported: `$(wildcard *.o)`"
+        ));
+
+        fs::write(
+            &makefile,
+            "all: out\nout: CFLAGS = -O2\nout: in\n\tcc $(CFLAGS) -o $@ $<\n",
+        )
+        .unwrap();
+        assert!(matches!(
+            DepGraphBuilder::from_makefile(&makefile),
+            Err(Error::InvalidManifest(_, msg)) if msg == "line 2: target-specific variables aren't supported"
+        ));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn ninja_import() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        for name in ["a.txt", "b.txt", "extra.txt"] {
+            fs::write(tmp.join(name), name.trim_end_matches(".txt")).unwrap();
+        }
+        fs::write(
+            tmp.join("build.ninja"),
+            "\
+# a comment
+cflags = -x
+rule cat
+  command = cat $in > $out && $
+      echo $flags >> $out
+  description = CAT $out
+
+pool heavy
+  depth = 2
+
+rule gen
+  command = printf one > $out && printf two > header.h
+  restat = 1
+  pool = heavy
+
+build ab.txt: cat a.txt b.txt | extra.txt
+  flags = ${cflags}-y
+build gen.txt | header.h: gen
+build all: phony ab.txt gen.txt
+default all
+",
+        )
+        .unwrap();
+        let makegraph = DepGraphBuilder::from_ninja(tmp.join("build.ninja"))
+            .unwrap()
+            .build()
+            .unwrap();
+        makegraph.make(MakeParams::None).unwrap();
+        assert_eq!(fs::read_to_string(tmp.join("ab.txt")).unwrap(), "ab-x-y\n");
+        assert_eq!(fs::read_to_string(tmp.join("header.h")).unwrap(), "two");
+        assert!(makegraph
+            .plan(MakeParams::None)
+            .unwrap()
+            .targets()
+            .is_empty());
+
+        fs::write(tmp.join("build.ninja"), "build x: missing\n").unwrap();
+        assert!(matches!(
+            DepGraphBuilder::from_ninja(tmp.join("build.ninja")),
+            Err(Error::InvalidManifest(_, msg)) if msg == "line 1: unknown rule `missing`"
+        ));
+    }
+
+    #[test]
+    fn ninja_export() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        fs::write(tmp.join("a.txt"), "a").unwrap();
+        fs::write(tmp.join("b.txt"), "b").unwrap();
+        let makegraph = DepGraphBuilder::new()
+            .add_shell_rule(
+                tmp.join("ab.txt"),
+                &[tmp.join("a.txt"), tmp.join("b.txt")],
+                "cat {deps} > {out}",
+            )
+            .add_command_rule(
+                tmp.join("c.txt"),
+                &[tmp.join("ab.txt")],
+                CommandSpec::new("cp").arg("{deps}").arg("{out}"),
+            )
+            .add_rule(tmp.join("d.txt"), &[tmp.join("a.txt")], copy_build)
+            .add_phony_rule("all", &[tmp.join("c.txt")])
+            .build()
+            .unwrap();
+        let ninja = makegraph.to_ninja();
+        assert!(ninja.contains("  command = cat $in > $out\n"));
+        assert!(ninja.contains("  command = cp $in $out\n"));
+        assert!(ninja.contains(&format!(
+            "build {}: unsupported {}\n",
+            tmp.join("d.txt").display(),
+            tmp.join("a.txt").display()
+        )));
+        assert!(ninja.contains(&format!(
+            "build all: phony {}\n",
+            tmp.join("c.txt").display()
+        )));
+
+        // the exported file builds the same targets when imported again
+        fs::write(tmp.join("build.ninja"), &ninja).unwrap();
+        let imported = DepGraphBuilder::from_ninja(tmp.join("build.ninja"))
+            .unwrap()
+            .build()
+            .unwrap();
+        imported.make(MakeParams::KeepGoing).unwrap_err();
+        assert_eq!(fs::read_to_string(tmp.join("c.txt")).unwrap(), "ab");
+        assert!(!tmp.join("d.txt").exists());
+    }
+
+    #[test]
+    #[cfg(not(feature = "cc"))]
+    fn compile_commands_export() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let cc = rules::CBuild::new()
+            .compiler("clang")
+            .define("NDEBUG", None);
+        let builder = rules::c_object(
+            DepGraphBuilder::new(),
+            tmp.join("main.c"),
+            tmp.join("main.o"),
+            &cc,
+        );
+        let makegraph = builder
+            .add_shell_rule(
+                tmp.join("start.o"),
+                &[tmp.join("start.s")],
+                "as -o {out} {deps}",
+            )
+            .add_shell_rule(tmp.join("app"), &[tmp.join("main.o")], "ld -o {out} {deps}")
+            .build()
+            .unwrap();
+        let mut out = Vec::new();
+        makegraph.write_compile_commands(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let dir = std::env::current_dir().unwrap();
+        let main = format!(
+            "{{\"directory\": \"{}\", \"file\": \"{}\", \"output\": \"{}\", \"arguments\": \
+             [\"clang\", \"-DNDEBUG\", \"-c\", \"{1}\", \"-o\", \"{2}\"]}}",
+            dir.display(),
+            tmp.join("main.c").display(),
+            tmp.join("main.o").display()
+        );
+        let start = format!(
+            "{{\"directory\": \"{}\", \"file\": \"{}\", \"output\": \"{}\", \"command\": \
+             \"as -o {2} {1}\"}}",
+            dir.display(),
+            tmp.join("start.s").display(),
+            tmp.join("start.o").display()
+        );
+        assert!(out.contains(&main), "{}", out);
+        assert!(out.contains(&start), "{}", out);
+        // linking isn't compiling a source file
+        assert!(!out.contains("ld -o"));
+    }
+
+    #[test]
+    #[cfg(feature = "watch")]
+    fn watch() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        fs::write(tmp.join("in"), "one").unwrap();
+        let makegraph = DepGraphBuilder::new()
+            .add_rule(tmp.join("out"), &[tmp.join("in")], copy_build)
+            .build()
+            .unwrap();
+        let mut builds = 0;
+        makegraph
+            .watch_with_interval(
+                MakeParams::None,
+                Duration::from_millis(10),
+                |changed, result| {
+                    result.unwrap();
+                    builds += 1;
+                    if builds == 1 {
+                        assert!(changed.is_empty());
+                        assert_eq!(fs::read_to_string(tmp.join("out")).unwrap(), "one");
+                        // newer than `out` even if the clock hasn't ticked since it was built
+                        let mut file = File::create(tmp.join("in")).unwrap();
+                        file.write_all(b"three").unwrap();
+                        file.set_modified(SystemTime::now() + Duration::from_secs(5))
+                            .unwrap();
+                        true
+                    } else {
+                        assert_eq!(changed, [tmp.join("in")]);
+                        assert_eq!(fs::read_to_string(tmp.join("out")).unwrap(), "three");
+                        false
+                    }
+                },
+            )
+            .unwrap();
+        assert_eq!(builds, 2);
+    }
+
+    #[test]
+    #[cfg(feature = "watch")]
+    fn watch_uses_notifications() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path().to_owned();
+        fs::write(tmp.join("in"), "one").unwrap();
+        let makegraph = DepGraphBuilder::new()
+            .add_rule(tmp.join("out"), &[tmp.join("in")], copy_build)
+            .build()
+            .unwrap();
+        let mut builds = 0;
+        // polling this rarely would never see the change
+        makegraph
+            .watch_with_interval(MakeParams::None, Duration::from_secs(3600), |_, result| {
+                result.unwrap();
+                builds += 1;
+                if builds == 1 {
+                    // change the file once `watch` is waiting, replacing it in one step so the
+                    // rebuild can't see it half written
+                    let tmp = tmp.clone();
+                    std::thread::spawn(move || {
+                        std::thread::sleep(Duration::from_millis(200));
+                        let mut file = File::create(tmp.join("in.new")).unwrap();
+                        file.write_all(b"three").unwrap();
+                        file.set_modified(SystemTime::now() + Duration::from_secs(5))
+                            .unwrap();
+                        fs::rename(tmp.join("in.new"), tmp.join("in")).unwrap();
+                    });
+                }
+                builds == 1
+            })
+            .unwrap();
+        assert_eq!(fs::read_to_string(tmp.join("out")).unwrap(), "three");
+    }
+
+    #[test]
+    #[cfg(all(feature = "watch", feature = "parallel"))]
+    fn daemon() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        fs::write(tmp.join("a.in"), "a").unwrap();
+        fs::write(tmp.join("b.in"), "b").unwrap();
+        let makegraph = DepGraphBuilder::new()
+            .add_rule(tmp.join("a.out"), &[tmp.join("a.in")], copy_build)
+            .add_rule(tmp.join("b.out"), &[tmp.join("b.in")], copy_build)
+            .build()
+            .unwrap();
+        let (send, rebuilds) = mpsc::channel();
+        let daemon = makegraph.into_daemon_with_interval(
+            MakeParams::None,
+            Duration::from_millis(10),
+            move |changed, result| {
+                send.send((changed.to_vec(), result.is_ok())).unwrap();
+            },
+        );
+        let timeout = Duration::from_secs(10);
+        assert_eq!(rebuilds.recv_timeout(timeout).unwrap(), (vec![], true));
+        fs::write(tmp.join("a.in"), "aa").unwrap();
+        assert_eq!(
+            rebuilds.recv_timeout(timeout).unwrap(),
+            (vec![tmp.join("a.in")], true)
+        );
+        assert_eq!(fs::read_to_string(tmp.join("a.out")).unwrap(), "aa");
+
+        fs::remove_file(tmp.join("b.out")).unwrap();
+        daemon.build_target(tmp.join("b.out")).unwrap();
+        assert_eq!(fs::read_to_string(tmp.join("b.out")).unwrap(), "b");
+        assert!(matches!(
+            daemon.build_target(tmp.join("c.out")),
+            Err(Error::UnknownTarget(_))
+        ));
+        daemon.stop();
+    }
+
+    #[test]
+    #[cfg(all(feature = "watch", feature = "parallel"))]
+    fn daemon_polls_between_requests() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        fs::write(tmp.join("a.in"), "a").unwrap();
+        fs::write(tmp.join("b.in"), "b").unwrap();
+        let makegraph = DepGraphBuilder::new()
+            .add_rule(tmp.join("a.out"), &[tmp.join("a.in")], copy_build)
+            .add_rule(tmp.join("b.out"), &[tmp.join("b.in")], copy_build)
+            .build()
+            .unwrap();
+        let (send, rebuilds) = mpsc::channel();
+        let daemon = makegraph.into_daemon_with_interval(
+            MakeParams::None,
+            Duration::from_millis(50),
+            move |changed, result| {
+                send.send((changed.to_vec(), result.is_ok())).unwrap();
+            },
+        );
+        let timeout = Duration::from_secs(10);
+        assert_eq!(rebuilds.recv_timeout(timeout).unwrap(), (vec![], true));
+        fs::write(tmp.join("a.in"), "aa").unwrap();
+        // ask for builds more often than the poll interval until the change is noticed
+        let start = Instant::now();
+        let rebuild = loop {
+            daemon.build_target(tmp.join("b.out")).unwrap();
+            if let Ok(rebuild) = rebuilds.try_recv() {
+                break rebuild;
+            }
+            assert!(start.elapsed() < timeout, "the change was never polled for");
+            std::thread::sleep(Duration::from_millis(5));
+        };
+        assert_eq!(rebuild, (vec![tmp.join("a.in")], true));
+        assert_eq!(fs::read_to_string(tmp.join("a.out")).unwrap(), "aa");
+    }
+
+    #[test]
+    fn make_target() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        fs::write(tmp.join("in"), "in").unwrap();
+        let makegraph = DepGraphBuilder::new()
+            .add_rule(tmp.join("a"), &[tmp.join("in")], copy_build)
+            .add_rule(tmp.join("b"), &[tmp.join("a")], copy_build)
+            .add_rule(tmp.join("c"), &[tmp.join("in")], copy_build)
+            .build()
+            .unwrap();
+        makegraph
+            .make_target(tmp.join("b"), MakeParams::None)
+            .unwrap();
+        assert!(tmp.join("a").exists());
+        assert!(tmp.join("b").exists());
+        assert!(!tmp.join("c").exists());
+        assert!(matches!(
+            makegraph.make_target(tmp.join("c"), MakeParams::CheckOnly),
+            Err(Error::OutOfDate(stale)) if stale == [tmp.join("c")]
+        ));
+        assert!(matches!(
+            makegraph.make_target(tmp.join("d"), MakeParams::None),
+            Err(Error::UnknownTarget(_))
+        ));
+    }
+
+    #[test]
+    fn make_where() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        fs::create_dir(tmp.join("shaders")).unwrap();
+        fs::write(tmp.join("in"), "in").unwrap();
+        let makegraph = DepGraphBuilder::new()
+            .add_rule(tmp.join("common"), &[tmp.join("in")], copy_build)
+            .add_rule(tmp.join("shaders/a"), &[tmp.join("common")], copy_build)
+            .add_rule(tmp.join("shaders/b"), &[tmp.join("in")], copy_build)
+            .add_rule(tmp.join("other"), &[tmp.join("in")], copy_build)
+            .build()
+            .unwrap();
+        let shaders = tmp.join("shaders");
+        makegraph
+            .make_where(MakeParams::None, |path| path.starts_with(&shaders))
+            .unwrap();
+        assert!(tmp.join("common").exists());
+        assert!(tmp.join("shaders/a").exists());
+        assert!(tmp.join("shaders/b").exists());
+        assert!(!tmp.join("other").exists());
+    }
+
+    #[test]
+    fn subgraph() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        fs::write(tmp.join("in"), "in").unwrap();
+        let makegraph = DepGraphBuilder::new()
+            .add_rule(tmp.join("a"), &[tmp.join("in")], copy_build)
+            .add_rule(tmp.join("b"), &[tmp.join("a"), tmp.join("in")], copy_build)
+            .add_rule(tmp.join("c"), &[tmp.join("in")], copy_build)
+            .alias("first", tmp.join("a"))
+            .alias("third", tmp.join("c"))
+            .build()
+            .unwrap();
+        let sub = makegraph.subgraph(&[tmp.join("b")]).unwrap();
+        assert!(!sub.to_dot().contains(&*tmp.join("c").to_string_lossy()));
+        sub.make(MakeParams::None).unwrap();
+        assert_eq!(fs::read_to_string(tmp.join("b")).unwrap(), "inin");
+        assert!(!tmp.join("c").exists());
+        assert!(sub.is_up_to_date("first").unwrap());
+        assert!(matches!(
+            sub.make_target("third", MakeParams::None),
+            Err(Error::UnknownTarget(_))
+        ));
+        assert!(matches!(
+            makegraph.subgraph(&[tmp.join("d")]),
+            Err(Error::UnknownTarget(_))
+        ));
+    }
+
+    #[test]
+    fn dependency_queries() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let makegraph = DepGraphBuilder::new()
+            .add_rule(
+                tmp.join("a.o"),
+                &[tmp.join("a.c"), tmp.join("a.h")],
+                copy_build,
+            )
+            .add_rule(tmp.join("b.o"), &[tmp.join("b.c")], copy_build)
+            .add_rule(
+                tmp.join("app"),
+                &[tmp.join("a.o"), tmp.join("b.o")],
+                copy_build,
+            )
+            .build()
+            .unwrap();
+        assert_eq!(
+            makegraph.dependencies_of(tmp.join("a.o")).unwrap(),
+            [tmp.join("a.c"), tmp.join("a.h")]
+        );
+        let all = makegraph.all_dependencies_of(tmp.join("app")).unwrap();
+        assert_eq!(all.len(), 5);
+        assert!(
+            all.iter().position(|p| *p == tmp.join("a.h"))
+                < all.iter().position(|p| *p == tmp.join("a.o"))
+        );
+        assert_eq!(
+            makegraph.dependents_of(tmp.join("a.h")).unwrap(),
+            [tmp.join("a.o")]
+        );
+        assert_eq!(
+            makegraph.all_dependents_of(tmp.join("a.h")).unwrap(),
+            [tmp.join("a.o"), tmp.join("app")]
+        );
+        assert!(makegraph
+            .all_dependents_of(tmp.join("app"))
+            .unwrap()
+            .is_empty());
+        assert!(matches!(
+            makegraph.dependents_of(tmp.join("c.h")),
+            Err(Error::UnknownTarget(_))
+        ));
+    }
+
+    #[test]
+    fn roots_and_leaves() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let makegraph = DepGraphBuilder::new()
+            .add_rule(tmp.join("a.o"), &[tmp.join("a.c")], copy_build)
+            .add_rule_multi(
+                &[tmp.join("app"), tmp.join("app.map")],
+                &[tmp.join("a.o")],
+                |_, _| Ok(()),
+            )
+            .add_rule(tmp.join("docs"), &[tmp.join("README")], copy_build)
+            .group("all", &[tmp.join("app"), tmp.join("docs")])
+            .build()
+            .unwrap();
+        assert_eq!(
+            makegraph.roots().unwrap(),
+            [tmp.join("app"), tmp.join("app.map"), tmp.join("docs")]
+        );
+        let mut leaves = makegraph.leaves().unwrap();
+        leaves.sort();
+        assert_eq!(leaves, [tmp.join("README"), tmp.join("a.c")]);
+    }
+
+    #[test]
+    fn critical_path() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let log = [("a", 100), ("b", 300), ("c", 10), ("d", 50), ("e", 200)]
+            .iter()
+            .map(|(target, ms)| format!("{}\t0\t0\t0\t{}\n", ms, tmp.join(target).display()))
+            .collect::<String>();
+        fs::write(tmp.join("log"), format!("# depgraph log v4\n{}", log)).unwrap();
+        let builder = || {
+            DepGraphBuilder::new()
+                .add_rule(tmp.join("a"), &[tmp.join("in")], copy_build)
+                .add_rule(tmp.join("b"), &[tmp.join("in")], copy_build)
+                .add_rule(tmp.join("c"), &[tmp.join("a"), tmp.join("b")], copy_build)
+                .add_rule(tmp.join("d"), &[tmp.join("c")], copy_build)
+                .add_rule(tmp.join("e"), &[tmp.join("in")], copy_build)
+        };
+        let makegraph = builder().build_log(tmp.join("log")).build().unwrap();
+        let ms = Duration::from_millis;
+        assert_eq!(
+            makegraph.critical_path().unwrap(),
+            [
+                (tmp.join("b").as_path(), ms(300)),
+                (tmp.join("c").as_path(), ms(10)),
+                (tmp.join("d").as_path(), ms(50)),
+            ]
+        );
+        assert!(builder()
+            .build()
+            .unwrap()
+            .critical_path()
+            .unwrap()
+            .is_empty());
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_longest_first() {
+        use std::sync::Mutex;
+
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        fs::write(tmp.join("in"), "in").unwrap();
+        let log = [("short", 10), ("long", 500), ("final", 100)]
+            .iter()
+            .map(|(target, ms)| format!("{}\t0\t0\t0\t{}\n", ms, tmp.join(target).display()))
+            .collect::<String>();
+        fs::write(tmp.join("log"), format!("# depgraph log v4\n{}", log)).unwrap();
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let record = |order: &Arc<Mutex<Vec<PathBuf>>>| {
+            let order = order.clone();
+            move |out: &Path, deps: &[&Path]| {
+                order.lock().unwrap().push(out.to_owned());
+                copy_build(out, deps)
+            }
+        };
+        let makegraph = DepGraphBuilder::new()
+            .add_rule(tmp.join("short"), &[tmp.join("in")], record(&order))
+            .add_rule(tmp.join("long"), &[tmp.join("in")], record(&order))
+            .add_rule(tmp.join("final"), &[tmp.join("long")], record(&order))
+            .build_log(tmp.join("log"))
+            .build()
+            .unwrap();
+        makegraph.make_parallel(MakeParams::None, 1).unwrap();
+        assert_eq!(
+            *order.lock().unwrap(),
+            [tmp.join("long"), tmp.join("final"), tmp.join("short")]
+        );
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn rule_priority() {
+        use std::sync::Mutex;
+
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        fs::write(tmp.join("in"), "in").unwrap();
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let record = |order: &Arc<Mutex<Vec<PathBuf>>>| {
+            let order = order.clone();
+            move |out: &Path, deps: &[&Path]| {
+                order.lock().unwrap().push(out.to_owned());
+                copy_build(out, deps)
+            }
+        };
+        let makegraph = DepGraphBuilder::new()
+            .add_rule(tmp.join("a"), &[tmp.join("in")], record(&order))
+            .add_rule(tmp.join("b"), &[tmp.join("in")], record(&order))
+            .add_rule(tmp.join("obj"), &[tmp.join("in")], record(&order))
+            .add_rule_with_opts(
+                tmp.join("link"),
+                &[tmp.join("obj")],
+                RuleOptions {
+                    priority: 10,
+                    ..Default::default()
+                },
+                record(&order),
+            )
+            .build()
+            .unwrap();
+        makegraph.make_parallel(MakeParams::None, 1).unwrap();
+        assert_eq!(
+            *order.lock().unwrap(),
+            [
+                tmp.join("obj"),
+                tmp.join("link"),
+                tmp.join("a"),
+                tmp.join("b")
+            ]
+        );
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn pools() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        fs::write(tmp.join("in"), "in").unwrap();
+        let running = Arc::new(AtomicUsize::new(0));
+        let most = Arc::new(AtomicUsize::new(0));
+        let link = |running: &Arc<AtomicUsize>, most: &Arc<AtomicUsize>| {
+            let (running, most) = (running.clone(), most.clone());
+            move |out: &Path, deps: &[&Path]| {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                most.fetch_max(now, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(20));
+                running.fetch_sub(1, Ordering::SeqCst);
+                copy_build(out, deps)
+            }
+        };
+        let options = RuleOptions {
+            pool: Some("link".to_owned()),
+            ..Default::default()
+        };
+        let builder = || {
+            let mut builder = DepGraphBuilder::new();
+            for name in ["a", "b", "c"] {
+                builder = builder.add_rule_with_opts(
+                    tmp.join(name),
+                    &[tmp.join("in")],
+                    options.clone(),
+                    link(&running, &most),
+                );
+            }
+            builder
+        };
+        let makegraph = builder().pool("link", 1).build().unwrap();
+        makegraph.make_parallel(MakeParams::None, 3).unwrap();
+        assert_eq!(most.load(Ordering::SeqCst), 1);
+        assert!(makegraph.to_ninja().contains("pool link\n  depth = 1\n"));
+        assert!(matches!(builder().build(), Err(Error::UnknownPool(pool)) if pool == "link"));
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn console_pool() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        fs::write(tmp.join("in"), "in").unwrap();
+        let running = Arc::new(AtomicUsize::new(0));
+        let most = Arc::new(AtomicUsize::new(0));
+        let options = RuleOptions {
+            pool: Some(CONSOLE_POOL.to_owned()),
+            ..Default::default()
+        };
+        let mut builder = DepGraphBuilder::new().add_command_rule_with_opts(
+            tmp.join("cmd"),
+            &[tmp.join("in")],
+            options.clone(),
+            CommandSpec::shell("cp {deps} {out}"),
+        );
+        for name in ["a", "b"] {
+            let (running, most) = (running.clone(), most.clone());
+            builder = builder.add_rule_with_opts(
+                tmp.join(name),
+                &[tmp.join("in")],
+                options.clone(),
+                move |out, deps| {
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    most.fetch_max(now, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(20));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    copy_build(out, deps)
+                },
+            );
+        }
+        let makegraph = builder.build().unwrap();
+        makegraph.make_parallel(MakeParams::None, 3).unwrap();
+        assert_eq!(most.load(Ordering::SeqCst), 1);
+        assert_eq!(fs::read_to_string(tmp.join("cmd")).unwrap(), "in");
+        let ninja = makegraph.to_ninja();
+        assert!(ninja.contains("ninja_required_version = 1.5\n"));
+        assert!(ninja.contains("  pool = console\n"));
+        assert!(!ninja.contains("pool console"));
+    }
+
+    #[test]
+    fn stateful_build_fn() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        fs::write(tmp.join("in"), "in").unwrap();
+        let mut runs = 0;
+        let makegraph = DepGraphBuilder::new()
+            .add_rule_mut(tmp.join("out"), &[tmp.join("in")], move |out, _| {
+                runs += 1;
+                fs::write(out, runs.to_string()).map_err(|e| e.to_string())
+            })
+            .build()
+            .unwrap();
+        makegraph.make(MakeParams::ForceBuild).unwrap();
+        makegraph.make(MakeParams::ForceBuild).unwrap();
+        assert_eq!(fs::read_to_string(tmp.join("out")).unwrap(), "2");
+    }
+
+    #[cfg(not(feature = "parallel"))]
+    #[test]
+    fn build_fn_not_send() {
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        fs::write(tmp.join("in"), "in").unwrap();
+        let runs = Rc::new(Cell::new(0));
+        let counter = runs.clone();
+        let makegraph = DepGraphBuilder::new()
+            .add_rule(tmp.join("out"), &[tmp.join("in")], move |out, deps| {
+                counter.set(counter.get() + 1);
+                copy_build(out, deps)
+            })
+            .build()
+            .unwrap();
+        makegraph.make(MakeParams::None).unwrap();
+        assert_eq!(runs.get(), 1);
+    }
+
+    #[test]
+    fn one_shot_build_fn() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        fs::write(tmp.join("in"), "in").unwrap();
+        let contents = String::from("moved");
+        let builder = || {
+            let contents = contents.clone();
+            DepGraphBuilder::new().add_rule_once(
+                tmp.join("out"),
+                &[tmp.join("in")],
+                move |out, _| fs::write(out, contents).map_err(|e| e.to_string()),
+            )
+        };
+        builder()
+            .build()
+            .unwrap()
+            .make_once(MakeParams::None)
+            .unwrap();
+        assert_eq!(fs::read_to_string(tmp.join("out")).unwrap(), "moved");
+
+        let makegraph = builder().build().unwrap();
+        makegraph.make(MakeParams::ForceBuild).unwrap();
+        assert!(matches!(
+            makegraph.make(MakeParams::ForceBuild),
+            Err(Error::BuildFailed { .. })
+        ));
+    }
+
+    #[test]
+    fn scoped_build_fn() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        fs::write(tmp.join("in"), "in").unwrap();
+        let banner = String::from("banner");
+        let makegraph = DepGraphBuilder::new()
+            .add_scoped_rule(tmp.join("out"), &[tmp.join("in")], |out, _| {
+                fs::write(out, &banner).map_err(|e| e.to_string())
+            })
+            .add_scoped_rule_with_opts(
+                tmp.join("slow"),
+                &[tmp.join("in")],
+                RuleOptions {
+                    timeout: Some(Duration::from_millis(1)),
+                    ..Default::default()
+                },
+                |out, deps| {
+                    thread::sleep(Duration::from_millis(20));
+                    copy_build(out, deps)
+                },
+            )
+            .build()
+            .unwrap();
+        match makegraph.make(MakeParams::KeepGoing) {
+            Err(Error::BuildsFailed { failed, .. }) => {
+                assert!(
+                    matches!(&failed[..], [(_, Error::Timeout(path))] if *path == tmp.join("slow"))
+                )
+            }
+            other => panic!("expected a timeout, got {:?}", other),
+        }
+        assert_eq!(fs::read_to_string(tmp.join("out")).unwrap(), banner);
+        assert!(tmp.join("slow").exists());
+    }
+
+    #[test]
+    fn build_failed_target() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        fs::write(tmp.join("in"), "in").unwrap();
+        let makegraph = DepGraphBuilder::new()
+            .add_rule(tmp.join("out"), &[tmp.join("in")], |_, _| {
+                Err("oops".to_owned())
+            })
+            .build()
+            .unwrap();
+        match makegraph.make(MakeParams::None) {
+            Err(Error::BuildFailed {
+                target,
+                dependencies,
+                source,
+            }) => {
+                assert_eq!(target, tmp.join("out"));
+                assert_eq!(dependencies, vec![tmp.join("in")]);
+                assert_eq!(source.to_string(), "oops");
+            }
+            other => panic!("expected a build failure, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn build_error_source() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let makegraph = DepGraphBuilder::new()
+            .add_fallible_rule(tmp.join("out"), &[] as &[&Path], |out, _| {
+                fs::read(out.with_extension("missing"))?;
+                Ok(())
+            })
+            .build()
+            .unwrap();
+        match makegraph.make(MakeParams::None) {
+            Err(e @ Error::BuildFailed { .. }) => {
+                let source = std::error::Error::source(&e).unwrap();
+                let io = source.downcast_ref::<io::Error>().unwrap();
+                assert_eq!(io.kind(), io::ErrorKind::NotFound);
+            }
+            other => panic!("expected a build failure, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn cycle() {
+        let result = DepGraphBuilder::new()
+            .add_rule("in", &[] as &[&Path], copy_build)
+            .add_rule("a", &["in", "b"], copy_build)
+            .add_rule("b", &["c"], copy_build)
+            .add_rule("c", &["a"], copy_build)
+            .build();
+        match result {
+            Err(e @ Error::Cycle(_)) => assert_eq!(
+                e.to_string(),
+                "cyclic dependencies detected: a -> b -> c -> a"
+            ),
+            other => panic!("expected a cycle, got {:?}", other.err()),
+        }
+        let result = DepGraphBuilder::new()
+            .add_rule("a", &["a"], copy_build)
+            .build();
+        assert!(matches!(result, Err(Error::Cycle(cycle)) if cycle == [PathBuf::from("a")]));
+    }
+
+    #[test]
+    fn aliases() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        fs::write(tmp.join("in"), "in").unwrap();
+        let makegraph = DepGraphBuilder::new()
+            .add_rule(tmp.join("a"), &[tmp.join("in")], copy_build)
+            .add_rule(tmp.join("b"), &[tmp.join("in")], copy_build)
+            .alias("first", tmp.join("a"))
+            .build()
+            .unwrap();
+        assert_eq!(
+            makegraph.explain("first", MakeParams::None).unwrap(),
+            RebuildReason::MissingOutput
+        );
+        makegraph.make_target("first", MakeParams::None).unwrap();
+        assert!(tmp.join("a").exists());
+        assert!(!tmp.join("b").exists());
+
+        let result = DepGraphBuilder::new()
+            .add_rule(tmp.join("a"), &[tmp.join("in")], copy_build)
+            .alias("first", tmp.join("c"))
+            .build();
+        assert!(matches!(result, Err(Error::UnknownTarget(path)) if path == tmp.join("c")));
+    }
+
+    #[test]
+    fn groups() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        fs::write(tmp.join("in"), "in").unwrap();
+        let makegraph = DepGraphBuilder::new()
+            .add_rule(tmp.join("a"), &[tmp.join("in")], copy_build)
+            .add_rule(tmp.join("b"), &[tmp.join("in")], copy_build)
+            .add_rule(tmp.join("c"), &[tmp.join("in")], copy_build)
+            .group("some", &[tmp.join("a"), tmp.join("b")])
+            .add_rule(tmp.join("ab"), &[Path::new("some")], copy_build)
+            .build()
+            .unwrap();
+        makegraph.make_target("some", MakeParams::None).unwrap();
+        assert!(tmp.join("a").exists());
+        assert!(tmp.join("b").exists());
+        assert!(!tmp.join("c").exists());
+        makegraph
+            .make_target(tmp.join("ab"), MakeParams::None)
+            .unwrap();
+        assert_eq!(fs::read_to_string(tmp.join("ab")).unwrap(), "inin");
+    }
+
+    #[test]
+    fn default_target() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        fs::write(tmp.join("in"), "in").unwrap();
+        let makegraph = DepGraphBuilder::new()
+            .add_rule(tmp.join("a"), &[tmp.join("in")], copy_build)
+            .add_rule(tmp.join("docs"), &[tmp.join("in")], copy_build)
+            .group("release", &[tmp.join("a")])
+            .default_target("release")
+            .build()
+            .unwrap();
+        assert_eq!(
+            makegraph.plan(MakeParams::None).unwrap().targets(),
+            [tmp.join("a")]
+        );
+        makegraph.make(MakeParams::None).unwrap();
+        assert!(tmp.join("a").exists());
+        assert!(!tmp.join("docs").exists());
+        makegraph.verify().unwrap();
+        makegraph.make_all(MakeParams::None).unwrap();
+        assert!(tmp.join("docs").exists());
+
+        let result = DepGraphBuilder::new()
+            .add_rule(tmp.join("a"), &[tmp.join("in")], copy_build)
+            .default_target("all")
+            .build();
+        assert!(matches!(result, Err(Error::UnknownTarget(_))));
+    }
+
+    #[test]
+    fn custom_targets() {
+        use std::sync::Mutex;
+
+        /// A table in a database, with the time it was last loaded.
+        struct Table(Arc<Mutex<Option<SystemTime>>>);
+
+        impl Target for Table {
+            fn key(&self) -> PathBuf {
+                PathBuf::from("table:users")
+            }
+
+            fn is_present(&self) -> DepResult<bool> {
+                Ok(self.0.lock().unwrap().is_some())
+            }
+
+            fn stamp(&self) -> DepResult<Option<SystemTime>> {
+                Ok(*self.0.lock().unwrap())
+            }
+        }
+
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        fs::write(tmp.join("users.csv"), "users").unwrap();
+        let loaded = Arc::new(Mutex::new(None));
+        let loads = Arc::new(Mutex::new(0));
+        let (table_loaded, table_loads) = (loaded.clone(), loads.clone());
+        let report = tmp.join("report");
+        let makegraph = DepGraphBuilder::new()
+            .add_target_rule(
+                Table(loaded.clone()),
+                &[tmp.join("users.csv")],
+                move |out, _| {
+                    assert_eq!(out, Path::new("table:users"));
+                    *table_loaded.lock().unwrap() = Some(SystemTime::now());
+                    *table_loads.lock().unwrap() += 1;
+                    Ok(())
+                },
+            )
+            .add_rule(&report, &[Path::new("table:users")], |out, _| {
+                fs::write(out, "report").map_err(|e| e.to_string())
+            })
+            .build()
+            .unwrap();
+        assert_eq!(
+            makegraph.explain("table:users", MakeParams::None).unwrap(),
+            RebuildReason::MissingOutput
+        );
+        makegraph.make(MakeParams::None).unwrap();
+        assert_eq!(*loads.lock().unwrap(), 1);
+        assert!(report.exists());
+        makegraph.make(MakeParams::None).unwrap();
+        assert_eq!(*loads.lock().unwrap(), 1);
+        makegraph.verify().unwrap();
+
+        // reloading the table makes the report stale
+        *loaded.lock().unwrap() = Some(SystemTime::now() + Duration::from_secs(60));
+        assert_eq!(
+            makegraph.explain(&report, MakeParams::None).unwrap(),
+            RebuildReason::DepNewer(PathBuf::from("table:users"))
+        );
+        assert_eq!(makegraph.clean().unwrap(), [tmp.join("report")]);
+
+        let result = DepGraphBuilder::new()
+            .add_external_target(Table(Arc::new(Mutex::new(None))))
+            .add_rule(&report, &[Path::new("table:users")], |_, _| Ok(()))
+            .build()
+            .unwrap()
+            .make(MakeParams::None);
+        assert!(
+            matches!(result, Err(Error::MissingFile(path)) if path == Path::new("table:users"))
+        );
+    }
+
+    #[test]
+    fn memory_targets() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        fs::write(tmp.join("in"), "in").unwrap();
+        let upper = MemoryTarget::new("upper");
+        let (set_upper, get_upper) = (upper.clone(), upper.clone());
+        let loads = Arc::new(AtomicUsize::new(0));
+        let writes = Arc::new(AtomicUsize::new(0));
+        let (loads2, writes2) = (loads.clone(), writes.clone());
+        let makegraph = DepGraphBuilder::new()
+            .add_target_rule(upper.clone(), &[tmp.join("in")], move |_, deps| {
+                loads2.fetch_add(1, Ordering::SeqCst);
+                let text = fs::read_to_string(deps[0]).map_err(|e| e.to_string())?;
+                set_upper.set(text.to_uppercase());
+                Ok(())
+            })
+            .add_rule(tmp.join("out"), &[Path::new("upper")], move |out, _| {
+                writes2.fetch_add(1, Ordering::SeqCst);
+                let text = get_upper.get().ok_or("upper wasn't built")?;
+                fs::write(out, text).map_err(|e| e.to_string())
+            })
+            .build()
+            .unwrap();
+        makegraph.make(MakeParams::None).unwrap();
+        makegraph.make(MakeParams::None).unwrap();
+        assert_eq!(fs::read_to_string(tmp.join("out")).unwrap(), "IN");
+        assert_eq!(loads.load(Ordering::SeqCst), 1);
+        assert_eq!(writes.load(Ordering::SeqCst), 1);
+
+        let touch_in = |contents: &str, secs| {
+            fs::write(tmp.join("in"), contents).unwrap();
+            File::options()
+                .write(true)
+                .open(tmp.join("in"))
+                .unwrap()
+                .set_modified(SystemTime::now() + Duration::from_secs(secs))
+                .unwrap();
+        };
+        // the value is rebuilt the same, so `out` isn't
+        touch_in("in", 5);
+        makegraph.make(MakeParams::None).unwrap();
+        assert_eq!(loads.load(Ordering::SeqCst), 2);
+        assert_eq!(writes.load(Ordering::SeqCst), 1);
+        touch_in("other", 10);
+        makegraph.make(MakeParams::None).unwrap();
+        assert_eq!(fs::read_to_string(tmp.join("out")).unwrap(), "OTHER");
+        assert_eq!(writes.load(Ordering::SeqCst), 2);
+
+        upper.clear();
+        assert_eq!(upper.get(), None);
+        assert!(!makegraph.is_up_to_date("upper").unwrap());
+    }
+
+    #[test]
+    fn rule_freshness_checker() {
+        use std::sync::Mutex;
+
+        /// Tables in a database, which exist once created.
+        #[derive(Default)]
+        struct Tables(Mutex<HashSet<PathBuf>>);
+
+        impl FreshnessChecker for Tables {
+            fn is_stale(&self, target: &Path, _: &[&Path]) -> DepResult<bool> {
+                Ok(!self.exists(target)?)
+            }
+
+            fn exists(&self, target: &Path) -> DepResult<bool> {
+                Ok(self.0.lock().unwrap().contains(target))
+            }
+        }
+
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        fs::write(tmp.join("schema.sql"), "create table users").unwrap();
+        let tables = Arc::new(Tables::default());
+        let options = RuleOptions {
+            freshness_checker: Some(tables.clone()),
+            ..Default::default()
+        };
+        let created = tables.clone();
+        let makegraph = DepGraphBuilder::new()
+            .add_rule_with_opts(
+                "table:users",
+                &[tmp.join("schema.sql")],
+                options.clone(),
+                move |out, _| {
+                    created.0.lock().unwrap().insert(out.to_owned());
+                    Ok(())
+                },
+            )
+            .add_rule_with_opts("table:posts", &[tmp.join("schema.sql")], options, |_, _| {
+                Ok(())
+            })
+            .build()
+            .unwrap();
+        assert!(!makegraph.is_up_to_date("table:users").unwrap());
+        makegraph
+            .make_target("table:users", MakeParams::None)
+            .unwrap();
+        assert!(makegraph.is_up_to_date("table:users").unwrap());
+        // the second rule doesn't create its table
+        assert!(matches!(
+            makegraph.make_target("table:posts", MakeParams::None),
+            Err(Error::MissingFile(path)) if path == Path::new("table:posts")
+        ));
+    }
+
+    #[test]
+    fn custom_freshness_checker() {
+        struct AlwaysStale;
+        impl FreshnessChecker for AlwaysStale {
+            fn is_stale(&self, _: &Path, _: &[&Path]) -> DepResult<bool> {
+                Ok(true)
+            }
+        }
+
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let makegraph = DepGraphBuilder::new()
+            .add_rule(tmp.join("out"), &[tmp.join("in")], copy_build)
+            .freshness_checker(AlwaysStale)
+            .build()
+            .unwrap();
+        fs::write(tmp.join("in"), "one").unwrap();
+        makegraph.make(MakeParams::None).unwrap();
+        // make the output look newer than its input, it should still be rebuilt
+        fs::write(tmp.join("in"), "two").unwrap();
+        fs::write(tmp.join("out"), "stale").unwrap();
+        makegraph.make(MakeParams::None).unwrap();
+        assert_eq!(fs::read_to_string(tmp.join("out")).unwrap(), "two");
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn async_build() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let makegraph = DepGraphBuilder::new()
+            .add_async_rule(
+                tmp.join("file1"),
+                &[tmp.join("file2")],
+                |out, deps| async move {
+                    let deps: Vec<&Path> = deps.iter().map(|p| p.as_path()).collect();
+                    copy_build(&out, &deps)
+                },
+            )
+            .add_rule(tmp.join("file2"), &[tmp.join("file3")], copy_build)
+            .build()
+            .unwrap();
+        {
+            let mut file3 = File::create(tmp.join("file3")).unwrap();
+            writeln!(&mut file3, "file3").unwrap();
+        }
+        asynchronous::block_on(makegraph.make_async(MakeParams::None)).unwrap();
+        assert!(tmp.join("file1").exists());
+        match makegraph.make(MakeParams::ForceBuild) {
+            Err(Error::AsyncRule(path)) => assert_eq!(path, tmp.join("file1")),
+            other => panic!("expected an async rule error, got {:?}", other),
+        }
+    }
+
+    #[cfg(all(feature = "async", not(feature = "tokio")))]
+    #[test]
+    fn delay_wakes_latest_waker() {
+        use std::future::Future;
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::task::{Context, Poll, Wake, Waker};
+
+        #[derive(Default)]
+        struct Flag(AtomicBool);
+        impl Wake for Flag {
+            fn wake(self: Arc<Self>) {
+                self.0.store(true, Ordering::SeqCst);
+            }
+        }
+
+        let mut delay = std::pin::pin!(asynchronous::delay(Duration::from_millis(50)));
+        let (first, second) = (Arc::new(Flag::default()), Arc::new(Flag::default()));
+        for flag in [&first, &second] {
+            let waker = Waker::from(flag.clone());
+            let poll = delay.as_mut().poll(&mut Context::from_waker(&waker));
+            assert!(poll.is_pending());
+        }
+        let start = Instant::now();
+        while !second.0.load(Ordering::SeqCst) {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(!first.0.load(Ordering::SeqCst));
+        let waker = Waker::from(second);
+        assert_eq!(
+            delay.as_mut().poll(&mut Context::from_waker(&waker)),
+            Poll::Ready(())
+        );
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn tokio_timeout() {
+        let tmp_dir = TempDir::new("depgraph-tests").unwrap();
+        let tmp = tmp_dir.path();
+        let opts = RuleOptions {
+            timeout: Some(Duration::from_millis(50)),
+            ..Default::default()
+        };
+        let makegraph = DepGraphBuilder::new()
+            .add_async_rule_with_opts(tmp.join("out"), &[] as &[&Path], opts, |_, _| async {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+                Ok(())
+            })
+            .build()
+            .unwrap();
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        match runtime.block_on(makegraph.make_async(MakeParams::None)) {
+            Err(Error::Timeout(path)) => assert_eq!(path, tmp.join("out")),
+            other => panic!("expected a timeout, got {:?}", other),
+        }
     }
 }
@@ -0,0 +1,220 @@
+//! A persistent log of successful builds, similar to ninja's `.ninja_log`.
+//!
+//! Each time a rule is built, a line is appended recording how long it took and a fingerprint of
+//! its inputs. On the next run the log is loaded, and a target whose inputs no longer match the
+//! fingerprint is rebuilt even if the modification times say it is up to date (for example when
+//! a dependency has been replaced with an older copy). Hashing every input on every check would
+//! be slow, so the modification times of the inputs are recorded as well, and the inputs are only
+//! hashed again if any of those has changed.
+//!
+//! The rule's own fingerprint (see `RuleOptions::fingerprint`) and the values of the environment
+//! variables it depends on (see `RuleOptions::env_deps`) are recorded too, so a target is also
+//! rebuilt when either changes.
+//!
+//! The file starts with a `# depgraph log v4` header, followed by one
+//! `duration_ms\tmtimes\tfingerprint\trule_fingerprint\ttarget` line per build. Later lines
+//! override earlier ones. Targets are escaped the same way as in a `ContentHash` database.
+
+use std::collections::HashMap;
+use std::env;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::freshness::newest_modified;
+use crate::hash::{escape_path, hash_file, unescape_path, Fnv};
+use crate::{DepResult, RuleOptions};
+
+const HEADER: &str = "# depgraph log v4";
+
+/// (Internal) What was recorded about the last successful build of a target.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct LogEntry {
+    pub(crate) duration: Duration,
+    /// From `mtime_fingerprint`
+    pub(crate) mtimes: u64,
+    pub(crate) fingerprint: u64,
+    pub(crate) rule: u64,
+}
+
+impl LogEntry {
+    /// Whether `deps` have the same contents as when the entry was recorded. They are only
+    /// hashed if their modification times have changed.
+    pub(crate) fn deps_unchanged(&self, deps: &[&Path]) -> io::Result<bool> {
+        if self.mtimes == mtime_fingerprint(deps)? {
+            return Ok(true);
+        }
+        Ok(self.fingerprint == fingerprint(deps)?)
+    }
+}
+
+/// (Internal) The loaded build log.
+pub(crate) struct BuildLog {
+    path: PathBuf,
+    entries: Mutex<HashMap<PathBuf, LogEntry>>,
+}
+
+impl BuildLog {
+    /// Load the log at `path`, starting a new one if it doesn't exist or is from an incompatible
+    /// version.
+    pub(crate) fn open(path: PathBuf) -> DepResult<BuildLog> {
+        let mut entries = HashMap::new();
+        let mut lines_read = 0;
+        match File::open(&path) {
+            Ok(file) => {
+                let mut lines = BufReader::new(file).lines();
+                if let Some(header) = lines.next() {
+                    if header? == HEADER {
+                        for line in lines {
+                            let line = line?;
+                            lines_read += 1;
+                            if let Some((target, entry)) = parse_line(&line) {
+                                entries.insert(target, entry);
+                            }
+                        }
+                    }
+                }
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e.into()),
+        }
+        let log = BuildLog {
+            path,
+            entries: Mutex::new(entries),
+        };
+        // rewrite the log if it is new, from an old version, or mostly out of date entries
+        let live = log.entries.lock().unwrap().len();
+        if lines_read == 0 || lines_read > 2 * live + 100 {
+            log.compact()?;
+        }
+        Ok(log)
+    }
+
+    /// Where the log is stored.
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The last recorded build of `target`.
+    pub(crate) fn get(&self, target: &Path) -> Option<LogEntry> {
+        self.entries.lock().unwrap().get(target).copied()
+    }
+
+    /// Record a successful build of `target`.
+    pub(crate) fn record(&self, target: &Path, entry: LogEntry) -> DepResult<()> {
+        let mut entries = self.entries.lock().unwrap();
+        let mut file = OpenOptions::new().append(true).open(&self.path)?;
+        writeln!(file, "{}", format_line(target, &entry))?;
+        entries.insert(target.to_owned(), entry);
+        Ok(())
+    }
+
+    /// Rewrite the log with only the current entries.
+    fn compact(&self) -> DepResult<()> {
+        let entries = self.entries.lock().unwrap();
+        let mut tmp_name = self.path.clone().into_os_string();
+        tmp_name.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_name);
+        {
+            let mut out = io::BufWriter::new(File::create(&tmp_path)?);
+            writeln!(out, "{}", HEADER)?;
+            for (target, entry) in entries.iter() {
+                writeln!(out, "{}", format_line(target, entry))?;
+            }
+            out.flush()?;
+        }
+        fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+fn parse_line(line: &str) -> Option<(PathBuf, LogEntry)> {
+    let mut parts = line.splitn(5, '\t');
+    let duration = parts.next()?.parse().ok()?;
+    let mtimes = u64::from_str_radix(parts.next()?, 16).ok()?;
+    let fingerprint = u64::from_str_radix(parts.next()?, 16).ok()?;
+    let rule = u64::from_str_radix(parts.next()?, 16).ok()?;
+    let target = unescape_path(parts.next()?)?;
+    Some((
+        target,
+        LogEntry {
+            duration: Duration::from_millis(duration),
+            mtimes,
+            fingerprint,
+            rule,
+        },
+    ))
+}
+
+fn format_line(target: &Path, entry: &LogEntry) -> String {
+    format!(
+        "{}\t{:016x}\t{:016x}\t{:016x}\t{}",
+        entry.duration.as_millis(),
+        entry.mtimes,
+        entry.fingerprint,
+        entry.rule,
+        escape_path(target)
+    )
+}
+
+/// A fingerprint of the names and modification times of a target's dependencies, which is much
+/// cheaper to work out than `fingerprint`. A directory's modification time is the latest of
+/// anything in it.
+pub(crate) fn mtime_fingerprint(deps: &[&Path]) -> io::Result<u64> {
+    let mut hasher = Fnv::new();
+    for dep in deps {
+        let modified = newest_modified(dep)?
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        hasher.write(dep.to_string_lossy().as_bytes());
+        hasher.write(&modified.as_nanos().to_le_bytes());
+    }
+    Ok(hasher.finish())
+}
+
+/// A fingerprint of the names and contents of a target's dependencies.
+pub(crate) fn fingerprint(deps: &[&Path]) -> io::Result<u64> {
+    let mut hasher = Fnv::new();
+    for dep in deps {
+        hasher.write(dep.to_string_lossy().as_bytes());
+        hasher.write(&hash_file(dep)?);
+    }
+    Ok(hasher.finish())
+}
+
+/// A fingerprint of a rule's definition, from `RuleOptions::fingerprint` and the current values
+/// of `RuleOptions::env_deps`.
+pub(crate) fn rule_fingerprint(options: &RuleOptions) -> u64 {
+    rule_fingerprint_with(options, |name| env::var_os(name))
+}
+
+/// Like `rule_fingerprint`, looking up the values of `RuleOptions::env_deps` with `var` rather
+/// than in the environment.
+pub(crate) fn rule_fingerprint_with<F>(options: &RuleOptions, var: F) -> u64
+where
+    F: Fn(&str) -> Option<OsString>,
+{
+    let mut hasher = Fnv::new();
+    hasher.write(
+        options
+            .fingerprint
+            .as_deref()
+            .unwrap_or_default()
+            .as_bytes(),
+    );
+    for name in &options.env_deps {
+        hasher.write(name.as_bytes());
+        // distinguish unset variables from empty ones
+        match var(name) {
+            Some(value) => {
+                hasher.write(&[1]);
+                hasher.write(value.as_encoded_bytes());
+            }
+            None => hasher.write(&[0]),
+        }
+    }
+    hasher.finish()
+}
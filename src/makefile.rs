@@ -0,0 +1,385 @@
+//! Importing rules from a Makefile.
+
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+
+use crate::command::quote;
+use crate::{DepGraphBuilder, DepResult, Error};
+
+/// The directives that aren't supported, and are reported as errors rather than misread as rules.
+const DIRECTIVES: &[&str] = &[
+    "include", "-include", "sinclude", "define", "endef", "ifeq", "ifneq", "ifdef", "ifndef",
+    "else", "endif", "export", "unexport", "override", "vpath",
+];
+
+/// (Internal) A target and what it depends on, as read from the Makefile.
+struct MakeRule {
+    target: String,
+    prerequisites: Vec<String>,
+    /// The recipe lines, with variables not yet expanded
+    recipe: Option<Vec<String>>,
+}
+
+impl<'a> DepGraphBuilder<'a> {
+    /// Create a builder with the rules in the Makefile at `path`.
+    ///
+    /// This understands the simple parts of GNU make syntax: explicit rules with recipes,
+    /// variables (`=`, `:=`, `?=` and `+=`, referenced as `$(NAME)` or `${NAME}`), the automatic
+    /// variables `$@`, `$<` and `$^`, `.PHONY`, comments and line continuations. Functions,
+    /// pattern and suffix rules, target-specific variables (`target: NAME = value`),
+    /// conditionals and `include` aren't supported, and are reported as errors with
+    /// `Error::InvalidManifest`.
+    ///
+    /// Each recipe becomes a shell rule (see `add_shell_rule`). Like make, each recipe line is run
+    /// in its own shell, and the lines are run in order until one fails. Lines starting with `-`
+    /// can fail without failing the rule, and `@` (which only affects echoing in make) is
+    /// ignored. The recipe lines themselves are passed to the shell unchanged, which is `sh` on
+    /// Unix but `cmd` on Windows, so a Makefile whose recipes use POSIX commands only works on
+    /// Unix. Targets listed as prerequisites of `.PHONY`, and targets without a recipe, become
+    /// phony rules (see `add_phony_rule`); the recipes of phony targets, such as `clean`, are
+    /// ignored. Undefined variables are taken from the environment, as in make.
+    pub fn from_makefile<P: AsRef<Path>>(path: P) -> DepResult<DepGraphBuilder<'a>> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)?;
+        let invalid = |message| Error::InvalidManifest(path.to_owned(), message);
+        let (rules, vars) = parse(&contents).map_err(invalid)?;
+        let phony: HashSet<String> = rules
+            .iter()
+            .filter(|rule| rule.target == ".PHONY")
+            .flat_map(|rule| rule.prerequisites.iter().cloned())
+            .collect();
+        let mut builder = DepGraphBuilder::new();
+        for rule in rules {
+            if is_special_target(&rule.target) {
+                continue;
+            }
+            builder = match rule.recipe {
+                Some(ref recipe) if !phony.contains(&rule.target) => {
+                    let script = script(recipe, &rule, &vars).map_err(invalid)?;
+                    builder.add_shell_rule(&rule.target, &rule.prerequisites, &script)
+                }
+                _ => builder.add_phony_rule(&rule.target, &rule.prerequisites),
+            };
+        }
+        Ok(builder)
+    }
+}
+
+/// Whether `target` is one of make's special targets, like `.PHONY` and `.SUFFIXES`.
+fn is_special_target(target: &str) -> bool {
+    target.len() > 1
+        && target.starts_with('.')
+        && target[1..]
+            .chars()
+            .all(|c| c.is_ascii_uppercase() || c == '_')
+}
+
+/// The value of each variable, and whether it is expanded when used (`=`) rather than when set.
+type Vars = HashMap<String, (String, bool)>;
+
+/// Read the rules and variables from a Makefile.
+fn parse(contents: &str) -> Result<(Vec<MakeRule>, Vars), String> {
+    let mut rules: Vec<MakeRule> = Vec::new();
+    let mut vars = Vars::new();
+    // the rules the recipe lines being read belong to
+    let mut current: Vec<usize> = Vec::new();
+    for (number, line) in logical_lines(contents) {
+        let at_line = |message: String| format!("line {}: {}", number, message);
+        if let Some(recipe_line) = line.strip_prefix('\t') {
+            if current.is_empty() {
+                return Err(at_line("recipe line without a rule".to_owned()));
+            }
+            for &i in &current {
+                rules[i]
+                    .recipe
+                    .get_or_insert_with(Vec::new)
+                    .push(recipe_line.to_owned());
+            }
+            continue;
+        }
+        let line = strip_comment(&line);
+        if line.trim().is_empty() {
+            continue;
+        }
+        let first_word = line.split_whitespace().next().unwrap_or_default();
+        if DIRECTIVES.contains(&first_word) {
+            return Err(at_line(format!("`{}` isn't supported", first_word)));
+        }
+        if let Some((name, op, value)) = assignment(line) {
+            let value = value.trim_start();
+            match op {
+                "=" => {
+                    vars.insert(name.to_owned(), (value.to_owned(), true));
+                }
+                ":=" | "::=" => {
+                    let value = expand(value, &vars, None).map_err(at_line)?;
+                    vars.insert(name.to_owned(), (value, false));
+                }
+                "?=" => {
+                    if !vars.contains_key(name) && env::var_os(name).is_none() {
+                        vars.insert(name.to_owned(), (value.to_owned(), true));
+                    }
+                }
+                "+=" => {
+                    // appending to a `:=` variable expands the new part straight away
+                    let value = match vars.get(name) {
+                        Some((_, false)) => expand(value, &vars, None).map_err(at_line)?,
+                        _ => value.to_owned(),
+                    };
+                    let (old, _) = vars
+                        .entry(name.to_owned())
+                        .or_insert_with(|| (String::new(), true));
+                    if !old.is_empty() {
+                        old.push(' ');
+                    }
+                    old.push_str(&value);
+                }
+                _ => return Err(at_line(format!("`{}` assignments aren't supported", op))),
+            }
+            current.clear();
+            continue;
+        }
+        // a rule: `targets: prerequisites`, optionally followed by `; recipe`
+        let (line, inline_recipe) = match line.split_once(';') {
+            Some((line, recipe)) => (line, Some(recipe.trim_start())),
+            None => (line, None),
+        };
+        let line = expand(line, &vars, None).map_err(at_line)?;
+        let (targets, prerequisites) = match line.split_once(':') {
+            Some((_, rest)) if rest.starts_with(':') => {
+                return Err(at_line("double-colon rules aren't supported".to_owned()))
+            }
+            Some(split) => split,
+            None => return Err(at_line("expected a rule or variable assignment".to_owned())),
+        };
+        if targets.contains('%') {
+            return Err(at_line("pattern rules aren't supported".to_owned()));
+        }
+        if assignment(prerequisites).is_some() {
+            return Err(at_line(
+                "target-specific variables aren't supported".to_owned(),
+            ));
+        }
+        let prerequisites: Vec<String> = prerequisites
+            .split_whitespace()
+            .filter(|word| *word != "|")
+            .map(str::to_owned)
+            .collect();
+        current.clear();
+        for target in targets.split_whitespace() {
+            let i = match rules.iter().position(|rule| rule.target == target) {
+                Some(i) => i,
+                None => {
+                    rules.push(MakeRule {
+                        target: target.to_owned(),
+                        prerequisites: Vec::new(),
+                        recipe: None,
+                    });
+                    rules.len() - 1
+                }
+            };
+            // like make, prerequisites from several rules for a target add up
+            for prerequisite in &prerequisites {
+                if !rules[i].prerequisites.contains(prerequisite) {
+                    rules[i].prerequisites.push(prerequisite.clone());
+                }
+            }
+            if let Some(recipe) = inline_recipe {
+                rules[i].recipe = Some(vec![recipe.to_owned()]);
+            }
+            current.push(i);
+        }
+        if current.is_empty() {
+            return Err(at_line("rule without a target".to_owned()));
+        }
+    }
+    Ok((rules, vars))
+}
+
+/// The lines of `contents` with continuations joined, along with the number of the line each
+/// starts on.
+fn logical_lines(contents: &str) -> Vec<(usize, String)> {
+    let mut lines = Vec::new();
+    let mut current: Option<(usize, String)> = None;
+    for (i, line) in contents.lines().enumerate() {
+        let (number, mut text) = match current.take() {
+            Some((number, mut text)) => {
+                if text.starts_with('\t') {
+                    // the shell handles continuations in recipes
+                    text.push_str("\\\n");
+                    text.push_str(line.strip_prefix('\t').unwrap_or(line));
+                } else {
+                    text.push(' ');
+                    text.push_str(line.trim_start());
+                }
+                (number, text)
+            }
+            None => (i + 1, line.to_owned()),
+        };
+        let backslashes = text.len() - text.trim_end_matches('\\').len();
+        if backslashes % 2 == 1 {
+            text.pop();
+            if !text.starts_with('\t') {
+                text.truncate(text.trim_end().len());
+            }
+            current = Some((number, text));
+        } else {
+            lines.push((number, text));
+        }
+    }
+    lines.extend(current);
+    lines
+}
+
+/// `line` without any comment.
+fn strip_comment(line: &str) -> &str {
+    let mut prev = ' ';
+    for (i, c) in line.char_indices() {
+        if c == '#' && prev != '\\' {
+            return &line[..i];
+        }
+        prev = c;
+    }
+    line
+}
+
+/// The variable name, operator and value, if `line` is a variable assignment.
+fn assignment(line: &str) -> Option<(&str, &str, &str)> {
+    let bytes = line.as_bytes();
+    for (i, &b) in bytes.iter().enumerate() {
+        match b {
+            b'=' => {
+                let (name_end, op_start) = match i.checked_sub(1).map(|j| bytes[j]) {
+                    Some(b'?' | b'+' | b'!') => (i - 1, i - 1),
+                    _ => (i, i),
+                };
+                let name = line[..name_end].trim();
+                return valid_name(name).then(|| (name, &line[op_start..=i], &line[i + 1..]));
+            }
+            b':' => {
+                let op = ["::=", ":="]
+                    .into_iter()
+                    .find(|op| line[i..].starts_with(op))?;
+                let name = line[..i].trim();
+                return valid_name(name).then(|| (name, op, &line[i + op.len()..]));
+            }
+            _ => (),
+        }
+    }
+    None
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty() && !name.contains(char::is_whitespace) && !name.contains('$')
+}
+
+/// Expand the variable references in `text`. In a recipe, `rule` is the rule it belongs to, for
+/// the automatic variables.
+fn expand(text: &str, vars: &Vars, rule: Option<&MakeRule>) -> Result<String, String> {
+    expand_depth(text, vars, rule, 0)
+}
+
+fn expand_depth(
+    text: &str,
+    vars: &Vars,
+    rule: Option<&MakeRule>,
+    depth: usize,
+) -> Result<String, String> {
+    if depth > 100 {
+        return Err("variable refers to itself".to_owned());
+    }
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if c != '$' {
+            out.push(c);
+            continue;
+        }
+        let name = match chars.next() {
+            None => return Err("`$` at the end of a line".to_owned()),
+            Some((_, '$')) => {
+                out.push('$');
+                continue;
+            }
+            Some((start, open @ ('(' | '{'))) => {
+                let close = if open == '(' { ')' } else { '}' };
+                let mut nesting = 1;
+                let mut end = None;
+                for (j, c) in chars.by_ref() {
+                    if c == open {
+                        nesting += 1;
+                    } else if c == close {
+                        nesting -= 1;
+                        if nesting == 0 {
+                            end = Some(j);
+                            break;
+                        }
+                    }
+                }
+                let end = end.ok_or_else(|| format!("unclosed `{}`", &text[i..=start]))?;
+                let name = expand_depth(&text[start + 1..end], vars, rule, depth)?;
+                if name.contains(char::is_whitespace) || name.contains(',') {
+                    return Err(format!("functions aren't supported: `{}`", &text[i..=end]));
+                }
+                name
+            }
+            Some((_, c)) => c.to_string(),
+        };
+        match (name.as_str(), rule) {
+            ("@", Some(_)) => out.push_str("{out}"),
+            ("^" | "+", Some(_)) => out.push_str("{deps}"),
+            ("<", Some(rule)) => {
+                if let Some(first) = rule.prerequisites.first() {
+                    out.push_str(&quote(OsStr::new(first)));
+                }
+            }
+            ("@" | "^" | "+" | "<", None) => (),
+            ("?" | "*" | "%" | "|", _) => {
+                return Err(format!("`${}` isn't supported", name));
+            }
+            (name, _) => match vars.get(name) {
+                Some((value, true)) => out.push_str(&expand_depth(value, vars, rule, depth + 1)?),
+                Some((value, false)) => out.push_str(value),
+                None => out.push_str(&env::var(name).unwrap_or_default()),
+            },
+        }
+    }
+    Ok(out)
+}
+
+/// A command that does nothing and succeeds, in the shell `CommandSpec::shell` uses.
+#[cfg(not(windows))]
+const NO_OP: &str = "true";
+#[cfg(windows)]
+const NO_OP: &str = "(call )";
+
+/// The shell script for `recipe`, running each line in its own shell and stopping at the first
+/// failure. Both `sh` and `cmd` understand the `&&`, `||` and brackets used to join the lines.
+fn script(recipe: &[String], rule: &MakeRule, vars: &Vars) -> Result<String, String> {
+    let mut commands = Vec::new();
+    for line in recipe {
+        let line = expand(line, vars, Some(rule))?;
+        let mut line = line.trim_start();
+        let mut ignore_errors = false;
+        while let Some(c) = line.chars().next().filter(|c| "@-+".contains(*c)) {
+            ignore_errors |= c == '-';
+            line = line[1..].trim_start();
+        }
+        if line.is_empty() {
+            continue;
+        }
+        if ignore_errors {
+            commands.push(format!("({} || {})", line, NO_OP));
+        } else {
+            commands.push(format!("({})", line));
+        }
+    }
+    if commands.is_empty() {
+        Ok(NO_OP.to_owned())
+    } else {
+        Ok(commands.join(" && "))
+    }
+}
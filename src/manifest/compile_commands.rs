@@ -0,0 +1,185 @@
+//! (Internal) Importing the commands in a `compile_commands.json` compilation database.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::Deserialize;
+
+use crate::{CommandSpec, DepGraphBuilder, DepResult, Error, IncludeScanner, RuleOptions};
+
+/// (Internal) An entry in a compilation database, as written. Other keys are ignored, as other
+/// tools add their own.
+#[derive(Deserialize)]
+struct EntryDef {
+    directory: PathBuf,
+    file: PathBuf,
+    output: Option<PathBuf>,
+    arguments: Option<Vec<String>>,
+    command: Option<String>,
+}
+
+/// (Internal) An entry in a compilation database.
+struct Entry {
+    directory: PathBuf,
+    file: PathBuf,
+    output: PathBuf,
+    arguments: Vec<String>,
+}
+
+impl<'a> DepGraphBuilder<'a> {
+    /// Create a builder with a rule for each entry in the `compile_commands.json` compilation
+    /// database at `path`, such as one written by CMake or Bear, so a build captured from another
+    /// build system can be re-run.
+    ///
+    /// Each rule builds the object file named by the command's `-o` argument (or the entry's
+    /// `output`, if the command doesn't have one) from the entry's `file`, by running the command
+    /// in the entry's `directory`. Relative paths are relative to that directory. A `command`
+    /// string is split into arguments as the shell would, without expanding anything, and run
+    /// directly like an `arguments` array. The headers the file includes are dependencies, found
+    /// with an `IncludeScanner` searching the command's `-I` directories.
+    ///
+    /// Fails with `Error::InvalidManifest` if the file can't be parsed, or an entry has no output.
+    pub fn from_compile_commands<P: AsRef<Path>>(path: P) -> DepResult<DepGraphBuilder<'a>> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)?;
+        let invalid = |message| Error::InvalidManifest(path.to_owned(), message);
+        let defs: Vec<EntryDef> =
+            serde_json::from_str(&contents).map_err(|e| invalid(e.to_string()))?;
+        let entries = defs
+            .into_iter()
+            .enumerate()
+            .map(|(i, def)| {
+                def.into_entry()
+                    .map_err(|message| invalid(format!("entry {}: {}", i + 1, message)))
+            })
+            .collect::<DepResult<Vec<_>>>()?;
+        Ok(entries
+            .into_iter()
+            .fold(DepGraphBuilder::new(), |builder, entry| {
+                entry.add_to(builder)
+            }))
+    }
+}
+
+impl Entry {
+    fn add_to<'a>(self, builder: DepGraphBuilder<'a>) -> DepGraphBuilder<'a> {
+        let scanner = include_dirs(&self.arguments)
+            .into_iter()
+            .fold(IncludeScanner::new(), |scanner, dir| {
+                scanner.include_dir(self.directory.join(dir))
+            });
+        let options = RuleOptions {
+            scanner: Some(Arc::new(scanner)),
+            ..Default::default()
+        };
+        let command = CommandSpec::new(&self.arguments[0])
+            .args(&self.arguments[1..])
+            .current_dir(&self.directory);
+        builder.add_command_rule_with_opts(
+            self.directory.join(self.output),
+            &[self.directory.join(self.file)],
+            options,
+            command,
+        )
+    }
+}
+
+impl EntryDef {
+    fn into_entry(self) -> Result<Entry, String> {
+        let arguments = match (self.arguments, self.command) {
+            (Some(arguments), _) => arguments,
+            (None, Some(command)) => split(&command)?,
+            (None, None) => return Err("missing `arguments` or `command`".to_owned()),
+        };
+        if arguments.is_empty() {
+            return Err("the command is empty".to_owned());
+        }
+        let output = output_argument(&arguments)
+            .map(PathBuf::from)
+            .or(self.output)
+            .ok_or("no `-o` argument or `output`")?;
+        Ok(Entry {
+            directory: self.directory,
+            file: self.file,
+            output,
+            arguments,
+        })
+    }
+}
+
+/// The value of the `-o` argument, which may be separate or joined on.
+fn output_argument(arguments: &[String]) -> Option<String> {
+    let mut args = arguments.iter();
+    while let Some(arg) = args.next() {
+        if arg == "-o" {
+            return args.next().cloned();
+        } else if let Some(output) = arg.strip_prefix("-o") {
+            return Some(output.to_owned());
+        }
+    }
+    None
+}
+
+/// The values of the `-I` arguments, which may be separate or joined on.
+fn include_dirs(arguments: &[String]) -> Vec<&str> {
+    let mut args = arguments.iter();
+    let mut dirs = Vec::new();
+    while let Some(arg) = args.next() {
+        if arg == "-I" {
+            dirs.extend(args.next().map(String::as_str));
+        } else if let Some(dir) = arg.strip_prefix("-I") {
+            dirs.push(dir);
+        }
+    }
+    dirs
+}
+
+/// Split a command line into arguments like a Unix shell, handling quotes and backslashes.
+fn split(command: &str) -> Result<Vec<String>, String> {
+    let mut words = Vec::new();
+    let mut word = None;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err("unterminated `'` in command".to_owned()),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => word.push(c),
+                            Some('\n') => {}
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err("unterminated `\"` in command".to_owned()),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err("unterminated `\"` in command".to_owned()),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some('\n') => {}
+                Some(c) => word.get_or_insert_with(String::new).push(c),
+                None => return Err("trailing `\\` in command".to_owned()),
+            },
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
@@ -0,0 +1,230 @@
+//! Declaring rules in a manifest file (the `manifest` feature).
+//!
+//! A manifest lets rules that run commands be added without writing any Rust. Each rule is a
+//! table with these keys:
+//!
+//! - `output`: the file the rule builds (required)
+//! - `deps`: a list of the files it depends on
+//! - `command`: what to run (required). A string is run by the shell, and a list is a program
+//!   followed by its arguments. `{out}` and `{deps}` are replaced as for `CommandSpec`.
+//! - `env`: a table of environment variables to set for the command
+//!
+//! In TOML, the rules are an array of tables called `rule`:
+//!
+//! ```toml
+//! [[rule]]
+//! output = "out/logo.ktx2"
+//! deps = ["assets/logo.png"]
+//! command = "toktx --t2 {out} {deps}"
+//!
+//! [[rule]]
+//! output = "out/shaders/main.spv"
+//! deps = ["shaders/main.frag"]
+//! command = ["glslc", "-o", "{out}", "{deps}"]
+//! env = { GLSLC_OPT = "-O" }
+//! ```
+//!
+//! The same rules in YAML are a list called `rule`:
+//!
+//! ```yaml
+//! rule:
+//!   - output: out/logo.ktx2
+//!     deps: [assets/logo.png]
+//!     command: toktx --t2 {out} {deps}
+//!   - output: out/shaders/main.spv
+//!     deps: [shaders/main.frag]
+//!     command: [glslc, -o, "{out}", "{deps}"]
+//!     env:
+//!       GLSLC_OPT: -O
+//! ```
+//!
+//! In JSON, the rules are an array called `rule`, and `JSON_SCHEMA` describes the format:
+//!
+//! ```json
+//! {
+//!   "rule": [
+//!     {
+//!       "output": "out/logo.ktx2",
+//!       "deps": ["assets/logo.png"],
+//!       "command": "toktx --t2 {out} {deps}"
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! A manifest can also have a table called `group`, naming lists of targets that can be built
+//! together (see `DepGraphBuilder::group`), and a table called `alias`, giving short names to
+//! targets (see `DepGraphBuilder::alias`). In TOML:
+//!
+//! ```toml
+//! [group]
+//! textures = ["out/logo.ktx2", "out/grass.ktx2"]
+//!
+//! [alias]
+//! logo = "out/logo.ktx2"
+//! ```
+//!
+//! The manifest is a dependency of every rule it defines, so editing it rebuilds them, but it
+//! isn't passed to the commands.
+//!
+//! This feature also adds `DepGraphBuilder::from_compile_commands`, which reads the JSON
+//! compilation databases written by CMake and other tools.
+
+mod compile_commands;
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::{CommandSpec, DepGraphBuilder, DepResult, Error, RuleOptions};
+
+/// A [JSON Schema](https://json-schema.org) for JSON manifests, for tools that generate them.
+///
+/// This is also published as `schema/manifest.schema.json` in the repository.
+pub const JSON_SCHEMA: &str = include_str!("../../schema/manifest.schema.json");
+
+/// (Internal) Everything declared in a manifest.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    #[serde(default, rename = "rule")]
+    rules: Vec<RuleDef>,
+    /// Each group and its targets
+    #[serde(default, rename = "group")]
+    groups: BTreeMap<String, Vec<String>>,
+    /// Each alias and its target
+    #[serde(default, rename = "alias")]
+    aliases: BTreeMap<String, String>,
+}
+
+/// (Internal) A rule read from a manifest.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleDef {
+    output: PathBuf,
+    #[serde(default)]
+    deps: Vec<PathBuf>,
+    command: CommandDef,
+    #[serde(default)]
+    env: BTreeMap<String, String>,
+}
+
+/// (Internal) A rule's command: a shell script, or a program followed by its arguments.
+#[derive(Deserialize)]
+#[serde(
+    untagged,
+    expecting = "`command` should be a string or a list of strings"
+)]
+enum CommandDef {
+    Shell(String),
+    Exec(Vec<String>),
+}
+
+impl<'a> DepGraphBuilder<'a> {
+    /// Create a builder with the rules declared in the TOML manifest at `path`.
+    ///
+    /// See the `manifest` module documentation for the format. Fails with
+    /// `Error::InvalidManifest` if the file can't be parsed or a rule is missing something.
+    pub fn from_toml<P: AsRef<Path>>(path: P) -> DepResult<DepGraphBuilder<'a>> {
+        DepGraphBuilder::new().add_manifest(path.as_ref(), |text| {
+            toml::from_str(text).map_err(|e| match e.span() {
+                Some(span) => format!("line {}: {}", line(text, span.start), e.message()),
+                None => e.message().to_owned(),
+            })
+        })
+    }
+
+    /// Create a builder with the rules declared in the YAML manifest at `path`.
+    ///
+    /// See the `manifest` module documentation for the format. Fails with
+    /// `Error::InvalidManifest` if the file can't be parsed or a rule is missing something.
+    pub fn from_yaml<P: AsRef<Path>>(path: P) -> DepResult<DepGraphBuilder<'a>> {
+        DepGraphBuilder::new().add_manifest(path.as_ref(), |text| {
+            serde_yaml::from_str(text).map_err(|e| e.to_string())
+        })
+    }
+
+    /// Create a builder with the rules declared in the JSON manifest at `path`.
+    ///
+    /// See the `manifest` module documentation for the format, and `manifest::JSON_SCHEMA` for a
+    /// schema describing it. Fails with `Error::InvalidManifest` if the file can't be parsed or
+    /// a rule is missing something.
+    pub fn from_json<P: AsRef<Path>>(path: P) -> DepResult<DepGraphBuilder<'a>> {
+        DepGraphBuilder::new().add_manifest(path.as_ref(), |text| {
+            serde_json::from_str(text).map_err(|e| e.to_string())
+        })
+    }
+
+    /// Add the rules from the manifest at `path`, read with `parse`.
+    fn add_manifest(
+        self,
+        path: &Path,
+        parse: fn(&str) -> Result<Manifest, String>,
+    ) -> DepResult<DepGraphBuilder<'a>> {
+        let contents = fs::read_to_string(path)?;
+        let invalid = |message| Error::InvalidManifest(path.to_owned(), message);
+        let manifest = parse(&contents).map_err(invalid)?;
+        let mut builder = self;
+        for (i, def) in manifest.rules.into_iter().enumerate() {
+            builder = def
+                .add_to(builder, path)
+                .map_err(|message| invalid(format!("rule {}: {}", i + 1, message)))?;
+        }
+        let builder = manifest
+            .groups
+            .into_iter()
+            .fold(builder, |builder, (name, targets)| {
+                builder.group(&name, &targets)
+            });
+        Ok(manifest
+            .aliases
+            .into_iter()
+            .fold(builder, |builder, (name, target)| {
+                builder.alias(&name, target)
+            }))
+    }
+}
+
+impl RuleDef {
+    fn add_to<'a>(
+        self,
+        builder: DepGraphBuilder<'a>,
+        manifest: &Path,
+    ) -> Result<DepGraphBuilder<'a>, String> {
+        let manifest = manifest.to_owned();
+        let mut deps = self.deps;
+        deps.push(manifest.clone());
+        let command = match self.command {
+            CommandDef::Shell(script) => CommandSpec::shell(&script),
+            CommandDef::Exec(args) => match args.split_first() {
+                Some((program, args)) => CommandSpec::new(program).args(args),
+                None => return Err("`command` is empty".to_owned()),
+            },
+        };
+        let command = self
+            .env
+            .into_iter()
+            .fold(command, |command, (name, value)| command.env(name, value));
+        let options = RuleOptions {
+            fingerprint: Some(command.fingerprint()),
+            ..Default::default()
+        };
+        Ok(
+            builder.add_rule_with_opts(self.output, &deps, options, move |out, deps| {
+                let deps: Vec<&Path> = deps
+                    .iter()
+                    .copied()
+                    .filter(|dep| *dep != manifest)
+                    .collect();
+                command.run(out, &deps)
+            }),
+        )
+    }
+}
+
+/// The line number of the byte at `pos` in `text`, for error messages.
+fn line(text: &str, pos: usize) -> usize {
+    text[..pos].matches('\n').count() + 1
+}
@@ -0,0 +1,566 @@
+//! Reading and writing ninja build files.
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::command::quote;
+use crate::CONSOLE_POOL;
+use crate::{CommandSpec, DepGraph, DepGraphBuilder, DepResult, Error, RuleOptions};
+
+/// How deeply variables may refer to each other before giving up.
+const MAX_DEPTH: usize = 64;
+
+/// (Internal) The variables and rules visible at a point in a ninja file.
+#[derive(Debug, Clone, Default)]
+struct Scope {
+    vars: HashMap<String, String>,
+    /// The unexpanded bindings of each rule
+    rules: HashMap<String, HashMap<String, String>>,
+}
+
+/// (Internal) The state of an import: the builder the rules are added to, and the build
+/// directory that the paths in the files are relative to.
+struct Importer {
+    builder: DepGraphBuilder<'static>,
+    dir: PathBuf,
+}
+
+impl<'a> DepGraphBuilder<'a> {
+    /// Create a builder with the build statements in the ninja file at `path`.
+    ///
+    /// This supports variables, `rule` and `build` statements (including implicit outputs and
+    /// implicit and order-only dependencies), the `phony` rule, `include` and `subninja`. Each
+    /// build statement runs its rule's `command` with the shell, from the directory containing the
+    /// ninja file, and the paths in the file are taken to be relative to that directory. The
+    /// `depfile`, `restat` and `pool` rule variables are used as `RuleOptions::depfile`,
+    /// `RuleOptions::restat` and `RuleOptions::pool`, and `pool` statements add pools with `pool`;
+    /// `console` is the built-in `CONSOLE_POOL`. Order-only dependencies are treated as normal
+    /// dependencies, and `default` statements are ignored.
+    ///
+    /// Fails with `Error::InvalidManifest` if the file can't be parsed.
+    pub fn from_ninja<P: AsRef<Path>>(path: P) -> DepResult<DepGraphBuilder<'a>> {
+        let path = path.as_ref();
+        let mut importer = Importer {
+            builder: DepGraphBuilder::new(),
+            dir: path.parent().unwrap_or(Path::new("")).to_owned(),
+        };
+        importer.read(path, &mut Scope::default())?;
+        Ok(importer.builder)
+    }
+}
+
+impl Importer {
+    /// Read the statements in the file at `path`, adding its variables and rules to `scope`.
+    fn read(&mut self, path: &Path, scope: &mut Scope) -> DepResult<()> {
+        let contents = fs::read_to_string(path)?;
+        let lines = logical_lines(&contents);
+        let invalid = |number: usize, message: String| {
+            Error::InvalidManifest(path.to_owned(), format!("line {}: {}", number, message))
+        };
+        let mut i = 0;
+        while i < lines.len() {
+            let (number, ref line) = lines[i];
+            i += 1;
+            if line.starts_with([' ', '\t']) {
+                return Err(invalid(number, "unexpected indentation".to_owned()));
+            }
+            // the indented bindings belonging to this statement
+            let start = i;
+            while i < lines.len() && lines[i].1.starts_with([' ', '\t']) {
+                i += 1;
+            }
+            let mut bindings = Vec::new();
+            for (number, binding) in &lines[start..i] {
+                bindings
+                    .push(parse_binding(binding.trim_start()).map_err(|e| invalid(*number, e))?);
+            }
+            let (keyword, rest) = line.split_once(' ').unwrap_or((line, ""));
+            let rest = rest.trim_start();
+            match keyword {
+                "rule" => {
+                    scope
+                        .rules
+                        .insert(rest.trim().to_owned(), bindings.into_iter().collect());
+                }
+                "build" => {
+                    let vars = bindings
+                        .into_iter()
+                        .map(|(name, value)| {
+                            Ok((
+                                name,
+                                expand(&value, &|name| scope.vars.get(name).cloned(), 0)?,
+                            ))
+                        })
+                        .collect::<Result<HashMap<_, _>, String>>()
+                        .map_err(|e| invalid(number, e))?;
+                    self.build(rest, vars, scope)
+                        .map_err(|e| invalid(number, e))?;
+                }
+                "pool" => {
+                    let depth = bindings
+                        .iter()
+                        .find(|(name, _)| name == "depth")
+                        .and_then(|(_, depth)| depth.trim().parse().ok())
+                        .filter(|depth| *depth > 0)
+                        .ok_or_else(|| {
+                            invalid(number, "expected a depth for the pool".to_owned())
+                        })?;
+                    let name = rest.trim().to_owned();
+                    if name == CONSOLE_POOL {
+                        return Err(invalid(number, "the console pool is built in".to_owned()));
+                    }
+                    self.take_builder(|builder| builder.pool(&name, depth));
+                }
+                "default" => (),
+                "include" | "subninja" => {
+                    let file = expand(rest, &|name| scope.vars.get(name).cloned(), 0)
+                        .map_err(|e| invalid(number, e))?;
+                    let file = self.resolve(&file);
+                    if keyword == "include" {
+                        self.read(&file, scope)?;
+                    } else {
+                        self.read(&file, &mut scope.clone())?;
+                    }
+                }
+                _ => {
+                    let (name, value) = parse_binding(line).map_err(|e| invalid(number, e))?;
+                    let value = expand(&value, &|name| scope.vars.get(name).cloned(), 0)
+                        .map_err(|e| invalid(number, e))?;
+                    scope.vars.insert(name, value);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Add the rule for a build statement, where `line` is everything after `build` and `vars`
+    /// are its expanded bindings.
+    fn build(
+        &mut self,
+        line: &str,
+        vars: HashMap<String, String>,
+        scope: &Scope,
+    ) -> Result<(), String> {
+        let tokens = tokenize(line);
+        let colon = tokens
+            .iter()
+            .position(|token| *token == Token::Separator(":"))
+            .ok_or("expected `:` in build statement")?;
+        let (outputs, inputs) = (&tokens[..colon], &tokens[colon + 1..]);
+        let (rule_name, inputs) = match inputs.split_first() {
+            Some((Token::Word(rule), inputs)) => (rule.as_str(), inputs),
+            _ => return Err("expected a rule name after `:`".to_owned()),
+        };
+        let file_lookup = |name: &str| vars.get(name).or_else(|| scope.vars.get(name)).cloned();
+        let outputs = expand_groups(outputs, &file_lookup)?;
+        let inputs = expand_groups(inputs, &file_lookup)?;
+        let explicit_outputs = outputs.first().cloned().unwrap_or_default();
+        let explicit_inputs = inputs.first().cloned().unwrap_or_default();
+        let all_outputs: Vec<PathBuf> = outputs
+            .iter()
+            .take(2)
+            .flatten()
+            .map(|path| self.resolve(path))
+            .collect();
+        // implicit and order-only dependencies (validations, after `|@`, are left out)
+        let deps: Vec<PathBuf> = inputs
+            .iter()
+            .take(3)
+            .flatten()
+            .map(|path| self.resolve(path))
+            .collect();
+        if all_outputs.is_empty() {
+            return Err("build statement has no outputs".to_owned());
+        }
+
+        if rule_name == "phony" {
+            for output in &all_outputs {
+                self.take_builder(|builder| builder.add_phony_rule(output, &deps));
+            }
+            return Ok(());
+        }
+        let rule = scope
+            .rules
+            .get(rule_name)
+            .ok_or_else(|| format!("unknown rule `{}`", rule_name))?;
+        let quote_all = |paths: &[String], separator: &str| {
+            paths
+                .iter()
+                .map(|path| quote(OsStr::new(path)))
+                .collect::<Vec<_>>()
+                .join(separator)
+        };
+        let (in_, in_newline, out) = (
+            quote_all(&explicit_inputs, " "),
+            quote_all(&explicit_inputs, "\n"),
+            quote_all(&explicit_outputs, " "),
+        );
+        let lookup = |name: &str| rule_var(name, &in_, &in_newline, &out, &vars, rule, scope, 0);
+        let command = lookup("command")
+            .transpose()?
+            .filter(|command| !command.is_empty())
+            .ok_or_else(|| format!("rule `{}` has no command", rule_name))?;
+        let options = RuleOptions {
+            depfile: lookup("depfile")
+                .transpose()?
+                .filter(|depfile| !depfile.is_empty())
+                .map(|depfile| self.resolve(&depfile)),
+            restat: lookup("restat")
+                .transpose()?
+                .is_some_and(|restat| !restat.is_empty()),
+            pool: lookup("pool").transpose()?.filter(|pool| !pool.is_empty()),
+            ..Default::default()
+        };
+        let mut command = CommandSpec::shell(&command);
+        if !self.dir.as_os_str().is_empty() {
+            command = command.current_dir(&self.dir);
+        }
+        let extra_outputs = all_outputs[1..].to_vec();
+        self.take_builder(|builder| {
+            let mut builder =
+                builder.add_command_rule_with_opts(&all_outputs[0], &deps, options, command);
+            builder.rules.last_mut().unwrap().extra_outputs = extra_outputs;
+            builder
+        });
+        Ok(())
+    }
+
+    fn take_builder(
+        &mut self,
+        f: impl FnOnce(DepGraphBuilder<'static>) -> DepGraphBuilder<'static>,
+    ) {
+        let builder = std::mem::take(&mut self.builder);
+        self.builder = f(builder);
+    }
+
+    /// `path` from a ninja file, relative to the current directory rather than the build
+    /// directory.
+    fn resolve(&self, path: &str) -> PathBuf {
+        self.dir.join(path)
+    }
+}
+
+impl<'a> DepGraph<'a> {
+    /// Write a ninja file that builds the same targets as this graph, so it can be run (and
+    /// profiled) outside of cargo with `ninja -f <file>`.
+    ///
+    /// Rules added with `add_command_rule` (including shell rules) become ninja build statements
+    /// running the same command, from the current directory, with the same dependencies, extra
+    /// outputs, depfile, restat setting and pool. Phony rules become `phony` build statements, and
+    /// pools other than the built-in console pool become `pool` statements. Other
+    /// build functions can't be run by ninja, so their targets get a rule that fails, saying so,
+    /// if ninja needs to build them. Commands are written for a Unix shell.
+    pub fn write_ninja<W: Write>(&self, mut out: W) -> io::Result<()> {
+        writeln!(out, "# Generated by depgraph")?;
+        // the console pool needs ninja 1.5
+        let console = self
+            .graph
+            .node_weights()
+            .any(|dep| dep.options.pool.as_deref() == Some(CONSOLE_POOL));
+        let version = if console { "1.5" } else { "1.3" };
+        writeln!(out, "ninja_required_version = {}", version)?;
+        writeln!(out)?;
+        writeln!(out, "rule unsupported")?;
+        writeln!(
+            out,
+            "  command = echo \"$out is built by a Rust build function, which ninja can't run\" >&2 && false"
+        )?;
+        let mut pools: Vec<_> = self
+            .pools
+            .iter()
+            .filter(|(name, _)| *name != CONSOLE_POOL)
+            .collect();
+        pools.sort();
+        for (name, depth) in pools {
+            writeln!(out)?;
+            writeln!(out, "pool {}", name)?;
+            writeln!(out, "  depth = {}", depth)?;
+        }
+        // rules are shared between targets with the same command
+        let mut rules: HashMap<String, String> = HashMap::new();
+        for idx in self.graph.node_indices() {
+            let dep = &self.graph[idx];
+            if dep.extra_output || (dep.build_fn.is_none() && !dep.phony) {
+                continue;
+            }
+            let deps: Vec<String> = self
+                .dependency_files(idx)
+                .iter()
+                .map(|path| escape_path(path))
+                .collect();
+            writeln!(out)?;
+            if dep.phony {
+                writeln!(
+                    out,
+                    "build {}: phony {}",
+                    escape_path(&dep.filename),
+                    deps.join(" ")
+                )?;
+                continue;
+            }
+            let rule = match dep
+                .command
+                .as_ref()
+                .and_then(|command| command.ninja_command())
+            {
+                Some(command) => {
+                    if let Some(rule) = rules.get(&command) {
+                        rule.clone()
+                    } else {
+                        let rule = format!("r{}", rules.len());
+                        writeln!(out, "rule {}", rule)?;
+                        writeln!(out, "  command = {}", command)?;
+                        writeln!(out)?;
+                        rules.insert(command, rule.clone());
+                        rule
+                    }
+                }
+                None => "unsupported".to_owned(),
+            };
+            write!(out, "build {}", escape_path(&dep.filename))?;
+            if !dep.extra_outputs.is_empty() {
+                let extra: Vec<String> = dep.extra_outputs.iter().map(|p| escape_path(p)).collect();
+                write!(out, " | {}", extra.join(" "))?;
+            }
+            writeln!(out, ": {} {}", rule, deps.join(" "))?;
+            if let Some(ref depfile) = dep.options.depfile {
+                writeln!(out, "  depfile = {}", escape_path(depfile))?;
+            }
+            if dep.options.restat {
+                writeln!(out, "  restat = 1")?;
+            }
+            if let Some(ref pool) = dep.options.pool {
+                writeln!(out, "  pool = {}", pool)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Get a ninja file that builds this graph. See `write_ninja`.
+    pub fn to_ninja(&self) -> String {
+        let mut out = Vec::new();
+        self.write_ninja(&mut out)
+            .expect("writing to a Vec can't fail");
+        String::from_utf8(out).expect("file names are written with `Path::display`")
+    }
+}
+
+/// Escape `path` for a ninja file.
+fn escape_path(path: &Path) -> String {
+    path.display()
+        .to_string()
+        .replace('$', "$$")
+        .replace(' ', "$ ")
+        .replace(':', "$:")
+}
+
+/// The value of the variable `name` for a build statement using `rule`, looked up in the
+/// special variables, then the statement's bindings, the rule's and the file's. Returns `None`
+/// if it isn't set.
+#[allow(clippy::too_many_arguments)]
+fn rule_var(
+    name: &str,
+    in_: &str,
+    in_newline: &str,
+    out: &str,
+    build_vars: &HashMap<String, String>,
+    rule: &HashMap<String, String>,
+    scope: &Scope,
+    depth: usize,
+) -> Option<Result<String, String>> {
+    match name {
+        "in" => Some(Ok(in_.to_owned())),
+        "in_newline" => Some(Ok(in_newline.to_owned())),
+        "out" => Some(Ok(out.to_owned())),
+        _ => {
+            if let Some(value) = build_vars.get(name) {
+                Some(Ok(value.clone()))
+            } else if let Some(value) = rule.get(name) {
+                let lookup = |name: &str| {
+                    rule_var(
+                        name,
+                        in_,
+                        in_newline,
+                        out,
+                        build_vars,
+                        rule,
+                        scope,
+                        depth + 1,
+                    )
+                    .and_then(Result::ok)
+                };
+                Some(expand(value, &lookup, depth + 1))
+            } else {
+                scope.vars.get(name).cloned().map(Ok)
+            }
+        }
+    }
+}
+
+/// (Internal) A part of a `build` line.
+#[derive(Debug, PartialEq)]
+enum Token {
+    /// A path or rule name, still escaped
+    Word(String),
+    /// `:`, `|`, `||` or `|@`
+    Separator(&'static str),
+}
+
+/// Split a `build` line into words and separators.
+fn tokenize(line: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut word = String::new();
+    let mut chars = line.chars().peekable();
+    let end_word = |word: &mut String, tokens: &mut Vec<Token>| {
+        if !word.is_empty() {
+            tokens.push(Token::Word(std::mem::take(word)));
+        }
+    };
+    while let Some(c) = chars.next() {
+        match c {
+            '$' => {
+                word.push('$');
+                word.extend(chars.next());
+            }
+            ' ' | '\t' => end_word(&mut word, &mut tokens),
+            ':' => {
+                end_word(&mut word, &mut tokens);
+                tokens.push(Token::Separator(":"));
+            }
+            '|' => {
+                end_word(&mut word, &mut tokens);
+                let separator = match chars.peek() {
+                    Some('|') => "||",
+                    Some('@') => "|@",
+                    _ => "|",
+                };
+                if separator != "|" {
+                    chars.next();
+                }
+                tokens.push(Token::Separator(separator));
+            }
+            c => word.push(c),
+        }
+    }
+    end_word(&mut word, &mut tokens);
+    tokens
+}
+
+/// Expand the words in `tokens`, grouped by the separators between them: explicit, then implicit
+/// (after `|`), then order-only (after `||`) and validations (after `|@`).
+fn expand_groups(
+    tokens: &[Token],
+    lookup: &dyn Fn(&str) -> Option<String>,
+) -> Result<Vec<Vec<String>>, String> {
+    let mut groups = vec![Vec::new(); 4];
+    let mut group = 0;
+    for token in tokens {
+        match token {
+            Token::Word(word) => groups[group].push(expand(word, lookup, 0)?),
+            Token::Separator(separator) => {
+                let next = match *separator {
+                    "|" => 1,
+                    "||" => 2,
+                    "|@" => 3,
+                    _ => return Err("unexpected `:`".to_owned()),
+                };
+                if next <= group {
+                    return Err(format!("unexpected `{}`", separator));
+                }
+                group = next;
+            }
+        }
+    }
+    Ok(groups)
+}
+
+/// Expand the variables and escapes in `value`, looking variables up with `lookup`. Unset
+/// variables expand to nothing.
+fn expand(
+    value: &str,
+    lookup: &dyn Fn(&str) -> Option<String>,
+    depth: usize,
+) -> Result<String, String> {
+    if depth > MAX_DEPTH {
+        return Err("variable refers to itself".to_owned());
+    }
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.char_indices().peekable();
+    while let Some((_, c)) = chars.next() {
+        if c != '$' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some((_, c @ ('$' | ' ' | ':'))) => out.push(c),
+            Some((start, '{')) => {
+                let end = value[start..].find('}').ok_or("unclosed `${`")? + start;
+                out.push_str(&lookup(&value[start + 1..end]).unwrap_or_default());
+                while chars.next_if(|&(i, _)| i <= end).is_some() {}
+            }
+            Some((start, c)) if is_var_char(c) => {
+                let mut end = start + c.len_utf8();
+                while let Some(&(i, c)) = chars.peek() {
+                    if !is_var_char(c) {
+                        break;
+                    }
+                    end = i + c.len_utf8();
+                    chars.next();
+                }
+                out.push_str(&lookup(&value[start..end]).unwrap_or_default());
+            }
+            Some((_, c)) => return Err(format!("invalid escape `${}`", c)),
+            None => return Err("`$` at the end of a line".to_owned()),
+        }
+    }
+    Ok(out)
+}
+
+fn is_var_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '-'
+}
+
+/// A `name = value` binding, with the value still escaped.
+fn parse_binding(line: &str) -> Result<(String, String), String> {
+    let (name, value) = line
+        .split_once('=')
+        .ok_or_else(|| format!("expected `name = value`, found `{}`", line))?;
+    let name = name.trim();
+    if name.is_empty() || !name.chars().all(|c| is_var_char(c) || c == '.') {
+        return Err(format!("invalid variable name `{}`", name));
+    }
+    Ok((name.to_owned(), value.trim_start().to_owned()))
+}
+
+/// The lines of `contents` with `$` line continuations joined and comments and blank lines
+/// removed, along with the number of the line each starts on.
+fn logical_lines(contents: &str) -> Vec<(usize, String)> {
+    let mut lines = Vec::new();
+    let mut current: Option<(usize, String)> = None;
+    for (i, line) in contents.lines().enumerate() {
+        let (number, mut text) = match current.take() {
+            Some((number, mut text)) => {
+                text.push_str(line.trim_start());
+                (number, text)
+            }
+            None => {
+                if line.trim_start().starts_with('#') || line.trim().is_empty() {
+                    continue;
+                }
+                (i + 1, line.to_owned())
+            }
+        };
+        let dollars = text.len() - text.trim_end_matches('$').len();
+        if dollars % 2 == 1 {
+            text.pop();
+            current = Some((number, text));
+        } else {
+            lines.push((number, text.trim_end().to_owned()));
+        }
+    }
+    lines.extend(current);
+    lines
+}
@@ -0,0 +1,147 @@
+//! Hooks for following the progress of a build.
+
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::time::{Duration, SystemTime};
+
+use crate::{DepGraph, DepResult, Error, MakeParams};
+
+/// Receives notifications as `make` works through the graph.
+///
+/// Pass an observer to `DepGraph::make_with_observer` (or `make_parallel_with_observer`). Every
+/// method has a default implementation that does nothing, so implement only the ones you need.
+/// When building in parallel the methods are called from the worker threads, which is why
+/// observers must be `Sync`.
+///
+/// Only targets with build functions are reported; source files are not.
+pub trait BuildObserver: Sync {
+    /// The build is about to start. `total` is the number of targets that have build functions.
+    fn on_begin(&self, total: usize) {
+        let _ = total;
+    }
+
+    /// `target` is stale and its build function is about to run.
+    fn on_start(&self, target: &Path) {
+        let _ = target;
+    }
+
+    /// `target` is up to date, so its build function won't be run.
+    fn on_skipped(&self, target: &Path) {
+        let _ = target;
+    }
+
+    /// The build function for `target` succeeded after running for `duration`.
+    fn on_finished(&self, target: &Path, duration: Duration) {
+        let _ = (target, duration);
+    }
+
+    /// Building `target` failed.
+    fn on_failed(&self, target: &Path, err: &Error) {
+        let _ = (target, err);
+    }
+
+    /// The build has finished with `result`. Targets that weren't built because a dependency
+    /// failed are never reported, so fewer targets than the `total` given to `on_begin` may have
+    /// been.
+    fn on_end(&self, result: &DepResult<()>) {
+        let _ = result;
+    }
+}
+
+/// The unit type is an observer that ignores all notifications.
+impl BuildObserver for () {}
+
+/// A notification about the progress of a build, sent by `DepGraph::make_with_events`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BuildEvent {
+    /// `target` is stale and its build function has started.
+    Started {
+        /// The target being built
+        target: PathBuf,
+        /// When the build function started
+        time: SystemTime,
+    },
+    /// `target` is up to date, so its build function won't be run.
+    Skipped {
+        /// The target that is up to date
+        target: PathBuf,
+        /// When the target was checked
+        time: SystemTime,
+    },
+    /// The build function for `target` succeeded.
+    Succeeded {
+        /// The target that was built
+        target: PathBuf,
+        /// When the build function finished
+        time: SystemTime,
+        /// How long the build function ran for
+        duration: Duration,
+    },
+    /// Building `target` failed.
+    Failed {
+        /// The target that failed
+        target: PathBuf,
+        /// When the failure happened
+        time: SystemTime,
+        /// A description of the error (the error itself is returned from `make_with_events`)
+        error: String,
+    },
+}
+
+/// Forwards observer notifications over a channel.
+struct EventSender(Sender<BuildEvent>);
+
+impl EventSender {
+    fn send(&self, event: BuildEvent) {
+        // if the receiver has hung up then nobody is interested in the events, but the build
+        // should carry on
+        let _ = self.0.send(event);
+    }
+}
+
+impl BuildObserver for EventSender {
+    fn on_start(&self, target: &Path) {
+        self.send(BuildEvent::Started {
+            target: target.to_owned(),
+            time: SystemTime::now(),
+        });
+    }
+
+    fn on_skipped(&self, target: &Path) {
+        self.send(BuildEvent::Skipped {
+            target: target.to_owned(),
+            time: SystemTime::now(),
+        });
+    }
+
+    fn on_finished(&self, target: &Path, duration: Duration) {
+        self.send(BuildEvent::Succeeded {
+            target: target.to_owned(),
+            time: SystemTime::now(),
+            duration,
+        });
+    }
+
+    fn on_failed(&self, target: &Path, err: &Error) {
+        self.send(BuildEvent::Failed {
+            target: target.to_owned(),
+            time: SystemTime::now(),
+            error: err.to_string(),
+        });
+    }
+}
+
+impl<'a> DepGraph<'a> {
+    /// Run the build, like `make`, sending a `BuildEvent` over `events` as each target is
+    /// processed.
+    ///
+    /// This makes it easy to follow progress from another thread. The build carries on even if
+    /// the receiving end of the channel is dropped.
+    pub fn make_with_events(
+        &self,
+        make_params: MakeParams,
+        events: Sender<BuildEvent>,
+    ) -> DepResult<()> {
+        self.make_with_observer(make_params, &EventSender(events))
+    }
+}
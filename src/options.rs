@@ -0,0 +1,124 @@
+//! Per-rule options.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::{FreshnessChecker, Scanner};
+
+/// The name of the built-in pool for rules that need the terminal to themselves, such as
+/// interactive tools or ones that print their own progress, like ninja's `console` pool.
+///
+/// At most one rule in it runs at a time, and command rules in it (see
+/// `DepGraphBuilder::add_command_rule`) are connected to the build's stdin, stdout and stderr
+/// rather than having their output captured. Other rules carry on running alongside it.
+///
+/// ```
+/// let opts = depgraph::RuleOptions {
+///     pool: Some(depgraph::CONSOLE_POOL.to_owned()),
+///     ..Default::default()
+/// };
+/// ```
+pub const CONSOLE_POOL: &str = "console";
+
+/// Options that change how an individual rule is run.
+///
+/// Use with `DepGraphBuilder::add_rule_with_opts`. Construct with struct update syntax to leave
+/// the other options at their defaults:
+///
+/// ```
+/// # use std::time::Duration;
+/// let opts = depgraph::RuleOptions {
+///     retries: 3,
+///     retry_delay: Duration::from_millis(500),
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct RuleOptions {
+    /// How many times to re-run the build function if it fails. The default is 0 (no retries).
+    pub retries: u32,
+    /// How long to wait before the first retry. The delay doubles for each subsequent retry.
+    pub retry_delay: Duration,
+    /// How long to let each attempt of the build function run before giving up on it with
+    /// `Error::Timeout`. The default is `None` (no limit).
+    ///
+    /// Synchronous build functions can't be stopped, so they are left running in the background;
+    /// if they start a child process, it is up to them to kill it.
+    pub timeout: Option<Duration>,
+    /// Run the build function every time, even if the target looks up to date (for example
+    /// because it embeds the current time or git commit). The default is `false`.
+    pub always_rebuild: bool,
+    /// Extra dependencies that don't have to exist, such as an optional config file.
+    ///
+    /// These are in addition to the rule's other dependencies. If one of them is missing, the
+    /// rule is built as if it weren't a dependency at all, and it isn't passed to the build
+    /// function.
+    pub optional_deps: Vec<PathBuf>,
+    /// Other files the build function may create as a side effect, like dependency (`.d`) or
+    /// source map files.
+    ///
+    /// These are removed by `DepGraph::clean` and kept by `DepGraph::prune`, but aren't checked
+    /// when deciding whether to rebuild, don't have to exist, and can't be used as dependencies.
+    pub implicit_outputs: Vec<PathBuf>,
+    /// A Makefile-format dependency file written by the build function, like the `.d` file from
+    /// `gcc -MMD`.
+    ///
+    /// The files listed in it are treated as extra dependencies of the rule, so a change to an
+    /// included header rebuilds the target. If it lists a file that no longer exists, the target
+    /// is rebuilt. It is read before deciding whether to rebuild, and again after building to
+    /// record the new list. Like implicit outputs, it is removed by `DepGraph::clean` and kept by
+    /// `DepGraph::prune`.
+    pub depfile: Option<PathBuf>,
+    /// A scanner run on each of the rule's dependencies to find further dependencies, such as
+    /// `IncludeScanner` for the headers a C file includes.
+    pub scanner: Option<Arc<dyn Scanner>>,
+    /// A description of what the rule does, such as the command and flags it runs.
+    ///
+    /// Changes to a build function can't be seen from the outside, so set this to something that
+    /// changes with it. If it differs from the last time the target was built, the target is
+    /// rebuilt. This needs a build log (see `DepGraphBuilder::build_log`) to remember the last
+    /// value between runs.
+    pub fingerprint: Option<String>,
+    /// After running the build function, put back the old modification time of any output whose
+    /// contents didn't change, so the targets that depend on it aren't rebuilt. The default is
+    /// `false`.
+    ///
+    /// Useful for code generators that usually write the same output. To tell that the rule
+    /// itself is then up to date, even though its output is older than its dependencies, it needs
+    /// a build log (see `DepGraphBuilder::build_log`); without one the build function is run
+    /// every time a dependency is newer.
+    pub restat: bool,
+    /// Environment variables the build function reads. If any of their values differ from the
+    /// last time the target was built, the target is rebuilt. Like `fingerprint`, this needs a
+    /// build log (see `DepGraphBuilder::build_log`).
+    pub env_deps: Vec<String>,
+    /// A freshness checker for this rule, in place of the graph's (see
+    /// `DepGraphBuilder::freshness_checker`).
+    ///
+    /// Its `exists` method is also used to check that the rule's outputs exist, so a target can be
+    /// something other than a file, such as a table in a database. Rules that depend on such a
+    /// target need a checker that understands it too.
+    pub freshness_checker: Option<Arc<dyn FreshnessChecker>>,
+    /// How soon `DepGraph::make_parallel` should start the rule, relative to others that are ready
+    /// at the same time. Higher priorities go first. The default is 0.
+    ///
+    /// This breaks ties between rules with the same build time still to come after them (see
+    /// `make_parallel`), which is all of them before the build log has recorded any durations.
+    /// The rules a rule depends on share its priority, if theirs is lower, so a slow link step
+    /// can start as early as possible.
+    pub priority: i32,
+    /// The pool the rule is in, which limits how many of the rules in it run at once (see
+    /// `DepGraphBuilder::pool`). The default is `None`, which leaves it to the number of jobs.
+    /// `CONSOLE_POOL` is always available.
+    pub pool: Option<String>,
+}
+
+impl RuleOptions {
+    /// The delay before retry number `attempt` (starting at 0).
+    pub(crate) fn delay_before_retry(&self, attempt: u32) -> Duration {
+        self.retry_delay
+            .checked_mul(1 << attempt.min(16))
+            .unwrap_or(Duration::MAX)
+    }
+}
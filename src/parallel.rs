@@ -0,0 +1,256 @@
+//! A simple scheduler that runs independent rules concurrently.
+//!
+//! The calling thread owns all scheduling state; worker threads only ever run
+//! `DepGraph::build_dependency` for nodes whose dependencies have all finished. With the `rayon`
+//! feature the workers are rayon's thread pool, otherwise a scoped thread is started for each
+//! job.
+//!
+//! A build function that panics doesn't take its worker down: the panic is caught and sent back
+//! to the calling thread, which waits for the running rules to finish and then resumes it, as
+//! `make` would.
+
+use std::any::Any;
+use std::env;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc;
+#[cfg(not(feature = "rayon"))]
+use std::sync::Mutex;
+use std::thread;
+#[cfg(feature = "rayon")]
+use std::time::Duration;
+
+use petgraph::graph::NodeIndex;
+
+use crate::{BuildObserver, DepGraph, DepResult, MakeParams};
+
+/// (Internal) What a worker sends back for a node: the result of building it, or the panic it
+/// raised.
+type Done = (NodeIndex<u32>, Result<DepResult<()>, Box<dyn Any + Send>>);
+
+/// The number of jobs to run when none is given: `NUM_JOBS` if set, otherwise the number of CPUs.
+pub(crate) fn default_jobs() -> usize {
+    default_jobs_from(env::var("NUM_JOBS").ok().as_deref())
+}
+
+/// The number of jobs to run given the value of `NUM_JOBS`, or the number of CPUs if it isn't a
+/// positive number.
+pub(crate) fn default_jobs_from(num_jobs: Option<&str>) -> usize {
+    num_jobs
+        .and_then(|jobs| jobs.parse().ok())
+        .filter(|jobs| *jobs > 0)
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()))
+}
+
+impl<'a> DepGraph<'a> {
+    /// Run the build, executing rules whose dependencies are all up to date concurrently.
+    ///
+    /// At most `jobs` build functions run at the same time. If `jobs` is 0, the `NUM_JOBS`
+    /// environment variable cargo sets for build scripts is used, so a build script doesn't run
+    /// more jobs than cargo was asked to, or if it isn't set, the number of available CPUs. If any
+    /// rule fails no new rules are started (unless using `MakeParams::KeepGoing`), rules that are
+    /// already running are allowed to finish, and the first error is returned.
+    ///
+    /// This needs the `parallel` feature, which requires build functions to be `Send + Sync`.
+    /// With the `rayon` feature, rules are run on rayon's global thread pool (still at most
+    /// `jobs` at once), or the pool of the caller if it is running on one, so they share threads
+    /// with the rest of the program's parallel work. `make` then builds in parallel too.
+    ///
+    /// If a build function panics, no new rules are started, and the panic is resumed once the
+    /// running rules have finished.
+    ///
+    /// When a build log is kept (see `DepGraphBuilder::build_log`), the durations it records are
+    /// used to start the rules on the critical path first (see `critical_path`): of the rules that
+    /// are ready, the one with the longest chain of builds still to come after it runs next. Ties
+    /// are broken by `RuleOptions::priority`.
+    pub fn make_parallel(&self, make_params: MakeParams, jobs: usize) -> DepResult<()> {
+        self.make_parallel_with_observer(make_params, jobs, &())
+    }
+
+    /// Run the build in parallel, like `make_parallel`, notifying `observer` of progress.
+    pub fn make_parallel_with_observer(
+        &self,
+        make_params: MakeParams,
+        jobs: usize,
+        observer: &dyn BuildObserver,
+    ) -> DepResult<()> {
+        // the toposort is only used to make sure the graph is still acyclic
+        self.toposort()?;
+        if make_params.check_only() {
+            return self.check_up_to_date(self.default_wanted.as_deref());
+        }
+        if make_params.touch() {
+            return self.touch(self.default_wanted.as_deref(), observer);
+        }
+        let jobs = match jobs {
+            0 => default_jobs(),
+            n => n,
+        };
+        self.make_parallel_from(self.default_wanted.as_deref(), make_params, jobs, observer)
+    }
+
+    /// Run the build in parallel, of everything or only the nodes in `wanted`.
+    pub(crate) fn make_parallel_from(
+        &self,
+        wanted: Option<&[bool]>,
+        make_params: MakeParams,
+        jobs: usize,
+        observer: &dyn BuildObserver,
+    ) -> DepResult<()> {
+        // the toposort is only used to make sure the graph is still acyclic
+        self.toposort()?;
+        if make_params.check_only() {
+            return self.check_up_to_date(wanted);
+        }
+        if make_params.touch() {
+            return self.touch(wanted, observer);
+        }
+        let force = make_params.force();
+
+        observer.on_begin(self.rule_count(wanted));
+
+        #[cfg(feature = "tracing")]
+        let dispatch = tracing::dispatcher::get_default(|dispatch| dispatch.clone());
+        let build = |idx| {
+            // rule spans go to the caller's subscriber, even if it is only set on its thread
+            #[cfg(feature = "tracing")]
+            let _guard = tracing::dispatcher::set_default(&dispatch);
+            // a panic is sent back, rather than leaving the scheduler waiting for the node forever
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                self.build_dependency(idx, force, observer)
+            }));
+            (idx, result)
+        };
+        let result = self.run_workers(jobs, &build, |start, wait| {
+            self.schedule(wanted, make_params, jobs, start, wait)
+        });
+        observer.on_end(&result);
+        result
+    }
+
+    /// Run the scheduling loop in `schedule`, giving it a function that starts `build`ing a node
+    /// on a rayon thread, and one that waits for a node to finish.
+    #[cfg(feature = "rayon")]
+    fn run_workers<S>(
+        &self,
+        _jobs: usize,
+        build: &(dyn Fn(NodeIndex<u32>) -> Done + Sync),
+        schedule: S,
+    ) -> DepResult<()>
+    where
+        S: FnOnce(&mut dyn FnMut(NodeIndex<u32>), &mut dyn FnMut() -> Done) -> DepResult<()>,
+    {
+        let (done_tx, done_rx) = mpsc::channel();
+        // the scheduling loop runs on this thread, so it doesn't take up one of rayon's
+        rayon::in_place_scope(|scope| {
+            let mut start = |idx| {
+                let done_tx = done_tx.clone();
+                scope.spawn(move |_| {
+                    // the scheduler has stopped listening if it panicked
+                    let _ = done_tx.send(build(idx));
+                });
+            };
+            let mut wait = || loop {
+                if let Ok(done) = done_rx.try_recv() {
+                    return done;
+                }
+                // if this thread is one of rayon's, blocking it could leave the spawned rules
+                // with no thread to run on, so it helps run them instead
+                match rayon::yield_now() {
+                    Some(rayon::Yield::Executed) => {}
+                    Some(rayon::Yield::Idle) => {
+                        if let Ok(done) = done_rx.recv_timeout(Duration::from_millis(1)) {
+                            return done;
+                        }
+                    }
+                    None => return done_rx.recv().expect("a sender is kept"),
+                }
+            };
+            schedule(&mut start, &mut wait)
+        })
+    }
+
+    /// Run the scheduling loop in `schedule`, giving it a function that hands a node to one of
+    /// `jobs` worker threads to `build`, and one that waits for a node to finish.
+    #[cfg(not(feature = "rayon"))]
+    fn run_workers<S>(
+        &self,
+        jobs: usize,
+        build: &(dyn Fn(NodeIndex<u32>) -> Done + Sync),
+        schedule: S,
+    ) -> DepResult<()>
+    where
+        S: FnOnce(&mut dyn FnMut(NodeIndex<u32>), &mut dyn FnMut() -> Done) -> DepResult<()>,
+    {
+        let (job_tx, job_rx) = mpsc::channel::<NodeIndex<u32>>();
+        let job_rx = Mutex::new(job_rx);
+        let (done_tx, done_rx) = mpsc::channel();
+
+        thread::scope(|scope| {
+            for _ in 0..jobs.min(self.graph.node_count()) {
+                let job_rx = &job_rx;
+                let done_tx = done_tx.clone();
+                scope.spawn(move || loop {
+                    // hold the lock only while waiting for the next job
+                    let next = job_rx.lock().unwrap().recv();
+                    match next {
+                        Ok(idx) => {
+                            if done_tx.send(build(idx)).is_err() {
+                                break;
+                            }
+                        }
+                        Err(_) => break,
+                    }
+                });
+            }
+            let result = schedule(&mut |idx| job_tx.send(idx).unwrap(), &mut || {
+                done_rx.recv().expect("a sender is kept")
+            });
+            // closing the job channel lets the workers exit
+            drop(job_tx);
+            result
+        })
+    }
+
+    /// Hand out nodes (of everything or only those in `wanted`) to `start` as their dependencies
+    /// finish, with at most `jobs` running at once, until there are none left. `wait` waits for
+    /// one of the running nodes to finish.
+    fn schedule(
+        &self,
+        wanted: Option<&[bool]>,
+        make_params: MakeParams,
+        jobs: usize,
+        start: &mut dyn FnMut(NodeIndex<u32>),
+        wait: &mut dyn FnMut() -> Done,
+    ) -> DepResult<()> {
+        let mut scheduler = self
+            .scheduler(wanted, make_params.keep_going())
+            .prioritize(self.build_priorities()?);
+        let mut running = 0;
+        let mut panicked = None;
+        loop {
+            // only start as many as can run, so the next job is picked when a worker is free
+            while running < jobs && panicked.is_none() {
+                match scheduler.next() {
+                    Some(idx) => start(idx),
+                    None => break,
+                }
+                running += 1;
+            }
+            if running == 0 {
+                break;
+            }
+            let (idx, result) = wait();
+            running -= 1;
+            match result {
+                Ok(result) => scheduler.finished(idx, result),
+                Err(payload) => {
+                    panicked.get_or_insert(payload);
+                }
+            }
+        }
+        if let Some(payload) = panicked {
+            panic::resume_unwind(payload);
+        }
+        scheduler.finish()
+    }
+}
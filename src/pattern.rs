@@ -0,0 +1,138 @@
+//! Generating rules for sets of files: make-style pattern rules, like `out/%.o: src/%.asm`, and
+//! `DepGraphBuilder::add_rules_from`.
+
+use std::collections::HashSet;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::{
+    glob, BuildAction, BuildError, DepGraphBuilder, MaybeSend, MaybeSync, Rule, RuleOptions,
+};
+
+/// (Internal) A pattern rule as added to the builder.
+pub(crate) struct PatternRule<'a> {
+    /// The target, with `%` standing for the stem
+    target: String,
+    /// The source file, with `%` standing for the stem
+    source: String,
+    build_fn: BuildAction<'a>,
+}
+
+impl<'a> DepGraphBuilder<'a> {
+    /// Add a rule for every file matching `source`, like a make pattern rule.
+    ///
+    /// `%` in `source` matches any part of a file name (the stem), and `%` in `target` is
+    /// replaced by the same stem. For example, `add_pattern_rule("out/%.o", "src/%.asm", f)`
+    /// adds a rule for `out/boot.o` depending on `src/boot.asm` if that file exists. The stem
+    /// can't contain a path separator.
+    ///
+    /// Matching files are found when the graph is built. If a target also has a normal rule, the
+    /// normal rule is used, and if several pattern rules produce the same target, the first one
+    /// added is used.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `target` or `source` doesn't contain exactly one `%`.
+    pub fn add_pattern_rule<F, P1, P2>(
+        mut self,
+        target: P1,
+        source: P2,
+        build_fn: F,
+    ) -> DepGraphBuilder<'a>
+    where
+        F: Fn(&Path, &[&Path]) -> Result<(), String> + MaybeSend + MaybeSync + 'static,
+        P1: AsRef<Path>,
+        P2: AsRef<Path>,
+    {
+        let target = target.as_ref().to_string_lossy().into_owned();
+        let source = source.as_ref().to_string_lossy().into_owned();
+        for pattern in [&target, &source] {
+            assert_eq!(
+                pattern.matches('%').count(),
+                1,
+                "pattern {:?} must contain exactly one `%`",
+                pattern
+            );
+        }
+        self.pattern_rules.push(PatternRule {
+            target,
+            source,
+            build_fn: BuildAction::Sync(Arc::new(move |out: &Path, deps: &[&Path]| {
+                build_fn(out, deps).map_err(BuildError::from)
+            })),
+        });
+        self
+    }
+
+    /// Add a rule for each of `files`, using `map` to turn each file into the rule's output,
+    /// dependencies and build function.
+    ///
+    /// Unlike `add_pattern_rule`, the files are given explicitly (for example from
+    /// `source_files`), and `map` can compute any output path and dependency list.
+    ///
+    /// ```no_run
+    /// # use std::path::{Path, PathBuf};
+    /// let protos = depgraph::source_files("proto").unwrap();
+    /// let builder = depgraph::DepGraphBuilder::new().add_rules_from(protos, |proto: &Path| {
+    ///     let output = PathBuf::from("src/gen").join(proto.with_extension("rs").file_name().unwrap());
+    ///     let build = |out: &Path, deps: &[&Path]| -> Result<(), String> {
+    ///         // run protoc on `deps[0]`, writing `out`
+    ///         Ok(())
+    ///     };
+    ///     (output, vec![proto.to_owned()], build)
+    /// });
+    /// ```
+    pub fn add_rules_from<I, F, B>(mut self, files: I, mut map: F) -> DepGraphBuilder<'a>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<Path>,
+        F: FnMut(&Path) -> (PathBuf, Vec<PathBuf>, B),
+        B: Fn(&Path, &[&Path]) -> Result<(), String> + MaybeSend + MaybeSync + 'static,
+    {
+        for file in files {
+            let (output, dependencies, build_fn) = map(file.as_ref());
+            self = self.add_rule(output, &dependencies, build_fn);
+        }
+        self
+    }
+}
+
+/// Make a rule for each file matching each of `patterns`, skipping targets that already have a
+/// rule in `rules`.
+pub(crate) fn instantiate<'a>(
+    patterns: &[PatternRule<'a>],
+    rules: &[Rule<'a>],
+    respect_ignore_files: bool,
+) -> io::Result<Vec<Rule<'a>>> {
+    let mut targets: HashSet<PathBuf> = rules.iter().map(|rule| rule.filename.clone()).collect();
+    let mut instances = Vec::new();
+    for pattern in patterns {
+        let (prefix, suffix) = pattern.source.split_once('%').unwrap();
+        let search = PathBuf::from(pattern.source.replace('%', "*"));
+        for source in glob::expand_all(vec![search], respect_ignore_files)? {
+            let path = source.to_string_lossy();
+            let stem = match path
+                .strip_prefix(prefix)
+                .and_then(|rest| rest.strip_suffix(suffix))
+            {
+                Some(stem) if !stem.is_empty() => stem,
+                _ => continue,
+            };
+            let target = PathBuf::from(pattern.target.replace('%', stem));
+            if !targets.insert(target.clone()) {
+                continue;
+            }
+            instances.push(Rule {
+                filename: target,
+                dependencies: vec![source.clone()],
+                build_fn: Some(pattern.build_fn.clone()),
+                options: RuleOptions::default(),
+                extra_outputs: Vec::new(),
+                command: None,
+                target: None,
+            });
+        }
+    }
+    Ok(instances)
+}
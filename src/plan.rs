@@ -0,0 +1,171 @@
+//! Working out what a build would do without doing it.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use petgraph::graph::NodeIndex;
+
+use crate::{DepGraph, DepResult, Error, MakeParams, RebuildReason};
+
+/// The targets that `make` would rebuild, in the order it would rebuild them.
+///
+/// Returned by `DepGraph::plan`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildPlan {
+    targets: Vec<PathBuf>,
+}
+
+impl BuildPlan {
+    /// The targets that would be rebuilt, in build order.
+    pub fn targets(&self) -> &[PathBuf] {
+        &self.targets
+    }
+
+    /// Whether `target` would be rebuilt.
+    pub fn contains<P: AsRef<Path>>(&self, target: P) -> bool {
+        self.targets.iter().any(|t| t == target.as_ref())
+    }
+
+    /// Whether everything is already up to date.
+    pub fn is_empty(&self) -> bool {
+        self.targets.is_empty()
+    }
+}
+
+impl fmt::Display for BuildPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for target in self.targets.iter() {
+            writeln!(f, "{}", target.display())?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> DepGraph<'a> {
+    /// Work out which targets `make` would rebuild, and in what order, without running any build
+    /// functions.
+    ///
+    /// A target is rebuilt if it is stale itself, or if any of its dependencies will be rebuilt.
+    /// Phony targets and the extra outputs of rules with several outputs aren't included. Returns
+    /// `Error::MissingFile` if a source file (one with no rule) is missing.
+    pub fn plan(&self, make_params: MakeParams) -> DepResult<BuildPlan> {
+        let targets = self
+            .rebuild_reasons(make_params, self.default_wanted.as_deref())?
+            .into_iter()
+            .filter(|(idx, reason)| reason.is_rebuild() && self.graph[*idx].build_fn.is_some())
+            .map(|(idx, _)| self.graph[idx].filename.clone())
+            .collect();
+        Ok(BuildPlan { targets })
+    }
+
+    /// Every target that is out of date, either itself or because something it depends on is, in
+    /// build order.
+    ///
+    /// This is the same as `plan(MakeParams::None)`, but borrows the paths from the graph.
+    pub fn stale_targets(&self) -> DepResult<Vec<&Path>> {
+        Ok(self
+            .rebuild_reasons(MakeParams::None, self.default_wanted.as_deref())?
+            .into_iter()
+            .filter(|(idx, reason)| reason.is_rebuild() && self.graph[*idx].build_fn.is_some())
+            .map(|(idx, _)| self.graph[idx].filename.as_path())
+            .collect())
+    }
+
+    /// Check that everything is up to date, returning `Error::OutOfDate` with the stale targets
+    /// (in build order) if not.
+    ///
+    /// No build functions are run. This is what `make` does with `MakeParams::CheckOnly`.
+    pub fn verify(&self) -> DepResult<()> {
+        self.check_up_to_date(self.default_wanted.as_deref())
+    }
+
+    /// Like `verify`, but only checking the nodes in `wanted` if it is given.
+    pub(crate) fn check_up_to_date(&self, wanted: Option<&[bool]>) -> DepResult<()> {
+        let stale: Vec<PathBuf> = self
+            .rebuild_reasons(MakeParams::None, wanted)?
+            .into_iter()
+            .filter(|(idx, reason)| reason.is_rebuild() && self.graph[*idx].build_fn.is_some())
+            .map(|(idx, _)| self.graph[idx].filename.clone())
+            .collect();
+        if stale.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::OutOfDate(stale))
+        }
+    }
+
+    /// Say whether `make` would rebuild `target`, and why.
+    ///
+    /// This is useful for debugging incremental builds: a target that keeps being rebuilt will
+    /// usually have a dependency that is newer than it, or that is itself being rebuilt. Only
+    /// `target` and the files it depends on are checked. Returns `Error::UnknownTarget` if
+    /// `target` isn't in the graph, and `Error::MissingFile` if a source file it depends on is
+    /// missing.
+    pub fn explain<P: AsRef<Path>>(
+        &self,
+        target: P,
+        make_params: MakeParams,
+    ) -> DepResult<RebuildReason> {
+        let target = self.node(target.as_ref())?;
+        let (_, reason) = self
+            .rebuild_reasons(make_params, Some(&self.reachable(&[target])))?
+            .pop()
+            .expect("the target is always last");
+        Ok(reason)
+    }
+
+    /// Whether `target` and everything it depends on are up to date, so that `make` wouldn't run
+    /// any of their build functions.
+    ///
+    /// Returns `Error::UnknownTarget` if `target` isn't in the graph, and `Error::MissingFile` if
+    /// a source file it depends on is missing.
+    pub fn is_up_to_date<P: AsRef<Path>>(&self, target: P) -> DepResult<bool> {
+        Ok(!self.explain(target, MakeParams::None)?.is_rebuild())
+    }
+
+    /// The reason each node would or wouldn't be rebuilt, in build order.
+    ///
+    /// If `wanted` is given, only the nodes in it are included.
+    pub(crate) fn rebuild_reasons(
+        &self,
+        make_params: MakeParams,
+        wanted: Option<&[bool]>,
+    ) -> DepResult<Vec<(NodeIndex, RebuildReason)>> {
+        let mut ordered_deps_rev = self.toposort()?;
+        if let Some(wanted) = wanted {
+            ordered_deps_rev.retain(|idx| wanted[idx.index()]);
+        }
+        let force = make_params.force();
+        let mut rebuild = vec![false; self.graph.node_count()];
+        let mut reasons = Vec::new();
+        for idx in ordered_deps_rev.into_iter().rev() {
+            let dep = self.graph.node_weight(idx).unwrap();
+            let children = self.children(idx);
+            let rebuilt_child = children.iter().find(|child| rebuild[child.index()]);
+            let reason = if dep.phony || dep.extra_output {
+                match rebuilt_child {
+                    Some(child) => RebuildReason::DepRebuilt(self.graph[*child].filename.clone()),
+                    None => RebuildReason::UpToDate,
+                }
+            } else if dep.build_fn.is_none() {
+                dep.check_exists()?;
+                RebuildReason::NoRule
+            } else if force {
+                RebuildReason::Forced
+            } else if dep.options.always_rebuild {
+                RebuildReason::AlwaysRebuild
+            } else if self.assume.old.contains(&dep.filename) {
+                RebuildReason::AssumedOld
+            } else if let Some(child) = rebuilt_child {
+                RebuildReason::DepRebuilt(self.graph[*child].filename.clone())
+            } else {
+                let deps = self.dependency_files(idx);
+                self.stale_reason(dep, &deps)?
+                    .unwrap_or(RebuildReason::UpToDate)
+            };
+            rebuild[idx.index()] = reason.is_rebuild();
+            reasons.push((idx, reason));
+        }
+        Ok(reasons)
+    }
+}
@@ -0,0 +1,171 @@
+//! A progress bar built on `indicatif` (the `progress` feature).
+
+use std::fmt;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use indicatif::{ProgressDrawTarget, ProgressStyle, TermLike};
+
+use crate::{BuildObserver, DepResult, Error};
+
+/// An observer that draws a progress bar, with the name of the current target, on stderr.
+///
+/// The bar is drawn with the `indicatif` crate, and is hidden when stderr isn't a terminal.
+///
+/// ```no_run
+/// # let graph = depgraph::DepGraphBuilder::new().build().unwrap();
+/// graph
+///     .make_with_observer(depgraph::MakeParams::None, &depgraph::ProgressBar::new())
+///     .unwrap();
+/// ```
+pub struct ProgressBar {
+    bar: indicatif::ProgressBar,
+}
+
+/// The width of the bar itself, in characters.
+const WIDTH: usize = 30;
+
+impl ProgressBar {
+    /// Create a progress bar. The total is filled in when the build starts.
+    pub fn new() -> ProgressBar {
+        ProgressBar::with_target(ProgressDrawTarget::stderr())
+    }
+
+    /// Create a progress bar that is drawn on `out` rather than stderr, whether or not it is a
+    /// terminal.
+    pub fn to_writer<W: Write + Send + 'static>(out: W) -> ProgressBar {
+        ProgressBar::with_target(ProgressDrawTarget::term_like(Box::new(Writer(Mutex::new(
+            Box::new(out),
+        )))))
+    }
+
+    /// Create a progress bar drawn on `target`.
+    fn with_target(target: ProgressDrawTarget) -> ProgressBar {
+        let style =
+            ProgressStyle::with_template(&format!("[{{bar:{}}}] {{pos}}/{{len}} {{msg}}", WIDTH))
+                .unwrap()
+                .progress_chars("== ");
+        ProgressBar {
+            bar: indicatif::ProgressBar::with_draw_target(Some(0), target).with_style(style),
+        }
+    }
+
+    /// Count `target` as done.
+    fn finish_one(&self, target: &Path) {
+        self.bar.inc(1);
+        self.bar.set_message(target.display().to_string());
+    }
+}
+
+impl Default for ProgressBar {
+    fn default() -> ProgressBar {
+        ProgressBar::new()
+    }
+}
+
+impl fmt::Debug for ProgressBar {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProgressBar")
+            .field("done", &self.bar.position())
+            .field("total", &self.bar.length().unwrap_or(0))
+            .finish()
+    }
+}
+
+impl BuildObserver for ProgressBar {
+    fn on_begin(&self, total: usize) {
+        self.bar.set_length(total as u64);
+        self.bar.reset();
+    }
+
+    fn on_start(&self, target: &Path) {
+        self.bar
+            .set_message(format!("building {}", target.display()));
+    }
+
+    fn on_skipped(&self, target: &Path) {
+        self.finish_one(target);
+    }
+
+    fn on_finished(&self, target: &Path, _: Duration) {
+        self.finish_one(target);
+    }
+
+    fn on_failed(&self, target: &Path, _: &Error) {
+        self.finish_one(target);
+    }
+
+    fn on_end(&self, result: &DepResult<()>) {
+        // targets whose dependencies failed are never reported, so the bar can stop short of
+        // the total
+        if result.is_ok() {
+            self.bar.finish();
+        } else {
+            self.bar.abandon_with_message("failed");
+        }
+    }
+}
+
+/// (Internal) A terminal that is really any writer, for `ProgressBar::to_writer`.
+struct Writer(Mutex<Box<dyn Write + Send>>);
+
+impl Writer {
+    fn write(&self, s: &str) -> io::Result<()> {
+        self.0.lock().unwrap().write_all(s.as_bytes())
+    }
+
+    /// Move the cursor `n` places in `direction` (one of the ANSI codes `A` to `D`).
+    fn move_cursor(&self, n: usize, direction: char) -> io::Result<()> {
+        // an ANSI cursor movement of 0 moves by 1
+        if n == 0 {
+            return Ok(());
+        }
+        self.write(&format!("\x1b[{}{}", n, direction))
+    }
+}
+
+impl fmt::Debug for Writer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Writer")
+    }
+}
+
+impl TermLike for Writer {
+    fn width(&self) -> u16 {
+        80
+    }
+
+    fn move_cursor_up(&self, n: usize) -> io::Result<()> {
+        self.move_cursor(n, 'A')
+    }
+
+    fn move_cursor_down(&self, n: usize) -> io::Result<()> {
+        self.move_cursor(n, 'B')
+    }
+
+    fn move_cursor_right(&self, n: usize) -> io::Result<()> {
+        self.move_cursor(n, 'C')
+    }
+
+    fn move_cursor_left(&self, n: usize) -> io::Result<()> {
+        self.move_cursor(n, 'D')
+    }
+
+    fn write_line(&self, s: &str) -> io::Result<()> {
+        self.write(&format!("{}\n", s))
+    }
+
+    fn write_str(&self, s: &str) -> io::Result<()> {
+        self.write(s)
+    }
+
+    fn clear_line(&self) -> io::Result<()> {
+        self.write("\r\x1b[2K")
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.0.lock().unwrap().flush()
+    }
+}
@@ -0,0 +1,107 @@
+//! Finding what targets depend on, and what depends on them.
+
+use std::path::Path;
+
+use petgraph::graph::NodeIndex;
+use petgraph::visit::{Dfs, Reversed};
+
+use crate::{DepGraph, DepResult};
+
+impl<'a> DepGraph<'a> {
+    /// The direct dependencies of `target`, in the order they were given to the builder.
+    ///
+    /// Returns `Error::UnknownTarget` if `target` isn't in the graph.
+    pub fn dependencies_of<P: AsRef<Path>>(&self, target: P) -> DepResult<Vec<&Path>> {
+        let idx = self.node(target.as_ref())?;
+        Ok(self.paths(self.children(idx)))
+    }
+
+    /// Everything `target` depends on, directly or not, in build order.
+    ///
+    /// Returns `Error::UnknownTarget` if `target` isn't in the graph.
+    pub fn all_dependencies_of<P: AsRef<Path>>(&self, target: P) -> DepResult<Vec<&Path>> {
+        let idx = self.node(target.as_ref())?;
+        let mut wanted = self.reachable(&[idx]);
+        wanted[idx.index()] = false;
+        self.in_build_order(&wanted)
+    }
+
+    /// The targets that depend directly on `target`, in build order.
+    ///
+    /// Returns `Error::UnknownTarget` if `target` isn't in the graph.
+    pub fn dependents_of<P: AsRef<Path>>(&self, target: P) -> DepResult<Vec<&Path>> {
+        let idx = self.node(target.as_ref())?;
+        let mut wanted = vec![false; self.graph.node_count()];
+        for parent in self.graph.neighbors_directed(idx, petgraph::Incoming) {
+            wanted[parent.index()] = true;
+        }
+        self.in_build_order(&wanted)
+    }
+
+    /// Everything that depends on `target`, directly or not, in build order. These are the
+    /// targets that would be rebuilt if `target` changed.
+    ///
+    /// Returns `Error::UnknownTarget` if `target` isn't in the graph.
+    pub fn all_dependents_of<P: AsRef<Path>>(&self, target: P) -> DepResult<Vec<&Path>> {
+        let idx = self.node(target.as_ref())?;
+        let mut wanted = vec![false; self.graph.node_count()];
+        let reversed = Reversed(&self.graph);
+        let mut dfs = Dfs::new(reversed, idx);
+        while let Some(parent) = dfs.next(reversed) {
+            wanted[parent.index()] = true;
+        }
+        wanted[idx.index()] = false;
+        self.in_build_order(&wanted)
+    }
+
+    /// The final outputs: the targets that nothing else depends on, in build order. Phony targets
+    /// (see `DepGraphBuilder::group`) aren't included, and don't count as depending on anything,
+    /// so the members of a group that nothing depends on are roots.
+    pub fn roots(&self) -> DepResult<Vec<&Path>> {
+        let wanted: Vec<bool> = self
+            .graph
+            .node_indices()
+            .map(|idx| {
+                let dep = &self.graph[idx];
+                (dep.build_fn.is_some() || dep.extra_output)
+                    && self
+                        .graph
+                        .neighbors_directed(idx, petgraph::Incoming)
+                        .all(|parent| self.graph[parent].phony || self.graph[parent].extra_output)
+            })
+            .collect();
+        self.in_build_order(&wanted)
+    }
+
+    /// The source files: those without a rule, in build order. These include the targets added
+    /// with `DepGraphBuilder::add_external_target`, but not the dependencies discovered by
+    /// depfiles and scanners.
+    pub fn leaves(&self) -> DepResult<Vec<&Path>> {
+        let wanted: Vec<bool> = self
+            .graph
+            .node_weights()
+            .map(|dep| dep.build_fn.is_none() && !dep.phony && !dep.extra_output)
+            .collect();
+        self.in_build_order(&wanted)
+    }
+
+    /// The paths of the nodes in `wanted`, in build order.
+    fn in_build_order(&self, wanted: &[bool]) -> DepResult<Vec<&Path>> {
+        let ordered = self.toposort()?;
+        Ok(self.paths(
+            ordered
+                .into_iter()
+                .rev()
+                .filter(|idx| wanted[idx.index()])
+                .collect(),
+        ))
+    }
+
+    /// The paths of `nodes`, in the same order.
+    fn paths(&self, nodes: Vec<NodeIndex>) -> Vec<&Path> {
+        nodes
+            .into_iter()
+            .map(|idx| self.graph[idx].filename.as_path())
+            .collect()
+    }
+}
@@ -0,0 +1,164 @@
+//! Recording what happened during a build.
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread::{self, ThreadId};
+use std::time::{Duration, Instant};
+
+use crate::{json, BuildObserver, DepGraph, DepResult, MakeParams};
+
+/// A record of the rules that ran during a build, returned by `DepGraph::make_with_report`.
+#[derive(Debug, Clone)]
+pub struct MakeReport {
+    entries: Vec<Entry>,
+    skipped: Vec<PathBuf>,
+    elapsed: Duration,
+}
+
+/// (Internal) A single build function run.
+#[derive(Debug, Clone)]
+struct Entry {
+    target: PathBuf,
+    /// when the build function started, relative to the start of the build
+    start: Duration,
+    duration: Duration,
+    /// which thread the build function ran on, numbered from 0 in order of first use
+    lane: usize,
+}
+
+impl MakeReport {
+    /// The targets that were built, with how long their build functions took, in the order they
+    /// finished.
+    pub fn built(&self) -> Vec<(&Path, Duration)> {
+        self.entries
+            .iter()
+            .map(|entry| (entry.target.as_path(), entry.duration))
+            .collect()
+    }
+
+    /// The targets that were already up to date.
+    pub fn skipped(&self) -> &[PathBuf] {
+        &self.skipped
+    }
+
+    /// How long the whole build took.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    /// The built targets, slowest first.
+    pub fn slowest(&self) -> Vec<(&Path, Duration)> {
+        let mut built = self.built();
+        built.sort_by_key(|&(_, duration)| std::cmp::Reverse(duration));
+        built
+    }
+
+    /// Write the report in the Chrome trace event format.
+    ///
+    /// Load the output in `about://tracing` (or Perfetto) to see when each rule ran, and on
+    /// which thread, to find the bottlenecks in a build.
+    pub fn write_chrome_trace<W: Write>(&self, mut out: W) -> io::Result<()> {
+        writeln!(out, "{{\"traceEvents\": [")?;
+        for (i, entry) in self.entries.iter().enumerate() {
+            writeln!(
+                out,
+                "  {{\"name\": {}, \"cat\": \"build\", \"ph\": \"X\", \"ts\": {}, \"dur\": {}, \
+                 \"pid\": 0, \"tid\": {}}}{}",
+                json::string(&entry.target.display().to_string()),
+                entry.start.as_micros(),
+                entry.duration.as_micros(),
+                entry.lane,
+                if i + 1 < self.entries.len() { "," } else { "" }
+            )?;
+        }
+        writeln!(out, "]}}")
+    }
+}
+
+/// (Internal) An observer that builds up a `MakeReport`.
+struct Recorder {
+    begin: Instant,
+    state: Mutex<RecorderState>,
+}
+
+#[derive(Default)]
+struct RecorderState {
+    /// start times of running build functions
+    started: HashMap<PathBuf, Instant>,
+    lanes: HashMap<ThreadId, usize>,
+    entries: Vec<Entry>,
+    skipped: Vec<PathBuf>,
+}
+
+impl Recorder {
+    fn new() -> Recorder {
+        Recorder {
+            begin: Instant::now(),
+            state: Mutex::new(RecorderState::default()),
+        }
+    }
+
+    fn into_report(self) -> MakeReport {
+        let state = self.state.into_inner().unwrap();
+        MakeReport {
+            entries: state.entries,
+            skipped: state.skipped,
+            elapsed: self.begin.elapsed(),
+        }
+    }
+}
+
+impl BuildObserver for Recorder {
+    fn on_start(&self, target: &Path) {
+        let mut state = self.state.lock().unwrap();
+        state.started.insert(target.to_owned(), Instant::now());
+    }
+
+    fn on_skipped(&self, target: &Path) {
+        let mut state = self.state.lock().unwrap();
+        state.skipped.push(target.to_owned());
+    }
+
+    fn on_finished(&self, target: &Path, duration: Duration) {
+        let mut state = self.state.lock().unwrap();
+        let started = state
+            .started
+            .remove(target)
+            .unwrap_or_else(|| Instant::now() - duration);
+        let next_lane = state.lanes.len();
+        let lane = *state
+            .lanes
+            .entry(thread::current().id())
+            .or_insert(next_lane);
+        state.entries.push(Entry {
+            target: target.to_owned(),
+            start: started.duration_since(self.begin),
+            duration,
+            lane,
+        });
+    }
+}
+
+impl<'a> DepGraph<'a> {
+    /// Run the build, like `make`, and return a report of the rules that ran.
+    pub fn make_with_report(&self, make_params: MakeParams) -> DepResult<MakeReport> {
+        let recorder = Recorder::new();
+        self.make_with_observer(make_params, &recorder)?;
+        Ok(recorder.into_report())
+    }
+
+    /// Run the build in parallel, like `make_parallel`, and return a report of the rules that
+    /// ran.
+    #[cfg(feature = "parallel")]
+    pub fn make_parallel_with_report(
+        &self,
+        make_params: MakeParams,
+        jobs: usize,
+    ) -> DepResult<MakeReport> {
+        let recorder = Recorder::new();
+        self.make_parallel_with_observer(make_params, jobs, &recorder)?;
+        Ok(recorder.into_report())
+    }
+}
@@ -0,0 +1,42 @@
+//! Keeping the modification times of outputs that a rebuild didn't change
+//! (`RuleOptions::restat`).
+
+use std::fs::{self, File};
+use std::io;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+use crate::hash::{hash_file, FileHash};
+use crate::DependencyNode;
+
+/// (Internal) The contents and modification times of a rule's outputs before it is rebuilt.
+pub(crate) struct Snapshot {
+    outputs: Vec<(PathBuf, FileHash, SystemTime)>,
+}
+
+impl Snapshot {
+    /// Record the existing outputs of `dep`, if it has `restat` set.
+    pub(crate) fn take(dep: &DependencyNode) -> io::Result<Snapshot> {
+        let mut outputs = Vec::new();
+        if dep.options.restat {
+            for output in dep.outputs().filter(|output| output.is_file()) {
+                let modified = fs::metadata(output)?.modified()?;
+                outputs.push((output.to_owned(), hash_file(output)?, modified));
+            }
+        }
+        Ok(Snapshot { outputs })
+    }
+
+    /// Put back the modification times of the outputs whose contents are the same as before.
+    pub(crate) fn restore_unchanged(&self) -> io::Result<()> {
+        for (output, hash, modified) in &self.outputs {
+            if hash_file(output)? == *hash {
+                File::options()
+                    .write(true)
+                    .open(output)?
+                    .set_modified(*modified)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,140 @@
+//! Converting game assets with external tools (the `assets` feature).
+//!
+//! Each conversion runs a stock tool by default, which can be replaced with
+//! `AssetTool::program`:
+//!
+//! | Conversion    | Tool                                   |
+//! |---------------|----------------------------------------|
+//! | `png_to_ktx2` | `toktx` (KTX-Software)                 |
+//! | `png_to_dds`  | `compressonatorcli` (AMD Compressonator) |
+//! | `wav_to_ogg`  | `oggenc` (vorbis-tools)                |
+//!
+//! ```no_run
+//! use depgraph::rules::assets::{self, AssetTool};
+//! let builder = assets::convert_all(
+//!     depgraph::DepGraphBuilder::new(),
+//!     &["assets/jump.wav", "assets/land.wav"],
+//!     "out/sounds",
+//!     "ogg",
+//!     &AssetTool::new().arg("-q6"),
+//!     assets::wav_to_ogg,
+//! );
+//! ```
+
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+
+use crate::{CommandSpec, DepGraphBuilder};
+
+/// Settings for running an asset conversion tool.
+#[derive(Debug, Clone, Default)]
+pub struct AssetTool {
+    program: Option<PathBuf>,
+    args: Vec<OsString>,
+}
+
+impl AssetTool {
+    /// Settings using the stock tool from the `PATH`, with no extra arguments.
+    pub fn new() -> AssetTool {
+        AssetTool::default()
+    }
+
+    /// Run `program` instead of the stock tool. It is passed the same arguments.
+    pub fn program<P: AsRef<Path>>(mut self, program: P) -> AssetTool {
+        self.program = Some(program.as_ref().to_owned());
+        self
+    }
+
+    /// Pass an extra argument, such as a quality setting. Arguments are passed before the input
+    /// and output files.
+    pub fn arg<S: AsRef<OsStr>>(mut self, arg: S) -> AssetTool {
+        self.args.push(arg.as_ref().to_owned());
+        self
+    }
+
+    /// The command running this tool (or `default`) with `before` and then the extra arguments,
+    /// followed by `after`.
+    fn command(&self, default: &str, before: &[&str], after: &[&str]) -> CommandSpec {
+        let program = match self.program {
+            Some(ref program) => program.as_os_str().to_owned(),
+            None => OsString::from(default),
+        };
+        CommandSpec::new(program)
+            .args(before)
+            .args(&self.args)
+            .args(after)
+    }
+}
+
+/// Add a rule compressing the image `src` to the KTX2 texture `out`, with `toktx --t2`.
+pub fn png_to_ktx2<'a, P1, P2>(
+    builder: DepGraphBuilder<'a>,
+    src: P1,
+    out: P2,
+    tool: &AssetTool,
+) -> DepGraphBuilder<'a>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    let command = tool.command("toktx", &["--t2"], &["{out}", "{deps}"]);
+    builder.add_command_rule(out, &[src], command)
+}
+
+/// Add a rule compressing the image `src` to the DDS texture `out`, with `compressonatorcli`.
+/// The default format is BC7; pass `-fd` and another format as arguments to change it.
+pub fn png_to_dds<'a, P1, P2>(
+    builder: DepGraphBuilder<'a>,
+    src: P1,
+    out: P2,
+    tool: &AssetTool,
+) -> DepGraphBuilder<'a>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    let before: &[&str] = if tool.args.iter().any(|arg| arg == "-fd") {
+        &[]
+    } else {
+        &["-fd", "BC7"]
+    };
+    let command = tool.command("compressonatorcli", before, &["{deps}", "{out}"]);
+    builder.add_command_rule(out, &[src], command)
+}
+
+/// Add a rule encoding the WAV file `src` to the Ogg Vorbis file `out`, with `oggenc`.
+pub fn wav_to_ogg<'a, P1, P2>(
+    builder: DepGraphBuilder<'a>,
+    src: P1,
+    out: P2,
+    tool: &AssetTool,
+) -> DepGraphBuilder<'a>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    let command = tool.command("oggenc", &[], &["-o", "{out}", "{deps}"]);
+    builder.add_command_rule(out, &[src], command)
+}
+
+/// Add a rule for each of `srcs` using `convert` (one of the functions in this module), putting
+/// the output in `out_dir` with the same file name but with the extension `extension`.
+pub fn convert_all<'a, P1, P2, F>(
+    builder: DepGraphBuilder<'a>,
+    srcs: &[P1],
+    out_dir: P2,
+    extension: &str,
+    tool: &AssetTool,
+    convert: F,
+) -> DepGraphBuilder<'a>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+    F: Fn(DepGraphBuilder<'a>, PathBuf, PathBuf, &AssetTool) -> DepGraphBuilder<'a>,
+{
+    srcs.iter().fold(builder, |builder, src| {
+        let src = src.as_ref();
+        let name = Path::new(src.file_name().unwrap_or_default()).with_extension(extension);
+        convert(builder, src.to_owned(), out_dir.as_ref().join(name), tool)
+    })
+}
@@ -0,0 +1,193 @@
+//! Compiling C, C++ and assembly files.
+
+#[cfg(not(feature = "cc"))]
+use std::env;
+#[cfg(not(feature = "cc"))]
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::{CommandSpec, DepGraphBuilder, IncludeScanner, RuleOptions};
+
+/// Settings for compiling a C file, for `c_object`. A small subset of what the `cc` crate's
+/// `cc::Build` offers, and like it the compiler is taken from the `CC` environment variable if
+/// it isn't set explicitly.
+///
+/// Without the `cc` feature the compiler is passed gcc-style flags, so this works with gcc and
+/// clang but not MSVC, and `CFLAGS` and the target aren't looked at. With it the command comes
+/// from `cc::Build::get_compiler`, so it is the same one the `cc` crate would run: see
+/// `CBuild::from_cc`.
+#[derive(Debug, Clone, Default)]
+pub struct CBuild {
+    compiler: Option<PathBuf>,
+    include_dirs: Vec<PathBuf>,
+    defines: Vec<(String, Option<String>)>,
+    flags: Vec<String>,
+    /// The settings the others are added to
+    #[cfg(feature = "cc")]
+    base: cc::Build,
+}
+
+impl CBuild {
+    /// Settings with no include directories, defines or flags.
+    pub fn new() -> CBuild {
+        CBuild::default()
+    }
+
+    /// Settings starting from `build`, for things `CBuild` has no method for, such as the target
+    /// or optimization level. The include directories, defines and flags added to the `CBuild`
+    /// are added to `build` when making the command.
+    ///
+    /// `cc::Build::new()` takes the target and optimization level from the environment variables
+    /// cargo sets for build scripts (`TARGET`, `HOST` and `OPT_LEVEL`), so outside a build script
+    /// they have to be set on `build`, or `c_object` panics.
+    #[cfg(feature = "cc")]
+    pub fn from_cc(build: cc::Build) -> CBuild {
+        CBuild {
+            base: build,
+            ..CBuild::default()
+        }
+    }
+
+    /// Use `compiler` rather than `$CC` (or `cc` if that isn't set).
+    pub fn compiler<P: AsRef<Path>>(mut self, compiler: P) -> CBuild {
+        self.compiler = Some(compiler.as_ref().to_owned());
+        self
+    }
+
+    /// Add a directory to search for headers (`-I`). It is also searched for header
+    /// dependencies.
+    pub fn include<P: AsRef<Path>>(mut self, dir: P) -> CBuild {
+        self.include_dirs.push(dir.as_ref().to_owned());
+        self
+    }
+
+    /// Define a preprocessor macro (`-D`), with an optional value.
+    pub fn define(mut self, name: &str, value: Option<&str>) -> CBuild {
+        self.defines
+            .push((name.to_owned(), value.map(str::to_owned)));
+        self
+    }
+
+    /// Pass an extra flag to the compiler, such as `-O2`.
+    pub fn flag(mut self, flag: &str) -> CBuild {
+        self.flags.push(flag.to_owned());
+        self
+    }
+
+    /// The command to compile one file.
+    #[cfg(feature = "cc")]
+    fn command(&self) -> CommandSpec {
+        let mut build = self.base.clone();
+        build.cargo_metadata(false);
+        if let Some(ref compiler) = self.compiler {
+            build.compiler(compiler);
+        }
+        for dir in &self.include_dirs {
+            build.include(dir);
+        }
+        for (name, value) in &self.defines {
+            build.define(name, value.as_deref());
+        }
+        for flag in &self.flags {
+            build.flag(flag);
+        }
+        let tool = build.get_compiler();
+        let command = tool
+            .get_envs()
+            .fold(CommandSpec::new(tool.path()), |command, (key, value)| {
+                command.env(key, value)
+            })
+            .args(tool.args());
+        if tool.is_like_msvc() {
+            command.args(["/c", "{deps}", "/Fo{out}"])
+        } else {
+            command.args(["-c", "{deps}", "-o", "{out}"])
+        }
+    }
+
+    /// The command to compile one file.
+    #[cfg(not(feature = "cc"))]
+    fn command(&self) -> CommandSpec {
+        let compiler = match self.compiler {
+            Some(ref compiler) => compiler.as_os_str().to_owned(),
+            None => env::var_os("CC").unwrap_or_else(|| OsString::from("cc")),
+        };
+        let mut command = CommandSpec::new(compiler);
+        for dir in &self.include_dirs {
+            command = command.arg("-I").arg(dir);
+        }
+        for (name, value) in &self.defines {
+            command = command.arg(match value {
+                Some(value) => format!("-D{}={}", name, value),
+                None => format!("-D{}", name),
+            });
+        }
+        command
+            .args(&self.flags)
+            .args(["-c", "{deps}", "-o", "{out}"])
+    }
+
+    /// The scanner that finds the headers a file includes.
+    fn scanner(&self) -> IncludeScanner {
+        self.include_dirs
+            .iter()
+            .fold(IncludeScanner::new(), |scanner, dir| {
+                scanner.include_dir(dir)
+            })
+    }
+}
+
+/// Add a rule compiling the C (or C++, or assembly) file `src` to the object file `out`.
+///
+/// The headers `src` includes are found with an `IncludeScanner` and treated as dependencies,
+/// and the compiler command is the rule's fingerprint, so changing the flags rebuilds the object
+/// file when using a build log. The `CC` environment variable is a dependency unless the
+/// compiler is set explicitly, and with the `cc` feature so are `CFLAGS` and `TARGET`.
+///
+/// # Panics
+///
+/// With the `cc` feature, if `cc::Build::get_compiler` does: see `CBuild::from_cc`.
+///
+/// ```no_run
+/// use depgraph::rules::{c_object, CBuild};
+/// let cc = CBuild::new().include("include").define("NDEBUG", None).flag("-O2");
+/// let builder = depgraph::DepGraphBuilder::new();
+/// let builder = c_object(builder, "src/fast.c", "out/fast.o", &cc);
+/// ```
+pub fn c_object<'a, P1, P2>(
+    builder: DepGraphBuilder<'a>,
+    src: P1,
+    out: P2,
+    build: &CBuild,
+) -> DepGraphBuilder<'a>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    let options = RuleOptions {
+        scanner: Some(Arc::new(build.scanner())),
+        env_deps: env_deps(build),
+        ..Default::default()
+    };
+    builder.add_command_rule_with_opts(out, &[src], options, build.command())
+}
+
+/// The environment variables that change the command.
+#[cfg(feature = "cc")]
+fn env_deps(build: &CBuild) -> Vec<String> {
+    let mut vars = vec!["CFLAGS".to_owned(), "TARGET".to_owned()];
+    if build.compiler.is_none() {
+        vars.push("CC".to_owned());
+    }
+    vars
+}
+
+/// The environment variables that change the command.
+#[cfg(not(feature = "cc"))]
+fn env_deps(build: &CBuild) -> Vec<String> {
+    match build.compiler {
+        Some(_) => Vec::new(),
+        None => vec!["CC".to_owned()],
+    }
+}
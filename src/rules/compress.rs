@@ -0,0 +1,136 @@
+//! Compressing files (the `compression` feature).
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use crate::util;
+use crate::{DepGraphBuilder, RuleOptions};
+
+/// Add a rule compressing `src` into `out` with gzip at `level` (1 to 9), using the `flate2`
+/// crate.
+///
+/// The output doesn't include the file name or modification time of `src`, so it only changes
+/// when the contents do. Changing `level` only rebuilds the output if there is a build log (see
+/// `DepGraphBuilder::build_log`).
+///
+/// # Panics
+///
+/// Panics if `level` is out of range.
+pub fn gzip<'a, P1, P2>(
+    builder: DepGraphBuilder<'a>,
+    src: P1,
+    out: P2,
+    level: u32,
+) -> DepGraphBuilder<'a>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    assert!((1..=9).contains(&level), "gzip level must be from 1 to 9");
+    compress_rule(builder, src, out, "gzip", level, |src, out, level| {
+        let mut encoder = flate2::write::GzEncoder::new(out, flate2::Compression::new(level));
+        io::copy(src, &mut encoder)?;
+        encoder.finish()?.flush()
+    })
+}
+
+/// Add a rule compressing `src` into `out` with zstd at `level` (1 to 22), using the `zstd`
+/// crate.
+///
+/// Changing `level` only rebuilds the output if there is a build log (see
+/// `DepGraphBuilder::build_log`).
+///
+/// # Panics
+///
+/// Panics if `level` is out of range.
+pub fn zstd<'a, P1, P2>(
+    builder: DepGraphBuilder<'a>,
+    src: P1,
+    out: P2,
+    level: u32,
+) -> DepGraphBuilder<'a>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    assert!((1..=22).contains(&level), "zstd level must be from 1 to 22");
+    compress_rule(builder, src, out, "zstd", level, |src, out, level| {
+        zstd::stream::copy_encode(src, &mut *out, level as i32)?;
+        out.flush()
+    })
+}
+
+/// Add a rule compressing `src` into `out` with brotli at `level` (0 to 11), using the `brotli`
+/// crate.
+///
+/// Changing `level` only rebuilds the output if there is a build log (see
+/// `DepGraphBuilder::build_log`).
+///
+/// # Panics
+///
+/// Panics if `level` is out of range.
+pub fn brotli<'a, P1, P2>(
+    builder: DepGraphBuilder<'a>,
+    src: P1,
+    out: P2,
+    level: u32,
+) -> DepGraphBuilder<'a>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    assert!(level <= 11, "brotli level must be from 0 to 11");
+    compress_rule(builder, src, out, "brotli", level, |src, out, level| {
+        // a 4 MiB (2^22 byte) window
+        let mut encoder = brotli::CompressorWriter::new(out, 64 * 1024, level, 22);
+        io::copy(src, &mut encoder)?;
+        // the end of the stream is written when the encoder is finished
+        encoder.into_inner().flush()
+    })
+}
+
+/// How a format compresses everything read from `src` into `out` at a level.
+type Compress = fn(&mut BufReader<File>, &mut BufWriter<File>, u32) -> io::Result<()>;
+
+/// Add a rule compressing `src` with `compress`, writing to a temporary file that is renamed to
+/// `out` once it succeeds.
+fn compress_rule<'a, P1, P2>(
+    builder: DepGraphBuilder<'a>,
+    src: P1,
+    out: P2,
+    format: &'static str,
+    level: u32,
+    compress: Compress,
+) -> DepGraphBuilder<'a>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    // remembered in the build log, so changing the level rebuilds the output
+    let options = RuleOptions {
+        fingerprint: Some(format!("{} level {}", format, level)),
+        ..Default::default()
+    };
+    builder.add_rule_with_opts(out, &[src], options, move |out, deps| {
+        let tmp_path = util::temp_path(out).map_err(|e| e.to_string())?;
+        let result = File::open(deps[0])
+            .and_then(|src| {
+                let tmp = File::create(&tmp_path)?;
+                compress(&mut BufReader::new(src), &mut BufWriter::new(tmp), level)
+            })
+            .and_then(|()| fs::rename(&tmp_path, out))
+            .map_err(|e| {
+                format!(
+                    "couldn't compress {} with {}: {}",
+                    deps[0].display(),
+                    format,
+                    e
+                )
+            });
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
+        result
+    })
+}
@@ -0,0 +1,97 @@
+//! Downloading files with a pinned checksum (the `fetch` feature).
+
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::{sha256, util};
+use crate::{DepGraphBuilder, DepResult, FreshnessChecker, RebuildReason, RuleOptions};
+
+/// Add a rule downloading `url` to `dest`, and checking that its SHA-256 digest is `sha256` (in
+/// hex).
+///
+/// The file is only downloaded when `dest` is missing or doesn't have the digest `sha256`, so
+/// changing the pinned digest downloads it again. `dest` is hashed each time the graph is checked
+/// to decide this. A changed `url` also downloads it again if there is a build log (see
+/// `DepGraphBuilder::build_log`). If the digest of the download doesn't match, the rule fails
+/// and `dest` is left as it was.
+///
+/// Files are fetched over HTTP or HTTPS with the `ureq` crate.
+///
+/// ```no_run
+/// let builder = depgraph::rules::download(
+///     depgraph::DepGraphBuilder::new(),
+///     "https://example.com/model.bin",
+///     "model.bin",
+///     "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08",
+/// );
+/// ```
+pub fn download<'a, P: AsRef<Path>>(
+    builder: DepGraphBuilder<'a>,
+    url: &str,
+    dest: P,
+    sha256: &str,
+) -> DepGraphBuilder<'a> {
+    let url = url.to_owned();
+    let expected = sha256.to_ascii_lowercase();
+    let options = RuleOptions {
+        fingerprint: Some(format!("download {} sha256 {}", url, expected)),
+        freshness_checker: Some(Arc::new(HasDigest(expected.clone()))),
+        ..Default::default()
+    };
+    let deps: &[&Path] = &[];
+    builder.add_rule_with_opts(dest, deps, options, move |out, _| {
+        if let Some(parent) = out.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let tmp_path = util::temp_path(out).map_err(|e| e.to_string())?;
+        let result = fetch(&url, &tmp_path, &expected)
+            .and_then(|()| fs::rename(&tmp_path, out).map_err(|e| e.to_string()));
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
+        result
+    })
+}
+
+/// (Internal) A freshness checker for downloads: the target is stale if it doesn't have the
+/// expected SHA-256 digest.
+struct HasDigest(String);
+
+impl FreshnessChecker for HasDigest {
+    fn is_stale(&self, target: &Path, deps: &[&Path]) -> DepResult<bool> {
+        Ok(self.explain(target, deps)?.is_some())
+    }
+
+    fn explain(&self, target: &Path, _deps: &[&Path]) -> DepResult<Option<RebuildReason>> {
+        if !target.exists() {
+            return Ok(Some(RebuildReason::MissingOutput));
+        }
+        Ok(if sha256::file_digest(target)? == self.0 {
+            None
+        } else {
+            Some(RebuildReason::Stale)
+        })
+    }
+}
+
+/// Download `url` to `path` and check its digest.
+fn fetch(url: &str, path: &Path, expected: &str) -> Result<(), String> {
+    let response = ureq::get(url)
+        .call()
+        .map_err(|e| format!("downloading {}: {}", url, e))?;
+    let mut file = File::create(path).map_err(|e| e.to_string())?;
+    io::copy(&mut response.into_body().into_reader(), &mut file)
+        .and_then(|_| file.sync_all())
+        .map_err(|e| format!("downloading {}: {}", url, e))?;
+    let actual = sha256::file_digest(path).map_err(|e| e.to_string())?;
+    if actual == expected {
+        Ok(())
+    } else {
+        Err(format!(
+            "checksum mismatch for {}: expected sha256 {}, got {}",
+            url, expected, actual
+        ))
+    }
+}
@@ -0,0 +1,76 @@
+//! Copying and combining files.
+
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+
+use crate::util;
+use crate::DepGraphBuilder;
+
+/// Add a rule copying the file `src` to `dst`, creating the directories containing `dst` if
+/// needed.
+///
+/// The copy is written to a temporary file and renamed into place, so an interrupted build never
+/// leaves a partial copy behind. `dst` gets the current time as its modification time rather
+/// than the time of `src`, so it's newer than `src` and isn't copied again on the next build.
+pub fn copy<'a, P1, P2>(builder: DepGraphBuilder<'a>, src: P1, dst: P2) -> DepGraphBuilder<'a>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    builder.add_rule(dst, &[src], |out, deps| {
+        copy_file(deps[0], out).map_err(|e| {
+            format!(
+                "couldn't copy {} to {}: {}",
+                deps[0].display(),
+                out.display(),
+                e
+            )
+        })
+    })
+}
+
+/// Add a rule writing the contents of each of `inputs`, in order, to `out`, creating the
+/// directories containing `out` if needed. Like `copy`, the output is written atomically.
+pub fn concat<'a, P1, P2>(
+    builder: DepGraphBuilder<'a>,
+    out: P1,
+    inputs: &[P2],
+) -> DepGraphBuilder<'a>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    builder.add_rule(out, inputs, |out, deps| {
+        concat_files(deps, out).map_err(|e| format!("couldn't write {}: {}", out.display(), e))
+    })
+}
+
+fn concat_files(srcs: &[&Path], dst: &Path) -> io::Result<()> {
+    let mut contents = Vec::new();
+    for src in srcs {
+        contents.extend(fs::read(src)?);
+    }
+    if let Some(parent) = dst.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    util::atomic_write(dst, contents)
+}
+
+fn copy_file(src: &Path, dst: &Path) -> io::Result<()> {
+    if let Some(parent) = dst.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp_path = util::temp_path(dst)?;
+    let result = fs::copy(src, &tmp_path).and_then(|_| {
+        // `fs::copy` keeps the permissions but not the modification time of `src`
+        File::options().write(true).open(&tmp_path)?.sync_all()
+    });
+    match result.and_then(|()| fs::rename(&tmp_path, dst)) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            let _ = fs::remove_file(&tmp_path);
+            Err(e)
+        }
+    }
+}
@@ -0,0 +1,37 @@
+//! Ready-made rules for common build steps.
+//!
+//! Each helper takes the builder and returns it with the rule added, so they can be used in the
+//! middle of a chain of builder calls.
+
+#[cfg(feature = "assets")]
+pub mod assets;
+mod c;
+#[cfg(feature = "compression")]
+mod compress;
+#[cfg(feature = "fetch")]
+mod download;
+mod files;
+mod protoc;
+#[cfg(feature = "schema-tools")]
+mod schema;
+pub mod shaders;
+#[cfg(feature = "templates")]
+mod stamp;
+#[cfg(feature = "templates")]
+mod template;
+mod verify;
+mod wasm;
+
+pub use self::c::{c_object, CBuild};
+#[cfg(feature = "compression")]
+pub use self::compress::{brotli, gzip, zstd};
+#[cfg(feature = "fetch")]
+pub use self::download::download;
+pub use self::files::{concat, copy};
+pub use self::protoc::{protoc, Protoc};
+#[cfg(feature = "schema-tools")]
+pub use self::schema::{capnp, flatc, SchemaCompiler};
+#[cfg(feature = "templates")]
+pub use self::template::{template, TemplateContext};
+pub use self::verify::{verified_stamp, verify_sha256};
+pub use self::wasm::{wasm_cargo, wasm_pack, WasmBuild};
@@ -0,0 +1,141 @@
+//! Generating code from Protocol Buffers definitions with `protoc`.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::{CommandSpec, DepGraphBuilder, DepResult, ProtoScanner, RuleOptions};
+
+/// Settings for running `protoc`, for `rules::protoc`.
+#[derive(Debug, Clone)]
+pub struct Protoc {
+    protoc: Option<PathBuf>,
+    include_dirs: Vec<PathBuf>,
+    plugin: String,
+}
+
+impl Default for Protoc {
+    fn default() -> Protoc {
+        Protoc {
+            protoc: None,
+            include_dirs: Vec::new(),
+            plugin: "prost".to_owned(),
+        }
+    }
+}
+
+impl Protoc {
+    /// Settings for generating Rust code with the `protoc-gen-prost` plugin, with no include
+    /// directories.
+    pub fn new() -> Protoc {
+        Protoc::default()
+    }
+
+    /// Use `protoc` rather than `$PROTOC` (or `protoc` on the `PATH` if that isn't set).
+    pub fn protoc<P: AsRef<Path>>(mut self, protoc: P) -> Protoc {
+        self.protoc = Some(protoc.as_ref().to_owned());
+        self
+    }
+
+    /// Add a directory to search for imports (`-I`). The `.proto` files themselves must be in
+    /// one of these directories too.
+    pub fn include<P: AsRef<Path>>(mut self, dir: P) -> Protoc {
+        self.include_dirs.push(dir.as_ref().to_owned());
+        self
+    }
+
+    /// Use the output plugin `name` (passing `--{name}_out`) instead of `prost`.
+    pub fn plugin(mut self, name: &str) -> Protoc {
+        self.plugin = name.to_owned();
+        self
+    }
+}
+
+/// Add rules generating Rust code in `out_dir` from the `.proto` files `protos`.
+///
+/// Like `prost-build`, code is generated per protobuf package, so there is a rule for each
+/// package, with a target named after it (`my.package.rs`, or `_.rs` for files without a
+/// package) and each of its files as dependencies. The files they import are found with a
+/// `ProtoScanner`, so editing an imported file rebuilds the packages that use it.
+///
+/// The files are read now to find their packages, so they must exist.
+///
+/// ```no_run
+/// use depgraph::rules::{protoc, Protoc};
+/// let out_dir = std::env::var("OUT_DIR").unwrap();
+/// let builder = depgraph::DepGraphBuilder::new();
+/// let builder = protoc(builder, &["proto/api.proto"], &out_dir, &Protoc::new().include("proto"))
+///     .unwrap();
+/// ```
+pub fn protoc<'a, P1, P2>(
+    builder: DepGraphBuilder<'a>,
+    protos: &[P1],
+    out_dir: P2,
+    settings: &Protoc,
+) -> DepResult<DepGraphBuilder<'a>>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    let out_dir = out_dir.as_ref();
+    let mut packages: BTreeMap<String, Vec<&Path>> = BTreeMap::new();
+    for proto in protos {
+        let proto = proto.as_ref();
+        let package = package_of(&fs::read_to_string(proto)?)
+            .unwrap_or("_")
+            .to_owned();
+        packages.entry(package).or_default().push(proto);
+    }
+    let scanner = settings
+        .include_dirs
+        .iter()
+        .fold(ProtoScanner::new(), |scanner, dir| scanner.import_dir(dir));
+    let scanner = Arc::new(scanner);
+    let mut builder = builder;
+    for (package, files) in packages {
+        let options = RuleOptions {
+            scanner: Some(scanner.clone()),
+            env_deps: match settings.protoc {
+                Some(_) => Vec::new(),
+                None => vec!["PROTOC".to_owned()],
+            },
+            ..Default::default()
+        };
+        builder = builder.add_command_rule_with_opts(
+            out_dir.join(format!("{}.rs", package)),
+            &files,
+            options,
+            command(settings, out_dir),
+        );
+    }
+    Ok(builder)
+}
+
+/// The command to generate code in `out_dir`.
+fn command(settings: &Protoc, out_dir: &Path) -> CommandSpec {
+    let protoc = match settings.protoc {
+        Some(ref protoc) => protoc.as_os_str().to_owned(),
+        None => env::var_os("PROTOC").unwrap_or_else(|| OsString::from("protoc")),
+    };
+    let mut command = CommandSpec::new(protoc);
+    for dir in &settings.include_dirs {
+        command = command.arg("-I").arg(dir);
+    }
+    let mut out_arg = OsString::from(format!("--{}_out=", settings.plugin));
+    out_arg.push(out_dir);
+    command.arg(out_arg).arg("{deps}")
+}
+
+/// The package declared in the proto file `contents`, if any.
+fn package_of(contents: &str) -> Option<&str> {
+    contents.lines().find_map(|line| {
+        let rest = line.trim_start().strip_prefix("package")?;
+        if !rest.starts_with(char::is_whitespace) {
+            return None;
+        }
+        Some(rest.trim().trim_end_matches(';').trim_end())
+    })
+}
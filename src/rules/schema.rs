@@ -0,0 +1,178 @@
+//! Generating code from FlatBuffers and Cap'n Proto schemas (the `schema-tools` feature).
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::scanners::{find_in, quoted, scan_transitively};
+use crate::{CommandSpec, DepGraphBuilder, DepResult, RuleOptions, Scanner};
+
+/// Settings for running a schema compiler, for `rules::flatc` and `rules::capnp`.
+#[derive(Debug, Clone, Default)]
+pub struct SchemaCompiler {
+    program: Option<PathBuf>,
+    include_dirs: Vec<PathBuf>,
+}
+
+impl SchemaCompiler {
+    /// Settings using the compiler on the `PATH`, with no include directories.
+    pub fn new() -> SchemaCompiler {
+        SchemaCompiler::default()
+    }
+
+    /// Run `program` instead of `flatc` or `capnp` from the `PATH`.
+    pub fn program<P: AsRef<Path>>(mut self, program: P) -> SchemaCompiler {
+        self.program = Some(program.as_ref().to_owned());
+        self
+    }
+
+    /// Add a directory to search for included or imported schemas (`-I`).
+    pub fn include<P: AsRef<Path>>(mut self, dir: P) -> SchemaCompiler {
+        self.include_dirs.push(dir.as_ref().to_owned());
+        self
+    }
+
+    fn command(&self, default: &str) -> CommandSpec {
+        let program = match self.program {
+            Some(ref program) => program.as_os_str().to_owned(),
+            None => OsString::from(default),
+        };
+        let mut command = CommandSpec::new(program);
+        for dir in &self.include_dirs {
+            command = command.arg("-I").arg(dir);
+        }
+        command
+    }
+
+    fn options(&self, syntax: Syntax) -> RuleOptions {
+        RuleOptions {
+            scanner: Some(Arc::new(SchemaScanner {
+                syntax,
+                include_dirs: self.include_dirs.clone(),
+            })),
+            ..Default::default()
+        }
+    }
+}
+
+/// Add a rule for each FlatBuffers schema in `schemas`, generating Rust code in `out_dir` with
+/// `flatc --rust`.
+///
+/// The target for `monster.fbs` is `monster_generated.rs`. Schemas pulled in with `include` are
+/// found next to the including file or in the include directories, and are dependencies too.
+pub fn flatc<'a, P1, P2>(
+    builder: DepGraphBuilder<'a>,
+    schemas: &[P1],
+    out_dir: P2,
+    settings: &SchemaCompiler,
+) -> DepResult<DepGraphBuilder<'a>>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    let out_dir = out_dir.as_ref();
+    let mut builder = builder;
+    for schema in schemas {
+        let schema = schema.as_ref();
+        let command = settings
+            .command("flatc")
+            .args(["--rust", "-o"])
+            .arg(out_dir)
+            .arg("{deps}");
+        builder = builder.add_command_rule_with_opts(
+            out_dir.join(output_name(schema, "_generated.rs")?),
+            &[schema],
+            settings.options(Syntax::FlatBuffers),
+            command,
+        );
+    }
+    Ok(builder)
+}
+
+/// Add a rule for each Cap'n Proto schema in `schemas`, generating Rust code in `out_dir` with
+/// `capnp compile -orust` (which needs the `capnpc-rust` plugin).
+///
+/// The target for `point.capnp` is `point_capnp.rs`. Imported schemas are found next to the
+/// importing file, or for absolute imports (`/capnp/c++.capnp`) in the include directories, and
+/// are dependencies too.
+pub fn capnp<'a, P1, P2>(
+    builder: DepGraphBuilder<'a>,
+    schemas: &[P1],
+    out_dir: P2,
+    settings: &SchemaCompiler,
+) -> DepResult<DepGraphBuilder<'a>>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    let out_dir = out_dir.as_ref();
+    let mut builder = builder;
+    for schema in schemas {
+        let schema = schema.as_ref();
+        // strip the schema's directory so the output goes straight into `out_dir`
+        let mut src_prefix = OsString::from("--src-prefix=");
+        src_prefix.push(schema.parent().unwrap_or(Path::new("")));
+        let mut out_arg = OsString::from("-orust:");
+        out_arg.push(out_dir);
+        let command = settings
+            .command("capnp")
+            .arg("compile")
+            .arg(src_prefix)
+            .arg(out_arg)
+            .arg("{deps}");
+        builder = builder.add_command_rule_with_opts(
+            out_dir.join(output_name(schema, "_capnp.rs")?),
+            &[schema],
+            settings.options(Syntax::Capnp),
+            command,
+        );
+    }
+    Ok(builder)
+}
+
+/// The file stem of `schema` followed by `suffix`.
+fn output_name(schema: &Path, suffix: &str) -> DepResult<String> {
+    match schema.file_stem() {
+        Some(stem) => Ok(format!("{}{}", stem.to_string_lossy(), suffix)),
+        None => Err(crate::Error::MissingFile(schema.to_owned())),
+    }
+}
+
+/// (Internal) Which schema language a `SchemaScanner` reads.
+#[derive(Debug, Clone, Copy)]
+enum Syntax {
+    FlatBuffers,
+    Capnp,
+}
+
+/// (Internal) Finds the schemas a schema includes or imports.
+#[derive(Debug)]
+struct SchemaScanner {
+    syntax: Syntax,
+    include_dirs: Vec<PathBuf>,
+}
+
+impl Scanner for SchemaScanner {
+    fn scan(&self, source: &Path) -> DepResult<Vec<PathBuf>> {
+        scan_transitively(source, |line, file| {
+            let local = |name: &str| {
+                let dir = file.parent().unwrap_or(Path::new(""));
+                Some(dir.join(name)).filter(|path| path.is_file())
+            };
+            match self.syntax {
+                Syntax::FlatBuffers => {
+                    let name = quoted(line.trim_start().strip_prefix("include")?.trim_start())?;
+                    local(name).or_else(|| find_in(&self.include_dirs, name))
+                }
+                Syntax::Capnp => {
+                    let start = line.find("import")? + "import".len();
+                    let name = quoted(line[start..].trim_start())?;
+                    match name.strip_prefix('/') {
+                        Some(name) => find_in(&self.include_dirs, name),
+                        None => local(name),
+                    }
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,150 @@
+//! Compiling shaders: GLSL to SPIR-V with `glslc`, translating with `naga`, and converting
+//! SPIR-V back to source with `spirv-cross`.
+//!
+//! ```no_run
+//! use depgraph::rules::shaders::{self, ShaderCompiler};
+//! let glslc = ShaderCompiler::new().include("shaders/common").arg("-O");
+//! let builder = shaders::glslc_all(
+//!     depgraph::DepGraphBuilder::new(),
+//!     &["shaders/mesh.vert", "shaders/mesh.frag"],
+//!     "out/shaders",
+//!     &glslc,
+//! );
+//! ```
+
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::{CommandSpec, DepGraphBuilder, IncludeScanner, RuleOptions};
+
+/// Settings for running a shader tool.
+#[derive(Debug, Clone, Default)]
+pub struct ShaderCompiler {
+    program: Option<PathBuf>,
+    include_dirs: Vec<PathBuf>,
+    args: Vec<OsString>,
+}
+
+impl ShaderCompiler {
+    /// Settings using the tool on the `PATH`, with no include directories or extra arguments.
+    pub fn new() -> ShaderCompiler {
+        ShaderCompiler::default()
+    }
+
+    /// Run `program` instead of the tool from the `PATH`.
+    pub fn program<P: AsRef<Path>>(mut self, program: P) -> ShaderCompiler {
+        self.program = Some(program.as_ref().to_owned());
+        self
+    }
+
+    /// Add a directory to search for `#include`d files. Only `glslc` is passed these (with
+    /// `-I`), but they are searched for dependencies whatever the tool.
+    pub fn include<P: AsRef<Path>>(mut self, dir: P) -> ShaderCompiler {
+        self.include_dirs.push(dir.as_ref().to_owned());
+        self
+    }
+
+    /// Pass an extra argument to the tool, such as `-O` or `--target-env=vulkan1.2`.
+    pub fn arg<S: AsRef<OsStr>>(mut self, arg: S) -> ShaderCompiler {
+        self.args.push(arg.as_ref().to_owned());
+        self
+    }
+
+    fn command(&self, default: &str) -> CommandSpec {
+        let program = match self.program {
+            Some(ref program) => program.as_os_str().to_owned(),
+            None => OsString::from(default),
+        };
+        CommandSpec::new(program).args(&self.args)
+    }
+
+    fn options(&self) -> RuleOptions {
+        let scanner = self
+            .include_dirs
+            .iter()
+            .fold(IncludeScanner::new(), |scanner, dir| {
+                scanner.include_dir(dir)
+            });
+        RuleOptions {
+            scanner: Some(Arc::new(scanner)),
+            ..Default::default()
+        }
+    }
+}
+
+/// Add a rule compiling the GLSL shader `src` to the SPIR-V file `out` with `glslc`.
+///
+/// The shader stage is worked out by `glslc` from the extension (`.vert`, `.frag`, `.comp` and
+/// so on). Files pulled in with `#include` are dependencies.
+pub fn glslc<'a, P1, P2>(
+    builder: DepGraphBuilder<'a>,
+    src: P1,
+    out: P2,
+    settings: &ShaderCompiler,
+) -> DepGraphBuilder<'a>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    let mut command = settings.command("glslc");
+    for dir in &settings.include_dirs {
+        command = command.arg("-I").arg(dir);
+    }
+    let command = command.args(["{deps}", "-o", "{out}"]);
+    builder.add_command_rule_with_opts(out, &[src], settings.options(), command)
+}
+
+/// Add a `glslc` rule for each of `srcs`, writing the SPIR-V for `mesh.vert` to
+/// `mesh.vert.spv` in `out_dir`.
+pub fn glslc_all<'a, P1, P2>(
+    builder: DepGraphBuilder<'a>,
+    srcs: &[P1],
+    out_dir: P2,
+    settings: &ShaderCompiler,
+) -> DepGraphBuilder<'a>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    srcs.iter().fold(builder, |builder, src| {
+        let src = src.as_ref();
+        let mut name = src.file_name().unwrap_or_default().to_owned();
+        name.push(".spv");
+        glslc(builder, src, out_dir.as_ref().join(name), settings)
+    })
+}
+
+/// Add a rule translating the shader `src` to `out` with `naga`, which picks the languages from
+/// the file extensions (for example `.wgsl` to `.spv`).
+pub fn naga<'a, P1, P2>(
+    builder: DepGraphBuilder<'a>,
+    src: P1,
+    out: P2,
+    settings: &ShaderCompiler,
+) -> DepGraphBuilder<'a>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    let command = settings.command("naga").args(["{deps}", "{out}"]);
+    builder.add_command_rule_with_opts(out, &[src], settings.options(), command)
+}
+
+/// Add a rule converting the SPIR-V file `src` to source code in `out` with `spirv-cross`. Pass
+/// the output language as arguments, such as `--msl` or `--hlsl`.
+pub fn spirv_cross<'a, P1, P2>(
+    builder: DepGraphBuilder<'a>,
+    src: P1,
+    out: P2,
+    settings: &ShaderCompiler,
+) -> DepGraphBuilder<'a>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    let command = settings
+        .command("spirv-cross")
+        .args(["{deps}", "--output", "{out}"]);
+    builder.add_command_rule_with_opts(out, &[src], RuleOptions::default(), command)
+}
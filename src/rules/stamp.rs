@@ -0,0 +1,54 @@
+//! (Internal) Stamp files recording the settings a rule was last run with.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::util;
+use crate::{DepGraphBuilder, DepResult, FreshnessChecker, RebuildReason, RuleOptions};
+
+/// Add a rule writing `settings` to the stamp file `stamp`, which is `out` with `suffix`
+/// appended, and return the builder and the stamp's path.
+///
+/// The stamp is only rewritten when its contents differ from `settings`, so a rule that depends
+/// on it is rebuilt when the settings change, even without a build log.
+pub(crate) fn settings_stamp<'a>(
+    builder: DepGraphBuilder<'a>,
+    out: &Path,
+    suffix: &str,
+    settings: String,
+) -> (DepGraphBuilder<'a>, PathBuf) {
+    let mut stamp = out.as_os_str().to_owned();
+    stamp.push(suffix);
+    let stamp = PathBuf::from(stamp);
+    let options = RuleOptions {
+        freshness_checker: Some(Arc::new(HasContents(settings.clone()))),
+        ..Default::default()
+    };
+    let deps: &[&Path] = &[];
+    let builder = builder.add_rule_with_opts(&stamp, deps, options, move |out, _| {
+        util::atomic_write(out, &settings).map_err(|e| e.to_string())
+    });
+    (builder, stamp)
+}
+
+/// (Internal) A freshness checker for stamps: the target is stale if it doesn't contain the
+/// expected text.
+struct HasContents(String);
+
+impl FreshnessChecker for HasContents {
+    fn is_stale(&self, target: &Path, deps: &[&Path]) -> DepResult<bool> {
+        Ok(self.explain(target, deps)?.is_some())
+    }
+
+    fn explain(&self, target: &Path, _deps: &[&Path]) -> DepResult<Option<RebuildReason>> {
+        if !target.exists() {
+            return Ok(Some(RebuildReason::MissingOutput));
+        }
+        Ok(if fs::read(target)? == self.0.as_bytes() {
+            None
+        } else {
+            Some(RebuildReason::RuleChanged)
+        })
+    }
+}
@@ -0,0 +1,87 @@
+//! Rendering templates into generated files (the `templates` feature).
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use minijinja::{Environment, UndefinedBehavior};
+
+use crate::rules::stamp;
+use crate::util;
+use crate::DepGraphBuilder;
+
+/// The values substituted into a template by `rules::template`.
+#[derive(Debug, Clone, Default)]
+pub struct TemplateContext {
+    values: BTreeMap<String, String>,
+}
+
+impl TemplateContext {
+    /// An empty context.
+    pub fn new() -> TemplateContext {
+        TemplateContext::default()
+    }
+
+    /// Set the variable `name` to `value`, as a string.
+    pub fn set<V: fmt::Display>(mut self, name: &str, value: V) -> TemplateContext {
+        self.values.insert(name.to_owned(), value.to_string());
+        self
+    }
+
+    /// The contents of the context's stamp file, which change whenever any of the values do.
+    fn stamp_contents(&self) -> String {
+        format!("{:?}\n", self.values)
+    }
+
+    /// Render `template`, read from the file `name`, with this context.
+    fn render(&self, name: &str, template: &str) -> Result<String, minijinja::Error> {
+        let mut env = Environment::new();
+        env.set_undefined_behavior(UndefinedBehavior::Strict);
+        env.set_keep_trailing_newline(true);
+        env.template_from_named_str(name, template)?
+            .render(&self.values)
+    }
+}
+
+/// Add a rule rendering the [Jinja](https://jinja.palletsprojects.com) template file `template`
+/// into `out` with the `minijinja` crate, so `{{ name }}` is replaced with the value of `name` in
+/// `context`.
+///
+/// Using a variable missing from the context is an error. The context is written to a stamp
+/// file, `out` with `.context` appended, which the output depends on along with the template,
+/// so the output is rebuilt when either changes.
+///
+/// ```no_run
+/// use depgraph::rules::{self, TemplateContext};
+/// let context = TemplateContext::new()
+///     .set("version", env!("CARGO_PKG_VERSION"))
+///     .set("port", 8080);
+/// let builder = rules::template(
+///     depgraph::DepGraphBuilder::new(),
+///     "config.toml.in",
+///     "config.toml",
+///     context,
+/// );
+/// ```
+pub fn template<'a, P1, P2>(
+    builder: DepGraphBuilder<'a>,
+    template: P1,
+    out: P2,
+    context: TemplateContext,
+) -> DepGraphBuilder<'a>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    let out = out.as_ref();
+    let (builder, stamp) =
+        stamp::settings_stamp(builder, out, ".context", context.stamp_contents());
+    builder.add_rule(out, &[template.as_ref(), &stamp], move |out, deps| {
+        let template = fs::read_to_string(deps[0]).map_err(|e| e.to_string())?;
+        let rendered = context
+            .render(&deps[0].to_string_lossy(), &template)
+            .map_err(|e| format!("couldn't render {}: {}", deps[0].display(), e))?;
+        util::atomic_write(out, rendered).map_err(|e| e.to_string())
+    })
+}
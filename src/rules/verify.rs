@@ -0,0 +1,58 @@
+//! Checking files against a known digest.
+
+use std::path::{Path, PathBuf};
+
+use crate::{sha256, util};
+use crate::{DepGraphBuilder, RuleOptions};
+
+/// Add a rule checking that the SHA-256 digest of `file` is `sha256` (in hex), which creates the
+/// stamp file `verified_stamp(file)` when it is.
+///
+/// Rules that need the checked file can depend on both it and the stamp, so they are only run
+/// once it has been verified. The check is run again whenever `file` changes, and when `sha256`
+/// changes if there is a build log (see `DepGraphBuilder::build_log`).
+///
+/// ```no_run
+/// use depgraph::rules;
+/// let builder = rules::verify_sha256(
+///     depgraph::DepGraphBuilder::new(),
+///     "vendor/lib.tar.gz",
+///     "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08",
+/// );
+/// let stamp = rules::verified_stamp("vendor/lib.tar.gz");
+/// let builder = builder.add_rule("vendor/lib", &["vendor/lib.tar.gz".into(), stamp], |out, deps| {
+///     // unpack deps[0] into out
+/// #   Ok(())
+/// });
+/// ```
+pub fn verify_sha256<'a, P: AsRef<Path>>(
+    builder: DepGraphBuilder<'a>,
+    file: P,
+    sha256: &str,
+) -> DepGraphBuilder<'a> {
+    let expected = sha256.to_ascii_lowercase();
+    let options = RuleOptions {
+        fingerprint: Some(format!("verify sha256 {}", expected)),
+        ..Default::default()
+    };
+    let stamp = verified_stamp(&file);
+    builder.add_rule_with_opts(stamp, &[file], options, move |out, deps| {
+        let actual = sha256::file_digest(deps[0]).map_err(|e| e.to_string())?;
+        if actual != expected {
+            return Err(format!(
+                "checksum mismatch for {}: expected sha256 {}, got {}",
+                deps[0].display(),
+                expected,
+                actual
+            ));
+        }
+        util::atomic_write(out, format!("{}\n", actual)).map_err(|e| e.to_string())
+    })
+}
+
+/// The stamp file created by `verify_sha256` for `file`: its path with `.verified` appended.
+pub fn verified_stamp<P: AsRef<Path>>(file: P) -> PathBuf {
+    let mut stamp = file.as_ref().as_os_str().to_owned();
+    stamp.push(".verified");
+    PathBuf::from(stamp)
+}
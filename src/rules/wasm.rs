@@ -0,0 +1,177 @@
+//! Building a sibling crate to WebAssembly.
+
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::{CommandSpec, DepGraphBuilder, RuleOptions};
+
+/// Settings for building a crate to WebAssembly, for `rules::wasm_cargo` and
+/// `rules::wasm_pack`.
+#[derive(Debug, Clone)]
+pub struct WasmBuild {
+    release: bool,
+    target: String,
+    target_dir: Option<PathBuf>,
+    args: Vec<OsString>,
+}
+
+impl Default for WasmBuild {
+    fn default() -> WasmBuild {
+        WasmBuild {
+            release: true,
+            target: "wasm32-unknown-unknown".to_owned(),
+            target_dir: None,
+            args: Vec::new(),
+        }
+    }
+}
+
+impl WasmBuild {
+    /// Settings for a release build for `wasm32-unknown-unknown`.
+    pub fn new() -> WasmBuild {
+        WasmBuild::default()
+    }
+
+    /// Build in debug mode rather than release mode.
+    pub fn debug(mut self) -> WasmBuild {
+        self.release = false;
+        self
+    }
+
+    /// Build for the target `triple`, such as `wasm32-wasip1`, instead of
+    /// `wasm32-unknown-unknown`. Only used by `wasm_cargo`.
+    pub fn target(mut self, triple: &str) -> WasmBuild {
+        self.target = triple.to_owned();
+        self
+    }
+
+    /// Where cargo should put its build files. Only used by `wasm_cargo`.
+    ///
+    /// This must be different from the target directory of the crate whose build script is
+    /// running, or cargo waits forever for the lock on it. The default is a `wasm-target`
+    /// directory next to the output.
+    pub fn target_dir<P: AsRef<Path>>(mut self, dir: P) -> WasmBuild {
+        self.target_dir = Some(dir.as_ref().to_owned());
+        self
+    }
+
+    /// Pass an extra argument to `cargo build` or `wasm-pack build`.
+    pub fn arg<S: AsRef<OsStr>>(mut self, arg: S) -> WasmBuild {
+        self.args.push(arg.as_ref().to_owned());
+        self
+    }
+}
+
+/// The dependencies and options for a rule building the crate in `crate_dir`: its manifest and
+/// source tree, and if they exist its lock file and build script.
+fn crate_deps(crate_dir: &Path) -> ([PathBuf; 2], RuleOptions) {
+    let options = RuleOptions {
+        optional_deps: vec![crate_dir.join("Cargo.lock"), crate_dir.join("build.rs")],
+        ..Default::default()
+    };
+    (
+        [crate_dir.join("Cargo.toml"), crate_dir.join("src")],
+        options,
+    )
+}
+
+/// Add a rule building the crate in `crate_dir` with `cargo build --target wasm32-unknown-unknown`
+/// and copying the resulting `.wasm` file to `out`.
+///
+/// The crate is rebuilt when anything in its `src` directory, its `Cargo.toml`, `Cargo.lock` or
+/// `build.rs` changes. Cargo is run from the `CARGO` environment variable if set (as it is in
+/// build scripts). The crate must be a `cdylib` or binary.
+pub fn wasm_cargo<'a, P1, P2>(
+    builder: DepGraphBuilder<'a>,
+    crate_dir: P1,
+    out: P2,
+    settings: &WasmBuild,
+) -> DepGraphBuilder<'a>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    let crate_dir = crate_dir.as_ref().to_owned();
+    let (deps, options) = crate_deps(&crate_dir);
+    let settings = settings.clone();
+    builder.add_rule_with_opts(out, &deps, options, move |out: &Path, _: &[&Path]| {
+        let target_dir = match settings.target_dir {
+            Some(ref dir) => dir.clone(),
+            None => out.with_file_name("wasm-target"),
+        };
+        let manifest = crate_dir.join("Cargo.toml");
+        let cargo = env::var_os("CARGO").unwrap_or_else(|| OsString::from("cargo"));
+        let mut command = CommandSpec::new(cargo)
+            .arg("build")
+            .arg("--manifest-path")
+            .arg(&manifest)
+            .arg("--target")
+            .arg(&settings.target)
+            .arg("--target-dir")
+            .arg(&target_dir)
+            .args(&settings.args);
+        if settings.release {
+            command = command.arg("--release");
+        }
+        command.run(out, &[])?;
+        let name = package_name(&manifest)?.replace('-', "_");
+        let profile = if settings.release { "release" } else { "debug" };
+        let artifact = target_dir
+            .join(&settings.target)
+            .join(profile)
+            .join(format!("{}.wasm", name));
+        fs::copy(&artifact, out)
+            .map(|_| ())
+            .map_err(|e| format!("couldn't copy {}: {}", artifact.display(), e))
+    })
+}
+
+/// Add a rule building the crate in `crate_dir` with `wasm-pack build`, putting the package in
+/// the directory `out_dir`.
+///
+/// `out_dir` should be absolute, as `wasm-pack` treats a relative one as relative to the crate.
+/// The crate is rebuilt under the same conditions as for `wasm_cargo`. `--target web` is passed
+/// unless another `--target` is given in the arguments.
+pub fn wasm_pack<'a, P1, P2>(
+    builder: DepGraphBuilder<'a>,
+    crate_dir: P1,
+    out_dir: P2,
+    settings: &WasmBuild,
+) -> DepGraphBuilder<'a>
+where
+    P1: AsRef<Path>,
+    P2: AsRef<Path>,
+{
+    let crate_dir = crate_dir.as_ref();
+    let (deps, options) = crate_deps(crate_dir);
+    let mut command = CommandSpec::new("wasm-pack")
+        .arg("build")
+        .arg(crate_dir)
+        .args(["--out-dir", "{out}"]);
+    if !settings.args.iter().any(|arg| arg == "--target") {
+        command = command.args(["--target", "web"]);
+    }
+    if !settings.release {
+        command = command.arg("--dev");
+    }
+    let command = command.args(&settings.args);
+    builder.add_command_rule_with_opts(out_dir, &deps, options, command)
+}
+
+/// The package name from the manifest at `manifest`.
+fn package_name(manifest: &Path) -> Result<String, String> {
+    let contents = fs::read_to_string(manifest).map_err(|e| e.to_string())?;
+    let mut in_package = false;
+    for line in contents.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_package = line == "[package]";
+        } else if let Some(value) = line.strip_prefix("name").map(str::trim_start) {
+            if let (true, Some(value)) = (in_package, value.strip_prefix('=')) {
+                return Ok(value.trim().trim_matches('"').to_owned());
+            }
+        }
+    }
+    Err(format!("no package name in {}", manifest.display()))
+}
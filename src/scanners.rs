@@ -0,0 +1,175 @@
+//! Finding the files a source file includes, to use as extra dependencies.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::DepResult;
+
+/// Something that can find the files a source file depends on by reading it, such as the headers
+/// a C file includes.
+///
+/// Attach a scanner to a rule with `RuleOptions::scanner`. It is run on each of the rule's
+/// dependencies when deciding whether to rebuild, and the files it returns are treated as extra
+/// dependencies, so a change to one of them rebuilds the target.
+pub trait Scanner: fmt::Debug + Send + Sync {
+    /// The files that `source` depends on. Files that can't be found should be left out.
+    fn scan(&self, source: &Path) -> DepResult<Vec<PathBuf>>;
+}
+
+/// A scanner for C, C++ and assembly `#include` directives, including those in the included
+/// files.
+///
+/// Also recognises the `.include` (GNU as) and `%include` (NASM) forms. A quoted include
+/// (`#include "a.h"`) is looked for next to the file containing it and then in the include
+/// directories, and an angle-bracket include (`#include <a.h>`) only in the include directories.
+/// Includes that can't be found, such as system headers, are skipped.
+///
+/// This doesn't run the preprocessor, so includes inside `#if` blocks are always followed, and
+/// includes named by macros are missed.
+#[derive(Debug, Clone, Default)]
+pub struct IncludeScanner {
+    include_dirs: Vec<PathBuf>,
+}
+
+impl IncludeScanner {
+    /// A scanner with no include directories.
+    pub fn new() -> IncludeScanner {
+        IncludeScanner::default()
+    }
+
+    /// Add a directory to search for included files, like the `-I` compiler flag. Directories
+    /// are searched in the order they were added.
+    pub fn include_dir<P: AsRef<Path>>(mut self, dir: P) -> IncludeScanner {
+        self.include_dirs.push(dir.as_ref().to_owned());
+        self
+    }
+
+    /// Find the file `name`, included from `from`.
+    fn resolve(&self, name: &str, quoted: bool, from: &Path) -> Option<PathBuf> {
+        let local = match from.parent() {
+            Some(dir) if quoted => Some(dir.join(name)),
+            _ => None,
+        };
+        local
+            .filter(|path| path.is_file())
+            .or_else(|| find_in(&self.include_dirs, name))
+    }
+}
+
+impl Scanner for IncludeScanner {
+    fn scan(&self, source: &Path) -> DepResult<Vec<PathBuf>> {
+        scan_transitively(source, |line, file| {
+            let (name, quoted) = parse_include(line)?;
+            self.resolve(name, quoted, file)
+        })
+    }
+}
+
+/// A scanner for the `import` statements in Protocol Buffers (`.proto`) files, including those
+/// in the imported files.
+///
+/// Like `protoc`, imports are looked for in each of the import directories in turn, or the
+/// current directory if there are none. Imports that can't be found are skipped.
+#[derive(Debug, Clone, Default)]
+pub struct ProtoScanner {
+    import_dirs: Vec<PathBuf>,
+}
+
+impl ProtoScanner {
+    /// A scanner that looks for imports in the current directory.
+    pub fn new() -> ProtoScanner {
+        ProtoScanner::default()
+    }
+
+    /// Add a directory to search for imported files, like `protoc`'s `-I` flag.
+    pub fn import_dir<P: AsRef<Path>>(mut self, dir: P) -> ProtoScanner {
+        self.import_dirs.push(dir.as_ref().to_owned());
+        self
+    }
+}
+
+impl Scanner for ProtoScanner {
+    fn scan(&self, source: &Path) -> DepResult<Vec<PathBuf>> {
+        scan_transitively(source, |line, _| {
+            let name = parse_import(line)?;
+            if self.import_dirs.is_empty() {
+                Some(PathBuf::from(name)).filter(|path| path.is_file())
+            } else {
+                find_in(&self.import_dirs, name)
+            }
+        })
+    }
+}
+
+/// Find the files `source` depends on, and the files they depend on, and so on. `resolve`
+/// returns the file a line of a file refers to, if it does.
+pub(crate) fn scan_transitively<F>(source: &Path, mut resolve: F) -> DepResult<Vec<PathBuf>>
+where
+    F: FnMut(&str, &Path) -> Option<PathBuf>,
+{
+    let mut found = Vec::new();
+    let mut seen = HashSet::new();
+    seen.insert(source.to_owned());
+    let mut queue = vec![source.to_owned()];
+    while let Some(file) = queue.pop() {
+        let contents = fs::read(&file)?;
+        for line in String::from_utf8_lossy(&contents).lines() {
+            if let Some(path) = resolve(line, &file) {
+                if seen.insert(path.clone()) {
+                    found.push(path.clone());
+                    queue.push(path);
+                }
+            }
+        }
+    }
+    Ok(found)
+}
+
+/// The first of `dirs` containing the file `name`.
+pub(crate) fn find_in(dirs: &[PathBuf], name: &str) -> Option<PathBuf> {
+    dirs.iter()
+        .map(|dir| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+/// If `line` is a proto `import` statement, the name of the imported file.
+fn parse_import(line: &str) -> Option<&str> {
+    let rest = line.trim_start().strip_prefix("import")?.trim_start();
+    let rest = match rest
+        .strip_prefix("public")
+        .or_else(|| rest.strip_prefix("weak"))
+    {
+        Some(rest) => rest.trim_start(),
+        None => rest,
+    };
+    quoted(rest)
+}
+
+/// The contents of the double-quoted string at the start of `text`.
+pub(crate) fn quoted(text: &str) -> Option<&str> {
+    let rest = text.strip_prefix('"')?;
+    Some(&rest[..rest.find('"')?])
+}
+
+/// If `line` is an include directive, the name of the included file and whether it was quoted
+/// (rather than in angle brackets).
+fn parse_include(line: &str) -> Option<(&str, bool)> {
+    let line = line.trim_start();
+    let rest = match line.strip_prefix('#') {
+        Some(rest) => rest.trim_start().strip_prefix("include")?,
+        None => line
+            .strip_prefix(".include")
+            .or_else(|| line.strip_prefix("%include"))?,
+    };
+    let rest = rest.trim_start();
+    let (close, quoted) = match rest.chars().next()? {
+        '"' => ('"', true),
+        '<' => ('>', false),
+        _ => return None,
+    };
+    let rest = &rest[1..];
+    let end = rest.find(close)?;
+    Some((&rest[..end], quoted))
+}
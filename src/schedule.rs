@@ -0,0 +1,181 @@
+//! Tracking which rules are ready to build, shared by all the `make` variants.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use petgraph::graph::NodeIndex;
+use petgraph::Graph;
+
+use crate::{DepResult, DependencyNode, Error};
+
+/// (Internal) Hands out nodes once all of their dependencies have been built, and collects
+/// failures.
+pub(crate) struct Scheduler<'a> {
+    graph: &'a Graph<DependencyNode<'a>, ()>,
+    keep_going: bool,
+    /// number of unfinished dependencies for each node
+    remaining: Vec<usize>,
+    /// whether a dependency of each node has failed or been skipped
+    blocked: Vec<bool>,
+    /// whether each node is part of the build, see `only`
+    wanted: Vec<bool>,
+    /// which ready node to hand out first, see `prioritize`
+    priority: Option<Vec<(Duration, i32)>>,
+    /// the pool each node is in, as an index into `pools`
+    pool: Vec<Option<usize>>,
+    /// the depth of each pool and how many of its nodes are running, see `pools`
+    pools: Vec<(usize, usize)>,
+    ready: Vec<NodeIndex<u32>>,
+    failed: Vec<(PathBuf, Error)>,
+    skipped: Vec<PathBuf>,
+}
+
+impl<'a> Scheduler<'a> {
+    /// Start scheduling `graph`, which must be acyclic.
+    ///
+    /// If `keep_going` is false, no more nodes are handed out after the first failure.
+    pub(crate) fn new(graph: &'a Graph<DependencyNode<'a>, ()>, keep_going: bool) -> Scheduler<'a> {
+        let remaining: Vec<usize> = graph
+            .node_indices()
+            .map(|idx| graph.neighbors_directed(idx, petgraph::Outgoing).count())
+            .collect();
+        // reversed so that nodes are popped in the order they were added
+        let ready = graph
+            .node_indices()
+            .rev()
+            .filter(|idx| remaining[idx.index()] == 0)
+            .collect();
+        Scheduler {
+            graph,
+            keep_going,
+            remaining,
+            blocked: vec![false; graph.node_count()],
+            wanted: vec![true; graph.node_count()],
+            priority: None,
+            pool: vec![None; graph.node_count()],
+            pools: Vec::new(),
+            ready,
+            failed: Vec::new(),
+            skipped: Vec::new(),
+        }
+    }
+
+    /// Only hand out the nodes where `wanted` is true, which must include all of their
+    /// dependencies.
+    pub(crate) fn only(mut self, wanted: Vec<bool>) -> Scheduler<'a> {
+        self.ready.retain(|idx| wanted[idx.index()]);
+        self.wanted = wanted;
+        self
+    }
+
+    /// Hand out the ready node with the highest `priority` first, rather than in the order the
+    /// nodes were added. Nodes with the same priority are still handed out in that order.
+    #[cfg(feature = "parallel")]
+    pub(crate) fn prioritize(mut self, priority: Vec<(Duration, i32)>) -> Scheduler<'a> {
+        self.priority = Some(priority);
+        self
+    }
+
+    /// Hand out at most the given number of nodes in each pool at once (see
+    /// `RuleOptions::pool`). Every pool the nodes are in must be given.
+    pub(crate) fn pools(mut self, depths: &HashMap<String, usize>) -> Scheduler<'a> {
+        let mut indices = HashMap::new();
+        for idx in self.graph.node_indices() {
+            if let Some(ref name) = self.graph[idx].options.pool {
+                let pool = *indices.entry(name).or_insert_with(|| {
+                    self.pools.push((depths[name], 0));
+                    self.pools.len() - 1
+                });
+                self.pool[idx.index()] = Some(pool);
+            }
+        }
+        self
+    }
+
+    /// The next node that can be built, if any.
+    ///
+    /// Returning `None` doesn't mean the build is over, as nodes that are currently running may
+    /// make more nodes ready when they finish.
+    pub(crate) fn next(&mut self) -> Option<NodeIndex<u32>> {
+        if !self.keep_going && !self.failed.is_empty() {
+            return None;
+        }
+        let available = |idx: &NodeIndex<u32>| {
+            self.pool[idx.index()].is_none_or(|pool| self.pools[pool].1 < self.pools[pool].0)
+        };
+        let i = match self.priority {
+            // the last of the highest, as `ready` is otherwise taken from the end
+            Some(ref priority) => {
+                self.ready
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, idx)| available(idx))
+                    .max_by_key(|(_, idx)| priority[idx.index()])?
+                    .0
+            }
+            None => self.ready.iter().rposition(available)?,
+        };
+        let idx = self.ready.remove(i);
+        if let Some(pool) = self.pool[idx.index()] {
+            self.pools[pool].1 += 1;
+        }
+        Some(idx)
+    }
+
+    /// Record that a node handed out by `next` has finished.
+    pub(crate) fn finished(&mut self, idx: NodeIndex<u32>, result: DepResult<()>) {
+        if let Some(pool) = self.pool[idx.index()] {
+            self.pools[pool].1 -= 1;
+        }
+        let failed = match result {
+            Ok(()) => false,
+            Err(e) => {
+                self.failed.push((self.graph[idx].filename.clone(), e));
+                true
+            }
+        };
+        self.release_dependents(idx, failed);
+    }
+
+    /// Decrement the dependency counts of the dependents of `idx`, skipping any that can never be
+    /// built because `idx` (or something it depends on) failed.
+    fn release_dependents(&mut self, idx: NodeIndex<u32>, failed: bool) {
+        let mut stack = vec![(idx, failed)];
+        while let Some((idx, failed)) = stack.pop() {
+            for parent in self.graph.neighbors_directed(idx, petgraph::Incoming) {
+                let i = parent.index();
+                if !self.wanted[i] {
+                    continue;
+                }
+                self.remaining[i] -= 1;
+                self.blocked[i] |= failed;
+                if self.remaining[i] == 0 {
+                    if self.blocked[i] {
+                        self.skipped.push(self.graph[parent].filename.clone());
+                        stack.push((parent, true));
+                    } else {
+                        self.ready.push(parent);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Finish the build, returning the error(s) that occurred, if any.
+    ///
+    /// When not in keep-going mode, this is the first error. Otherwise all failures are collected
+    /// into `Error::BuildsFailed`.
+    pub(crate) fn finish(mut self) -> DepResult<()> {
+        if self.failed.is_empty() {
+            Ok(())
+        } else if !self.keep_going {
+            Err(self.failed.swap_remove(0).1)
+        } else {
+            Err(Error::BuildsFailed {
+                failed: self.failed,
+                skipped: self.skipped,
+            })
+        }
+    }
+}
@@ -0,0 +1,51 @@
+//! Extracting part of a graph.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::{DepGraph, DepResult};
+
+impl<'a> DepGraph<'a> {
+    /// A new graph containing only `targets` and everything they depend on, such as to run
+    /// separately or to draw a smaller diagram with `to_dot`.
+    ///
+    /// The new graph shares the build functions, freshness checker and build log of this one.
+    /// Aliases of targets that are left out are dropped, and the new graph has no default target.
+    /// Returns `Error::UnknownTarget` if one of `targets` isn't in the graph.
+    pub fn subgraph<P: AsRef<Path>>(&self, targets: &[P]) -> DepResult<DepGraph<'a>> {
+        let roots = targets
+            .iter()
+            .map(|target| self.node(target.as_ref()))
+            .collect::<DepResult<Vec<_>>>()?;
+        let wanted = self.reachable(&roots);
+        // edges are kept in order, so dependencies stay in the order they were given
+        let graph = self.graph.filter_map(
+            |idx, dep| wanted[idx.index()].then(|| dep.clone()),
+            |_, _| Some(()),
+        );
+        let file_hash: HashMap<_, _> = graph
+            .node_indices()
+            .map(|idx| (graph[idx].filename.clone(), idx))
+            .collect();
+        let aliases = self
+            .aliases
+            .iter()
+            .filter_map(|(name, idx)| {
+                let idx = file_hash.get(&self.graph[*idx].filename)?;
+                Some((name.clone(), *idx))
+            })
+            .collect();
+        Ok(DepGraph {
+            graph,
+            freshness: self.freshness.clone(),
+            log: self.log.clone(),
+            file_hash,
+            aliases,
+            default_wanted: None,
+            assume: self.assume.clone(),
+            stamps: Mutex::new(self.stamps.lock().unwrap().clone()),
+            pools: self.pools.clone(),
+        })
+    }
+}
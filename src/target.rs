@@ -0,0 +1,320 @@
+//! Targets that aren't files.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use crate::{DepGraph, DepGraphBuilder, DepResult, DependencyNode, RebuildReason, Rule};
+use crate::{Error, MaybeSend, MaybeSync, RuleOptions};
+
+/// Something a rule can build, or depend on, other than a file, such as a database migration
+/// that has been applied or a docker image that has been built.
+///
+/// Each target has a key, which is its name in the graph: rules depend on it by listing the key
+/// as a dependency, and its build function is passed the key as its output. The key shouldn't
+/// be the path of a real file. Files are targets too, with their path as the key and their
+/// modification time as the stamp.
+///
+/// A rule that builds a target, or depends on one, is stale if the target doesn't exist, or if
+/// any of its dependencies has changed since the graph last built it. If the graph hasn't built
+/// it, which is the case in a new process, it is stale if any dependency has a later stamp than
+/// it. The freshness checker and build log aren't used for these rules, since they only
+/// understand files.
+///
+/// ```no_run
+/// use std::path::PathBuf;
+/// use std::time::SystemTime;
+/// use depgraph::{DepResult, Target};
+///
+/// struct Migration(u32);
+///
+/// impl Target for Migration {
+///     fn key(&self) -> PathBuf {
+///         PathBuf::from(format!("migration:{}", self.0))
+///     }
+///
+///     fn is_present(&self) -> DepResult<bool> {
+///         # fn applied(_: u32) -> Option<SystemTime> { None }
+///         Ok(applied(self.0).is_some())
+///     }
+///
+///     fn stamp(&self) -> DepResult<Option<SystemTime>> {
+///         # fn applied(_: u32) -> Option<SystemTime> { None }
+///         Ok(applied(self.0))
+///     }
+/// }
+///
+/// # fn apply(_: &std::path::Path, _: &[&std::path::Path]) -> Result<(), String> { Ok(()) }
+/// let builder = depgraph::DepGraphBuilder::new()
+///     .add_target_rule(Migration(3), &["migrations/0003.sql"], apply);
+/// ```
+pub trait Target: Send + Sync {
+    /// The name of the target in the graph.
+    fn key(&self) -> PathBuf;
+
+    /// Whether the target exists.
+    fn is_present(&self) -> DepResult<bool>;
+
+    /// When the target last changed, or `None` if it doesn't exist. This is compared with the
+    /// stamps of the target's dependencies, and those of the targets that depend on it, like a
+    /// file's modification time.
+    fn stamp(&self) -> DepResult<Option<SystemTime>>;
+
+    /// When the target last changed in a way that matters to the targets that depend on it. This
+    /// is `stamp` by default, but can be earlier for a target that is sometimes rebuilt without
+    /// changing, so its dependents aren't rebuilt too.
+    fn changed(&self) -> DepResult<Option<SystemTime>> {
+        self.stamp()
+    }
+}
+
+impl Target for PathBuf {
+    fn key(&self) -> PathBuf {
+        self.clone()
+    }
+
+    fn is_present(&self) -> DepResult<bool> {
+        Ok(Path::exists(self))
+    }
+
+    fn stamp(&self) -> DepResult<Option<SystemTime>> {
+        match fs::metadata(self) {
+            Ok(meta) => Ok(Some(meta.modified()?)),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl<'a> DepGraphBuilder<'a> {
+    /// Add a rule that builds `target`, which needn't be a file, like `add_rule`.
+    ///
+    /// The build function is passed the target's key as its output, and other rules depend on the
+    /// target through its key. See `Target` for how staleness is decided.
+    pub fn add_target_rule<T, F, P>(
+        mut self,
+        target: T,
+        dependencies: &[P],
+        build_fn: F,
+    ) -> DepGraphBuilder<'a>
+    where
+        T: Target + 'static,
+        F: Fn(&Path, &[&Path]) -> Result<(), String> + MaybeSend + MaybeSync + 'static,
+        P: AsRef<Path>,
+    {
+        self = self.add_rule(target.key(), dependencies, build_fn);
+        self.rules.last_mut().unwrap().target = Some(Arc::new(target));
+        self
+    }
+
+    /// Add a target that rules can depend on but that isn't built by the graph, such as a remote
+    /// resource. Like a source file, it is an error if it doesn't exist when it is needed.
+    pub fn add_external_target<T: Target + 'static>(mut self, target: T) -> DepGraphBuilder<'a> {
+        self.rules.push(Rule {
+            filename: target.key(),
+            dependencies: Vec::new(),
+            build_fn: None,
+            options: RuleOptions::default(),
+            extra_outputs: Vec::new(),
+            command: None,
+            target: Some(Arc::new(target)),
+        });
+        self
+    }
+}
+
+impl<'a> DepGraph<'a> {
+    /// Whether the staleness of `dep` is decided by comparing stamps, because it or one of its
+    /// dependencies isn't a file.
+    pub(crate) fn uses_stamps(&self, dep: &DependencyNode, deps: &[&Path]) -> bool {
+        dep.target.is_some() || deps.iter().any(|dep| self.target(dep).is_some())
+    }
+
+    /// Why `dep` is stale, going by the stamps of it and its dependencies.
+    ///
+    /// If `dep` was built by this graph, it is stale if any dependency has changed since. Otherwise
+    /// the stamps are compared, as the clocks that set them may not be the same.
+    pub(crate) fn stamp_stale_reason(
+        &self,
+        dep: &DependencyNode,
+        deps: &[&Path],
+    ) -> DepResult<Option<RebuildReason>> {
+        let stamp = match self.stamp(&dep.filename)? {
+            Some(stamp) => stamp,
+            None => return Ok(Some(RebuildReason::MissingOutput)),
+        };
+        let stamps = self.stamps.lock().unwrap();
+        let built_against = stamps
+            .get(&dep.filename)
+            .filter(|built_against| built_against.len() == deps.len());
+        for (i, path) in deps.iter().enumerate() {
+            let changed = match self.changed(path)? {
+                Some(changed) => changed,
+                None => return Err(Error::MissingFile(path.to_path_buf())),
+            };
+            let newer = match built_against {
+                Some(built_against) => built_against[i] != Some(changed),
+                None => changed > stamp,
+            };
+            if newer {
+                return Ok(Some(RebuildReason::DepNewer(path.to_path_buf())));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Remember the stamps of the dependencies `dep` was just built against.
+    pub(crate) fn record_stamps(&self, dep: &DependencyNode, deps: &[&Path]) -> DepResult<()> {
+        let built_against = deps
+            .iter()
+            .map(|path| self.changed(path))
+            .collect::<DepResult<_>>()?;
+        self.stamps
+            .lock()
+            .unwrap()
+            .insert(dep.filename.clone(), built_against);
+        Ok(())
+    }
+
+    /// The stamp of the target or file `path`.
+    fn stamp(&self, path: &Path) -> DepResult<Option<SystemTime>> {
+        match self.target(path) {
+            Some(target) => target.stamp(),
+            None => path.to_path_buf().stamp(),
+        }
+    }
+
+    /// When the target or file `path` last changed, for the targets that depend on it.
+    fn changed(&self, path: &Path) -> DepResult<Option<SystemTime>> {
+        match self.target(path) {
+            Some(target) => target.changed(),
+            None => path.to_path_buf().stamp(),
+        }
+    }
+}
+
+/// A value built by one rule and used by others in the same process, such as a parsed file or an
+/// intermediate transform, without writing it to a temporary file.
+///
+/// Handles are cheap to clone and share the value: give one to `add_target_rule`, and move the
+/// others into the build functions that `set` and `get` it. The value only lives as long as the
+/// handles, so it is built again by the first `make` in each process.
+///
+/// Staleness goes by the identity of the value: setting a value equal to the current one leaves
+/// the targets that depend on it up to date.
+///
+/// ```no_run
+/// use depgraph::{DepGraphBuilder, MakeParams, MemoryTarget};
+///
+/// let config = MemoryTarget::new("config");
+/// let (set_config, get_config) = (config.clone(), config.clone());
+/// DepGraphBuilder::new()
+///     .add_target_rule(config, &["config.toml"], move |_, deps| {
+///         let text = std::fs::read_to_string(deps[0]).map_err(|e| e.to_string())?;
+///         set_config.set(text.to_uppercase());
+///         Ok(())
+///     })
+///     .add_rule("out/config.txt", &["config"], move |out, _| {
+///         let text = get_config.get().ok_or("config wasn't built")?;
+///         std::fs::write(out, text).map_err(|e| e.to_string())
+///     })
+///     .build()
+///     .unwrap()
+///     .make(MakeParams::None)
+///     .unwrap();
+/// ```
+pub struct MemoryTarget<T> {
+    key: PathBuf,
+    state: Arc<Mutex<Option<MemoryState<T>>>>,
+}
+
+/// (Internal) The value of a `MemoryTarget` once it is set.
+struct MemoryState<T> {
+    value: T,
+    /// when the value was last set
+    set: SystemTime,
+    /// when the value last changed
+    changed: SystemTime,
+}
+
+impl<T: PartialEq> MemoryTarget<T> {
+    /// Create an empty target named `key`.
+    pub fn new<P: Into<PathBuf>>(key: P) -> MemoryTarget<T> {
+        MemoryTarget {
+            key: key.into(),
+            state: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// Set the value, which is usually done by the target's build function.
+    pub fn set(&self, value: T) {
+        let now = SystemTime::now();
+        let mut state = self.state.lock().unwrap();
+        match *state {
+            Some(ref mut state) if state.value == value => state.set = now,
+            _ => {
+                *state = Some(MemoryState {
+                    value,
+                    set: now,
+                    changed: now,
+                })
+            }
+        }
+    }
+
+    /// Call `f` with the value, or `None` if it hasn't been built.
+    pub fn with<R, F: FnOnce(Option<&T>) -> R>(&self, f: F) -> R {
+        f(self
+            .state
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|state| &state.value))
+    }
+
+    /// Forget the value, so the target is built again.
+    pub fn clear(&self) {
+        *self.state.lock().unwrap() = None;
+    }
+}
+
+impl<T: PartialEq + Clone> MemoryTarget<T> {
+    /// A copy of the value, or `None` if it hasn't been built.
+    pub fn get(&self) -> Option<T> {
+        self.with(|value| value.cloned())
+    }
+}
+
+impl<T> Clone for MemoryTarget<T> {
+    fn clone(&self) -> MemoryTarget<T> {
+        MemoryTarget {
+            key: self.key.clone(),
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<T: Send> Target for MemoryTarget<T> {
+    fn key(&self) -> PathBuf {
+        self.key.clone()
+    }
+
+    fn is_present(&self) -> DepResult<bool> {
+        Ok(self.state.lock().unwrap().is_some())
+    }
+
+    fn stamp(&self) -> DepResult<Option<SystemTime>> {
+        Ok(self.state.lock().unwrap().as_ref().map(|state| state.set))
+    }
+
+    fn changed(&self) -> DepResult<Option<SystemTime>> {
+        Ok(self
+            .state
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|state| state.changed))
+    }
+}
@@ -0,0 +1,91 @@
+//! Marking targets as up to date without building them (`MakeParams::Touch`).
+
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use crate::{BuildObserver, DepGraph, DepResult, MakeParams};
+
+impl<'a> DepGraph<'a> {
+    /// Bring every stale target up to date by touching it instead of running its build function,
+    /// like `make -t`.
+    ///
+    /// Targets are touched in build order, so each ends up newer than its dependencies, and are
+    /// recorded as built with the freshness checker and build log. Missing targets are created
+    /// empty. Targets that aren't files (see `Target`) are left alone.
+    ///
+    /// If `wanted` is given, only the nodes in it are touched.
+    pub(crate) fn touch(
+        &self,
+        wanted: Option<&[bool]>,
+        observer: &dyn BuildObserver,
+    ) -> DepResult<()> {
+        observer.on_begin(self.rule_count(wanted));
+        let result = self.touch_stale(wanted, observer);
+        observer.on_end(&result);
+        result
+    }
+
+    /// Touch the stale targets, for `touch`.
+    fn touch_stale(&self, wanted: Option<&[bool]>, observer: &dyn BuildObserver) -> DepResult<()> {
+        for (idx, reason) in self.rebuild_reasons(MakeParams::None, wanted)? {
+            let dep = &self.graph[idx];
+            if dep.build_fn.is_none() {
+                continue;
+            }
+            // targets that aren't files can't be touched
+            if !reason.is_rebuild() || dep.target.is_some() {
+                observer.on_skipped(&dep.filename);
+                continue;
+            }
+            observer.on_start(&dep.filename);
+            for output in dep.outputs() {
+                touch(output)?;
+            }
+            let children = self.dependency_paths(idx)?;
+            // keep the duration of the last real build
+            let duration = self.last_duration(&dep.filename).unwrap_or_default();
+            self.record_built(dep, &children, duration)?;
+            observer.on_finished(&dep.filename, Duration::ZERO);
+        }
+        Ok(())
+    }
+}
+
+/// Set the modification time of `path` (a file or directory) to now, creating an empty file if
+/// it doesn't exist.
+pub(crate) fn touch(path: &Path) -> io::Result<()> {
+    let file = if path.is_dir() {
+        open_dir(path)?
+    } else {
+        File::options()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?
+    };
+    file.set_modified(SystemTime::now())
+}
+
+/// Open a directory to set its modification time.
+#[cfg(not(windows))]
+fn open_dir(path: &Path) -> io::Result<File> {
+    File::open(path)
+}
+
+/// Open a directory to set its modification time.
+///
+/// Windows only opens directories with `FILE_FLAG_BACKUP_SEMANTICS`, and setting the time needs
+/// `FILE_WRITE_ATTRIBUTES` access, which `File::open` doesn't ask for.
+#[cfg(windows)]
+fn open_dir(path: &Path) -> io::Result<File> {
+    use std::os::windows::fs::OpenOptionsExt;
+
+    const FILE_WRITE_ATTRIBUTES: u32 = 0x0100;
+    const FILE_FLAG_BACKUP_SEMANTICS: u32 = 0x0200_0000;
+    File::options()
+        .access_mode(FILE_WRITE_ATTRIBUTES)
+        .custom_flags(FILE_FLAG_BACKUP_SEMANTICS)
+        .open(path)
+}
@@ -0,0 +1,60 @@
+//! Printing a target's dependencies as a tree.
+
+use std::io::{self, Write};
+use std::path::Path;
+
+use petgraph::graph::NodeIndex;
+
+use crate::{DepGraph, DepResult};
+
+impl<'a> DepGraph<'a> {
+    /// Write the dependency tree of `target` as indented ASCII, similar to `cargo tree`.
+    ///
+    /// Dependencies that have already been shown elsewhere in the tree are marked with `(*)`
+    /// and not expanded again.
+    pub fn write_tree<P, W>(&self, target: P, mut out: W) -> DepResult<()>
+    where
+        P: AsRef<Path>,
+        W: Write,
+    {
+        let idx = self.node(target.as_ref())?;
+        let mut seen = vec![false; self.graph.node_count()];
+        writeln!(out, "{}", self.graph[idx].filename.display())?;
+        seen[idx.index()] = true;
+        self.write_subtree(idx, "", &mut seen, &mut out)?;
+        Ok(())
+    }
+
+    /// Print the dependency tree of `target` to stdout. See `write_tree`.
+    pub fn print_tree<P: AsRef<Path>>(&self, target: P) -> DepResult<()> {
+        self.write_tree(target, io::stdout().lock())
+    }
+
+    /// Helper function to write the children of `idx`, each line starting with `prefix`.
+    fn write_subtree<W: Write>(
+        &self,
+        idx: NodeIndex<u32>,
+        prefix: &str,
+        seen: &mut [bool],
+        out: &mut W,
+    ) -> io::Result<()> {
+        let children = self.children(idx);
+        for (i, child) in children.iter().enumerate() {
+            let last = i + 1 == children.len();
+            let (branch, indent) = if last {
+                ("└── ", "    ")
+            } else {
+                ("├── ", "│   ")
+            };
+            let filename = self.graph[*child].filename.display();
+            if seen[child.index()] {
+                writeln!(out, "{}{}{} (*)", prefix, branch, filename)?;
+            } else {
+                writeln!(out, "{}{}{}", prefix, branch, filename)?;
+                seen[child.index()] = true;
+                self.write_subtree(*child, &format!("{}{}", prefix, indent), seen, out)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,63 @@
+//! Helpers for writing build functions.
+
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use crate::sha256;
+
+/// Write `contents` to the file at `path`, so that it either has the new contents or is left as
+/// it was, never half-written.
+///
+/// The contents are written to a temporary file in the same directory, which is then renamed over
+/// `path`. Use this in build functions so that a build that is interrupted or fails part way
+/// through doesn't leave behind a truncated output that looks up to date.
+///
+/// ```no_run
+/// # use std::path::Path;
+/// fn build(out: &Path, deps: &[&Path]) -> Result<(), String> {
+///     let source = std::fs::read_to_string(deps[0]).map_err(|e| e.to_string())?;
+///     depgraph::util::atomic_write(out, source.to_uppercase()).map_err(|e| e.to_string())
+/// }
+/// ```
+pub fn atomic_write<P, C>(path: P, contents: C) -> io::Result<()>
+where
+    P: AsRef<Path>,
+    C: AsRef<[u8]>,
+{
+    let path = path.as_ref();
+    let tmp_path = temp_path(path)?;
+    let result = File::create(&tmp_path).and_then(|mut file| {
+        file.write_all(contents.as_ref())?;
+        file.sync_all()
+    });
+    match result.and_then(|()| fs::rename(&tmp_path, path)) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            // the original error is more useful than any from cleaning up
+            let _ = fs::remove_file(&tmp_path);
+            Err(e)
+        }
+    }
+}
+
+/// The SHA-256 digest of the contents of the file at `path`, as lowercase hex.
+pub fn sha256_file<P: AsRef<Path>>(path: P) -> io::Result<String> {
+    sha256::file_digest(path.as_ref())
+}
+
+/// A hidden file next to `path` to write to before renaming.
+pub(crate) fn temp_path(path: &Path) -> io::Result<PathBuf> {
+    let name = path.file_name().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is not a file path", path.display()),
+        )
+    })?;
+    let mut tmp_name = OsString::from(".");
+    tmp_name.push(name);
+    tmp_name.push(format!(".{}.tmp", process::id()));
+    Ok(path.with_file_name(tmp_name))
+}
@@ -0,0 +1,187 @@
+//! Rebuilding when source files change (the `watch` feature).
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::{DepGraph, DepResult, MakeParams};
+
+/// How often `DepGraph::watch` checks the source files, when it can't use file notifications.
+pub(crate) const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// (Internal) The modified time and size of each watched file, or `None` if it doesn't exist.
+pub(crate) type Snapshot = HashMap<PathBuf, Option<(SystemTime, u64)>>;
+
+impl<'a> DepGraph<'a> {
+    /// Build the graph, then wait for source files to change and build it again, until `callback`
+    /// returns `false`. This blocks the calling thread, and is meant for development loops, such
+    /// as recompiling shaders while an application runs.
+    ///
+    /// After each build `callback` is called with the source files that changed since the
+    /// previous build (none for the first) and the result of `make`. A failed build doesn't stop
+    /// the watch, so a fix can be picked up. Errors are only returned for problems finding the
+    /// files to watch.
+    ///
+    /// The watched files are the same ones `write_cargo_rerun_if_changed` lists: every file
+    /// without a rule, and the dependencies discovered by depfiles and scanners. The directories
+    /// they are in are watched with the operating system's file notifications (through the
+    /// `notify` crate). If that isn't possible, for example because a directory doesn't exist
+    /// yet, the files are polled every half second instead; see `watch_with_interval`.
+    pub fn watch<F>(&self, make_params: MakeParams, callback: F) -> DepResult<()>
+    where
+        F: FnMut(&[PathBuf], DepResult<()>) -> bool,
+    {
+        self.watch_with_interval(make_params, POLL_INTERVAL, callback)
+    }
+
+    /// Like `watch`, but checking the source files every `interval` if file notifications can't
+    /// be used.
+    pub fn watch_with_interval<F>(
+        &self,
+        make_params: MakeParams,
+        interval: Duration,
+        mut callback: F,
+    ) -> DepResult<()>
+    where
+        F: FnMut(&[PathBuf], DepResult<()>) -> bool,
+    {
+        let mut changed = Vec::new();
+        let mut snapshot = Snapshot::new();
+        let mut waiter = Waiter::new();
+        loop {
+            let result = self.make_watching(&mut snapshot, || self.make(make_params))?;
+            if !callback(&changed, result) {
+                return Ok(());
+            }
+            waiter.watch(&snapshot);
+            changed = loop {
+                waiter.wait(interval);
+                let changed = changed_files(&snapshot, &self.snapshot()?);
+                if !changed.is_empty() {
+                    break changed;
+                }
+            };
+        }
+    }
+
+    /// Run `make`, and update `snapshot` to the state of the source files it started from.
+    ///
+    /// The outer error is for problems finding the source files, and the inner one is the result
+    /// of `make`.
+    pub(crate) fn make_watching<F>(
+        &self,
+        snapshot: &mut Snapshot,
+        make: F,
+    ) -> DepResult<DepResult<()>>
+    where
+        F: FnOnce() -> DepResult<()>,
+    {
+        // take the snapshot before building, so changes made during the build aren't missed
+        let mut before = self.snapshot()?;
+        let result = make();
+        // the build may have discovered new dependencies to watch
+        for (path, state) in self.snapshot()? {
+            before.entry(path).or_insert(state);
+        }
+        *snapshot = before;
+        Ok(result)
+    }
+
+    /// The current state of the source files.
+    pub(crate) fn snapshot(&self) -> DepResult<Snapshot> {
+        Ok(self
+            .source_paths()?
+            .into_iter()
+            .map(|path| {
+                let state = fs::metadata(&path)
+                    .ok()
+                    .and_then(|meta| Some((meta.modified().ok()?, meta.len())));
+                (path, state)
+            })
+            .collect())
+    }
+}
+
+/// The files whose state differs between `old` and `new`, in order.
+pub(crate) fn changed_files(old: &Snapshot, new: &Snapshot) -> Vec<PathBuf> {
+    let mut changed: Vec<PathBuf> = old
+        .keys()
+        .chain(new.keys().filter(|path| !old.contains_key(*path)))
+        .filter(|path| old.get(*path).copied().flatten() != new.get(*path).copied().flatten())
+        .cloned()
+        .collect();
+    changed.sort();
+    changed
+}
+
+/// (Internal) Waits until the watched files may have changed, using the operating system's file
+/// notifications if it can, and polling otherwise.
+struct Waiter {
+    /// `None` once notifications have failed
+    watcher: Option<RecommendedWatcher>,
+    events: mpsc::Receiver<()>,
+    /// The directories being watched
+    dirs: HashSet<PathBuf>,
+    /// Whether directories were added since the last wait, so changes made before they were
+    /// watched may have been missed
+    added: bool,
+}
+
+impl Waiter {
+    fn new() -> Waiter {
+        let (send, events) = mpsc::channel();
+        let watcher = notify::recommended_watcher(move |_| {
+            let _ = send.send(());
+        })
+        .ok();
+        Waiter {
+            watcher,
+            events,
+            dirs: HashSet::new(),
+            added: false,
+        }
+    }
+
+    /// Watch the directories of all the files in `snapshot`, falling back to polling if any of
+    /// them can't be watched.
+    fn watch(&mut self, snapshot: &Snapshot) {
+        for path in snapshot.keys() {
+            let watcher = match self.watcher {
+                Some(ref mut watcher) => watcher,
+                None => return,
+            };
+            let dir = match path.parent() {
+                Some(dir) if dir != Path::new("") => dir,
+                _ => Path::new("."),
+            };
+            if self.dirs.contains(dir) {
+                continue;
+            }
+            if watcher.watch(dir, RecursiveMode::NonRecursive).is_err() {
+                self.watcher = None;
+                return;
+            }
+            self.dirs.insert(dir.to_owned());
+            self.added = true;
+        }
+    }
+
+    /// Block until a watched directory changes, or for `interval` when polling.
+    fn wait(&mut self, interval: Duration) {
+        if self.added {
+            self.added = false;
+        } else if self.watcher.is_some() {
+            // there is often a burst of events for one change
+            if self.events.recv().is_ok() {
+                while self.events.try_recv().is_ok() {}
+            }
+        } else {
+            thread::sleep(interval);
+        }
+    }
+}